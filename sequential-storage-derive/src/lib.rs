@@ -0,0 +1,147 @@
+//! Derive macros for [sequential-storage](https://docs.rs/sequential-storage).
+//!
+//! Don't use this crate directly, but enable the `derive` feature of `sequential-storage` instead.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Index, LitInt, Path};
+
+/// Derive the map `Value`, `FixedSizeValue` and `VersionedValue` traits for a struct of fixed size fields.
+///
+/// See the docs of `sequential_storage::map::FixedSizeValue` for more information.
+#[proc_macro_derive(Value, attributes(value))]
+pub fn derive_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "Value can only be derived for structs without generics",
+        ));
+    }
+
+    let mut version = 0u8;
+    let mut migrate = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("value"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                version = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                Ok(())
+            } else if meta.path.is_ident("migrate") {
+                migrate = Some(meta.value()?.parse::<Path>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported value attribute, expected `version` or `migrate`"))
+            }
+        })?;
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Value can only be derived for structs",
+        ));
+    };
+
+    let field_types: Vec<_> = data.fields.iter().map(|field| &field.ty).collect();
+    let field_accessors: Vec<_> = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(index);
+                quote!(#index)
+            }
+        })
+        .collect();
+    let field_variables: Vec<_> = (0..data.fields.len())
+        .map(|index| format_ident!("field_{}", index))
+        .collect();
+
+    let construct = match &data.fields {
+        Fields::Named(_) => quote!(Self { #(#field_accessors: #field_variables),* }),
+        Fields::Unnamed(_) => quote!(Self ( #(#field_variables),* )),
+        Fields::Unit => quote!(Self),
+    };
+
+    let map = quote!(::sequential_storage::map);
+
+    let migrate = migrate.map(|migrate| {
+        quote! {
+            fn migrate(version: u8, buffer: &'a [u8]) -> Result<Self, #map::SerializationError> {
+                #migrate(version, buffer)
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl<'a> #map::Value<'a> for #name {
+            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, #map::SerializationError> {
+                let (version, buffer) = buffer
+                    .split_first_mut()
+                    .ok_or(#map::SerializationError::BufferTooSmall)?;
+                *version = #version;
+
+                let mut offset = 0;
+                #(
+                    let size = <#field_types as #map::FixedSizeValue>::SIZE;
+                    let field = buffer
+                        .get_mut(offset..offset + size)
+                        .ok_or(#map::SerializationError::BufferTooSmall)?;
+                    // Deserializing relies on every field having its fixed size
+                    if #map::Value::serialize_into(&self.#field_accessors, field)? != size {
+                        return Err(#map::SerializationError::InvalidData);
+                    }
+                    offset += size;
+                )*
+
+                Ok(1 + offset)
+            }
+
+            fn deserialize_from(buffer: &'a [u8]) -> Result<Self, #map::SerializationError>
+            where
+                Self: Sized,
+            {
+                let (version, buffer) = buffer
+                    .split_first()
+                    .ok_or(#map::SerializationError::BufferTooSmall)?;
+                if *version != #version {
+                    return <Self as #map::VersionedValue>::migrate(*version, buffer);
+                }
+
+                #(
+                    let (field, buffer) = buffer
+                        .split_at_checked(<#field_types as #map::FixedSizeValue>::SIZE)
+                        .ok_or(#map::SerializationError::BufferTooSmall)?;
+                    let #field_variables = <#field_types as #map::Value>::deserialize_from(field)?;
+                )*
+                let _ = buffer;
+
+                Ok(#construct)
+            }
+        }
+
+        impl #map::FixedSizeValue for #name {
+            const SIZE: usize = 1 #(+ <#field_types as #map::FixedSizeValue>::SIZE)*;
+        }
+
+        impl<'a> #map::VersionedValue<'a> for #name {
+            const VERSION: u8 = #version;
+
+            #migrate
+        }
+    })
+}
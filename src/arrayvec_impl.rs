@@ -95,9 +95,18 @@ impl<const CAP: usize> Key for ArrayString<CAP> {
 
 #[cfg(test)]
 mod tests {
-    use core::str::FromStr;
+    use core::{ops::Range, str::FromStr};
 
     use super::*;
+    use crate::{
+        cache,
+        map::{fetch_item, store_item},
+        mock_flash::{self, WriteCountCheck},
+        AlignedBuf,
+    };
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+    const FLASH_RANGE: Range<u32> = 0x000..0x1000;
 
     #[test]
     fn serde_arrayvec() {
@@ -120,4 +129,112 @@ mod tests {
 
         assert_eq!((val, 14), new_val);
     }
+
+    #[futures_test::test]
+    async fn store_variable_length_keys() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let keys = [
+            ArrayVec::<u8, 16>::from_iter([0xAA; 16]),
+            ArrayVec::<u8, 16>::from_iter([0xAA; 5]),
+            ArrayVec::<u8, 16>::new(),
+        ];
+
+        for (value, key) in keys.iter().enumerate() {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                key,
+                &(value as u32),
+            )
+            .await
+            .unwrap();
+        }
+
+        for (value, key) in keys.iter().enumerate() {
+            assert_eq!(
+                fetch_item::<_, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    key,
+                )
+                .await
+                .unwrap(),
+                Some(value as u32)
+            );
+        }
+
+        assert_eq!(
+            fetch_item::<_, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &ArrayVec::<u8, 16>::from_iter([0xAA; 6]),
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[futures_test::test]
+    async fn store_string_keys() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let ssid = ArrayString::<16>::from_str("wifi.ssid").unwrap();
+        let password = ArrayString::<16>::from_str("wifi.password").unwrap();
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &ssid,
+            &&b"my network"[..],
+        )
+        .await
+        .unwrap();
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &password,
+            &&b"hunter2"[..],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fetch_item::<_, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &ssid,
+            )
+            .await
+            .unwrap(),
+            Some(&b"my network"[..])
+        );
+        assert_eq!(
+            fetch_item::<_, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &password,
+            )
+            .await
+            .unwrap(),
+            Some(&b"hunter2"[..])
+        );
+    }
 }
@@ -0,0 +1,468 @@
+use core::{fmt::Debug, num::NonZeroU32, ops::Range};
+
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::{
+    calculate_page_address, calculate_page_index, item::ItemHeader, map::Key, NorFlashExt,
+    PageState,
+};
+
+use super::{
+    key_filter::UncachedKeyFilter, key_pointers::UncachedKeyPointers, CacheImpl, CacheStats,
+    DirtTracker, Invalidate, KeyCacheImpl, PagePointersCache, PageStatesCache, PrivateCacheImpl,
+    PrivateKeyCacheImpl,
+};
+
+/// What the cache knows about a single page.
+///
+/// The contents are opaque. A [CacheStorage] only has to give back what was stored in it,
+/// either as this type or as the bytes from [Self::to_bytes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct CachedPage {
+    state: Option<PageState>,
+    after_erased: Option<NonZeroU32>,
+    after_written: Option<NonZeroU32>,
+}
+
+impl CachedPage {
+    /// A page the cache doesn't know anything about
+    pub const EMPTY: Self = Self {
+        state: None,
+        after_erased: None,
+        after_written: None,
+    };
+
+    /// The amount of bytes returned by [Self::to_bytes]
+    pub const BYTES: usize = 9;
+
+    /// Turn the page into bytes, e.g. to store it in memory that's not accessed as Rust types
+    pub fn to_bytes(&self) -> [u8; Self::BYTES] {
+        let mut bytes = [0; Self::BYTES];
+        bytes[0] = match self.state {
+            None => 0,
+            Some(PageState::Closed) => 1,
+            Some(PageState::PartialOpen) => 2,
+            Some(PageState::Open) => 3,
+        };
+        bytes[1..5].copy_from_slice(&self.after_erased.map_or(0, NonZeroU32::get).to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.after_written.map_or(0, NonZeroU32::get).to_le_bytes());
+        bytes
+    }
+
+    /// Get back the page from the bytes of [Self::to_bytes].
+    ///
+    /// Bytes that weren't made by [Self::to_bytes] give an empty page or one that's wrong,
+    /// so make sure that the memory holding the bytes is valid.
+    pub fn from_bytes(bytes: [u8; Self::BYTES]) -> Self {
+        Self {
+            state: match bytes[0] {
+                1 => Some(PageState::Closed),
+                2 => Some(PageState::PartialOpen),
+                3 => Some(PageState::Open),
+                _ => None,
+            },
+            after_erased: NonZeroU32::new(u32::from_le_bytes(bytes[1..5].try_into().unwrap())),
+            after_written: NonZeroU32::new(u32::from_le_bytes(bytes[5..9].try_into().unwrap())),
+        }
+    }
+}
+
+/// The memory that holds the state of a [CustomCache].
+///
+/// Implement this to keep the cache somewhere else than in a normal Rust value,
+/// e.g. in battery-backed SRAM so the cache survives a reset and doesn't have to be built up again.
+///
+/// The memory must start out with all pages [CachedPage::EMPTY] and not dirty, or with the state
+/// that was left by an earlier [CustomCache] for the same flash range.
+/// The same rules apply as for the other caches: the state must only ever be used with the same flash range,
+/// and the flash must not be changed without the cache.
+///
+/// ```rust
+/// # use sequential_storage::cache::{CacheStorage, CachedPage, CustomCache};
+/// #[derive(Debug)]
+/// struct Sram {
+///     pages: [[u8; CachedPage::BYTES]; 4],
+///     dirty: bool,
+/// }
+///
+/// impl CacheStorage for Sram {
+///     fn page_count(&self) -> usize {
+///         self.pages.len()
+///     }
+///
+///     fn page(&self, page_index: usize) -> CachedPage {
+///         CachedPage::from_bytes(self.pages[page_index])
+///     }
+///
+///     fn set_page(&mut self, page_index: usize, page: CachedPage) {
+///         self.pages[page_index] = page.to_bytes();
+///     }
+///
+///     fn is_dirty(&self) -> bool {
+///         self.dirty
+///     }
+///
+///     fn set_dirty(&mut self, dirty: bool) {
+///         self.dirty = dirty;
+///     }
+/// }
+///
+/// let mut cache = CustomCache::new(Sram {
+///     pages: [CachedPage::EMPTY.to_bytes(); 4],
+///     dirty: false,
+/// });
+/// ```
+pub trait CacheStorage: Debug {
+    /// The amount of pages the storage can hold. This must be at least the amount of pages in the flash range.
+    fn page_count(&self) -> usize;
+
+    /// Get the page that was last stored at the index
+    fn page(&self, page_index: usize) -> CachedPage;
+    /// Store the page at the index
+    fn set_page(&mut self, page_index: usize, page: CachedPage);
+
+    /// Get the dirty flag that was last stored
+    fn is_dirty(&self) -> bool;
+    /// Store the dirty flag. While it's set, an operation is in progress and the pages might not match the flash.
+    fn set_dirty(&mut self, dirty: bool);
+}
+
+/// A cache object that keeps track of the page states and some pointers to the items in the page,
+/// just like the [super::PagePointerCache], but keeps its state in a [CacheStorage] that you implement.
+///
+/// This cache has to be kept around and passed to *every* api call to the same memory region until the cache gets discarded.
+///
+/// Valid usecase:
+/// `Create cache 1` -> `use 1` -> `use 1` -> `create cache 2` -> `use 2` -> `use 2`
+///
+/// Invalid usecase:
+/// `Create cache 1` -> `use 1` -> `create cache 2` -> `use 2` -> `❌ use 1 ❌`
+///
+/// When the storage holds on to its state, the cache can be created again with the same storage
+/// (e.g. after a reset) and it continues where it left off.
+#[derive(Debug)]
+pub struct CustomCache<T: CacheStorage> {
+    pages: StoragePages<T>,
+    stats: CacheStats,
+    key_pointers: UncachedKeyPointers,
+    key_filter: UncachedKeyFilter,
+}
+
+impl<T: CacheStorage> CustomCache<T> {
+    /// Construct a new instance that keeps its state in the storage
+    pub const fn new(storage: T) -> Self {
+        Self {
+            pages: StoragePages(storage),
+            stats: CacheStats::new(),
+            key_pointers: UncachedKeyPointers,
+            key_filter: UncachedKeyFilter,
+        }
+    }
+
+    /// Get back the storage
+    pub fn into_storage(self) -> T {
+        self.pages.0
+    }
+
+    /// Get the hit and miss counts of the cache since it was created or since [Self::reset_stats] was called
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Set all hit and miss counts back to 0
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::new();
+    }
+}
+
+impl<T: CacheStorage> PrivateCacheImpl for CustomCache<T> {
+    type PSC = StoragePages<T>;
+    type PPC = StoragePages<T>;
+
+    fn dirt_tracker<R>(&mut self, f: impl FnOnce(&mut DirtTracker) -> R) -> Option<R> {
+        let mut dirt_tracker = DirtTracker::new();
+        if self.pages.0.is_dirty() {
+            dirt_tracker.mark_dirty();
+        }
+
+        let result = f(&mut dirt_tracker);
+
+        if dirt_tracker.is_dirty() != self.pages.0.is_dirty() {
+            self.pages.0.set_dirty(dirt_tracker.is_dirty());
+        }
+        Some(result)
+    }
+
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        Some(f(&mut self.stats))
+    }
+
+    fn page_states(&mut self) -> &mut Self::PSC {
+        &mut self.pages
+    }
+
+    fn page_pointers(&mut self) -> &mut Self::PPC {
+        &mut self.pages
+    }
+}
+
+impl<T: CacheStorage> CacheImpl for CustomCache<T> {}
+impl<KEY: Key, T: CacheStorage> KeyCacheImpl<KEY> for CustomCache<T> {}
+
+impl<T: CacheStorage> Invalidate for CustomCache<T> {
+    fn invalidate_cache_state(&mut self) {
+        PageStatesCache::invalidate_cache_state(&mut self.pages);
+        self.pages.0.set_dirty(false);
+    }
+}
+
+impl<KEY: Key, T: CacheStorage> PrivateKeyCacheImpl<KEY> for CustomCache<T> {
+    type KPC = UncachedKeyPointers;
+    type KF = UncachedKeyFilter;
+
+    fn key_pointers(&mut self) -> &mut Self::KPC {
+        &mut self.key_pointers
+    }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
+}
+
+/// The page states and the page pointers of a [CustomCache], which are both kept in the pages of the storage
+#[derive(Debug)]
+pub(crate) struct StoragePages<T: CacheStorage>(T);
+
+impl<T: CacheStorage> StoragePages<T> {
+    fn update(&mut self, page_index: usize, f: impl FnOnce(&mut CachedPage)) {
+        let mut page = self.0.page(page_index);
+        let old_page = page;
+        f(&mut page);
+        if page != old_page {
+            self.0.set_page(page_index, page);
+        }
+    }
+}
+
+impl<T: CacheStorage> PageStatesCache for StoragePages<T> {
+    fn get_page_state(&self, page_index: usize) -> Option<PageState> {
+        self.0.page(page_index).state
+    }
+
+    fn notice_page_state(&mut self, page_index: usize, new_state: PageState) {
+        self.update(page_index, |page| page.state = Some(new_state));
+    }
+
+    fn invalidate_cache_state(&mut self) {
+        for page_index in 0..self.0.page_count() {
+            self.update(page_index, |page| *page = CachedPage::EMPTY);
+        }
+    }
+}
+
+impl<T: CacheStorage> PagePointersCache for StoragePages<T> {
+    fn first_item_after_erased(&self, page_index: usize) -> Option<u32> {
+        self.0.page(page_index).after_erased.map(|val| val.get())
+    }
+
+    fn first_item_after_written(&self, page_index: usize) -> Option<u32> {
+        self.0.page(page_index).after_written.map(|val| val.get())
+    }
+
+    fn notice_item_written<S: NorFlash>(
+        &mut self,
+        flash_range: Range<u32>,
+        item_address: u32,
+        item_header: &ItemHeader,
+    ) {
+        let page_index = calculate_page_index::<S>(flash_range, item_address);
+
+        let next_item_address = item_header.next_item_address::<S>(item_address);
+
+        // We only care about the furthest written item, so discard if this is an earlier item
+        self.update(page_index, |page| {
+            if page
+                .after_written
+                .is_none_or(|after_written| after_written.get() < next_item_address)
+            {
+                page.after_written = NonZeroU32::new(next_item_address);
+            }
+        });
+    }
+
+    fn notice_item_erased<S: NorFlash>(
+        &mut self,
+        flash_range: Range<u32>,
+        item_address: u32,
+        item_header: &ItemHeader,
+    ) {
+        let page_index = calculate_page_index::<S>(flash_range.clone(), item_address);
+
+        // Either the item we point to or the first item on the page
+        let next_unerased_item = self.first_item_after_erased(page_index).unwrap_or_else(|| {
+            calculate_page_address::<S>(flash_range, page_index) + S::WORD_SIZE as u32
+        });
+
+        if item_address == next_unerased_item {
+            self.update(page_index, |page| {
+                page.after_erased =
+                    NonZeroU32::new(item_header.next_item_address::<S>(item_address))
+            });
+        }
+    }
+
+    fn notice_free_spot(&mut self, page_index: usize, free_address: u32) {
+        self.update(page_index, |page| {
+            if page
+                .after_written
+                .is_none_or(|after_written| after_written.get() < free_address)
+            {
+                page.after_written = NonZeroU32::new(free_address);
+            }
+        });
+    }
+
+    fn restore_pointers(
+        &mut self,
+        page_index: usize,
+        after_erased: Option<u32>,
+        after_written: Option<u32>,
+    ) {
+        self.update(page_index, |page| {
+            page.after_erased = after_erased.and_then(NonZeroU32::new);
+            page.after_written = after_written.and_then(NonZeroU32::new);
+        });
+    }
+
+    fn notice_page_state(&mut self, page_index: usize, new_state: PageState) {
+        if new_state.is_open() {
+            // This page was erased
+            self.update(page_index, |page| {
+                page.after_erased = None;
+                page.after_written = None;
+            });
+        }
+    }
+
+    fn invalidate_cache_state(&mut self) {
+        PageStatesCache::invalidate_cache_state(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::PagePointerCache,
+        map::{fetch_item, store_item},
+        mock_flash::{self, WriteCountCheck},
+        AlignedBuf,
+    };
+
+    use futures_test::test;
+
+    #[derive(Debug)]
+    struct Sram {
+        pages: [[u8; CachedPage::BYTES]; 4],
+        dirty: bool,
+    }
+
+    impl CacheStorage for Sram {
+        fn page_count(&self) -> usize {
+            self.pages.len()
+        }
+
+        fn page(&self, page_index: usize) -> CachedPage {
+            CachedPage::from_bytes(self.pages[page_index])
+        }
+
+        fn set_page(&mut self, page_index: usize, page: CachedPage) {
+            self.pages[page_index] = page.to_bytes();
+        }
+
+        fn is_dirty(&self) -> bool {
+            self.dirty
+        }
+
+        fn set_dirty(&mut self, dirty: bool) {
+            self.dirty = dirty;
+        }
+    }
+
+    #[test]
+    async fn same_as_page_pointer_cache() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x400;
+
+        let mut flash =
+            mock_flash::MockFlashBase::<4, 1, 256>::new(WriteCountCheck::Twice, None, true);
+        let mut reference_flash =
+            mock_flash::MockFlashBase::<4, 1, 256>::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let mut cache = CustomCache::new(Sram {
+            pages: [CachedPage::EMPTY.to_bytes(); 4],
+            dirty: false,
+        });
+        let mut reference_cache = PagePointerCache::<4>::new();
+
+        for i in 0..200u16 {
+            if i % 50 == 0 {
+                // Like a reset, the state is kept in the storage
+                cache = CustomCache::new(cache.into_storage());
+            }
+
+            let value = [i as u8; 20];
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &(i % 8),
+                &value.as_slice(),
+            )
+            .await
+            .unwrap();
+            store_item(
+                &mut reference_flash,
+                FLASH_RANGE,
+                &mut reference_cache,
+                &mut data_buffer,
+                &(i % 8),
+                &value.as_slice(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(
+                fetch_item::<u16, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    &(i % 8),
+                )
+                .await
+                .unwrap(),
+                Some(value.as_slice())
+            );
+            fetch_item::<u16, &[u8], _>(
+                &mut reference_flash,
+                FLASH_RANGE,
+                &mut reference_cache,
+                &mut data_buffer,
+                &(i % 8),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Even with the resets, nothing extra had to be read from the flash
+        assert_eq!(
+            flash
+                .stats_snapshot()
+                .compare_to(reference_flash.stats_snapshot()),
+            Default::default()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use core::{
+    fmt::Debug,
+    hash::{Hash, Hasher},
+};
+
+pub(crate) trait KeyFilter<KEY> {
+    /// True if the filter keeps track of the keys it's told about
+    fn is_enabled(&self) -> bool;
+
+    /// True if the key is known to not be stored in flash
+    fn is_key_absent(&self, key: &KEY) -> bool;
+
+    /// Let the filter know that the key is stored in flash
+    fn notice_key(&mut self, key: &KEY);
+    /// Let the filter know that all keys that are stored in flash have been noticed
+    fn notice_all_keys_seen(&mut self);
+}
+
+/// A bloom filter of all keys in flash.
+///
+/// It can only tell that a key is absent once all keys in flash have been seen.
+/// Keys that are removed are not taken out of the filter, because other keys may share its bits.
+pub(crate) struct CachedKeyFilter<const BYTES: usize> {
+    bits: [u8; BYTES],
+    all_keys_seen: bool,
+}
+
+impl<const BYTES: usize> Debug for CachedKeyFilter<BYTES> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CachedKeyFilter")
+            .field(
+                "bits_set",
+                &self.bits.iter().map(|b| b.count_ones()).sum::<u32>(),
+            )
+            .field("all_keys_seen", &self.all_keys_seen)
+            .finish()
+    }
+}
+
+impl<const BYTES: usize> CachedKeyFilter<BYTES> {
+    /// The amount of bits that is set for every key
+    const HASHES: u32 = 3;
+
+    pub(crate) const fn new() -> Self {
+        assert!(BYTES > 0);
+
+        Self {
+            bits: [0; BYTES],
+            all_keys_seen: false,
+        }
+    }
+
+    pub(crate) fn invalidate_cache_state(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Get the indices of the bits of the key
+    fn bit_indices<KEY: Hash>(key: &KEY) -> impl Iterator<Item = usize> {
+        let mut hasher = Fnv1aHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // Double hashing gives us as many hashes as we need from one
+        let (h1, h2) = (hash as u32, (hash >> 32) as u32 | 1);
+        (0..Self::HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) as usize) % (BYTES * 8))
+    }
+}
+
+impl<KEY: Hash, const BYTES: usize> KeyFilter<KEY> for CachedKeyFilter<BYTES> {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn is_key_absent(&self, key: &KEY) -> bool {
+        self.all_keys_seen
+            && Self::bit_indices(key).any(|index| self.bits[index / 8] & (1 << (index % 8)) == 0)
+    }
+
+    fn notice_key(&mut self, key: &KEY) {
+        for index in Self::bit_indices(key) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    fn notice_all_keys_seen(&mut self) {
+        self.all_keys_seen = true;
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct UncachedKeyFilter;
+
+impl<KEY> KeyFilter<KEY> for UncachedKeyFilter {
+    fn is_enabled(&self) -> bool {
+        false
+    }
+
+    fn is_key_absent(&self, _key: &KEY) -> bool {
+        false
+    }
+
+    fn notice_key(&mut self, _key: &KEY) {}
+
+    fn notice_all_keys_seen(&mut self) {}
+}
+
+/// A small non-cryptographic hasher, so we don't depend on any randomness
+struct Fnv1aHasher(u64);
+
+impl Fnv1aHasher {
+    const fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1aHasher {
+    fn finish(&self) -> u64 {
+        // The low bits of fnv are badly mixed, so we finish with the murmur3 finalizer
+        let mut hash = self.0;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+        hash ^= hash >> 33;
+        hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absent_keys() {
+        let mut filter = CachedKeyFilter::<8>::new();
+
+        for key in 0..10u16 {
+            filter.notice_key(&key);
+        }
+
+        // Nothing can be absent before all keys have been seen
+        assert!(!(0..1000u16).any(|key| filter.is_key_absent(&key)));
+
+        KeyFilter::<u16>::notice_all_keys_seen(&mut filter);
+
+        assert!(!(0..10u16).any(|key| filter.is_key_absent(&key)));
+        // There are false positives, but most keys must be found to be absent
+        assert!(
+            (10..1000u16)
+                .filter(|key| filter.is_key_absent(key))
+                .count()
+                > 500
+        );
+
+        filter.invalidate_cache_state();
+        assert!(!(0..1000u16).any(|key| filter.is_key_absent(&key)));
+    }
+}
@@ -3,7 +3,8 @@ use core::{fmt::Debug, num::NonZeroU32};
 use crate::map::Key;
 
 pub(crate) trait KeyPointersCache<KEY: Key> {
-    fn key_location(&self, key: &KEY) -> Option<u32>;
+    /// Get the location of the newest item of the key. A hit makes the key the most recently used one.
+    fn key_location(&mut self, key: &KEY) -> Option<u32>;
 
     fn notice_key_location(&mut self, key: &KEY, item_address: u32);
     fn notice_key_erased(&mut self, key: &KEY);
@@ -48,9 +49,12 @@ impl<KEY: Eq, const KEYS: usize> CachedKeyPointers<KEY, KEYS> {
 }
 
 impl<KEY: Key, const KEYS: usize> KeyPointersCache<KEY> for CachedKeyPointers<KEY, KEYS> {
-    fn key_location(&self, key: &KEY) -> Option<u32> {
-        self.key_index(key)
-            .map(|index| self.key_pointers[index].as_ref().unwrap().1.get())
+    fn key_location(&mut self, key: &KEY) -> Option<u32> {
+        let index = self.key_index(key)?;
+        let location = self.key_pointers[index].as_ref().unwrap().1.get();
+        // Keep the most recently used keys, so the keys that are fetched often aren't pushed out
+        move_to_front(&mut self.key_pointers, index);
+        Some(location)
     }
 
     fn notice_key_location(&mut self, key: &KEY, item_address: u32) {
@@ -80,7 +84,7 @@ impl<KEY: Key, const KEYS: usize> KeyPointersCache<KEY> for CachedKeyPointers<KE
 pub(crate) struct UncachedKeyPointers;
 
 impl<KEY: Key> KeyPointersCache<KEY> for UncachedKeyPointers {
-    fn key_location(&self, _key: &KEY) -> Option<u32> {
+    fn key_location(&mut self, _key: &KEY) -> Option<u32> {
         None
     }
 
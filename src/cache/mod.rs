@@ -1,21 +1,34 @@
 //! Module implementing all things cache related
 
-use core::{fmt::Debug, ops::Range};
+use core::{fmt::Debug, hash::Hash, ops::Range};
 
 use embedded_storage_async::nor_flash::NorFlash;
 
 use crate::{item::ItemHeader, map::Key, PageState};
 
 use self::{
+    key_filter::{CachedKeyFilter, KeyFilter, UncachedKeyFilter},
     key_pointers::{CachedKeyPointers, KeyPointersCache, UncachedKeyPointers},
     page_pointers::{CachedPagePointers, UncachedPagePointers},
     page_states::{CachedPageStates, UncachedPageStates},
 };
+#[cfg(feature = "alloc")]
+use self::{page_pointers::HeapPagePointers, page_states::HeapPageStates};
 
+mod custom;
+pub(crate) mod key_filter;
 pub(crate) mod key_pointers;
 pub(crate) mod page_pointers;
 pub(crate) mod page_states;
+mod registry;
+mod snapshot;
 mod tests;
+mod warm;
+
+pub use custom::{CacheStorage, CachedPage, CustomCache};
+pub use registry::{CacheRegistry, UnknownFlashRange};
+pub use snapshot::{restore_snapshot, save_snapshot};
+pub use warm::warm;
 
 pub(crate) use page_pointers::PagePointersCache;
 pub(crate) use page_states::PageStatesCache;
@@ -43,6 +56,7 @@ pub(crate) trait PrivateCacheImpl: Invalidate {
     type PPC: PagePointersCache;
 
     fn dirt_tracker<R>(&mut self, f: impl FnOnce(&mut DirtTracker) -> R) -> Option<R>;
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R>;
     fn page_states(&mut self) -> &mut Self::PSC;
     fn page_pointers(&mut self) -> &mut Self::PPC;
 
@@ -51,6 +65,17 @@ pub(crate) trait PrivateCacheImpl: Invalidate {
         self.dirt_tracker(|d| d.is_dirty()).unwrap_or_default()
     }
 
+    /// If an earlier operation was interrupted, the cache might not match the flash.
+    /// In that case the cache is invalidated so it's built up again from the flash.
+    fn recover_if_dirty(&mut self) {
+        if self.is_dirty() {
+            self.stats_tracker(|stats| {
+                stats.dirty_recoveries = stats.dirty_recoveries.saturating_add(1)
+            });
+            self.invalidate_cache_state();
+        }
+    }
+
     /// Mark the cache as potentially inconsistent with reality
     fn mark_dirty(&mut self) {
         self.dirt_tracker(|d| d.mark_dirty());
@@ -63,7 +88,15 @@ pub(crate) trait PrivateCacheImpl: Invalidate {
 
     /// Get the cache state of the requested page
     fn get_page_state(&mut self, page_index: usize) -> Option<PageState> {
-        self.page_states().get_page_state(page_index)
+        let page_state = self.page_states().get_page_state(page_index);
+        self.stats_tracker(|stats| {
+            if page_state.is_some() {
+                stats.page_state_hits = stats.page_state_hits.saturating_add(1);
+            } else {
+                stats.page_state_misses = stats.page_state_misses.saturating_add(1);
+            }
+        });
+        page_state
     }
 
     /// Let the cache know a page state changed
@@ -103,6 +136,23 @@ pub(crate) trait PrivateCacheImpl: Invalidate {
             .notice_item_written::<S>(flash_range, item_address, item_header)
     }
 
+    /// Let the cache know where the first free spot of the page is, so the next write doesn't have to search for it
+    fn notice_free_spot(&mut self, page_index: usize, free_address: u32) {
+        self.page_pointers()
+            .notice_free_spot(page_index, free_address)
+    }
+
+    /// Set the page pointers to the ones saved in a snapshot
+    fn restore_page_pointers(
+        &mut self,
+        page_index: usize,
+        after_erased: Option<u32>,
+        after_written: Option<u32>,
+    ) {
+        self.page_pointers()
+            .restore_pointers(page_index, after_erased, after_written)
+    }
+
     /// Let the cache know that an item has been erased from flash
     fn notice_item_erased<S: NorFlash>(
         &mut self,
@@ -116,6 +166,9 @@ pub(crate) trait PrivateCacheImpl: Invalidate {
     }
 }
 
+impl<T: CacheImpl> CacheImpl for &mut T {}
+impl<KEY: Key, T: KeyCacheImpl<KEY>> KeyCacheImpl<KEY> for &mut T {}
+
 impl<T: PrivateCacheImpl> PrivateCacheImpl for &mut T {
     type PSC = T::PSC;
     type PPC = T::PPC;
@@ -124,6 +177,10 @@ impl<T: PrivateCacheImpl> PrivateCacheImpl for &mut T {
         T::dirt_tracker(self, f)
     }
 
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        T::stats_tracker(self, f)
+    }
+
     fn page_states(&mut self) -> &mut Self::PSC {
         T::page_states(self)
     }
@@ -135,17 +192,25 @@ impl<T: PrivateCacheImpl> PrivateCacheImpl for &mut T {
 
 pub(crate) trait PrivateKeyCacheImpl<KEY: Key>: PrivateCacheImpl {
     type KPC: KeyPointersCache<KEY>;
+    type KF: KeyFilter<KEY>;
 
     fn key_pointers(&mut self) -> &mut Self::KPC;
+    fn key_filter(&mut self) -> &mut Self::KF;
 
+    /// Get the cached location of the key. A miss is counted by [Self::is_key_absent], which is always called next
     fn key_location(&mut self, key: &KEY) -> Option<u32> {
-        self.key_pointers().key_location(key)
+        let location = self.key_pointers().key_location(key);
+        if location.is_some() {
+            self.stats_tracker(|stats| stats.key_hits = stats.key_hits.saturating_add(1));
+        }
+        location
     }
 
     fn notice_key_location(&mut self, key: &KEY, item_address: u32, dirty: bool) {
         if dirty {
             self.mark_dirty();
         }
+        self.key_filter().notice_key(key);
         self.key_pointers().notice_key_location(key, item_address)
     }
     #[allow(unused)]
@@ -153,14 +218,47 @@ pub(crate) trait PrivateKeyCacheImpl<KEY: Key>: PrivateCacheImpl {
         self.mark_dirty();
         self.key_pointers().notice_key_erased(key)
     }
+
+    /// True if the key is known to not be stored in flash
+    fn is_key_absent(&mut self, key: &KEY) -> bool {
+        let absent = self.key_filter().is_key_absent(key);
+        self.stats_tracker(|stats| {
+            if absent {
+                stats.key_hits = stats.key_hits.saturating_add(1);
+            } else {
+                stats.key_misses = stats.key_misses.saturating_add(1);
+            }
+        });
+        absent
+    }
+
+    /// True if the cache does something with the keys that are seen while searching through the flash
+    fn wants_seen_keys(&mut self) -> bool {
+        self.key_filter().is_enabled()
+    }
+
+    /// Let the cache know that a key was seen while searching through the flash
+    fn notice_key_seen(&mut self, key: &KEY) {
+        self.key_filter().notice_key(key)
+    }
+
+    /// Let the cache know that all keys in flash have been seen
+    fn notice_all_keys_seen(&mut self) {
+        self.key_filter().notice_all_keys_seen()
+    }
 }
 
 impl<KEY: Key, T: PrivateKeyCacheImpl<KEY>> PrivateKeyCacheImpl<KEY> for &mut T {
     type KPC = T::KPC;
+    type KF = T::KF;
 
     fn key_pointers(&mut self) -> &mut Self::KPC {
         T::key_pointers(self)
     }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        T::key_filter(self)
+    }
 }
 
 #[derive(Debug)]
@@ -193,6 +291,44 @@ impl DirtTracker {
     }
 }
 
+/// The amount of times a cache could or couldn't answer a question, so the cache size can be tuned.
+/// The amount of page erases the cache saw is counted too.
+///
+/// These can be gotten with the `stats` function of the cache types. [NoCache] doesn't count anything.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct CacheStats {
+    /// The amount of times the state of a page was known, so it didn't have to be read
+    pub page_state_hits: u32,
+    /// The amount of times the state of a page had to be read from flash
+    pub page_state_misses: u32,
+    /// The amount of map lookups where the cache knew the location of the key, or knew it isn't stored
+    pub key_hits: u32,
+    /// The amount of map lookups where the flash had to be searched for the key.
+    /// For caches that don't cache keys, every lookup is a miss.
+    pub key_misses: u32,
+    /// The amount of times an operation found that an earlier operation was interrupted (e.g. by an error or
+    /// because its future was dropped). The cache might not have matched the flash then, so it was
+    /// invalidated and built up again. This happens automatically, so this is only for reporting.
+    pub dirty_recoveries: u32,
+    /// The amount of pages that were erased by operations that used this cache.
+    /// Erases done with other caches or without the crate are not seen.
+    pub page_erases: u32,
+}
+
+impl CacheStats {
+    const fn new() -> Self {
+        Self {
+            page_state_hits: 0,
+            page_state_misses: 0,
+            key_hits: 0,
+            key_misses: 0,
+            dirty_recoveries: 0,
+            page_erases: 0,
+        }
+    }
+}
+
 /// A cache object implementing no cache.
 ///
 /// This type of cache doesn't have to be kept around and may be constructed on every api call.
@@ -202,6 +338,7 @@ pub struct NoCache {
     page_states: UncachedPageStates,
     page_pointers: UncachedPagePointers,
     key_pointers: UncachedKeyPointers,
+    key_filter: UncachedKeyFilter,
 }
 
 impl NoCache {
@@ -211,6 +348,7 @@ impl NoCache {
             page_states: UncachedPageStates,
             page_pointers: UncachedPagePointers,
             key_pointers: UncachedKeyPointers,
+            key_filter: UncachedKeyFilter,
         }
     }
 }
@@ -230,6 +368,11 @@ impl PrivateCacheImpl for NoCache {
         None
     }
 
+    fn stats_tracker<R>(&mut self, _f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        // Nothing is cached, so there are no hits to count
+        None
+    }
+
     fn page_states(&mut self) -> &mut Self::PSC {
         &mut self.page_states
     }
@@ -248,10 +391,15 @@ impl Invalidate for NoCache {
 
 impl<KEY: Key> PrivateKeyCacheImpl<KEY> for NoCache {
     type KPC = UncachedKeyPointers;
+    type KF = UncachedKeyFilter;
 
     fn key_pointers(&mut self) -> &mut Self::KPC {
         &mut self.key_pointers
     }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
 }
 
 /// A cache object that keeps track of the page states.
@@ -268,9 +416,11 @@ impl<KEY: Key> PrivateKeyCacheImpl<KEY> for NoCache {
 #[derive(Debug)]
 pub struct PageStateCache<const PAGE_COUNT: usize> {
     dirt_tracker: DirtTracker,
+    stats: CacheStats,
     page_states: CachedPageStates<PAGE_COUNT>,
     page_pointers: UncachedPagePointers,
     key_pointers: UncachedKeyPointers,
+    key_filter: UncachedKeyFilter,
 }
 
 impl<const PAGE_COUNT: usize> PageStateCache<PAGE_COUNT> {
@@ -278,11 +428,23 @@ impl<const PAGE_COUNT: usize> PageStateCache<PAGE_COUNT> {
     pub const fn new() -> Self {
         Self {
             dirt_tracker: DirtTracker::new(),
+            stats: CacheStats::new(),
             page_states: CachedPageStates::new(),
             page_pointers: UncachedPagePointers,
             key_pointers: UncachedKeyPointers,
+            key_filter: UncachedKeyFilter,
         }
     }
+
+    /// Get the hit and miss counts of the cache since it was created or since [Self::reset_stats] was called
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Set all hit and miss counts back to 0
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::new();
+    }
 }
 
 impl<const PAGE_COUNT: usize> Default for PageStateCache<PAGE_COUNT> {
@@ -299,6 +461,10 @@ impl<const PAGE_COUNT: usize> PrivateCacheImpl for PageStateCache<PAGE_COUNT> {
         Some(f(&mut self.dirt_tracker))
     }
 
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        Some(f(&mut self.stats))
+    }
+
     fn page_states(&mut self) -> &mut Self::PSC {
         &mut self.page_states
     }
@@ -321,14 +487,23 @@ impl<const PAGE_COUNT: usize> Invalidate for PageStateCache<PAGE_COUNT> {
 
 impl<KEY: Key, const PAGE_COUNT: usize> PrivateKeyCacheImpl<KEY> for PageStateCache<PAGE_COUNT> {
     type KPC = UncachedKeyPointers;
+    type KF = UncachedKeyFilter;
 
     fn key_pointers(&mut self) -> &mut Self::KPC {
         &mut self.key_pointers
     }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
 }
 
 /// A cache object that keeps track of the page states and some pointers to the items in the page.
 ///
+/// One of the pointers is the write offset of every page, so storing an item doesn't have to search
+/// the page for the first free spot every time. The pointers are also used to skip the erased items
+/// at the start of queue pages.
+///
 /// This cache has to be kept around and passed to *every* api call to the same memory region until the cache gets discarded.
 ///
 /// Valid usecase:  
@@ -341,9 +516,11 @@ impl<KEY: Key, const PAGE_COUNT: usize> PrivateKeyCacheImpl<KEY> for PageStateCa
 #[derive(Debug)]
 pub struct PagePointerCache<const PAGE_COUNT: usize> {
     dirt_tracker: DirtTracker,
+    stats: CacheStats,
     page_states: CachedPageStates<PAGE_COUNT>,
     page_pointers: CachedPagePointers<PAGE_COUNT>,
     key_pointers: UncachedKeyPointers,
+    key_filter: UncachedKeyFilter,
 }
 
 impl<const PAGE_COUNT: usize> PagePointerCache<PAGE_COUNT> {
@@ -351,11 +528,23 @@ impl<const PAGE_COUNT: usize> PagePointerCache<PAGE_COUNT> {
     pub const fn new() -> Self {
         Self {
             dirt_tracker: DirtTracker::new(),
+            stats: CacheStats::new(),
             page_states: CachedPageStates::new(),
             page_pointers: CachedPagePointers::new(),
             key_pointers: UncachedKeyPointers,
+            key_filter: UncachedKeyFilter,
         }
     }
+
+    /// Get the hit and miss counts of the cache since it was created or since [Self::reset_stats] was called
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Set all hit and miss counts back to 0
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::new();
+    }
 }
 
 impl<const PAGE_COUNT: usize> Default for PagePointerCache<PAGE_COUNT> {
@@ -372,6 +561,10 @@ impl<const PAGE_COUNT: usize> PrivateCacheImpl for PagePointerCache<PAGE_COUNT>
         Some(f(&mut self.dirt_tracker))
     }
 
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        Some(f(&mut self.stats))
+    }
+
     fn page_states(&mut self) -> &mut Self::PSC {
         &mut self.page_states
     }
@@ -394,10 +587,116 @@ impl<const PAGE_COUNT: usize> Invalidate for PagePointerCache<PAGE_COUNT> {
 
 impl<KEY: Key, const PAGE_COUNT: usize> PrivateKeyCacheImpl<KEY> for PagePointerCache<PAGE_COUNT> {
     type KPC = UncachedKeyPointers;
+    type KF = UncachedKeyFilter;
 
     fn key_pointers(&mut self) -> &mut Self::KPC {
         &mut self.key_pointers
     }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
+}
+
+/// A cache object that keeps track of the page states and some pointers to the items in the page,
+/// just like the [PagePointerCache].
+///
+/// The difference is that the page count is given at runtime and the cache is allocated on the heap.
+/// This is useful for tools running on a host where the size of the flash region isn't known at compile time.
+///
+/// This cache has to be kept around and passed to *every* api call to the same memory region until the cache gets discarded.
+///
+/// Valid usecase:  
+/// `Create cache 1` -> `use 1` -> `use 1` -> `create cache 2` -> `use 2` -> `use 2`
+///
+/// Invalid usecase:  
+/// `Create cache 1` -> `use 1` -> `create cache 2` -> `use 2` -> `❌ use 1 ❌`
+///
+/// Make sure the page count is correct. If the number is lower than the actual amount, the code will panic at some point.
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub struct HeapPagePointerCache {
+    dirt_tracker: DirtTracker,
+    stats: CacheStats,
+    page_states: HeapPageStates,
+    page_pointers: HeapPagePointers,
+    key_pointers: UncachedKeyPointers,
+    key_filter: UncachedKeyFilter,
+}
+
+#[cfg(feature = "alloc")]
+impl HeapPagePointerCache {
+    /// Construct a new instance for a flash range with the given amount of pages
+    pub fn new(page_count: usize) -> Self {
+        Self {
+            dirt_tracker: DirtTracker::new(),
+            stats: CacheStats::new(),
+            page_states: HeapPageStates::new(page_count),
+            page_pointers: HeapPagePointers::new(page_count),
+            key_pointers: UncachedKeyPointers,
+            key_filter: UncachedKeyFilter,
+        }
+    }
+
+    /// Get the hit and miss counts of the cache since it was created or since [Self::reset_stats] was called
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Set all hit and miss counts back to 0
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::new();
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl PrivateCacheImpl for HeapPagePointerCache {
+    type PSC = HeapPageStates;
+    type PPC = HeapPagePointers;
+
+    fn dirt_tracker<R>(&mut self, f: impl FnOnce(&mut DirtTracker) -> R) -> Option<R> {
+        Some(f(&mut self.dirt_tracker))
+    }
+
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        Some(f(&mut self.stats))
+    }
+
+    fn page_states(&mut self) -> &mut Self::PSC {
+        &mut self.page_states
+    }
+
+    fn page_pointers(&mut self) -> &mut Self::PPC {
+        &mut self.page_pointers
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl CacheImpl for HeapPagePointerCache {}
+#[cfg(feature = "alloc")]
+impl<KEY: Key> KeyCacheImpl<KEY> for HeapPagePointerCache {}
+
+#[cfg(feature = "alloc")]
+impl Invalidate for HeapPagePointerCache {
+    fn invalidate_cache_state(&mut self) {
+        self.dirt_tracker.unmark_dirty();
+        self.page_states.invalidate_cache_state();
+        self.page_pointers.invalidate_cache_state();
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<KEY: Key> PrivateKeyCacheImpl<KEY> for HeapPagePointerCache {
+    type KPC = UncachedKeyPointers;
+    type KF = UncachedKeyFilter;
+
+    fn key_pointers(&mut self) -> &mut Self::KPC {
+        &mut self.key_pointers
+    }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
 }
 
 /// An object that caches the location of the newest item with a given key.
@@ -415,13 +714,19 @@ impl<KEY: Key, const PAGE_COUNT: usize> PrivateKeyCacheImpl<KEY> for PagePointer
 ///
 /// The number of key slots can be lower than the total amount of possible keys used, but this will lower
 /// the chance of a cache hit.
-/// The keys are cached in a fifo and any time its location is updated in cache it's added to the front.
+/// The keys are cached as an LRU: any time a location is looked up or updated in cache it's moved to the front,
+/// and when a new key doesn't fit, the least recently used key is evicted.
+/// So the most recently used keys are kept and fetching them again only reads their item.
+/// For a map with hundreds of keys where only a few are used often, `KEYS` only has to be big enough for the
+/// few keys that are used often.
 #[derive(Debug)]
 pub struct KeyPointerCache<const PAGE_COUNT: usize, KEY: Key, const KEYS: usize> {
     dirt_tracker: DirtTracker,
+    stats: CacheStats,
     page_states: CachedPageStates<PAGE_COUNT>,
     page_pointers: CachedPagePointers<PAGE_COUNT>,
     key_pointers: CachedKeyPointers<KEY, KEYS>,
+    key_filter: UncachedKeyFilter,
 }
 
 impl<const PAGE_COUNT: usize, KEY: Key, const KEYS: usize> KeyPointerCache<PAGE_COUNT, KEY, KEYS> {
@@ -429,11 +734,23 @@ impl<const PAGE_COUNT: usize, KEY: Key, const KEYS: usize> KeyPointerCache<PAGE_
     pub const fn new() -> Self {
         Self {
             dirt_tracker: DirtTracker::new(),
+            stats: CacheStats::new(),
             page_states: CachedPageStates::new(),
             page_pointers: CachedPagePointers::new(),
             key_pointers: CachedKeyPointers::new(),
+            key_filter: UncachedKeyFilter,
         }
     }
+
+    /// Get the hit and miss counts of the cache since it was created or since [Self::reset_stats] was called
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Set all hit and miss counts back to 0
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::new();
+    }
 }
 
 impl<const PAGE_COUNT: usize, KEY: Key, const KEYS: usize> Default
@@ -454,6 +771,10 @@ impl<const PAGE_COUNT: usize, KEY: Key, const KEYS: usize> PrivateCacheImpl
         Some(f(&mut self.dirt_tracker))
     }
 
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        Some(f(&mut self.stats))
+    }
+
     fn page_states(&mut self) -> &mut Self::PSC {
         &mut self.page_states
     }
@@ -487,8 +808,134 @@ impl<const PAGE_COUNT: usize, KEY: Key, const KEYS: usize> PrivateKeyCacheImpl<K
     for KeyPointerCache<PAGE_COUNT, KEY, KEYS>
 {
     type KPC = CachedKeyPointers<KEY, KEYS>;
+    type KF = UncachedKeyFilter;
+
+    fn key_pointers(&mut self) -> &mut Self::KPC {
+        &mut self.key_pointers
+    }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
+}
+
+/// A cache object that keeps track of which keys are definitely not stored in flash, using a bloom filter.
+/// This cache also caches pages states and page pointers.
+///
+/// This makes fetching a key that doesn't exist fast, because the flash doesn't have to be searched.
+/// Use it when lookups of missing keys are common, e.g. when checking for optional settings at boot.
+///
+/// This cache has to be kept around and passed to *every* api call to the same memory region until the cache gets discarded.
+///
+/// Valid usecase:  
+/// `Create cache 1` -> `use 1` -> `use 1` -> `create cache 2` -> `use 2` -> `use 2`
+///
+/// Invalid usecase:  
+/// `Create cache 1` -> `use 1` -> `create cache 2` -> `use 2` -> `❌ use 1 ❌`
+///
+/// Make sure the page count is correct. If the number is lower than the actual amount, the code will panic at some point.
+///
+/// The filter only knows which keys are missing once all keys in flash have been seen.
+/// That happens the first time a missing key is fetched, which still searches the whole flash.
+/// The filter has `FILTER_BYTES * 8` bits and every key sets 3 of them. When there are a lot of keys
+/// compared to the amount of bits, missing keys are more often thought to possibly exist and then the flash is searched as normal.
+/// Removed keys stay in the filter until the cache state is invalidated.
+#[derive(Debug)]
+pub struct KeyFilterCache<const PAGE_COUNT: usize, const FILTER_BYTES: usize> {
+    dirt_tracker: DirtTracker,
+    stats: CacheStats,
+    page_states: CachedPageStates<PAGE_COUNT>,
+    page_pointers: CachedPagePointers<PAGE_COUNT>,
+    key_pointers: UncachedKeyPointers,
+    key_filter: CachedKeyFilter<FILTER_BYTES>,
+}
+
+impl<const PAGE_COUNT: usize, const FILTER_BYTES: usize> KeyFilterCache<PAGE_COUNT, FILTER_BYTES> {
+    /// Construct a new instance
+    pub const fn new() -> Self {
+        Self {
+            dirt_tracker: DirtTracker::new(),
+            stats: CacheStats::new(),
+            page_states: CachedPageStates::new(),
+            page_pointers: CachedPagePointers::new(),
+            key_pointers: UncachedKeyPointers,
+            key_filter: CachedKeyFilter::new(),
+        }
+    }
+
+    /// Get the hit and miss counts of the cache since it was created or since [Self::reset_stats] was called
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Set all hit and miss counts back to 0
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::new();
+    }
+}
+
+impl<const PAGE_COUNT: usize, const FILTER_BYTES: usize> Default
+    for KeyFilterCache<PAGE_COUNT, FILTER_BYTES>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGE_COUNT: usize, const FILTER_BYTES: usize> PrivateCacheImpl
+    for KeyFilterCache<PAGE_COUNT, FILTER_BYTES>
+{
+    type PSC = CachedPageStates<PAGE_COUNT>;
+    type PPC = CachedPagePointers<PAGE_COUNT>;
+
+    fn dirt_tracker<R>(&mut self, f: impl FnOnce(&mut DirtTracker) -> R) -> Option<R> {
+        Some(f(&mut self.dirt_tracker))
+    }
+
+    fn stats_tracker<R>(&mut self, f: impl FnOnce(&mut CacheStats) -> R) -> Option<R> {
+        Some(f(&mut self.stats))
+    }
+
+    fn page_states(&mut self) -> &mut Self::PSC {
+        &mut self.page_states
+    }
+
+    fn page_pointers(&mut self) -> &mut Self::PPC {
+        &mut self.page_pointers
+    }
+}
+
+impl<const PAGE_COUNT: usize, const FILTER_BYTES: usize> CacheImpl
+    for KeyFilterCache<PAGE_COUNT, FILTER_BYTES>
+{
+}
+impl<KEY: Key + Hash, const PAGE_COUNT: usize, const FILTER_BYTES: usize> KeyCacheImpl<KEY>
+    for KeyFilterCache<PAGE_COUNT, FILTER_BYTES>
+{
+}
+
+impl<const PAGE_COUNT: usize, const FILTER_BYTES: usize> Invalidate
+    for KeyFilterCache<PAGE_COUNT, FILTER_BYTES>
+{
+    fn invalidate_cache_state(&mut self) {
+        self.dirt_tracker.unmark_dirty();
+        self.page_states.invalidate_cache_state();
+        self.page_pointers.invalidate_cache_state();
+        self.key_filter.invalidate_cache_state();
+    }
+}
+
+impl<KEY: Key + Hash, const PAGE_COUNT: usize, const FILTER_BYTES: usize> PrivateKeyCacheImpl<KEY>
+    for KeyFilterCache<PAGE_COUNT, FILTER_BYTES>
+{
+    type KPC = UncachedKeyPointers;
+    type KF = CachedKeyFilter<FILTER_BYTES>;
 
     fn key_pointers(&mut self) -> &mut Self::KPC {
         &mut self.key_pointers
     }
+
+    fn key_filter(&mut self) -> &mut Self::KF {
+        &mut self.key_filter
+    }
 }
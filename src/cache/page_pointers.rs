@@ -23,6 +23,17 @@ pub(crate) trait PagePointersCache: Debug {
         item_header: &ItemHeader,
     );
 
+    /// The first free spot on the page was found by searching the flash
+    fn notice_free_spot(&mut self, page_index: usize, free_address: u32);
+
+    /// Set the pointers of the page to the ones that were saved in a snapshot
+    fn restore_pointers(
+        &mut self,
+        page_index: usize,
+        after_erased: Option<u32>,
+        after_written: Option<u32>,
+    );
+
     fn notice_page_state(&mut self, page_index: usize, new_state: PageState);
     fn invalidate_cache_state(&mut self);
 }
@@ -123,6 +134,25 @@ impl<const PAGE_COUNT: usize> PagePointersCache for CachedPagePointers<PAGE_COUN
         }
     }
 
+    fn notice_free_spot(&mut self, page_index: usize, free_address: u32) {
+        if self
+            .first_item_after_written(page_index)
+            .is_none_or(|first_item_after_written| first_item_after_written < free_address)
+        {
+            self.after_written_pointers[page_index] = NonZeroU32::new(free_address);
+        }
+    }
+
+    fn restore_pointers(
+        &mut self,
+        page_index: usize,
+        after_erased: Option<u32>,
+        after_written: Option<u32>,
+    ) {
+        self.after_erased_pointers[page_index] = after_erased.and_then(NonZeroU32::new);
+        self.after_written_pointers[page_index] = after_written.and_then(NonZeroU32::new);
+    }
+
     fn notice_page_state(&mut self, page_index: usize, new_state: PageState) {
         if new_state.is_open() {
             // This page was erased
@@ -137,6 +167,106 @@ impl<const PAGE_COUNT: usize> PagePointersCache for CachedPagePointers<PAGE_COUN
     }
 }
 
+/// The same as [CachedPagePointers], but with the page count chosen at runtime
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub(crate) struct HeapPagePointers {
+    after_erased_pointers: alloc::vec::Vec<Option<NonZeroU32>>,
+    after_written_pointers: alloc::vec::Vec<Option<NonZeroU32>>,
+}
+
+#[cfg(feature = "alloc")]
+impl HeapPagePointers {
+    pub fn new(page_count: usize) -> Self {
+        Self {
+            after_erased_pointers: alloc::vec![None; page_count],
+            after_written_pointers: alloc::vec![None; page_count],
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl PagePointersCache for HeapPagePointers {
+    fn first_item_after_erased(&self, page_index: usize) -> Option<u32> {
+        self.after_erased_pointers[page_index].map(|val| val.get())
+    }
+
+    fn first_item_after_written(&self, page_index: usize) -> Option<u32> {
+        self.after_written_pointers[page_index].map(|val| val.get())
+    }
+
+    fn notice_item_written<S: NorFlash>(
+        &mut self,
+        flash_range: Range<u32>,
+        item_address: u32,
+        item_header: &ItemHeader,
+    ) {
+        let page_index = calculate_page_index::<S>(flash_range, item_address);
+
+        let next_item_address = item_header.next_item_address::<S>(item_address);
+
+        // We only care about the furthest written item, so discard if this is an earlier item
+        if let Some(first_item_after_written) = self.first_item_after_written(page_index) {
+            if next_item_address <= first_item_after_written {
+                return;
+            }
+        }
+
+        self.after_written_pointers[page_index] = NonZeroU32::new(next_item_address);
+    }
+
+    fn notice_item_erased<S: NorFlash>(
+        &mut self,
+        flash_range: Range<u32>,
+        item_address: u32,
+        item_header: &ItemHeader,
+    ) {
+        let page_index = calculate_page_index::<S>(flash_range.clone(), item_address);
+
+        // Either the item we point to or the first item on the page
+        let next_unerased_item = self.first_item_after_erased(page_index).unwrap_or_else(|| {
+            calculate_page_address::<S>(flash_range, page_index) + S::WORD_SIZE as u32
+        });
+
+        if item_address == next_unerased_item {
+            self.after_erased_pointers[page_index] =
+                NonZeroU32::new(item_header.next_item_address::<S>(item_address));
+        }
+    }
+
+    fn notice_free_spot(&mut self, page_index: usize, free_address: u32) {
+        if self
+            .first_item_after_written(page_index)
+            .is_none_or(|first_item_after_written| first_item_after_written < free_address)
+        {
+            self.after_written_pointers[page_index] = NonZeroU32::new(free_address);
+        }
+    }
+
+    fn restore_pointers(
+        &mut self,
+        page_index: usize,
+        after_erased: Option<u32>,
+        after_written: Option<u32>,
+    ) {
+        self.after_erased_pointers[page_index] = after_erased.and_then(NonZeroU32::new);
+        self.after_written_pointers[page_index] = after_written.and_then(NonZeroU32::new);
+    }
+
+    fn notice_page_state(&mut self, page_index: usize, new_state: PageState) {
+        if new_state.is_open() {
+            // This page was erased
+            self.after_erased_pointers[page_index] = None;
+            self.after_written_pointers[page_index] = None;
+        }
+    }
+
+    fn invalidate_cache_state(&mut self) {
+        self.after_erased_pointers.fill(None);
+        self.after_written_pointers.fill(None);
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct UncachedPagePointers;
 
@@ -165,6 +295,16 @@ impl PagePointersCache for UncachedPagePointers {
     ) {
     }
 
+    fn notice_free_spot(&mut self, _page_index: usize, _free_address: u32) {}
+
+    fn restore_pointers(
+        &mut self,
+        _page_index: usize,
+        _after_erased: Option<u32>,
+        _after_written: Option<u32>,
+    ) {
+    }
+
     fn notice_page_state(&mut self, _page_index: usize, _new_state: PageState) {}
 
     fn invalidate_cache_state(&mut self) {}
@@ -54,6 +54,36 @@ impl<const PAGE_COUNT: usize> PageStatesCache for CachedPageStates<PAGE_COUNT> {
     }
 }
 
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub(crate) struct HeapPageStates {
+    pages: alloc::vec::Vec<Option<PageState>>,
+}
+
+#[cfg(feature = "alloc")]
+impl HeapPageStates {
+    pub fn new(page_count: usize) -> Self {
+        Self {
+            pages: alloc::vec![None; page_count],
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl PageStatesCache for HeapPageStates {
+    fn get_page_state(&self, page_index: usize) -> Option<PageState> {
+        self.pages[page_index]
+    }
+
+    fn notice_page_state(&mut self, page_index: usize, new_state: PageState) {
+        self.pages[page_index] = Some(new_state);
+    }
+
+    fn invalidate_cache_state(&mut self) {
+        self.pages.fill(None);
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct UncachedPageStates;
 
@@ -0,0 +1,235 @@
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::NorFlash;
+
+use super::{CacheImpl, KeyCacheImpl};
+use crate::{
+    map::{Key, MapReader},
+    queue::Queue,
+};
+
+/// A container that keeps one cache for every flash range, so the right cache is always used with the right range.
+///
+/// All ranges use the same cache type. If the ranges need different kinds of caches,
+/// use a registry for every kind.
+///
+/// The registry can hand out a [Queue] or a [MapReader] that is bound to a flash range and its cache.
+/// For the free functions, get the cache of the range with [Self::cache].
+/// Both return an [UnknownFlashRange] error if the range isn't in the registry.
+///
+/// ```rust
+/// # use sequential_storage::cache::{CacheRegistry, PagePointerCache};
+/// # use sequential_storage::map;
+/// # use mock_flash::MockFlashBase;
+/// # use futures::executor::block_on;
+/// # type Flash = MockFlashBase<10, 1, 4096>;
+/// # // With the `_test` feature the crate exports the mock flash, which can't be included on its own then
+/// # #[cfg(feature = "_test")]
+/// # use sequential_storage::mock_flash;
+/// # #[cfg(not(feature = "_test"))]
+/// # mod mock_flash {
+/// #   include!("../mock_flash.rs");
+/// # }
+/// # block_on(async {
+/// # let mut flash = Flash::new(mock_flash::WriteCountCheck::Twice, None, false);
+/// const SETTINGS: core::ops::Range<u32> = 0x0000..0x2000;
+/// const LOGS: core::ops::Range<u32> = 0x2000..0x6000;
+///
+/// let mut caches = CacheRegistry::new([
+///     (SETTINGS, PagePointerCache::<4>::new()),
+///     (LOGS, PagePointerCache::<4>::new()),
+/// ]);
+/// let mut data_buffer = [0; 128];
+///
+/// map::store_item(&mut flash, SETTINGS, caches.cache(&SETTINGS).unwrap(), &mut data_buffer, &1u8, &42u32)
+///     .await
+///     .unwrap();
+///
+/// let mut logs = caches.queue(&mut flash, &LOGS).unwrap();
+/// logs.push(b"booted", false).await.unwrap();
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct CacheRegistry<C: CacheImpl, const REGIONS: usize> {
+    regions: [(Range<u32>, C); REGIONS],
+}
+
+impl<C: CacheImpl, const REGIONS: usize> CacheRegistry<C, REGIONS> {
+    /// Construct a new instance with the flash ranges and the caches that belong to them.
+    ///
+    /// Panics if any of the ranges overlap.
+    pub fn new(regions: [(Range<u32>, C); REGIONS]) -> Self {
+        for (index, (range, _)) in regions.iter().enumerate() {
+            for (other_range, _) in &regions[index + 1..] {
+                assert!(
+                    range.end <= other_range.start || other_range.end <= range.start,
+                    "The flash ranges of the caches overlap"
+                );
+            }
+        }
+
+        Self { regions }
+    }
+
+    /// Get the cache of the flash range
+    pub fn cache(&mut self, flash_range: &Range<u32>) -> Result<&mut C, UnknownFlashRange> {
+        self.get(flash_range)
+            .ok_or_else(|| UnknownFlashRange(flash_range.clone()))
+    }
+
+    /// Get the cache of the flash range, or None if the flash range is not in the registry
+    pub fn get(&mut self, flash_range: &Range<u32>) -> Option<&mut C> {
+        self.regions
+            .iter_mut()
+            .find(|(range, _)| range == flash_range)
+            .map(|(_, cache)| cache)
+    }
+
+    /// Get a [Queue] that uses the flash range with its cache.
+    /// The queue borrows the flash and the cache, so it can't outlive the registry.
+    pub fn queue<'a, S: NorFlash>(
+        &'a mut self,
+        flash: &'a mut S,
+        flash_range: &Range<u32>,
+    ) -> Result<Queue<&'a mut S, &'a mut C>, UnknownFlashRange> {
+        let cache = self.cache(flash_range)?;
+        Ok(Queue::new(flash, flash_range.clone(), cache))
+    }
+
+    /// Get a [MapReader] that reads the map in the flash range with its cache.
+    /// The reader borrows the flash and the cache, so it can't outlive the registry.
+    pub fn map_reader<'a, K: Key, S: NorFlash>(
+        &'a mut self,
+        flash: &'a mut S,
+        flash_range: &Range<u32>,
+    ) -> Result<MapReader<K, &'a mut S, &'a mut C>, UnknownFlashRange>
+    where
+        C: KeyCacheImpl<K>,
+    {
+        let cache = self.cache(flash_range)?;
+        Ok(MapReader::new(flash, flash_range.clone(), cache))
+    }
+
+    /// Get the flash range and the cache of the region with the index, in the order they were given to [Self::new]
+    pub fn region(&mut self, index: usize) -> (Range<u32>, &mut C) {
+        let (range, cache) = &mut self.regions[index];
+        (range.clone(), cache)
+    }
+
+    /// Iterate over all flash ranges with their cache
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Range<u32>, &mut C)> {
+        self.regions
+            .iter_mut()
+            .map(|(range, cache)| (range.clone(), cache))
+    }
+}
+
+/// The error of a [CacheRegistry] when the given flash range is not in the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct UnknownFlashRange(pub Range<u32>);
+
+impl core::fmt::Display for UnknownFlashRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "No cache registered for the flash range {:?}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnknownFlashRange {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::{warm, PagePointerCache},
+        map,
+        mock_flash::{self, WriteCountCheck},
+        queue, AlignedBuf,
+    };
+
+    use futures_test::test;
+
+    const SETTINGS: Range<u32> = 0x000..0x400;
+    const LOGS: Range<u32> = 0x400..0x800;
+
+    #[test]
+    async fn use_multiple_ranges() {
+        let mut flash =
+            mock_flash::MockFlashBase::<8, 4, 64>::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut caches = CacheRegistry::new([
+            (SETTINGS, PagePointerCache::<4>::new()),
+            (LOGS, PagePointerCache::<4>::new()),
+        ]);
+
+        for (range, cache) in caches.iter_mut() {
+            warm(&mut flash, range, cache).await.unwrap();
+        }
+
+        for i in 0..50u8 {
+            map::store_item(
+                &mut flash,
+                SETTINGS,
+                caches.cache(&SETTINGS).unwrap(),
+                &mut data_buffer,
+                &(i % 4),
+                &[i; 10].as_slice(),
+            )
+            .await
+            .unwrap();
+            caches
+                .queue(&mut flash, &LOGS)
+                .unwrap()
+                .push(&AlignedBuf([i; 20]), true)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            map::fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                SETTINGS,
+                caches.cache(&SETTINGS).unwrap(),
+                &mut data_buffer,
+                &1,
+            )
+            .await
+            .unwrap(),
+            Some([49; 10].as_slice())
+        );
+
+        let (range, cache) = caches.region(1);
+        assert_eq!(range, LOGS);
+        assert!(queue::peek(&mut flash, range, cache, &mut data_buffer)
+            .await
+            .unwrap()
+            .is_some());
+
+        assert_eq!(
+            caches
+                .map_reader::<u8, _>(&mut flash, &SETTINGS)
+                .unwrap()
+                .fetch_item::<&[u8]>(&mut data_buffer, &2)
+                .await
+                .unwrap(),
+            Some([46; 10].as_slice())
+        );
+
+        assert!(caches.get(&(0x800..0x1000)).is_none());
+        assert_eq!(
+            caches.cache(&(0x800..0x1000)).unwrap_err(),
+            UnknownFlashRange(0x800..0x1000)
+        );
+        assert!(caches.queue(&mut flash, &(0x000..0x800)).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    async fn overlapping_ranges() {
+        CacheRegistry::new([
+            (0x000..0x400, PagePointerCache::<4>::new()),
+            (0x200..0x600, PagePointerCache::<4>::new()),
+        ]);
+    }
+}
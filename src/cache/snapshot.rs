@@ -0,0 +1,713 @@
+//! Saving the state of a cache to flash so it doesn't have to be built up again after a reboot.
+//!
+//! The snapshots are stored as a log in their own flash range. Every record looks like this:
+//!
+//! ```text
+//! [generation: u32][length: u32][flash range start: u32][flash range end: u32][pages][crc: u32][padding] [consumed word]
+//! ```
+//!
+//! Every page takes 9 bytes: the page state and the two page pointers (0 if not known).
+//! The generation is one higher than that of the previous record, so the last record is always the newest.
+//!
+//! A record is only valid until the storage range is changed. A restored record is consumed by writing its
+//! consumed word, so it can't be restored again and a new snapshot has to be saved at the next clean shutdown.
+//! Before a record is restored, it's also checked against the flash: the page states must match the page markers,
+//! the free spot of every page must still be erased and the first item that's not erased must still be there.
+//! So when the flash was written after the record was saved (e.g. because of a power loss before the next save),
+//! the record is not used.
+
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::{
+    calculate_page_end_address, get_page_state, get_pages,
+    item::{crc32, ItemHeader},
+    round_up_to_alignment_usize, AlignedBuf, Error, NorFlashExt, PageState, MAX_WORD_SIZE,
+};
+
+use super::{CacheImpl, NoCache, PageStatesCache};
+
+const HEADER_LENGTH: usize = 8;
+const RANGE_LENGTH: usize = 8;
+const PAGE_LENGTH: usize = 9;
+const CRC_LENGTH: usize = 4;
+
+/// Save the state of the cache to the snapshot range, so it can be restored with [restore_snapshot] after a reboot.
+///
+/// Call this on a clean shutdown, after the last operation on the flash range.
+/// If the flash range is changed after saving, the snapshot must not be restored anymore.
+/// Any operation done with the cache counts as a change, so save (again) right before shutting down.
+///
+/// The `snapshot_range` must not overlap with the `flash_range`. It's used as a log, so it's only erased
+/// when it's full. Every snapshot takes `20 + 9 * page count` bytes rounded up to the word size, plus one word.
+///
+/// The data buffer must be long enough to hold the snapshot.
+/// If it's too small, [Error::BufferTooSmall] is returned with the required length.
+pub async fn save_snapshot<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &mut [u8],
+    snapshot_range: Range<u32>,
+) -> Result<(), Error<S::Error>> {
+    assert_ranges::<S>(&flash_range, &snapshot_range);
+
+    cache.recover_if_dirty();
+
+    let page_count = get_pages::<S>(flash_range.clone(), 0).count();
+    let record_length = record_length::<S>(page_count);
+
+    if record_length + S::WORD_SIZE > snapshot_range.len() {
+        return Err(Error::ItemTooBig);
+    }
+    if data_buffer.len() < record_length {
+        return Err(Error::BufferTooSmall(record_length));
+    }
+
+    let (last_record, free_address) =
+        find_last_record(flash, snapshot_range.clone(), data_buffer).await?;
+    let generation = last_record.map_or(0, |record| record.generation.wrapping_add(1));
+
+    let address = match free_address {
+        Some(free_address)
+            if free_address as usize + record_length + S::WORD_SIZE
+                <= snapshot_range.end as usize =>
+        {
+            free_address
+        }
+        _ => {
+            flash
+                .erase(snapshot_range.start, snapshot_range.end)
+                .await
+                .map_err(|e| Error::Storage {
+                    value: e,
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                })?;
+            snapshot_range.start
+        }
+    };
+
+    let payload_length = RANGE_LENGTH + page_count * PAGE_LENGTH;
+    data_buffer[..record_length].fill(0);
+    data_buffer[0..4].copy_from_slice(&generation.to_le_bytes());
+    data_buffer[4..8].copy_from_slice(&(payload_length as u32).to_le_bytes());
+    data_buffer[8..12].copy_from_slice(&flash_range.start.to_le_bytes());
+    data_buffer[12..16].copy_from_slice(&flash_range.end.to_le_bytes());
+
+    for page_index in get_pages::<S>(flash_range.clone(), 0) {
+        let page = &mut data_buffer[HEADER_LENGTH + RANGE_LENGTH + page_index * PAGE_LENGTH..]
+            [..PAGE_LENGTH];
+
+        // Not through `get_page_state` so this isn't counted in the cache stats
+        page[0] = match cache.page_states().get_page_state(page_index) {
+            None => 0,
+            Some(PageState::Closed) => 1,
+            Some(PageState::PartialOpen) => 2,
+            Some(PageState::Open) => 3,
+        };
+        page[1..5].copy_from_slice(
+            &cache
+                .first_item_after_erased(page_index)
+                .unwrap_or_default()
+                .to_le_bytes(),
+        );
+        page[5..9].copy_from_slice(
+            &cache
+                .first_item_after_written(page_index)
+                .unwrap_or_default()
+                .to_le_bytes(),
+        );
+    }
+
+    let crc_address = HEADER_LENGTH + payload_length;
+    let crc = crc32(&data_buffer[..crc_address]);
+    data_buffer[crc_address..][..CRC_LENGTH].copy_from_slice(&crc.to_le_bytes());
+
+    flash
+        .write(address, &data_buffer[..record_length])
+        .await
+        .map_err(|e| Error::Storage {
+            value: e,
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+
+    Ok(())
+}
+
+/// Restore the state of the cache from the newest snapshot that was saved with [save_snapshot].
+///
+/// Call this at boot, before doing anything else with the flash range.
+/// Returns true if the snapshot was restored. When false is returned, there was no usable snapshot and the cache
+/// starts out empty like normal. This happens when the snapshot was already restored before,
+/// when it was saved for a different flash range or when it's corrupted.
+///
+/// A restored snapshot is consumed, so if the device reboots without saving a new snapshot
+/// the next restore returns false. The snapshot is also checked against the page markers and the free spots
+/// of the pages in flash. If the flash was written after the snapshot was saved, false is returned as well.
+/// This costs a couple of small reads per page, which is a lot less than searching the pages for their free spots.
+///
+/// The data buffer must be long enough to hold the snapshot.
+/// If it's too small, [Error::BufferTooSmall] is returned with the required length.
+pub async fn restore_snapshot<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &mut [u8],
+    snapshot_range: Range<u32>,
+) -> Result<bool, Error<S::Error>> {
+    assert_ranges::<S>(&flash_range, &snapshot_range);
+
+    cache.invalidate_cache_state();
+
+    let page_count = get_pages::<S>(flash_range.clone(), 0).count();
+    let record_length = record_length::<S>(page_count);
+
+    if data_buffer.len() < record_length {
+        return Err(Error::BufferTooSmall(record_length));
+    }
+
+    let Some(record) = find_last_record(flash, snapshot_range.clone(), data_buffer)
+        .await?
+        .0
+    else {
+        return Ok(false);
+    };
+
+    let payload_length = RANGE_LENGTH + page_count * PAGE_LENGTH;
+    if record.payload_length != payload_length {
+        return Ok(false);
+    }
+
+    flash
+        .read(record.address, &mut data_buffer[..record_length])
+        .await
+        .map_err(|e| Error::Storage {
+            value: e,
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+
+    let crc_address = HEADER_LENGTH + payload_length;
+    let crc = u32::from_le_bytes(data_buffer[crc_address..][..CRC_LENGTH].try_into().unwrap());
+    if crc != crc32(&data_buffer[..crc_address]) {
+        return Ok(false);
+    }
+
+    let start = u32::from_le_bytes(data_buffer[8..12].try_into().unwrap());
+    let end = u32::from_le_bytes(data_buffer[12..16].try_into().unwrap());
+    if (start..end) != flash_range {
+        return Ok(false);
+    }
+
+    if !snapshot_matches_flash(flash, flash_range.clone(), data_buffer).await? {
+        return Ok(false);
+    }
+
+    let consumed_address = record.address + record_length as u32;
+    let mut consumed_word = AlignedBuf([0; MAX_WORD_SIZE]);
+    flash
+        .read(consumed_address, &mut consumed_word[..S::WORD_SIZE])
+        .await
+        .map_err(|e| Error::Storage {
+            value: e,
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+    if consumed_word[..S::WORD_SIZE]
+        .iter()
+        .any(|byte| *byte != 0xFF)
+    {
+        return Ok(false);
+    }
+
+    // Consume the snapshot before using it. If we lose power after this, the cache is simply built up again
+    consumed_word[..S::WORD_SIZE].fill(0);
+    flash
+        .write(consumed_address, &consumed_word[..S::WORD_SIZE])
+        .await
+        .map_err(|e| Error::Storage {
+            value: e,
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+
+    for page_index in get_pages::<S>(flash_range.clone(), 0) {
+        let page =
+            &data_buffer[HEADER_LENGTH + RANGE_LENGTH + page_index * PAGE_LENGTH..][..PAGE_LENGTH];
+
+        let page_state = match page[0] {
+            1 => Some(PageState::Closed),
+            2 => Some(PageState::PartialOpen),
+            3 => Some(PageState::Open),
+            _ => None,
+        };
+        if let Some(page_state) = page_state {
+            cache.notice_page_state(page_index, page_state, false);
+        }
+
+        let after_erased = u32::from_le_bytes(page[1..5].try_into().unwrap());
+        let after_written = u32::from_le_bytes(page[5..9].try_into().unwrap());
+        cache.restore_page_pointers(
+            page_index,
+            (after_erased != 0).then_some(after_erased),
+            (after_written != 0).then_some(after_written),
+        );
+    }
+
+    Ok(true)
+}
+
+/// Check the pages of the snapshot in the data buffer against the flash, to find snapshots that are outdated.
+///
+/// The page states are compared with the page markers in flash, the free spot of every page must still be
+/// erased and the first item that's not erased must still be there.
+/// This catches the flash being written after the snapshot was saved, e.g. when a device lost power before
+/// it could save a new snapshot.
+async fn snapshot_matches_flash<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    data_buffer: &[u8],
+) -> Result<bool, Error<S::Error>> {
+    for page_index in get_pages::<S>(flash_range.clone(), 0) {
+        let page =
+            &data_buffer[HEADER_LENGTH + RANGE_LENGTH + page_index * PAGE_LENGTH..][..PAGE_LENGTH];
+
+        let page_state = match page[0] {
+            1 => Some(PageState::Closed),
+            2 => Some(PageState::PartialOpen),
+            3 => Some(PageState::Open),
+            _ => None,
+        };
+        let after_erased = u32::from_le_bytes(page[1..5].try_into().unwrap());
+        let after_written = u32::from_le_bytes(page[5..9].try_into().unwrap());
+
+        if let Some(page_state) = page_state {
+            match get_page_state(flash, flash_range.clone(), &mut NoCache::new(), page_index).await
+            {
+                Ok(flash_page_state) if flash_page_state == page_state => {}
+                Ok(_) | Err(Error::Corrupted { .. }) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page_index) - S::WORD_SIZE as u32;
+
+        if after_written != 0 {
+            match ItemHeader::read_new(flash, after_written, page_data_end_address).await {
+                Ok(None) => {}
+                Ok(Some(_)) | Err(Error::Corrupted { .. }) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if after_erased != 0 && after_erased != after_written {
+            match ItemHeader::read_new(flash, after_erased, page_data_end_address).await {
+                Ok(Some(_)) => {}
+                Ok(None) | Err(Error::Corrupted { .. }) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+fn assert_ranges<S: NorFlash>(flash_range: &Range<u32>, snapshot_range: &Range<u32>) {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32);
+
+    assert_eq!(snapshot_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(snapshot_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(snapshot_range.end - snapshot_range.start >= S::ERASE_SIZE as u32);
+
+    assert!(snapshot_range.end <= flash_range.start || snapshot_range.start >= flash_range.end);
+}
+
+const fn record_length<S: NorFlash>(page_count: usize) -> usize {
+    round_up_to_alignment_usize::<S>(
+        HEADER_LENGTH + RANGE_LENGTH + page_count * PAGE_LENGTH + CRC_LENGTH,
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    address: u32,
+    generation: u32,
+    payload_length: usize,
+}
+
+/// Find the newest record in the snapshot range and the address after it where the next record can be written.
+///
+/// The free address is None if no free space could be found.
+async fn find_last_record<S: NorFlash>(
+    flash: &mut S,
+    snapshot_range: Range<u32>,
+    data_buffer: &mut [u8],
+) -> Result<(Option<Record>, Option<u32>), Error<S::Error>> {
+    let header_length = round_up_to_alignment_usize::<S>(HEADER_LENGTH);
+
+    let mut last_record = None;
+    let mut address = snapshot_range.start;
+
+    while address as usize + header_length <= snapshot_range.end as usize {
+        let header = &mut data_buffer[..header_length];
+        flash
+            .read(address, header)
+            .await
+            .map_err(|e| Error::Storage {
+                value: e,
+                #[cfg(feature = "_test")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            })?;
+
+        if header.iter().all(|byte| *byte == 0xFF) {
+            return Ok((last_record, Some(address)));
+        }
+
+        let generation = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let payload_length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+
+        let record_size = payload_length
+            .checked_add(HEADER_LENGTH + CRC_LENGTH)
+            .map(round_up_to_alignment_usize::<S>)
+            .and_then(|length| length.checked_add(S::WORD_SIZE));
+
+        match record_size {
+            Some(record_size) if record_size <= (snapshot_range.end - address) as usize => {
+                last_record = Some(Record {
+                    address,
+                    generation,
+                    payload_length,
+                });
+                address += record_size as u32;
+            }
+            // The length is corrupted, so we can't find the next record
+            _ => return Ok((last_record, None)),
+        }
+    }
+
+    Ok((last_record, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::PagePointerCache,
+        map::{fetch_item, store_item},
+        mock_flash::{self, WriteCountCheck},
+    };
+
+    use futures_test::test;
+
+    type MockFlash = mock_flash::MockFlashBase<6, 4, 64>;
+
+    const FLASH_RANGE: Range<u32> = 0x000..0x400;
+    const SNAPSHOT_RANGE: Range<u32> = 0x400..0x600;
+
+    #[test]
+    async fn save_and_restore() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<4>::new();
+
+        for i in 0..20u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &(i % 6),
+                &[i; 20].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Nothing has been saved yet
+        let mut restored_cache = PagePointerCache::<4>::new();
+        assert!(!restore_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut restored_cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE
+        )
+        .await
+        .unwrap());
+
+        save_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE,
+        )
+        .await
+        .unwrap();
+
+        assert!(restore_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut restored_cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE
+        )
+        .await
+        .unwrap());
+        // The stats are not part of the snapshot
+        cache.reset_stats();
+        assert_eq!(format!("{restored_cache:?}"), format!("{cache:?}"));
+
+        // The snapshot is consumed
+        assert!(!restore_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut PagePointerCache::<4>::new(),
+            &mut data_buffer,
+            SNAPSHOT_RANGE
+        )
+        .await
+        .unwrap());
+
+        // The restored cache can be used like normal
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut restored_cache,
+            &mut data_buffer,
+            &100u8,
+            &[100; 20].as_slice(),
+        )
+        .await
+        .unwrap();
+
+        for key in (0..6u8).chain([100]) {
+            let mut uncached = PagePointerCache::<4>::new();
+            let expected = fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut uncached,
+                &mut data_buffer,
+                &key,
+            )
+            .await
+            .unwrap()
+            .map(|value| value.to_vec());
+            assert!(expected.is_some());
+
+            assert_eq!(
+                fetch_item::<u8, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut restored_cache,
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap()
+                .map(|value| value.to_vec()),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    async fn snapshot_log_wraps() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<4>::new();
+
+        for i in 0..40u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &(i % 6),
+                &[i; 20].as_slice(),
+            )
+            .await
+            .unwrap();
+
+            save_snapshot(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                SNAPSHOT_RANGE,
+            )
+            .await
+            .unwrap();
+
+            let mut restored_cache = PagePointerCache::<4>::new();
+            assert!(restore_snapshot(
+                &mut flash,
+                FLASH_RANGE,
+                &mut restored_cache,
+                &mut data_buffer,
+                SNAPSHOT_RANGE
+            )
+            .await
+            .unwrap());
+            // The stats are not part of the snapshot
+            cache.reset_stats();
+            assert_eq!(format!("{restored_cache:?}"), format!("{cache:?}"));
+        }
+    }
+
+    #[test]
+    async fn outdated_snapshot() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<4>::new();
+
+        for i in 0..10u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &i,
+                &[i; 20].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        save_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE,
+        )
+        .await
+        .unwrap();
+
+        // Keep going after the save and then lose power before the next save
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &100u8,
+            &[100; 20].as_slice(),
+        )
+        .await
+        .unwrap();
+
+        let mut restored_cache = PagePointerCache::<4>::new();
+        assert!(!restore_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut restored_cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE
+        )
+        .await
+        .unwrap());
+
+        // Nothing gets overwritten
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut restored_cache,
+            &mut data_buffer,
+            &101u8,
+            &[101; 20].as_slice(),
+        )
+        .await
+        .unwrap();
+        for key in (0..10u8).chain([100, 101]) {
+            assert_eq!(
+                fetch_item::<u8, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut restored_cache,
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap(),
+                Some([key; 20].as_slice())
+            );
+        }
+
+        // Filling up the flash after the save so pages get erased is found too
+        save_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut restored_cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE,
+        )
+        .await
+        .unwrap();
+        for i in 0..60u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut restored_cache,
+                &mut data_buffer,
+                &(i % 3),
+                &[i; 20].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(!restore_snapshot(
+            &mut flash,
+            FLASH_RANGE,
+            &mut PagePointerCache::<4>::new(),
+            &mut data_buffer,
+            SNAPSHOT_RANGE
+        )
+        .await
+        .unwrap());
+    }
+
+    #[test]
+    async fn wrong_flash_range() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<4>::new();
+
+        save_snapshot(
+            &mut flash,
+            0x000..0x200,
+            &mut cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE,
+        )
+        .await
+        .unwrap();
+
+        assert!(!restore_snapshot(
+            &mut flash,
+            0x200..0x400,
+            &mut cache,
+            &mut data_buffer,
+            SNAPSHOT_RANGE
+        )
+        .await
+        .unwrap());
+
+        assert_eq!(
+            save_snapshot(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut [0; 16],
+                SNAPSHOT_RANGE,
+            )
+            .await,
+            Err(Error::BufferTooSmall(56))
+        );
+    }
+}
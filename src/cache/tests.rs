@@ -56,6 +56,21 @@ mod queue_tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
+    #[test]
+    async fn heap_page_pointer_cache() {
+        assert_eq!(
+            run_test(&mut crate::cache::HeapPagePointerCache::new(NUM_PAGES)).await,
+            FlashStatsResult {
+                erases: 146,
+                reads: 211172,
+                writes: 6299,
+                bytes_read: 1699320,
+                bytes_written: 53299
+            }
+        );
+    }
+
     async fn run_test(cache: &mut impl CacheImpl) -> FlashStatsResult {
         let mut flash =
             mock_flash::MockFlashBase::<NUM_PAGES, 1, 256>::new(WriteCountCheck::Twice, None, true);
@@ -109,7 +124,10 @@ mod map_tests {
     use core::ops::Range;
 
     use crate::{
-        cache::{KeyCacheImpl, KeyPointerCache, NoCache, PagePointerCache, PageStateCache},
+        cache::{
+            CacheStats, KeyCacheImpl, KeyFilterCache, KeyPointerCache, NoCache, PagePointerCache,
+            PageStateCache,
+        },
         map::{fetch_item, store_item},
         mock_flash::{self, FlashStatsResult, WriteCountCheck},
         AlignedBuf,
@@ -161,6 +179,21 @@ mod map_tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
+    #[test]
+    async fn heap_page_pointer_cache() {
+        assert_eq!(
+            run_test(&mut crate::cache::HeapPagePointerCache::new(NUM_PAGES)).await,
+            FlashStatsResult {
+                erases: 198,
+                reads: 163273,
+                writes: 5201,
+                bytes_read: 1641365,
+                bytes_written: 50401
+            }
+        );
+    }
+
     #[test]
     async fn key_pointer_cache_half() {
         assert_eq!(
@@ -189,6 +222,328 @@ mod map_tests {
         );
     }
 
+    #[test]
+    async fn key_filter_cache() {
+        assert_eq!(
+            run_test(&mut KeyFilterCache::<NUM_PAGES, 8>::new()).await,
+            FlashStatsResult {
+                erases: 198,
+                reads: 163273,
+                writes: 5201,
+                bytes_read: 1641365,
+                bytes_written: 50401
+            }
+        );
+    }
+
+    #[test]
+    async fn key_filter_cache_missing_keys() {
+        assert_eq!(
+            run_missing_keys_test(&mut PagePointerCache::<NUM_PAGES>::new()).await,
+            FlashStatsResult {
+                erases: 0,
+                reads: 2100,
+                writes: 0,
+                bytes_read: 20800,
+                bytes_written: 0
+            }
+        );
+        assert_eq!(
+            run_missing_keys_test(&mut KeyFilterCache::<NUM_PAGES, 8>::new()).await,
+            FlashStatsResult {
+                erases: 0,
+                reads: 105,
+                writes: 0,
+                bytes_read: 1040,
+                bytes_written: 0
+            }
+        );
+    }
+
+    #[test]
+    async fn key_pointer_cache_hot_key() {
+        assert_eq!(
+            run_hot_key_test(&mut PagePointerCache::<NUM_PAGES>::new()).await,
+            FlashStatsResult {
+                erases: 0,
+                reads: 862,
+                writes: 0,
+                bytes_read: 8536,
+                bytes_written: 0
+            }
+        );
+        assert_eq!(
+            run_hot_key_test(&mut KeyPointerCache::<NUM_PAGES, u16, 2>::new()).await,
+            FlashStatsResult {
+                erases: 0,
+                reads: 61,
+                writes: 0,
+                bytes_read: 608,
+                bytes_written: 0
+            }
+        );
+    }
+
+    #[test]
+    async fn page_pointer_cache_write_offset() {
+        let mut flash =
+            mock_flash::MockFlashBase::<NUM_PAGES, 1, 256>::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x400;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<NUM_PAGES>::new();
+
+        let mut store_reads = Vec::new();
+        for i in 0..10u16 {
+            let snapshot = flash.stats_snapshot();
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &i,
+                &[i as u8; 10].as_slice(),
+            )
+            .await
+            .unwrap();
+            store_reads.push(snapshot.compare_to(flash.stats_snapshot()).reads);
+        }
+
+        // The free spot of the open page is cached, so storing doesn't get slower when the page fills up
+        assert!(store_reads[1..]
+            .iter()
+            .all(|reads| *reads == store_reads[1]));
+        assert!(store_reads[1] < store_reads[0]);
+    }
+
+    #[test]
+    async fn cache_stats() {
+        let mut cache = KeyPointerCache::<NUM_PAGES, u16, 2>::new();
+        run_hot_key_test(&mut cache).await;
+
+        // Only the first fetch of the hot key has to search the flash
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                page_state_hits: 100,
+                page_state_misses: 4,
+                key_hits: 19,
+                key_misses: 1,
+                dirty_recoveries: 0,
+                page_erases: 0,
+            }
+        );
+
+        cache.reset_stats();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    async fn key_pointer_cache_lru() {
+        let mut flash =
+            mock_flash::MockFlashBase::<NUM_PAGES, 4, 256>::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x0000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = KeyPointerCache::<NUM_PAGES, u16, 4>::new();
+
+        for i in 0..200u16 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &i,
+                &[i as u8].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+        cache.reset_stats();
+
+        // Three keys are fetched all the time and a cold key once in a while
+        for round in 0..20u16 {
+            for key in [10, 20, 30].into_iter().chain([100 + round]) {
+                assert_eq!(
+                    fetch_item::<u16, &[u8], _>(
+                        &mut flash,
+                        FLASH_RANGE,
+                        &mut cache,
+                        &mut data_buffer,
+                        &key
+                    )
+                    .await
+                    .unwrap(),
+                    Some([key as u8].as_slice())
+                );
+            }
+        }
+
+        // The cold keys don't push out the hot keys, so only their first fetch misses
+        let stats = cache.stats();
+        assert_eq!(stats.key_hits, 19 * 3);
+        assert_eq!(stats.key_misses, 3 + 20);
+    }
+
+    #[test]
+    async fn recover_from_dirty_cache() {
+        let mut flash =
+            mock_flash::MockFlashBase::<NUM_PAGES, 1, 256>::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x400;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = KeyPointerCache::<NUM_PAGES, u16, 4>::new();
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &0u16,
+            &[0; 10].as_slice(),
+        )
+        .await
+        .unwrap();
+
+        // Interrupt a store halfway through
+        flash.bytes_until_shutoff = Some(4);
+        assert!(store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &0u16,
+            &[1; 10].as_slice(),
+        )
+        .await
+        .is_err());
+        flash.bytes_until_shutoff = None;
+        assert_eq!(cache.stats().dirty_recoveries, 0);
+
+        // The next operation rebuilds the cache without the caller having to do anything
+        assert_eq!(
+            fetch_item::<u16, &[u8], _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &0)
+                .await
+                .unwrap(),
+            Some([0; 10].as_slice())
+        );
+        assert_eq!(cache.stats().dirty_recoveries, 1);
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &0u16,
+            &[2; 10].as_slice(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_item::<u16, &[u8], _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &0)
+                .await
+                .unwrap(),
+            Some([2; 10].as_slice())
+        );
+        assert_eq!(cache.stats().dirty_recoveries, 1);
+    }
+
+    /// Store a lot of different keys while fetching one key over and over again
+    async fn run_hot_key_test(cache: &mut impl KeyCacheImpl<u16>) -> FlashStatsResult {
+        let mut flash =
+            mock_flash::MockFlashBase::<NUM_PAGES, 1, 256>::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x400;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for i in 0..10u16 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                cache,
+                &mut data_buffer,
+                &i,
+                &[i as u8; 10].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut stats = FlashStatsResult::default();
+        for i in 0..20u16 {
+            let snapshot = flash.stats_snapshot();
+            assert_eq!(
+                fetch_item::<u16, &[u8], _>(&mut flash, FLASH_RANGE, cache, &mut data_buffer, &0)
+                    .await
+                    .unwrap(),
+                Some([0; 10].as_slice())
+            );
+            stats += snapshot.compare_to(flash.stats_snapshot());
+
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                cache,
+                &mut data_buffer,
+                &(1 + i % 9),
+                &[i as u8; 10].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Only the fetches of the hot key are counted
+        stats
+    }
+
+    /// Store some keys and then fetch a lot of keys that don't exist
+    async fn run_missing_keys_test(cache: &mut impl KeyCacheImpl<u16>) -> FlashStatsResult {
+        let mut flash =
+            mock_flash::MockFlashBase::<NUM_PAGES, 1, 256>::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x400;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for i in 0..10u16 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                cache,
+                &mut data_buffer,
+                &i,
+                &[i as u8; 10].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let start_snapshot = flash.stats_snapshot();
+
+        for i in 0..100u16 {
+            assert_eq!(
+                fetch_item::<u16, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    cache,
+                    &mut data_buffer,
+                    &(i + 1000),
+                )
+                .await
+                .unwrap(),
+                None
+            );
+        }
+
+        let stats = start_snapshot.compare_to(flash.stats_snapshot());
+
+        // The keys that do exist must still be found
+        for i in 0..10u16 {
+            assert_eq!(
+                fetch_item::<u16, &[u8], _>(&mut flash, FLASH_RANGE, cache, &mut data_buffer, &i,)
+                    .await
+                    .unwrap(),
+                Some([i as u8; 10].as_slice())
+            );
+        }
+
+        stats
+    }
+
     async fn run_test(cache: &mut impl KeyCacheImpl<u16>) -> FlashStatsResult {
         let mut flash =
             mock_flash::MockFlashBase::<NUM_PAGES, 1, 256>::new(WriteCountCheck::Twice, None, true);
@@ -0,0 +1,276 @@
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::{
+    calculate_page_address, calculate_page_end_address, get_page_state, get_pages,
+    item::{find_next_free_item_spot, ItemHeaderIter},
+    Error, NorFlashExt, PageState,
+};
+
+use super::CacheImpl;
+
+/// Fill the cache by reading the state of all pages and the pointers into them from the flash.
+///
+/// Normally the cache is filled while doing the first operations, which makes those slower than the
+/// operations after it. Call this after creating the cache (e.g. at boot) to pay that cost at a moment you choose.
+/// It works for both the map and the queue.
+///
+/// This doesn't cache any keys. Caches that don't cache anything (like [super::NoCache]) only read the flash.
+///
+/// Warming never writes to the flash, not even to repair it.
+/// If the flash is found to be corrupted, [Error::Corrupted] is returned.
+/// The next map or queue operation will then try to repair it.
+pub async fn warm<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<(), Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32);
+
+    cache.recover_if_dirty();
+
+    for page_index in get_pages::<S>(flash_range.clone(), 0) {
+        let page_state = get_page_state(flash, flash_range.clone(), cache, page_index).await?;
+
+        if page_state == PageState::Open {
+            continue;
+        }
+
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page_index) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page_index) - S::WORD_SIZE as u32;
+
+        // Skip over the erased items at the start of the page, like the queue does when popping
+        if cache.first_item_after_erased(page_index).is_none() {
+            let first_unerased_item =
+                ItemHeaderIter::new(page_data_start_address, page_data_end_address)
+                    .traverse(flash, |header, _| header.crc.is_none())
+                    .await?
+                    .1;
+
+            let after_written = cache.first_item_after_written(page_index);
+            cache.restore_page_pointers(page_index, Some(first_unerased_item), after_written);
+        }
+
+        if page_state == PageState::PartialOpen {
+            // This remembers the free spot in the cache
+            find_next_free_item_spot(
+                flash,
+                flash_range.clone(),
+                cache,
+                page_data_start_address,
+                page_data_end_address,
+                0,
+            )
+            .await?;
+        }
+    }
+
+    cache.unmark_dirty();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::{NoCache, PagePointerCache},
+        map,
+        mock_flash::{self, FlashStatsResult, WriteCountCheck},
+        queue, AlignedBuf,
+    };
+
+    use futures_test::test;
+
+    type MockFlash = mock_flash::MockFlashBase<4, 4, 64>;
+
+    const FLASH_RANGE: Range<u32> = 0x000..0x400;
+
+    #[test]
+    async fn warm_queue() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<4>::new();
+
+        for i in 0..30u8 {
+            queue::push(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &AlignedBuf([i; 20]),
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        for _ in 0..15 {
+            queue::pop(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap();
+        }
+
+        let mut warm_flash = flash.clone();
+        let mut warm_cache = PagePointerCache::<4>::new();
+        warm(&mut warm_flash, FLASH_RANGE, &mut warm_cache)
+            .await
+            .unwrap();
+
+        // The warmed up cache doesn't need more reads than the one that was used all along
+        async fn run(flash: &mut MockFlash, cache: &mut PagePointerCache<4>) -> FlashStatsResult {
+            let mut data_buffer = AlignedBuf([0; 128]);
+            let snapshot = flash.stats_snapshot();
+            assert_eq!(
+                queue::peek(flash, FLASH_RANGE, cache, &mut data_buffer)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                &[15; 20]
+            );
+            queue::push(flash, FLASH_RANGE, cache, &AlignedBuf([100; 20]), false)
+                .await
+                .unwrap();
+            snapshot.compare_to(flash.stats_snapshot())
+        }
+
+        assert_eq!(
+            run(&mut warm_flash, &mut warm_cache).await,
+            run(&mut flash, &mut cache).await
+        );
+    }
+
+    #[test]
+    async fn warm_after_interrupted_group_only_reads() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        queue::push(
+            &mut flash,
+            FLASH_RANGE,
+            &mut NoCache::new(),
+            &AlignedBuf([1; 20]),
+            false,
+        )
+        .await
+        .unwrap();
+
+        // Power is lost after the first item of the group was written
+        flash.bytes_until_shutoff = Some(40);
+        assert!(queue::push_all(
+            &mut flash,
+            FLASH_RANGE,
+            &mut NoCache::new(),
+            &[&[2; 20], &[3; 20]],
+            false,
+        )
+        .await
+        .is_err());
+        flash.bytes_until_shutoff = None;
+
+        let snapshot = flash.stats_snapshot();
+        warm(&mut flash, FLASH_RANGE, &mut NoCache::new())
+            .await
+            .unwrap();
+        let mut cache = PagePointerCache::<4>::new();
+        warm(&mut flash, FLASH_RANGE, &mut cache).await.unwrap();
+        assert_eq!(snapshot.compare_to(flash.stats_snapshot()).writes, 0);
+
+        queue::push(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &AlignedBuf([4; 20]),
+            false,
+        )
+        .await
+        .unwrap();
+        for expected in [1, 4] {
+            assert_eq!(
+                queue::pop(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                &[expected; 20]
+            );
+        }
+        assert_eq!(
+            queue::pop(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn warm_map() {
+        let mut flash = MockFlash::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = PagePointerCache::<4>::new();
+
+        for i in 0..30u8 {
+            map::store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &(i % 5),
+                &[i; 20].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut warm_flash = flash.clone();
+        let mut warm_cache = PagePointerCache::<4>::new();
+        warm(&mut warm_flash, FLASH_RANGE, &mut warm_cache)
+            .await
+            .unwrap();
+
+        // Warming up a second time doesn't have to read anything
+        let snapshot = warm_flash.stats_snapshot();
+        warm(&mut warm_flash, FLASH_RANGE, &mut warm_cache)
+            .await
+            .unwrap();
+        assert_eq!(
+            snapshot.compare_to(warm_flash.stats_snapshot()),
+            Default::default()
+        );
+
+        // Warming up nothing only reads
+        let snapshot = warm_flash.stats_snapshot();
+        warm(&mut warm_flash, FLASH_RANGE, &mut NoCache::new())
+            .await
+            .unwrap();
+        assert_eq!(snapshot.compare_to(warm_flash.stats_snapshot()).writes, 0);
+
+        async fn run(flash: &mut MockFlash, cache: &mut PagePointerCache<4>) -> FlashStatsResult {
+            let mut data_buffer = AlignedBuf([0; 128]);
+            let snapshot = flash.stats_snapshot();
+            map::store_item(
+                flash,
+                FLASH_RANGE,
+                cache,
+                &mut data_buffer,
+                &100u8,
+                &[100; 20].as_slice(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                map::fetch_item::<u8, &[u8], _>(flash, FLASH_RANGE, cache, &mut data_buffer, &4)
+                    .await
+                    .unwrap(),
+                Some([29; 20].as_slice())
+            );
+            snapshot.compare_to(flash.stats_snapshot())
+        }
+
+        assert_eq!(
+            run(&mut warm_flash, &mut warm_cache).await,
+            run(&mut flash, &mut cache).await
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use heapless::{String, Vec};
+
+use crate::map::{SerializationError, Value};
+
+impl<'a, const N: usize> Value<'a> for Vec<u8, N> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        <&[u8] as Value>::serialize_into(&self.as_slice(), buffer)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        Vec::from_slice(buffer).map_err(|_| SerializationError::InvalidFormat)
+    }
+}
+
+impl<'a, const N: usize> Value<'a> for String<N> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        <&[u8] as Value>::serialize_into(&self.as_bytes(), buffer)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        String::from_utf8(Vec::deserialize_from(buffer)?)
+            .map_err(|_| SerializationError::InvalidFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{ops::Range, str::FromStr};
+
+    use super::*;
+    use crate::{
+        cache,
+        map::{fetch_item, store_item},
+        mock_flash::{self, WriteCountCheck},
+        AlignedBuf,
+    };
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+    const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+
+    #[futures_test::test]
+    async fn store_heapless_values() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let vec = Vec::<u8, 16>::from_slice(&[1, 2, 3]).unwrap();
+        let string = String::<32>::from_str("my network").unwrap();
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &vec,
+        )
+        .await
+        .unwrap();
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &1u8,
+            &string,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fetch_item::<u8, Vec<u8, 16>, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(vec)
+        );
+        assert_eq!(
+            fetch_item::<u8, String<32>, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &1,
+            )
+            .await
+            .unwrap(),
+            Some(string)
+        );
+
+        // Values that are too long or not utf8 can't be deserialized
+        assert_eq!(
+            fetch_item::<u8, Vec<u8, 2>, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await,
+            Err(crate::Error::SerializationError(
+                SerializationError::InvalidFormat
+            ))
+        );
+        assert_eq!(
+            String::<4>::deserialize_from(&[0xFF]),
+            Err(SerializationError::InvalidFormat)
+        );
+    }
+}
@@ -20,6 +20,22 @@
 //! The crc is calculated by [adapted_crc32] which never produces a 0 value on its own
 //! and has some other modifications to make corruption less likely to happen.
 //!
+//! If `Length'` is the [crc16] of the length xor'd with [METADATA_LENGTH_CRC_MASK] instead, the data of the item starts with
+//! a metadata header. The queue uses it to store e.g. a channel with an item without changing the data that's given back.
+//! The marker is part of the length, so it survives when the item is erased.
+//! The metadata header starts with the version of its layout. Items without the marker have the layout of version 0,
+//! which is how every item was stored before metadata existed, so a queue written by an older version of the crate
+//! is read as is after an upgrade. Items with a version that isn't known are skipped.
+//!
+//! If the crc is the [tombstone_crc32] of the data instead, the item is a tombstone.
+//! The map uses it to mark the key in the data as removed without touching the older items of the key.
+//! Readers that don't know about tombstones see it as a corrupted item.
+//!
+//! A group of items that has to become visible at once starts with a group header that has [GROUP_CRC] as its crc.
+//! Its data is the commit marker spot followed by the items of the group. As long as the commit marker isn't written,
+//! the group header is read as an erased item spanning the whole group. Once it is, it's read as an empty erased item,
+//! so the commit marker and the items after it are found like any other item.
+//!
 
 use core::num::NonZeroU32;
 use core::ops::Range;
@@ -38,6 +54,8 @@ pub struct ItemHeader {
     /// Length of the item payload (so not including the header and not including word alignment)
     pub length: u16,
     pub crc: Option<NonZeroU32>,
+    /// The data starts with a metadata header. See the module docs.
+    pub has_metadata: bool,
 }
 
 impl ItemHeader {
@@ -80,22 +98,60 @@ impl ItemHeader {
             u16::from_le_bytes(header_slice[Self::LENGTH_CRC_FIELD].try_into().unwrap());
         let calculated_length_crc = crc16(&header_slice[Self::LENGTH_FIELD]);
 
-        if calculated_length_crc != length_crc {
+        let has_metadata = if length_crc == calculated_length_crc {
+            false
+        } else if length_crc == calculated_length_crc ^ METADATA_LENGTH_CRC_MASK {
+            true
+        } else {
             return Err(Error::Corrupted {
                 #[cfg(feature = "_test")]
                 backtrace: std::backtrace::Backtrace::capture(),
             });
-        }
+        };
+
+        let length = u16::from_le_bytes(header_slice[Self::LENGTH_FIELD].try_into().unwrap());
+
+        match u32::from_le_bytes(header_slice[Self::DATA_CRC_FIELD].try_into().unwrap()) {
+            0 => Ok(Some(Self {
+                length,
+                crc: None,
+                has_metadata,
+            })),
+            GROUP_CRC => {
+                // Only a group has to look at the next header, so other items don't pay for the extra read
+                let commit_marker_address = Self::data_address::<S>(address);
+                let header_slice = &mut buffer[..round_up_to_alignment_usize::<S>(Self::LENGTH)];
+
+                flash
+                    .read(commit_marker_address, header_slice)
+                    .await
+                    .map_err(|e| Error::Storage {
+                        value: e,
+                        #[cfg(feature = "_test")]
+                        backtrace: std::backtrace::Backtrace::capture(),
+                    })?;
 
-        Ok(Some(Self {
-            length: u16::from_le_bytes(header_slice[Self::LENGTH_FIELD].try_into().unwrap()),
-            crc: {
-                match u32::from_le_bytes(header_slice[Self::DATA_CRC_FIELD].try_into().unwrap()) {
-                    0 => None,
-                    value => Some(NonZeroU32::new(value).unwrap()),
+                if header_slice.iter().all(|b| *b == 0xFF) {
+                    // Not committed, so the whole group is skipped
+                    Ok(Some(Self {
+                        length,
+                        crc: None,
+                        has_metadata: false,
+                    }))
+                } else {
+                    Ok(Some(Self {
+                        length: 0,
+                        crc: None,
+                        has_metadata: false,
+                    }))
                 }
-            },
-        }))
+            }
+            value => Ok(Some(Self {
+                length,
+                crc: Some(NonZeroU32::new(value).unwrap()),
+                has_metadata,
+            })),
+        }
     }
 
     pub async fn read_item<'d, S: NorFlash>(
@@ -134,27 +190,173 @@ impl ItemHeader {
                         header: self,
                         data_buffer,
                     }))
+                } else if tombstone_crc32(data) == header_crc {
+                    Ok(MaybeItem::Tombstone(Item {
+                        header: self,
+                        data_buffer,
+                    }))
                 } else {
-                    return Ok(MaybeItem::Corrupted(self, data_buffer));
+                    Ok(MaybeItem::Corrupted(self, data_buffer))
                 }
             }
         }
     }
 
+    /// Read a part of the data of the item at the given address, starting at the given offset in the data.
+    /// Returns the amount of bytes that were read into the buffer.
+    /// This is 0 when the offset is at the end of the data.
+    ///
+    /// The data is not checked against the crc.
+    pub async fn read_data_part<S: NorFlash>(
+        &self,
+        flash: &mut S,
+        address: u32,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Result<usize, Error<S::Error>> {
+        let mut read_buffer = AlignedBuf([0; MAX_WORD_SIZE]);
+        let data_address = ItemHeader::data_address::<S>(address);
+        let length = self.length as usize;
+
+        let mut read = 0;
+        while offset + read < length && read < buffer.len() {
+            // Reads have to be aligned, so we read whole words and only copy what's needed
+            let position = offset + read;
+            let aligned_position = round_down_to_alignment_usize::<S>(position);
+            let read_len = round_up_to_alignment_usize::<S>(length - aligned_position)
+                .min(round_down_to_alignment_usize::<S>(read_buffer.len()));
+
+            flash
+                .read(
+                    data_address + aligned_position as u32,
+                    &mut read_buffer[..read_len],
+                )
+                .await
+                .map_err(|e| Error::Storage {
+                    value: e,
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                })?;
+
+            let available = &read_buffer[position - aligned_position..]
+                [..(read_len.min(length - aligned_position) - (position - aligned_position))];
+            let len = available.len().min(buffer.len() - read);
+            buffer[read..][..len].copy_from_slice(&available[..len]);
+            read += len;
+        }
+
+        Ok(read)
+    }
+
+    /// Check if the item at the given address holds exactly the given data.
+    /// The data is compared in parts, so no buffer is needed that can fit all of the data.
+    pub async fn data_equals<S: NorFlash>(
+        &self,
+        flash: &mut S,
+        address: u32,
+        data: &[u8],
+    ) -> Result<bool, Error<S::Error>> {
+        if self.has_metadata
+            || self.length as usize != data.len()
+            || self.crc != Some(adapted_crc32(data))
+        {
+            return Ok(false);
+        }
+
+        let mut buffer = [0; MAX_WORD_SIZE];
+        let mut offset = 0;
+        while offset < data.len() {
+            let read = self
+                .read_data_part(flash, address, offset, &mut buffer)
+                .await?;
+            if buffer[..read] != data[offset..][..read] {
+                return Ok(false);
+            }
+            offset += read;
+        }
+
+        Ok(true)
+    }
+
+    /// Check the data of the item at the given address against the crc without needing a buffer
+    /// that can fit all of the data.
+    pub async fn verify_data<S: NorFlash>(
+        &self,
+        flash: &mut S,
+        address: u32,
+        end_address: u32,
+    ) -> Result<bool, Error<S::Error>> {
+        Ok(self.check_data(flash, address, end_address).await? == DataCheck::Valid)
+    }
+
+    /// Like [Self::verify_data], but also tells whether the item is a tombstone.
+    pub async fn check_data<S: NorFlash>(
+        &self,
+        flash: &mut S,
+        address: u32,
+        end_address: u32,
+    ) -> Result<DataCheck, Error<S::Error>> {
+        let Some(header_crc) = self.crc else {
+            return Ok(DataCheck::Corrupted);
+        };
+
+        if self.next_item_address::<S>(address) > end_address {
+            return Ok(DataCheck::Corrupted);
+        }
+
+        let mut buffer = [0; MAX_WORD_SIZE];
+        let mut crc = CRC32_INITIAL;
+        let mut offset = 0;
+        loop {
+            let read = self
+                .read_data_part(flash, address, offset, &mut buffer)
+                .await?;
+            if read == 0 {
+                break;
+            }
+
+            crc = !crc32_with_initial(&buffer[..read], crc);
+            offset += read;
+        }
+
+        if adapt_crc32(!crc) == header_crc {
+            Ok(DataCheck::Valid)
+        } else if adapt_crc32(crc) == header_crc {
+            Ok(DataCheck::Tombstone)
+        } else {
+            Ok(DataCheck::Corrupted)
+        }
+    }
+
     async fn write<S: NorFlash>(&self, flash: &mut S, address: u32) -> Result<(), Error<S::Error>> {
+        self.write_part(
+            flash,
+            address,
+            0..round_up_to_alignment_usize::<S>(Self::LENGTH),
+        )
+        .await
+    }
+
+    /// Only write the given byte range of the header. The range must be word aligned.
+    async fn write_part<S: NorFlash>(
+        &self,
+        flash: &mut S,
+        address: u32,
+        part: Range<usize>,
+    ) -> Result<(), Error<S::Error>> {
         let mut buffer = AlignedBuf([0xFF; MAX_WORD_SIZE]);
 
         buffer[Self::DATA_CRC_FIELD]
             .copy_from_slice(&self.crc.map(|crc| crc.get()).unwrap_or(0).to_le_bytes());
         buffer[Self::LENGTH_FIELD].copy_from_slice(&self.length.to_le_bytes());
-        buffer[Self::LENGTH_CRC_FIELD]
-            .copy_from_slice(&crc16(&self.length.to_le_bytes()).to_le_bytes());
+        let mut length_crc = crc16(&self.length.to_le_bytes());
+        if self.has_metadata {
+            length_crc ^= METADATA_LENGTH_CRC_MASK;
+        }
+        buffer[Self::LENGTH_CRC_FIELD].copy_from_slice(&length_crc.to_le_bytes());
 
         flash
-            .write(
-                address,
-                &buffer[..round_up_to_alignment_usize::<S>(Self::LENGTH)],
-            )
+            .write(address + part.start as u32, &buffer[part])
             .await
             .map_err(|e| Error::Storage {
                 value: e,
@@ -177,6 +379,80 @@ impl ItemHeader {
         Ok(self)
     }
 
+    /// Write the header of an item that is erased from the start without touching the data after it.
+    pub async fn write_erased<S: NorFlash>(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl PrivateCacheImpl,
+        address: u32,
+        length: u16,
+    ) -> Result<Self, Error<S::Error>> {
+        let header = Self {
+            length,
+            crc: None,
+            has_metadata: false,
+        };
+        cache.notice_item_written::<S>(flash_range.clone(), address, &header);
+        cache.notice_item_erased::<S>(flash_range, address, &header);
+        header.write(flash, address).await?;
+        Ok(header)
+    }
+
+    /// Write the header of a group of items at the given address.
+    /// The `length` is the length of all items of the group including their headers.
+    ///
+    /// The items are written from [ItemHeader::group_items_address] onwards.
+    /// Until [ItemHeader::write_commit_marker] is called, the group is read as one erased item,
+    /// so none of its items can be found. If the group is never committed, it stays that way.
+    ///
+    /// If the group with its header doesn't fit in an item, [Error::ItemTooBig] is returned.
+    pub async fn write_group<S: NorFlash>(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl PrivateCacheImpl,
+        address: u32,
+        length: usize,
+    ) -> Result<Self, Error<S::Error>> {
+        let length = length
+            .checked_add(Self::data_address::<S>(0) as usize)
+            .and_then(|length| u16::try_from(length).ok())
+            .ok_or(Error::ItemTooBig)?;
+
+        let header = Self {
+            length,
+            crc: NonZeroU32::new(GROUP_CRC),
+            has_metadata: false,
+        };
+        cache.notice_item_written::<S>(flash_range, address, &header);
+        header.write(flash, address).await?;
+        Ok(header)
+    }
+
+    /// Get the address of the first item of the group with its header at the given address
+    pub const fn group_items_address<S: NorFlash>(address: u32) -> u32 {
+        Self::data_address::<S>(Self::data_address::<S>(address))
+    }
+
+    /// Write the commit marker of the group with its header at the given address.
+    /// This makes all items of the group reachable at once.
+    ///
+    /// The marker is an erased header without data that directly follows the group header.
+    pub async fn write_commit_marker<S: NorFlash>(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl PrivateCacheImpl,
+        address: u32,
+    ) -> Result<Self, Error<S::Error>> {
+        Self::write_erased(
+            flash,
+            flash_range,
+            cache,
+            Self::data_address::<S>(address),
+            0,
+        )
+        .await
+    }
+
     /// Get the address of the start of the data for this item
     pub const fn data_address<S: NorFlash>(address: u32) -> u32 {
         address + round_up_to_alignment::<S>(Self::LENGTH as u32)
@@ -208,10 +484,6 @@ impl<'d> Item<'d> {
         &self.data_buffer[..self.header.length as usize]
     }
 
-    pub fn data_mut(&mut self) -> &mut [u8] {
-        &mut self.data_buffer[..self.header.length as usize]
-    }
-
     /// Destruct the item to get back the full data buffer
     pub fn destruct(self) -> (ItemHeader, &'d mut [u8]) {
         (self.header, self.data_buffer)
@@ -227,6 +499,26 @@ impl<'d> Item<'d> {
         let header = ItemHeader {
             length: data.len() as u16,
             crc: Some(adapted_crc32(data)),
+            has_metadata: false,
+        };
+
+        Self::write_raw(flash, flash_range, cache, &header, data, address).await?;
+
+        Ok(header)
+    }
+
+    /// Write a new tombstone item. See the module docs.
+    pub async fn write_new_tombstone<S: NorFlash>(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl PrivateCacheImpl,
+        address: u32,
+        data: &'d [u8],
+    ) -> Result<ItemHeader, Error<S::Error>> {
+        let header = ItemHeader {
+            length: data.len() as u16,
+            crc: Some(tombstone_crc32(data)),
+            has_metadata: false,
         };
 
         Self::write_raw(flash, flash_range, cache, &header, data, address).await?;
@@ -234,6 +526,77 @@ impl<'d> Item<'d> {
         Ok(header)
     }
 
+    /// Write a new item of which the data is made up of all parts after each other.
+    ///
+    /// With `has_metadata` the header is marked so the first parts are read as a metadata header. See the module docs.
+    pub async fn write_new_parts<S: NorFlash>(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl PrivateCacheImpl,
+        address: u32,
+        parts: &[&[u8]],
+        has_metadata: bool,
+    ) -> Result<ItemHeader, Error<S::Error>> {
+        if let ([data], false) = (parts, has_metadata) {
+            return Item::write_new(flash, flash_range, cache, address, data).await;
+        }
+
+        let header = ItemHeader {
+            length: parts.iter().map(|part| part.len()).sum::<usize>() as u16,
+            crc: Some(adapted_crc32_parts(parts)),
+            has_metadata,
+        };
+
+        cache.notice_item_written::<S>(flash_range, address, &header);
+        header.write(flash, address).await?;
+
+        // The parts don't have to line up with the words, so everything goes through an aligned buffer
+        let mut buffer = AlignedBuf([0; MAX_WORD_SIZE * 2]);
+        let buffer_len = round_down_to_alignment_usize::<S>(buffer.len());
+        let mut buffered = 0;
+        let mut write_address = ItemHeader::data_address::<S>(address);
+
+        for part in parts {
+            let mut part = *part;
+
+            while !part.is_empty() {
+                let len = part.len().min(buffer_len - buffered);
+                buffer[buffered..][..len].copy_from_slice(&part[..len]);
+                buffered += len;
+                part = &part[len..];
+
+                if buffered == buffer_len {
+                    Self::write_data(flash, write_address, &buffer[..buffered]).await?;
+                    write_address += buffered as u32;
+                    buffered = 0;
+                }
+            }
+        }
+
+        if buffered > 0 {
+            let len = round_up_to_alignment_usize::<S>(buffered);
+            buffer[buffered..len].fill(0);
+            Self::write_data(flash, write_address, &buffer[..len]).await?;
+        }
+
+        Ok(header)
+    }
+
+    async fn write_data<S: NorFlash>(
+        flash: &mut S,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), Error<S::Error>> {
+        flash
+            .write(address, data)
+            .await
+            .map_err(|e| Error::Storage {
+                value: e,
+                #[cfg(feature = "_test")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            })
+    }
+
     async fn write_raw<S: NorFlash>(
         flash: &mut S,
         flash_range: Range<u32>,
@@ -321,6 +684,14 @@ pub struct ItemUnborrowed {
 }
 
 impl ItemUnborrowed {
+    /// Create an unborrowed item of which the data was never read into a buffer
+    pub fn without_data(header: ItemHeader) -> Self {
+        Self {
+            header,
+            data_buffer_len: 0,
+        }
+    }
+
     /// Reborrows the data. Watch out! Make sure the data buffer hasn't changed since unborrowing!
     pub fn reborrow(self, data_buffer: &mut [u8]) -> Item<'_> {
         Item {
@@ -330,6 +701,140 @@ impl ItemUnborrowed {
     }
 }
 
+/// Writes a new item piece by piece, so the data doesn't have to be in memory all at once.
+///
+/// The header is written first, but without the crc. This claims the space for the item.
+/// Only when the item is finished, the crc is written. Until then the item is seen as corrupted,
+/// so an unfinished item is never read back.
+pub struct ItemWriter {
+    address: u32,
+    length: u16,
+    written: usize,
+    flushed: usize,
+    crc: u32,
+    buffer: AlignedBuf<MAX_WORD_SIZE>,
+}
+
+impl ItemWriter {
+    /// Start a new item at the given address and write its header.
+    ///
+    /// The crc of the header must be writable separately, so the word size can't be bigger than 4 bytes.
+    /// This is checked at compile time.
+    pub async fn new<S: NorFlash>(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl PrivateCacheImpl,
+        address: u32,
+        length: u16,
+    ) -> Result<Self, Error<S::Error>> {
+        const {
+            assert!(
+                S::WORD_SIZE <= ItemHeader::DATA_CRC_FIELD.end - ItemHeader::DATA_CRC_FIELD.start,
+                "The word size of the flash must be 4 bytes or smaller"
+            )
+        };
+
+        let header = ItemHeader {
+            length,
+            crc: None,
+            has_metadata: false,
+        };
+        cache.notice_item_written::<S>(flash_range, address, &header);
+        header
+            .write_part(
+                flash,
+                address,
+                ItemHeader::DATA_CRC_FIELD.end..ItemHeader::LENGTH,
+            )
+            .await?;
+
+        Ok(Self {
+            address,
+            length,
+            written: 0,
+            flushed: 0,
+            crc: CRC32_INITIAL,
+            buffer: AlignedBuf([0; MAX_WORD_SIZE]),
+        })
+    }
+
+    /// The amount of bytes that still have to be written before the item can be finished
+    pub fn remaining(&self) -> usize {
+        self.length as usize - self.written
+    }
+
+    /// Write the next part of the data.
+    /// If the part is bigger than what's remaining, [Error::BufferTooBig] is returned and nothing is written.
+    pub async fn write<S: NorFlash>(
+        &mut self,
+        flash: &mut S,
+        mut data: &[u8],
+    ) -> Result<(), Error<S::Error>> {
+        if data.len() > self.remaining() {
+            return Err(Error::BufferTooBig);
+        }
+
+        self.crc = !crc32_with_initial(data, self.crc);
+
+        // The parts don't have to line up with the words, so everything goes through an aligned buffer
+        while !data.is_empty() {
+            let buffered = self.written - self.flushed;
+            let len = data.len().min(self.buffer.len() - buffered);
+            self.buffer[buffered..][..len].copy_from_slice(&data[..len]);
+            self.written += len;
+            data = &data[len..];
+
+            if self.written - self.flushed == self.buffer.len() {
+                self.flush(flash).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn flush<S: NorFlash>(&mut self, flash: &mut S) -> Result<(), Error<S::Error>> {
+        let buffered = self.written - self.flushed;
+        let len = round_up_to_alignment_usize::<S>(buffered);
+        self.buffer[buffered..len].fill(0);
+
+        Item::write_data(
+            flash,
+            ItemHeader::data_address::<S>(self.address) + self.flushed as u32,
+            &self.buffer[..len],
+        )
+        .await?;
+
+        self.flushed = self.written;
+        Ok(())
+    }
+
+    /// Finish the item by writing the crc.
+    /// If not all data has been written yet, [Error::BufferTooSmall] is returned with the amount of missing bytes.
+    pub async fn finish<S: NorFlash>(
+        mut self,
+        flash: &mut S,
+    ) -> Result<ItemHeader, Error<S::Error>> {
+        if self.remaining() > 0 {
+            return Err(Error::BufferTooSmall(self.remaining()));
+        }
+
+        if self.written > self.flushed {
+            self.flush(flash).await?;
+        }
+
+        let header = ItemHeader {
+            length: self.length,
+            crc: Some(adapt_crc32(!self.crc)),
+            has_metadata: false,
+        };
+        header
+            .write_part(flash, self.address, ItemHeader::DATA_CRC_FIELD)
+            .await?;
+
+        Ok(header)
+    }
+}
+
 /// Scans through the items to find the first spot that is free to store a new item.
 ///
 /// - `end_address` is exclusive.
@@ -346,7 +851,7 @@ pub async fn find_next_free_item_spot<S: NorFlash>(
     let free_item_address = match cache.first_item_after_written(page_index) {
         Some(free_item_address) => free_item_address,
         None => {
-            ItemHeaderIter::new(
+            let free_item_address = ItemHeaderIter::new(
                 cache
                     .first_item_after_erased(page_index)
                     .unwrap_or(0)
@@ -355,7 +860,10 @@ pub async fn find_next_free_item_spot<S: NorFlash>(
             )
             .traverse(flash, |_, _| true)
             .await?
-            .1
+            .1;
+
+            cache.notice_free_spot(page_index, free_item_address);
+            free_item_address
         }
     };
 
@@ -375,6 +883,15 @@ pub enum MaybeItem<'d> {
     Corrupted(ItemHeader, &'d mut [u8]),
     Erased(ItemHeader, &'d mut [u8]),
     Present(Item<'d>),
+    Tombstone(Item<'d>),
+}
+
+/// The result of [ItemHeader::check_data]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataCheck {
+    Valid,
+    Tombstone,
+    Corrupted,
 }
 
 impl<'d> core::fmt::Debug for MaybeItem<'d> {
@@ -387,6 +904,7 @@ impl<'d> core::fmt::Debug for MaybeItem<'d> {
                 .finish(),
             Self::Erased(arg0, _) => f.debug_tuple("Erased").field(arg0).finish(),
             Self::Present(arg0) => f.debug_tuple("Present").field(arg0).finish(),
+            Self::Tombstone(arg0) => f.debug_tuple("Tombstone").field(arg0).finish(),
         }
     }
 }
@@ -394,7 +912,7 @@ impl<'d> core::fmt::Debug for MaybeItem<'d> {
 impl<'d> MaybeItem<'d> {
     pub fn unwrap<E>(self) -> Result<Item<'d>, Error<E>> {
         match self {
-            MaybeItem::Corrupted(_, _) => Err(Error::Corrupted {
+            MaybeItem::Corrupted(_, _) | MaybeItem::Tombstone(_) => Err(Error::Corrupted {
                 #[cfg(feature = "_test")]
                 backtrace: std::backtrace::Backtrace::capture(),
             }),
@@ -426,7 +944,38 @@ fn crc16(data: &[u8]) -> u16 {
 
 /// Calculate the crc32 of the data as used by the crate.
 fn adapted_crc32(data: &[u8]) -> NonZeroU32 {
-    match crc32(data) {
+    adapt_crc32(crc32(data))
+}
+
+/// Calculate the crc32 as used by the crate of the data that is made up of all parts after each other.
+fn adapted_crc32_parts(parts: &[&[u8]]) -> NonZeroU32 {
+    // The crc can be continued by feeding the inverted crc back in as the initial value
+    let crc = parts
+        .iter()
+        .fold(CRC32_INITIAL, |crc, part| !crc32_with_initial(part, crc));
+
+    adapt_crc32(!crc)
+}
+
+/// The crc of a group header.
+///
+/// [adapt_crc32] never gives a crc with a zeroed first byte, so this can't be mistaken for the crc of an item.
+/// The first byte isn't erased either, so a header that got only partly written is never read as free space.
+const GROUP_CRC: u32 = u32::from_le_bytes([0x00, b'G', b'R', b'P']);
+
+/// What the `Length'` of a header is xor'd with to mark that the data starts with a metadata header.
+///
+/// A header with a mark is read as corrupted by versions of the crate from before it existed,
+/// instead of having the metadata read as part of the data.
+pub const METADATA_LENGTH_CRC_MASK: u16 = u16::from_le_bytes([b'M', b'D']);
+
+/// Calculate the crc32 of a tombstone item. It always differs from the [adapted_crc32] of the same data.
+fn tombstone_crc32(data: &[u8]) -> NonZeroU32 {
+    adapt_crc32(!crc32(data))
+}
+
+fn adapt_crc32(crc: u32) -> NonZeroU32 {
+    match crc {
         // CRC may not be 0 as that already means 'erased'
         0 => NonZeroU32::new(1).unwrap(),
         // To aid in early shutoff/cancellation, we make sure that if the first byte of
@@ -439,10 +988,12 @@ fn adapted_crc32(data: &[u8]) -> NonZeroU32 {
     }
 }
 
-fn crc32(data: &[u8]) -> u32 {
-    // We use a modified initial value because the normal 0xFFFFFFF does not pass
-    // the `crc32_all_ones_resistant` test
-    crc32_with_initial(data, 0xEEEEEEEE)
+// We use a modified initial value because the normal 0xFFFFFFF does not pass
+// the `crc32_all_ones_resistant` test
+const CRC32_INITIAL: u32 = 0xEEEEEEEE;
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_with_initial(data, CRC32_INITIAL)
 }
 
 fn crc32_with_initial(data: &[u8], initial: u32) -> u32 {
@@ -506,6 +1057,73 @@ pub async fn is_page_empty<S: NorFlash>(
     }
 }
 
+/// The amount of items of every kind that were found on a page by [check_page_items]
+#[derive(Debug, Default)]
+pub struct PageItemsCheck {
+    /// The amount of items that can be read just fine
+    pub items: usize,
+    /// The amount of items that are erased
+    pub erased_items: usize,
+    /// The amount of items of which the data doesn't match the crc
+    pub corrupted_items: usize,
+    /// The amount of item headers that are corrupted
+    pub corrupted_headers: usize,
+    /// True if data was found after the last item
+    pub unfinished_write: bool,
+}
+
+/// Read all items between the addresses and check them against their crc.
+/// Nothing is modified.
+pub async fn check_page_items<S: NorFlash>(
+    flash: &mut S,
+    page_data_start_address: u32,
+    page_data_end_address: u32,
+) -> Result<PageItemsCheck, Error<S::Error>> {
+    let mut check = PageItemsCheck::default();
+
+    let mut address = page_data_start_address;
+    loop {
+        match ItemHeader::read_new(flash, address, page_data_end_address).await {
+            Ok(Some(header)) => {
+                if header.crc.is_none() {
+                    check.erased_items += 1;
+                } else {
+                    match header
+                        .check_data(flash, address, page_data_end_address)
+                        .await?
+                    {
+                        DataCheck::Valid | DataCheck::Tombstone => check.items += 1,
+                        DataCheck::Corrupted => check.corrupted_items += 1,
+                    }
+                }
+
+                address = header.next_item_address::<S>(address);
+            }
+            Ok(None) => break,
+            Err(Error::Corrupted { .. }) => {
+                check.corrupted_headers += 1;
+                address = ItemHeader::data_address::<S>(address);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // After the last item everything should be erased
+    match ItemHeader::read_new(
+        flash,
+        ItemHeader::data_address::<S>(address),
+        page_data_end_address,
+    )
+    .await
+    {
+        Ok(None) => {}
+        Ok(Some(_)) | Err(Error::Corrupted { .. }) => check.unfinished_write = true,
+        Err(e) => return Err(e),
+    }
+
+    Ok(check)
+}
+
 pub struct ItemIter {
     header: ItemHeaderIter,
 }
@@ -532,6 +1150,9 @@ impl ItemIter {
                 MaybeItem::Corrupted(_, buffer) | MaybeItem::Erased(_, buffer) => {
                     data_buffer.replace(buffer);
                 }
+                MaybeItem::Tombstone(item) => {
+                    data_buffer.replace(item.destruct().1);
+                }
                 MaybeItem::Present(item) => {
                     return Ok(Some((item, address)));
                 }
@@ -539,11 +1160,39 @@ impl ItemIter {
         }
         Ok(None)
     }
+
+    /// Like [Self::next], but tombstones are returned too. The bool is true if the item is a tombstone.
+    pub async fn next_with_tombstones<'m, S: NorFlash>(
+        &mut self,
+        flash: &mut S,
+        data_buffer: &'m mut [u8],
+    ) -> Result<Option<(Item<'m>, u32, bool)>, Error<S::Error>> {
+        let mut data_buffer = Some(data_buffer);
+        while let (Some(header), address) = self.header.next(flash).await? {
+            let buffer = data_buffer.take().unwrap();
+            match header
+                .read_item(flash, buffer, address, self.header.end_address)
+                .await?
+            {
+                MaybeItem::Corrupted(_, buffer) | MaybeItem::Erased(_, buffer) => {
+                    data_buffer.replace(buffer);
+                }
+                MaybeItem::Present(item) => {
+                    return Ok(Some((item, address, false)));
+                }
+                MaybeItem::Tombstone(item) => {
+                    return Ok(Some((item, address, true)));
+                }
+            }
+        }
+        Ok(None)
+    }
 }
 
 pub struct ItemHeaderIter {
     current_address: u32,
     end_address: u32,
+    corrupted_headers: usize,
 }
 
 impl ItemHeaderIter {
@@ -551,9 +1200,15 @@ impl ItemHeaderIter {
         Self {
             current_address: start_address,
             end_address,
+            corrupted_headers: 0,
         }
     }
 
+    /// The amount of corrupted headers that have been skipped so far
+    pub fn corrupted_headers(&self) -> usize {
+        self.corrupted_headers
+    }
+
     /// Fetch next item
     pub async fn next<S: NorFlash>(
         &mut self,
@@ -587,6 +1242,7 @@ impl ItemHeaderIter {
                     return Ok((None, self.current_address));
                 }
                 Err(Error::Corrupted { .. }) => {
+                    self.corrupted_headers += 1;
                     self.current_address = ItemHeader::data_address::<S>(self.current_address);
                 }
                 Err(e) => return Err(e),
@@ -599,6 +1255,16 @@ impl ItemHeaderIter {
 mod tests {
     use super::*;
 
+    #[test]
+    fn crc32_parts_same_as_whole() {
+        let data: [u8; 64] = core::array::from_fn(|i| i as u8);
+
+        for split in 0..data.len() {
+            let (a, b) = data.split_at(split);
+            assert_eq!(adapted_crc32_parts(&[a, b]), adapted_crc32(&data));
+        }
+    }
+
     #[test]
     fn crc32_all_ones_resistant() {
         const DATA: [u8; 1024] = [0xFF; 1024];
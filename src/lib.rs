@@ -15,11 +15,22 @@ use core::{
 use embedded_storage_async::nor_flash::NorFlash;
 use map::SerializationError;
 
+// Lets the code generated by the derive macros refer to this crate by name in the tests
+#[cfg(all(test, feature = "derive"))]
+extern crate self as sequential_storage;
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[cfg(feature = "arrayvec")]
 mod arrayvec_impl;
 pub mod cache;
+#[cfg(feature = "heapless")]
+mod heapless_impl;
 mod item;
 pub mod map;
+#[cfg(feature = "postcard")]
+mod postcard_impl;
 pub mod queue;
 
 #[cfg(any(test, doctest, feature = "_test"))]
@@ -57,6 +68,24 @@ pub const fn item_overhead_size<S: NorFlash>() -> u32 {
     item::ItemHeader::data_address::<S>(0)
 }
 
+/// Get the largest length of data that can ever be stored in a single item for the given flash type.
+///
+/// For the queue this is the maximum length of the data that can be pushed.
+/// For the map this is the maximum length of the serialized key and value together.
+/// Anything bigger will be rejected with [Error::ItemTooBig].
+///
+/// A single item can't span multiple pages. To store more data than this, use [queue::push_large_item]
+/// or [map::store_large_item], which split the data into multiple items.
+pub const fn max_item_size<S: NorFlash>() -> usize {
+    let size = calculate_page_size::<S>().saturating_sub(item_overhead_size::<S>() as usize);
+
+    if size > u16::MAX as usize {
+        u16::MAX as usize
+    } else {
+        size
+    }
+}
+
 // Type representing buffer aligned to 4 byte boundary.
 #[repr(align(4))]
 pub(crate) struct AlignedBuf<const SIZE: usize>(pub(crate) [u8; SIZE]);
@@ -240,6 +269,7 @@ async fn open_page<S: NorFlash>(
     page_index: usize,
 ) -> Result<(), Error<S::Error>> {
     cache.notice_page_state(page_index, PageState::Open, true);
+    cache.stats_tracker(|stats| stats.page_erases = stats.page_erases.saturating_add(1));
 
     flash
         .erase(
@@ -329,6 +359,7 @@ async fn partial_close_page<S: NorFlash>(
 
 /// The state of a page
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
 enum PageState {
     /// This page was fully written and has now been sealed
     Closed,
@@ -398,6 +429,11 @@ pub enum Error<S> {
     ///
     /// See the readme for more info about the constraints on item sizes.
     ItemTooBig,
+    /// The key is reserved for future use by the crate and can't be used by the application.
+    /// See [map::Key::is_reserved].
+    ReservedKey,
+    /// Two flash ranges that must be separate overlap each other.
+    OverlappingRanges,
 }
 
 impl<S> From<SerializationError> for Error<S> {
@@ -434,6 +470,8 @@ where
             ),
             Error::SerializationError(value) => write!(f, "Map value error: {value}"),
             Error::ItemTooBig => write!(f, "The item is too big to fit in the flash"),
+            Error::ReservedKey => write!(f, "The key is reserved and can't be used"),
+            Error::OverlappingRanges => write!(f, "The flash ranges overlap"),
         }
     }
 }
@@ -523,6 +561,43 @@ mod tests {
         flash.write(offset, &buf[..bytes.len()]).await
     }
 
+    #[test]
+    async fn max_item_size_fits() {
+        // 256 byte pages, minus two words of page state and the item header
+        assert_eq!(max_item_size::<MockFlash>(), 256 - 8 - 8);
+
+        let mut flash = MockFlash::default();
+        let data = AlignedBuf([0; 256]);
+
+        queue::push(
+            &mut flash,
+            0x000..0x400,
+            &mut cache::NoCache::new(),
+            &data[..max_item_size::<MockFlash>()],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            queue::push(
+                &mut flash,
+                0x000..0x400,
+                &mut cache::NoCache::new(),
+                &data[..max_item_size::<MockFlash>() + 1],
+                false,
+            )
+            .await,
+            Err(Error::ItemTooBig)
+        );
+
+        // Big pages are limited by the u16 length of the items
+        assert_eq!(
+            max_item_size::<mock_flash::MockFlashBase<2, 4, 0x10000>>(),
+            u16::MAX as usize
+        );
+    }
+
     #[test]
     async fn test_find_pages() {
         // Page setup:
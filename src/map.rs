@@ -10,6 +10,10 @@
 //! # use mock_flash::MockFlashBase;
 //! # use futures::executor::block_on;
 //! # type Flash = MockFlashBase<10, 1, 4096>;
+//! # // With the `_test` feature the crate exports the mock flash, which can't be included on its own then
+//! # #[cfg(feature = "_test")]
+//! # use sequential_storage::mock_flash;
+//! # #[cfg(not(feature = "_test"))]
 //! # mod mock_flash {
 //! #   include!("mock_flash.rs");
 //! # }
@@ -96,18 +100,58 @@
 //!
 //! For your convenience there are premade implementations for the [Key] and [Value] traits.
 //!
+//! Keys don't have to be fixed size. With the `arrayvec` feature, `ArrayVec<u8, N>` can be used as a key.
+//! It's stored with its length, so keys of different lengths up to `N` bytes can be used in the same map.
+//! Likewise `ArrayString<N>` can be used to address items by name, like `"wifi.ssid"`.
+//! When fetched, string keys are checked to be valid utf8.
+//!
+//! With the `heapless` feature, `heapless::Vec<u8, N>` and `heapless::String<N>` can be used as values.
+//! With the `derive` feature, [Value] can be derived for structs with fixed size fields. See [FixedSizeValue].
+//! To change the layout of a value in a firmware update, store it as [Versioned]. See [VersionedValue].
+//! With the `postcard` feature, any serde type can be stored as a value by wrapping it in `Postcard`.
+//!
+//! Values that don't fit in a single page can be stored in chunks with [store_large_item]. See [Chunked].
+//! To write many changes together, collect them in RAM with a [WriteBuffer] first.
+//!
+//! With the `embedded-io-async` feature, all items can be exported to a byte stream, e.g. to make a backup.
+//! See `export` for the format of the stream. With `import` the backup can be restored again.
+//!
+
+use core::{
+    marker::PhantomData,
+    num::NonZeroUsize,
+    ops::{Bound, RangeBounds, RangeInclusive},
+};
 
 use embedded_storage_async::nor_flash::MultiwriteNorFlash;
 
-use crate::item::{find_next_free_item_spot, Item, ItemHeader, ItemIter};
+use crate::item::{
+    check_page_items, find_next_free_item_spot, DataCheck, Item, ItemHeader, ItemIter,
+};
 
 use self::{
-    cache::{KeyCacheImpl, PrivateKeyCacheImpl},
+    cache::{CacheImpl, KeyCacheImpl, PrivateKeyCacheImpl},
     item::{ItemHeaderIter, ItemUnborrowed},
 };
 
 use super::*;
 
+mod chunked;
+#[cfg(feature = "embedded-io-async")]
+mod stream;
+mod write_buffer;
+pub use chunked::{fetch_large_item, remove_large_item, store_large_item, Chunked};
+#[cfg(feature = "embedded-io-async")]
+pub use stream::{export, import, StreamError, EXPORT_MAGIC, EXPORT_VERSION};
+pub use write_buffer::WriteBuffer;
+
+#[cfg(feature = "postcard")]
+pub use crate::postcard_impl::Postcard;
+
+/// Derive the [Value], [FixedSizeValue] and [VersionedValue] traits for a struct. See [FixedSizeValue] for more information.
+#[cfg(feature = "derive")]
+pub use sequential_storage_derive::Value;
+
 /// Get the last stored value from the flash that is associated with the given key.
 /// If no value with the key is found, None is returned.
 ///
@@ -141,183 +185,312 @@ pub async fn fetch_item<'d, K: Key, V: Value<'d>, S: NorFlash>(
         return Ok(None);
     };
 
-    let data_len = item.header.length as usize;
+    Ok(Some(deserialize_item_value::<K, V, S::Error>(
+        data_buffer,
+        item.header.length as usize,
+        item_key_len,
+    )?))
+}
+
+/// Deserialize the value of the item that has been read into the data buffer.
+/// The key length is found from the data if it's not given.
+fn deserialize_item_value<'d, K: Key, V: Value<'d>, E>(
+    data_buffer: &'d [u8],
+    data_len: usize,
+    item_key_len: Option<usize>,
+) -> Result<V, Error<E>> {
     let item_key_len = match item_key_len {
         Some(item_key_len) => item_key_len,
         None => K::get_len(&data_buffer[..data_len])?,
     };
 
-    Ok(Some(
-        V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
-            .map_err(Error::SerializationError)?,
-    ))
+    V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
+        .map_err(Error::SerializationError)
 }
 
-/// Fetch the item, but with the item unborrowed, the address of the item and the length of the key
-#[allow(clippy::type_complexity)]
-async fn fetch_item_with_location<'d, K: Key, S: NorFlash>(
+/// A key that is bound to the type of its value, so the compiler checks that the right value type is used.
+/// Use it with [fetch_typed_item] and [store_typed_item].
+///
+/// It's easiest to implement with the [map_keys] macro:
+///
+/// ```rust
+/// sequential_storage::map_keys! {
+///     key_type: u8;
+///     /// The brightness of the screen
+///     pub Brightness: u8 = 1;
+///     pub DeviceId: [u8; 16] = 2;
+/// }
+///
+/// use sequential_storage::map::TypedKey;
+/// assert_eq!(Brightness::KEY, 1);
+/// assert_eq!(DeviceId::KEY, 2);
+/// ```
+///
+/// *All typed keys used on a flash range must have the same [Key] type.*
+pub trait TypedKey {
+    /// The type of the key in the map
+    type Key: Key;
+    /// The type of the value that is stored with the key.
+    /// The value can't borrow from the data buffer.
+    type Value: for<'a> Value<'a>;
+    /// The key in the map
+    const KEY: Self::Key;
+}
+
+/// Declare a set of [TypedKey](crate::map::TypedKey)s. Every key becomes a unit struct that binds the key to
+/// its value type.
+///
+/// The key type has to be given first and must be the same for all keys on a flash range.
+/// See [TypedKey](crate::map::TypedKey) for an example.
+#[macro_export]
+macro_rules! map_keys {
+    (
+        key_type: $key_type:ty;
+        $($(#[$meta:meta])* $vis:vis $name:ident: $value:ty = $key:expr;)*
+    ) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            $vis struct $name;
+
+            impl $crate::map::TypedKey for $name {
+                type Key = $key_type;
+                type Value = $value;
+                const KEY: $key_type = $key;
+            }
+        )*
+    };
+}
+
+/// Get the last stored value of a typed key like [fetch_item] does. Returns None if there's no value.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+pub async fn fetch_typed_item<T: TypedKey, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
-    cache: &mut impl PrivateKeyCacheImpl<K>,
+    cache: &mut impl KeyCacheImpl<T::Key>,
+    data_buffer: &mut [u8],
+) -> Result<Option<T::Value>, Error<S::Error>> {
+    fetch_item::<T::Key, T::Value, S>(flash, flash_range, cache, data_buffer, &T::KEY).await
+}
+
+/// Store the value of a typed key like [store_item] does.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+pub async fn store_typed_item<T: TypedKey, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<T::Key>,
+    data_buffer: &mut [u8],
+    item: &T::Value,
+) -> Result<(), Error<S::Error>> {
+    store_item(flash, flash_range, cache, data_buffer, &T::KEY, item).await
+}
+
+/// Get the last stored value of the key like [fetch_item]. If the key has no value yet, the default is stored
+/// and returned instead. This makes it easy to initialize values on the first boot.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn fetch_or_store<'d, K: Key, V: Value<'d>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &'d mut [u8],
-    search_key: &K,
-) -> Result<Option<(ItemUnborrowed, u32, Option<usize>)>, Error<S::Error>> {
-    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
-    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
-    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+    key: &K,
+    default: V,
+) -> Result<V, Error<S::Error>> {
+    let result = run_with_auto_repair!(
+        function = fetch_or_store_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            key,
+            &default
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    );
 
-    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
-    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+    let Some((item_key_len, data_len)) = result? else {
+        return Ok(default);
+    };
 
-    if cache.is_dirty() {
-        cache.invalidate_cache_state();
-    }
+    V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
+        .map_err(Error::SerializationError)
+}
 
-    'cache: {
-        if let Some(cached_location) = cache.key_location(search_key) {
-            let page_index = calculate_page_index::<S>(flash_range.clone(), cached_location);
-            let page_data_end_address =
-                calculate_page_end_address::<S>(flash_range.clone(), page_index)
-                    - S::WORD_SIZE as u32;
+/// Returns the key length and the data length of the stored item if there is one.
+/// The data of the item is then in the data buffer.
+async fn fetch_or_store_inner<'d, K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    default: &dyn Value<'d>,
+) -> Result<Option<(usize, usize)>, Error<S::Error>> {
+    if let Some((item, _, item_key_len)) =
+        fetch_item_with_location(flash, flash_range.clone(), cache, data_buffer, key).await?
+    {
+        let data_len = item.header.length as usize;
+        let item_key_len = match item_key_len {
+            Some(item_key_len) => item_key_len,
+            None => K::get_len(&data_buffer[..data_len])?,
+        };
 
-            let Some(header) =
-                ItemHeader::read_new(flash, cached_location, page_data_end_address).await?
-            else {
-                // The cache points to a non-existing item?
-                if cfg!(feature = "_test") {
-                    panic!("Wrong cache value. Addr: {cached_location}");
-                }
-                cache.invalidate_cache_state();
-                break 'cache;
-            };
+        return Ok(Some((item_key_len, data_len)));
+    }
 
-            let item = header
-                .read_item(flash, data_buffer, cached_location, page_data_end_address)
-                .await?;
+    store_item_inner(
+        flash,
+        flash_range,
+        cache,
+        data_buffer,
+        [(key, default)].as_slice(),
+    )
+    .await?;
 
-            match item {
-                item::MaybeItem::Corrupted(_, _) | item::MaybeItem::Erased(_, _) => {
-                    if cfg!(feature = "_test") {
-                        panic!("Wrong cache value. Addr: {cached_location}");
-                    }
+    Ok(None)
+}
 
-                    // The cache points to a corrupted or erased item?
-                    cache.invalidate_cache_state();
-                    break 'cache;
-                }
-                item::MaybeItem::Present(item) => {
-                    return Ok(Some((item.unborrow(), cached_location, None)));
-                }
-            }
-        }
-    }
+/// Get the last stored value of the key like [fetch_item], but only if it hasn't expired yet at the given time.
+/// The value must have been stored as an [Expiring] value.
+///
+/// A value is expired when `now` is equal to or later than its expiry time.
+/// Then None is returned, just like when there's no value at all.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn fetch_unexpired_item<'d, K: Key, V: Value<'d>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &'d mut [u8],
+    search_key: &K,
+    now: u64,
+) -> Result<Option<V>, Error<S::Error>> {
+    Ok(
+        fetch_item::<K, Expiring<V>, S>(flash, flash_range, cache, data_buffer, search_key)
+            .await?
+            .filter(|item| now < item.expires_at)
+            .map(|item| item.value),
+    )
+}
 
-    // We need to find the page we were last using. This should be the only partial open page.
-    let mut last_used_page =
-        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?;
-
-    if last_used_page.is_none() {
-        // In the event that all pages are still open or the last used page was just closed, we search for the first open page.
-        // If the page one before that is closed, then that's the last used page.
-        if let Some(first_open_page) =
-            find_first_page(flash, flash_range.clone(), cache, 0, PageState::Open).await?
-        {
-            let previous_page = previous_page::<S>(flash_range.clone(), first_open_page);
-            if get_page_state(flash, flash_range.clone(), cache, previous_page)
-                .await?
-                .is_closed()
-            {
-                last_used_page = Some(previous_page);
-            } else {
-                // The page before the open page is not closed, so it must be open.
-                // This means that all pages are open and that we don't have any items yet.
-                cache.unmark_dirty();
-                return Ok(None);
-            }
-        } else {
-            // There are no open pages, so everything must be closed.
-            // Something is up and this should never happen.
-            // To recover, we will just erase all the flash.
-            return Err(Error::Corrupted {
-                #[cfg(feature = "_test")]
-                backtrace: std::backtrace::Backtrace::capture(),
-            });
-        }
+/// Remove all keys of which the newest value is an [Expiring] value that has expired at the given time,
+/// so their items don't take up space anymore. Returns the amount of keys that were removed.
+///
+/// Only the keys for which the key filter returns true are checked. Values that weren't stored as [Expiring]
+/// values are recognized by their missing marker and are never removed.
+/// The keys are removed like with [remove_item], so the space is freed once their pages are erased.
+///
+/// The expired keys are collected in `expired_keys` in one pass over the map and are then removed together.
+/// If more keys expired than fit in it, this is repeated until all expired keys are removed.
+/// So a bigger `expired_keys` buffer means fewer passes. Its contents afterwards are not specified.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read to find the expired values, like [iter] does.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn remove_expired_items<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    now: u64,
+    key_filter: impl Fn(&K) -> bool,
+    expired_keys: &mut [Option<K>],
+) -> Result<usize, Error<S::Error>> {
+    if expired_keys.is_empty() {
+        return Err(Error::BufferTooSmall(1));
     }
 
-    // We must now find the most recent storage item with the key that was asked for.
-    // If we don't find it in the current page, then we check again in the previous page if that page is closed.
-
-    let mut current_page_to_check = last_used_page.unwrap();
-    let mut newest_found_item_data = None;
+    let mut removed = 0;
 
     loop {
-        let page_data_start_address =
-            calculate_page_address::<S>(flash_range.clone(), current_page_to_check)
-                + S::WORD_SIZE as u32;
-        let page_data_end_address =
-            calculate_page_end_address::<S>(flash_range.clone(), current_page_to_check)
-                - S::WORD_SIZE as u32;
+        expired_keys.fill(None);
+        let mut found = 0;
 
-        let mut it = ItemIter::new(page_data_start_address, page_data_end_address);
-        while let Some((item, address)) = it.next(flash, data_buffer).await? {
-            let (found_key, found_key_len) = K::deserialize_from(item.data())?;
-            if found_key == *search_key {
-                newest_found_item_data = Some((address, found_key_len));
+        let mut iterator = iter_filtered(flash, flash_range.clone(), cache, &key_filter).await?;
+        while found < expired_keys.len() {
+            let Some((key, value)) = iterator.next::<&[u8]>(data_buffer).await? else {
+                break;
+            };
+
+            match Expiring::<&[u8]>::deserialize_from(value) {
+                Ok(item) if now >= item.expires_at => {
+                    expired_keys[found] = Some(key);
+                    found += 1;
+                }
+                _ => {}
             }
         }
 
-        // We've found the item! We can stop searching
-        if let Some((newest_found_item_address, _)) = newest_found_item_data.as_ref() {
-            cache.notice_key_location(search_key, *newest_found_item_address, false);
-
-            break;
+        if found == 0 {
+            return Ok(removed);
         }
+        let more_left = found == expired_keys.len();
 
-        // We have not found the item. We've got to look in the previous page, but only if that page is closed and contains data.
-        let previous_page = previous_page::<S>(flash_range.clone(), current_page_to_check);
+        let expired_keys = &expired_keys[..found];
+        run_with_auto_repair!(
+            function = remove_item_inner::<K, _>(
+                flash,
+                flash_range.clone(),
+                cache,
+                data_buffer,
+                RemoveItems::Matching(&|key| expired_keys.iter().flatten().any(|k| k == key))
+            )
+            .await,
+            repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+        )?;
+        removed += found;
 
-        if get_page_state(flash, flash_range.clone(), cache, previous_page).await?
-            != PageState::Closed
-        {
-            // We've looked through all the pages with data and couldn't find the item
-            cache.unmark_dirty();
-            return Ok(None);
+        if !more_left {
+            return Ok(removed);
         }
-
-        current_page_to_check = previous_page;
-    }
-
-    cache.unmark_dirty();
-
-    // We now need to reread the item because we lost all its data other than its address
-
-    if let Some((newest_found_item_address, newest_found_item_key_len)) = newest_found_item_data {
-        let item = ItemHeader::read_new(flash, newest_found_item_address, u32::MAX)
-            .await?
-            .ok_or_else(|| {
-                // How come there's no item header here!? We just found it!
-                Error::Corrupted {
-                    #[cfg(feature = "_test")]
-                    backtrace: std::backtrace::Backtrace::capture(),
-                }
-            })?
-            .read_item(flash, data_buffer, newest_found_item_address, u32::MAX)
-            .await?;
-
-        Ok(Some((
-            item.unwrap()?.unborrow(),
-            newest_found_item_address,
-            Some(newest_found_item_key_len),
-        )))
-    } else {
-        Ok(None)
     }
 }
 
-/// Store a key-value pair into flash memory.
-/// It will overwrite the last value that has the same key.
-/// The flash needs to be at least 2 pages long.
+/// Get the last stored values of the key, newest first. This can be used to e.g. go back to a previous setting.
+/// The history is filled with up to `history.len()` values and the amount of values that were found is returned.
+///
+/// Only the values that are still in flash can be found. When a page is erased, only the newest value of a key
+/// is kept, so over time old values disappear. Values of a removed key can't be found either.
+///
+/// Because the same data buffer is used for every value, the values can't borrow from the buffer.
 ///
 /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
 /// rounded up to flash word alignment.
@@ -330,242 +503,265 @@ async fn fetch_item_with_location<'d, K: Key, S: NorFlash>(
 /// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
 ///
 /// </div>
-pub async fn store_item<'d, K: Key, V: Value<'d>, S: NorFlash>(
+pub async fn fetch_history<K: Key, V: for<'a> Value<'a>, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &mut [u8],
-    key: &K,
-    item: &V,
-) -> Result<(), Error<S::Error>> {
+    search_key: &K,
+    history: &mut [Option<V>],
+) -> Result<usize, Error<S::Error>> {
     run_with_auto_repair!(
-        function =
-            store_item_inner(flash, flash_range.clone(), cache, data_buffer, key, item).await,
+        function = fetch_history_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            search_key,
+            history
+        )
+        .await,
         repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
     )
 }
 
-async fn store_item_inner<'d, K: Key, S: NorFlash>(
+async fn fetch_history_inner<K: Key, V: for<'a> Value<'a>, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &mut [u8],
-    key: &K,
-    item: &dyn Value<'d>,
-) -> Result<(), Error<S::Error>> {
+    search_key: &K,
+    history: &mut [Option<V>],
+) -> Result<usize, Error<S::Error>> {
     assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
     assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
-
     assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
 
     assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
     assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
 
-    if cache.is_dirty() {
-        cache.invalidate_cache_state();
-    }
+    cache.recover_if_dirty();
 
-    let mut recursion_level = 0;
-    loop {
-        // Check if we're in an infinite recursion which happens when we don't have enough space to store the new data
-        if recursion_level == get_pages::<S>(flash_range.clone(), 0).count() {
-            cache.unmark_dirty();
-            return Err(Error::FullStorage);
-        }
-
-        // If there is a partial open page, we try to write in that first if there is enough space
-        let next_page_to_use = if let Some(partial_open_page) =
-            find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?
-        {
-            // We found a partial open page, but at this point it's relatively cheap to do a consistency check
-            if !get_page_state(
-                flash,
-                flash_range.clone(),
-                cache,
-                next_page::<S>(flash_range.clone(), partial_open_page),
-            )
-            .await?
-            .is_open()
-            {
-                // Oh oh, the next page which serves as the buffer page is not open. We're corrupt.
-                // This likely happened because of an unexpected shutdown during data migration from the
-                // then new buffer page to the new partial open page.
-                // The repair function should be able to repair this.
-                return Err(Error::Corrupted {
-                    #[cfg(feature = "_test")]
-                    backtrace: std::backtrace::Backtrace::capture(),
-                });
-            }
-
-            // We've got to search where the free space is since the page starts with items present already
-
-            let page_data_start_address =
-                calculate_page_address::<S>(flash_range.clone(), partial_open_page)
-                    + S::WORD_SIZE as u32;
-            let page_data_end_address =
-                calculate_page_end_address::<S>(flash_range.clone(), partial_open_page)
-                    - S::WORD_SIZE as u32;
-
-            let key_len = key.serialize_into(data_buffer)?;
-            let item_data_length = key_len
-                + item
-                    .serialize_into(&mut data_buffer[key_len..])
-                    .map_err(Error::SerializationError)?;
-
-            if item_data_length > u16::MAX as usize
-                || item_data_length
-                    > calculate_page_size::<S>()
-                        .saturating_sub(ItemHeader::data_address::<S>(0) as usize)
-            {
-                cache.unmark_dirty();
-                return Err(Error::ItemTooBig);
-            }
+    let Some(last_used_page) = find_last_used_page(flash, flash_range.clone(), cache).await? else {
+        cache.unmark_dirty();
+        return Ok(0);
+    };
 
-            let free_spot_address = find_next_free_item_spot(
-                flash,
-                flash_range.clone(),
-                cache,
-                page_data_start_address,
-                page_data_end_address,
-                item_data_length as u32,
-            )
-            .await?;
+    let mut found = 0;
+    let mut current_page_to_check = last_used_page;
 
-            match free_spot_address {
-                Some(free_spot_address) => {
-                    cache.notice_key_location(key, free_spot_address, true);
-                    Item::write_new(
-                        flash,
-                        flash_range.clone(),
-                        cache,
-                        free_spot_address,
-                        &data_buffer[..item_data_length],
-                    )
-                    .await?;
+    // Go over the pages from new to old until the history is full
+    while found < history.len() {
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), current_page_to_check)
+                + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), current_page_to_check)
+                - S::WORD_SIZE as u32;
 
-                    cache.unmark_dirty();
-                    return Ok(());
-                }
-                None => {
-                    // The item doesn't fit here, so we need to close this page and move to the next
-                    close_page(flash, flash_range.clone(), cache, partial_open_page).await?;
-                    Some(next_page::<S>(flash_range.clone(), partial_open_page))
+        // The items on a page go from old to new, so first count them to know where each one goes in the history.
+        // A tombstone removed the key, so the history ends at the newest one.
+        let mut page_matches = 0;
+        let mut page_tombstones = 0;
+        let mut it = ItemIter::new(page_data_start_address, page_data_end_address);
+        while let Some((item, _, is_tombstone)) =
+            it.next_with_tombstones(flash, data_buffer).await?
+        {
+            if search_key.matches_serialized(item.data())? {
+                if is_tombstone {
+                    page_matches = 0;
+                    page_tombstones += 1;
+                } else {
+                    page_matches += 1;
                 }
             }
-        } else {
-            None
-        };
-
-        // If we get here, there was no partial page found or the partial page has now been closed because the item didn't fit.
-        // If there was a partial page, then we need to look at the next page. It's supposed to be open since it was the previous empty buffer page.
-        // The new buffer page has to be emptied if it was closed.
-        // If there was no partial page, we just use the first open page.
-
-        match next_page_to_use {
-            Some(next_page_to_use) => {
-                let next_page_state =
-                    get_page_state(flash, flash_range.clone(), cache, next_page_to_use).await?;
-
-                if !next_page_state.is_open() {
-                    // What was the previous buffer page was not open...
-                    return Err(Error::Corrupted {
-                        #[cfg(feature = "_test")]
-                        backtrace: std::backtrace::Backtrace::capture(),
-                    });
-                }
+        }
 
-                // Since we're gonna write data here, let's already partially close the page
-                // This could be done after moving the data, but this is more robust in the
-                // face of shutdowns and cancellations
-                partial_close_page(flash, flash_range.clone(), cache, next_page_to_use).await?;
+        let mut page_match = 0;
+        let mut tombstones_left = page_tombstones;
+        let mut it = ItemIter::new(page_data_start_address, page_data_end_address);
+        while let Some((item, _, is_tombstone)) =
+            it.next_with_tombstones(flash, data_buffer).await?
+        {
+            if !search_key.matches_serialized(item.data())? {
+                continue;
+            }
+            if is_tombstone {
+                tombstones_left -= 1;
+                continue;
+            }
+            if tombstones_left > 0 {
+                continue;
+            }
+            let found_key_len = K::get_len(item.data())?;
 
-                let next_buffer_page = next_page::<S>(flash_range.clone(), next_page_to_use);
-                let next_buffer_page_state =
-                    get_page_state(flash, flash_range.clone(), cache, next_buffer_page).await?;
+            let history_index = found + page_matches - 1 - page_match;
+            page_match += 1;
 
-                if !next_buffer_page_state.is_open() {
-                    migrate_items::<K, _>(
-                        flash,
-                        flash_range.clone(),
-                        cache,
-                        data_buffer,
-                        next_buffer_page,
-                        next_page_to_use,
-                    )
-                    .await?;
-                }
+            if let Some(entry) = history.get_mut(history_index) {
+                *entry = Some(
+                    V::deserialize_from(&item.data()[found_key_len..])
+                        .map_err(Error::SerializationError)?,
+                );
             }
-            None => {
-                // There's no partial open page, so we just gotta turn the first open page into a partial open one
-                let first_open_page =
-                    match find_first_page(flash, flash_range.clone(), cache, 0, PageState::Open)
-                        .await?
-                    {
-                        Some(first_open_page) => first_open_page,
-                        None => {
-                            // Uh oh, no open pages.
-                            // Something has gone wrong.
-                            // We should never get here.
-                            return Err(Error::Corrupted {
-                                #[cfg(feature = "_test")]
-                                backtrace: std::backtrace::Backtrace::capture(),
-                            });
-                        }
-                    };
+        }
 
-                partial_close_page(flash, flash_range.clone(), cache, first_open_page).await?;
-            }
+        found = (found + page_matches).min(history.len());
+
+        let previous_page = previous_page::<S>(flash_range.clone(), current_page_to_check);
+        if page_tombstones > 0
+            || get_page_state(flash, flash_range.clone(), cache, previous_page).await?
+                != PageState::Closed
+            || previous_page == last_used_page
+        {
+            break;
         }
 
-        // If we get here, we just freshly partially closed a new page, so the next loop iteration should succeed.
-        recursion_level += 1;
+        current_page_to_check = previous_page;
     }
+
+    cache.unmark_dirty();
+    Ok(found)
 }
 
-/// Fully remove an item. Additional calls to fetch with the same key will return None until
-/// a new one is stored again.
+/// Store a value while keeping the previous `keep - 1` values of the key, so they can be fetched again with
+/// [fetch_item_version] to e.g. roll back a critical setting.
+/// The new value is counted in `keep` too, which is why it can't be 0.
 ///
-/// <div class="warning">
-/// This is really slow!
+/// Unlike the values found by [fetch_history], the kept values survive the erase of a page.
+/// This is because all kept values are stored together in one item, newest first. Every value is prefixed
+/// with its length as a little endian u16. So keep in mind that the item grows with every kept value.
 ///
-/// All items in flash have to be read and deserialized to find the items with the key.
-/// This is unlikely to be cached well.
-/// </div>
+/// Values stored with this function must be fetched with [fetch_item_version], not with [fetch_item].
+/// The same `keep` doesn't have to be used for every store. If it's lower than before,
+/// the oldest values are dropped.
+///
+/// The history buffer must be long enough to hold all kept values with their lengths.
+/// If it's not, [Error::BufferTooSmall] is returned.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment. Here the value is all kept values together.
 ///
 /// <div class="warning">
 ///
 /// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
 /// *multiple [Value] types. See the module-level docs for more information about this.*
 ///
-/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
-///
 /// </div>
-pub async fn remove_item<K: Key, S: MultiwriteNorFlash>(
+#[allow(clippy::too_many_arguments)]
+pub async fn store_item_with_history<'d, K: Key, V: Value<'d>, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &mut [u8],
-    search_key: &K,
+    history_buffer: &mut [u8],
+    key: &K,
+    item: &V,
+    keep: NonZeroUsize,
 ) -> Result<(), Error<S::Error>> {
-    run_with_auto_repair!(
-        function = remove_item_inner::<K, _>(
-            flash,
-            flash_range.clone(),
-            cache,
-            data_buffer,
-            Some(search_key)
-        )
-        .await,
-        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    let (length, value_buffer) = history_buffer
+        .split_first_chunk_mut::<2>()
+        .ok_or(Error::BufferTooSmall(2))?;
+    let item_length = item
+        .serialize_into(value_buffer)
+        .map_err(Error::SerializationError)?;
+    *length = u16::try_from(item_length)
+        .map_err(|_| Error::ItemTooBig)?
+        .to_le_bytes();
+    let mut history_length = 2 + item_length;
+
+    if let Some(mut previous) =
+        fetch_item::<K, &[u8], S>(flash, flash_range.clone(), cache, data_buffer, key).await?
+    {
+        for _ in 1..keep.get() {
+            let Some((length, value)) = previous.split_first_chunk::<2>() else {
+                break;
+            };
+            let entry_length = 2 + u16::from_le_bytes(*length) as usize;
+            if value.len() < entry_length - 2 {
+                return Err(Error::SerializationError(SerializationError::InvalidFormat));
+            }
+
+            history_buffer
+                .get_mut(history_length..history_length + entry_length)
+                .ok_or(Error::BufferTooSmall(history_length + entry_length))?
+                .copy_from_slice(&previous[..entry_length]);
+            history_length += entry_length;
+            previous = &previous[entry_length..];
+        }
+    }
+
+    store_item(
+        flash,
+        flash_range,
+        cache,
+        data_buffer,
+        key,
+        &&history_buffer[..history_length],
     )
+    .await
 }
 
-/// Fully remove all stored items. Additional calls to fetch with any key will return None until
-/// new items are stored again.
+/// Get a value that was stored with [store_item_with_history].
+/// Version 0 is the newest value, 1 the value before it, and so on.
+/// If there's no value for the key or the version isn't kept, None is returned.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment. Here the value is all kept values together.
 ///
 /// <div class="warning">
-/// This might be really slow!
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
 /// </div>
+pub async fn fetch_item_version<'d, K: Key, V: Value<'d>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &'d mut [u8],
+    search_key: &K,
+    version: usize,
+) -> Result<Option<V>, Error<S::Error>> {
+    let Some(mut history) =
+        fetch_item::<K, &'d [u8], S>(flash, flash_range, cache, data_buffer, search_key).await?
+    else {
+        return Ok(None);
+    };
+
+    for _ in 0..version {
+        let Some((length, value)) = history.split_first_chunk::<2>() else {
+            return Ok(None);
+        };
+        history = value
+            .get(u16::from_le_bytes(*length) as usize..)
+            .ok_or(Error::SerializationError(SerializationError::InvalidFormat))?;
+    }
+
+    let Some((length, value)) = history.split_first_chunk::<2>() else {
+        return Ok(None);
+    };
+    let value = value
+        .get(..u16::from_le_bytes(*length) as usize)
+        .ok_or(Error::SerializationError(SerializationError::InvalidFormat))?;
+
+    Ok(Some(
+        V::deserialize_from(value).map_err(Error::SerializationError)?,
+    ))
+}
+
+/// Fetch a part of the value of the item with the given key.
+/// This way a value can be read in chunks, e.g. to stream a big certificate out of the map.
+///
+/// The bytes of the value in the given range are read into the data buffer. The amount of bytes that were read
+/// is returned. This is less than the length of the range if the value ends before the range does
+/// and 0 if the range starts at or after the end of the value.
+/// If no value with the key is found, None is returned.
+///
+/// The data buffer only has to be as long as the range and as the longest serialized [Key], not the whole item.
+/// Because the whole item is never in memory at once, it's checked against its crc in parts.
+/// This costs some more reads than [fetch_item] does. If the flash needs to be repaired, the data buffer
+/// must be long enough for that like it is for [fetch_item].
 ///
 /// <div class="warning">
 ///
@@ -573,868 +769,6603 @@ pub async fn remove_item<K: Key, S: MultiwriteNorFlash>(
 /// *multiple [Value] types. See the module-level docs for more information about this.*
 ///
 /// </div>
-pub async fn remove_all_items<K: Key, S: MultiwriteNorFlash>(
+pub async fn fetch_item_range<K: Key, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &mut [u8],
-) -> Result<(), Error<S::Error>> {
+    search_key: &K,
+    value_range: Range<usize>,
+) -> Result<Option<usize>, Error<S::Error>> {
     run_with_auto_repair!(
-        function =
-            remove_item_inner::<K, _>(flash, flash_range.clone(), cache, data_buffer, None).await,
+        function = fetch_item_range_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            search_key,
+            value_range.clone()
+        )
+        .await,
         repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
     )
 }
 
-/// If `search_key` is None, then all items will be removed
-async fn remove_item_inner<K: Key, S: MultiwriteNorFlash>(
+async fn fetch_item_range_inner<K: Key, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &mut [u8],
-    search_key: Option<&K>,
-) -> Result<(), Error<S::Error>> {
-    if let Some(key) = &search_key {
-        cache.notice_key_erased(key);
-    } else {
-        cache.invalidate_cache_state();
+    search_key: &K,
+    value_range: Range<usize>,
+) -> Result<Option<usize>, Error<S::Error>> {
+    if data_buffer.len() < value_range.len() {
+        return Err(Error::BufferTooSmall(value_range.len()));
     }
 
-    // Search for the last used page. We're gonna erase from the one after this first.
-    // If we get an early shutoff or cancellation, this will make it so that we don't return
-    // an old version of the key on the next fetch.
-    let last_used_page =
-        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen)
-            .await?
-            .unwrap_or_default();
+    let Some((header, address, key_len)) =
+        fetch_item_header(flash, flash_range, cache, data_buffer, search_key).await?
+    else {
+        return Ok(None);
+    };
 
-    // Go through all the pages
-    for page_index in get_pages::<S>(
-        flash_range.clone(),
-        next_page::<S>(flash_range.clone(), last_used_page),
-    ) {
-        if get_page_state(flash, flash_range.clone(), cache, page_index)
-            .await?
-            .is_open()
-        {
-            // This page is open, we don't have to check it
-            continue;
-        }
+    let value_len = header.length as usize - key_len;
+    let read_range = value_range.start.min(value_len)..value_range.end.min(value_len);
 
-        let page_data_start_address =
-            calculate_page_address::<S>(flash_range.clone(), page_index) + S::WORD_SIZE as u32;
-        let page_data_end_address =
-            calculate_page_end_address::<S>(flash_range.clone(), page_index) - S::WORD_SIZE as u32;
+    let read = header
+        .read_data_part(
+            flash,
+            address,
+            key_len + read_range.start,
+            &mut data_buffer[..read_range.len()],
+        )
+        .await?;
 
-        // Go through all items on the page
-        let mut item_headers = ItemHeaderIter::new(page_data_start_address, page_data_end_address);
+    Ok(Some(read))
+}
 
-        while let (Some(item_header), item_address) = item_headers.next(flash).await? {
-            let item = item_header
-                .read_item(flash, data_buffer, item_address, page_data_end_address)
-                .await?;
+/// Find the newest item of the key without reading all of its data into the data buffer.
+/// The header of the item, its address and the length of the key are returned.
+async fn fetch_item_header<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    search_key: &K,
+) -> Result<Option<(ItemHeader, u32, usize)>, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
 
-            match item {
-                item::MaybeItem::Corrupted(_, _) => continue,
-                item::MaybeItem::Erased(_, _) => continue,
-                item::MaybeItem::Present(item) => {
-                    let item_match = match search_key {
-                        Some(search_key) => K::deserialize_from(item.data())?.0 == *search_key,
-                        _ => true,
-                    };
-                    // If this item has the same key as the key we're trying to erase, then erase the item.
-                    // But keep going! We need to erase everything.
-                    if item_match {
-                        item.header
-                            .erase_data(flash, flash_range.clone(), cache, item_address)
-                            .await?;
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let mut newest_found_item = None;
+
+    'cache: {
+        if let Some(cached_location) = cache.key_location(search_key) {
+            let page_index = calculate_page_index::<S>(flash_range.clone(), cached_location);
+            let page_data_end_address =
+                calculate_page_end_address::<S>(flash_range.clone(), page_index)
+                    - S::WORD_SIZE as u32;
+
+            if let Some(header) =
+                ItemHeader::read_new(flash, cached_location, page_data_end_address).await?
+            {
+                if let Some((key, key_len)) = read_item_key::<K, _>(
+                    flash,
+                    &header,
+                    cached_location,
+                    page_data_end_address,
+                    data_buffer,
+                )
+                .await?
+                {
+                    if key == *search_key
+                        && header
+                            .verify_data(flash, cached_location, page_data_end_address)
+                            .await?
+                    {
+                        newest_found_item = Some((header, cached_location, key_len));
+                        break 'cache;
                     }
                 }
             }
+
+            // The cache points to an item that doesn't have the key?
+            if cfg!(feature = "_test") {
+                panic!("Wrong cache value. Addr: {cached_location}");
+            }
+            cache.invalidate_cache_state();
         }
     }
 
-    // We're done, we now know the cache is in a good state
-    cache.unmark_dirty();
+    if newest_found_item.is_none() {
+        if cache.is_key_absent(search_key) {
+            cache.unmark_dirty();
+            return Ok(None);
+        }
 
-    Ok(())
-}
+        let Some(last_used_page) = find_last_used_page(flash, flash_range.clone(), cache).await?
+        else {
+            // All pages are open, so we don't have any items yet
+            cache.notice_all_keys_seen();
+            cache.unmark_dirty();
+            return Ok(None);
+        };
 
-/// Anything implementing this trait can be used as a key in the map functions.
-///
-/// It provides a way to serialize and deserialize the key.
-///
-/// The `Eq` bound is used because we need to be able to compare keys and the
-/// `Clone` bound helps us pass the key around.
-///
-/// The key cannot have a lifetime like the [Value]
-pub trait Key: Eq + Clone + Sized {
-    /// Serialize the key into the given buffer.
-    /// The serialized size is returned.
-    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError>;
-    /// Deserialize the key from the given buffer.
-    /// The key is returned together with the serialized length.
-    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError>;
-    /// Get the length of the key from the buffer.
-    /// This is an optimized version of [Self::deserialize_from] that doesn't have to deserialize everything.
-    fn get_len(buffer: &[u8]) -> Result<usize, SerializationError> {
-        Self::deserialize_from(buffer).map(|(_, len)| len)
-    }
-}
+        // Search from the newest page back like fetch_item does, but only read the keys of the items
+        let mut current_page_to_check = last_used_page;
+        let mut removed = false;
+        loop {
+            let page_data_start_address =
+                calculate_page_address::<S>(flash_range.clone(), current_page_to_check)
+                    + S::WORD_SIZE as u32;
+            let page_data_end_address =
+                calculate_page_end_address::<S>(flash_range.clone(), current_page_to_check)
+                    - S::WORD_SIZE as u32;
 
-macro_rules! impl_key_num {
-    ($int:ty) => {
-        impl Key for $int {
-            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-                let len = core::mem::size_of::<Self>();
-                if buffer.len() < len {
-                    return Err(SerializationError::BufferTooSmall);
+            let mut item_headers =
+                ItemHeaderIter::new(page_data_start_address, page_data_end_address);
+            while let (Some(header), address) = item_headers.next(flash).await? {
+                let Some((key, key_len)) = read_item_key::<K, _>(
+                    flash,
+                    &header,
+                    address,
+                    page_data_end_address,
+                    data_buffer,
+                )
+                .await?
+                else {
+                    continue;
+                };
+
+                // Only the items with the key we're looking for are checked against their crc
+                if key == *search_key {
+                    match header
+                        .check_data(flash, address, page_data_end_address)
+                        .await?
+                    {
+                        DataCheck::Valid => {
+                            newest_found_item = Some((header, address, key_len));
+                            removed = false;
+                        }
+                        DataCheck::Tombstone => {
+                            newest_found_item = None;
+                            removed = true;
+                        }
+                        DataCheck::Corrupted => {}
+                    }
                 }
-                buffer[..len].copy_from_slice(&self.to_le_bytes());
-                Ok(len)
+                cache.notice_key_seen(&key);
             }
 
-            fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
-                let len = core::mem::size_of::<Self>();
-                if buffer.len() < len {
-                    return Err(SerializationError::BufferTooSmall);
-                }
-
-                Ok((
-                    Self::from_le_bytes(buffer[..len].try_into().unwrap()),
-                    core::mem::size_of::<Self>(),
-                ))
+            // The newest item of the key is a tombstone, so the key has been removed
+            if removed {
+                cache.notice_key_erased(search_key);
+                cache.unmark_dirty();
+                return Ok(None);
             }
 
-            fn get_len(_buffer: &[u8]) -> Result<usize, SerializationError> {
-                Ok(core::mem::size_of::<Self>())
+            if let Some((_, newest_found_item_address, _)) = newest_found_item.as_ref() {
+                cache.notice_key_location(search_key, *newest_found_item_address, false);
+                break;
             }
-        }
-    };
-}
 
-impl_key_num!(u8);
-impl_key_num!(u16);
-impl_key_num!(u32);
-impl_key_num!(u64);
-impl_key_num!(u128);
-impl_key_num!(i8);
-impl_key_num!(i16);
-impl_key_num!(i32);
-impl_key_num!(i64);
-impl_key_num!(i128);
+            let previous_page = previous_page::<S>(flash_range.clone(), current_page_to_check);
 
-impl<const N: usize> Key for [u8; N] {
-    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-        if buffer.len() < N {
-            return Err(SerializationError::BufferTooSmall);
+            if get_page_state(flash, flash_range.clone(), cache, previous_page).await?
+                != PageState::Closed
+            {
+                // We've looked through all the pages with data and couldn't find the item
+                cache.notice_all_keys_seen();
+                cache.unmark_dirty();
+                return Ok(None);
+            }
+
+            current_page_to_check = previous_page;
         }
-        buffer[..N].copy_from_slice(self);
-        Ok(N)
     }
 
-    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
-        if buffer.len() < N {
-            return Err(SerializationError::BufferTooSmall);
-        }
+    cache.unmark_dirty();
 
-        Ok((buffer[..N].try_into().unwrap(), N))
-    }
+    Ok(newest_found_item)
+}
 
-    fn get_len(_buffer: &[u8]) -> Result<usize, SerializationError> {
-        Ok(N)
-    }
+/// Information about the stored value of a key. See [item_info].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ItemInfo {
+    /// The length of the serialized value
+    pub value_length: usize,
+    /// The address in flash where the item starts with its header
+    pub address: u32,
+    /// The index of the page in the flash range that holds the item
+    pub page_index: usize,
 }
 
-/// The trait that defines how map values are serialized and deserialized.
+/// Get information about the last stored value of the key without reading the value,
+/// e.g. to know how big the buffer must be to fetch it.
+/// If no value with the key is found, None is returned.
 ///
-/// It also carries a lifetime so that zero-copy deserialization is supported.
-/// Zero-copy serialization is not supported due to technical restrictions.
-pub trait Value<'a> {
-    /// Serialize the value into the given buffer. If everything went ok, this function returns the length
-    /// of the used part of the buffer.
-    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError>;
-    /// Deserialize the value from the buffer. Because of the added lifetime, the implementation can borrow from the
-    /// buffer which opens up some zero-copy possibilities.
-    ///
-    /// The buffer will be the same length as the serialize function returned for this value. Though note that the length
-    /// is written from flash, so bitflips can affect that (though the length is separately crc-protected) and the key deserialization might
-    /// return a wrong length.
-    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
-    where
-        Self: Sized;
-}
+/// Like [fetch_item_range], the data buffer only has to be as long as the longest serialized [Key].
+/// If the flash needs to be repaired, the data buffer must be long enough for that like it is for [fetch_item].
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn item_info<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    search_key: &K,
+) -> Result<Option<ItemInfo>, Error<S::Error>> {
+    let item = run_with_auto_repair!(
+        function =
+            fetch_item_header(flash, flash_range.clone(), cache, data_buffer, search_key).await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )?;
 
-impl<'a> Value<'a> for &'a [u8] {
-    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-        if buffer.len() < self.len() {
-            return Err(SerializationError::BufferTooSmall);
-        }
+    Ok(item.map(|(header, address, key_len)| ItemInfo {
+        value_length: header.length as usize - key_len,
+        address,
+        page_index: calculate_page_index::<S>(flash_range, address),
+    }))
+}
 
-        buffer[..self.len()].copy_from_slice(self);
-        Ok(self.len())
+/// Read the key of the item without reading all of its data.
+/// Returns None if the item is erased or the key can't be read because the item is corrupted.
+async fn read_item_key<K: Key, S: NorFlash>(
+    flash: &mut S,
+    header: &ItemHeader,
+    address: u32,
+    end_address: u32,
+    data_buffer: &mut [u8],
+) -> Result<Option<(K, usize)>, Error<S::Error>> {
+    if header.crc.is_none() {
+        return Ok(None);
     }
 
-    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
-    where
-        Self: Sized,
-    {
-        Ok(buffer)
-    }
-}
+    let read = header
+        .read_data_part(flash, address, 0, data_buffer)
+        .await?;
 
-impl<'a, const N: usize> Value<'a> for [u8; N] {
-    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-        if buffer.len() < self.len() {
-            return Err(SerializationError::BufferTooSmall);
+    match K::deserialize_from(&data_buffer[..read]) {
+        Ok(key) => Ok(Some(key)),
+        Err(_) if !header.verify_data(flash, address, end_address).await? => Ok(None),
+        Err(SerializationError::BufferTooSmall) if read < header.length as usize => {
+            Err(Error::BufferTooSmall(header.length as usize))
         }
-
-        buffer[..self.len()].copy_from_slice(self);
-        Ok(self.len())
-    }
-
-    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
-    where
-        Self: Sized,
-    {
-        buffer
-            .try_into()
-            .map_err(|_| SerializationError::BufferTooSmall)
+        Err(e) => Err(Error::SerializationError(e)),
     }
 }
 
-macro_rules! impl_map_item_num {
-    ($int:ty) => {
-        impl<'a> Value<'a> for $int {
-            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-                buffer[..core::mem::size_of::<Self>()].copy_from_slice(&self.to_le_bytes());
-                Ok(core::mem::size_of::<Self>())
-            }
+/// Fetch the item, but with the item unborrowed, the address of the item and the length of the key
+#[allow(clippy::type_complexity)]
+async fn fetch_item_with_location<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateKeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    search_key: &K,
+) -> Result<Option<(ItemUnborrowed, u32, Option<usize>)>, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
 
-            fn deserialize_from(buffer: &[u8]) -> Result<Self, SerializationError> {
-                Ok(Self::from_le_bytes(
-                    buffer[..core::mem::size_of::<Self>()]
-                        .try_into()
-                        .map_err(|_| SerializationError::BufferTooSmall)?,
-                ))
-            }
-        }
-    };
-}
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
 
-impl_map_item_num!(u8);
-impl_map_item_num!(u16);
-impl_map_item_num!(u32);
-impl_map_item_num!(u64);
-impl_map_item_num!(u128);
-impl_map_item_num!(i8);
-impl_map_item_num!(i16);
-impl_map_item_num!(i32);
-impl_map_item_num!(i64);
-impl_map_item_num!(i128);
-impl_map_item_num!(f32);
-impl_map_item_num!(f64);
+    cache.recover_if_dirty();
 
-/// Error for map value (de)serialization.
-///
-/// This error type is predefined (in contrast to using generics) to save many kilobytes of binary size.
-#[non_exhaustive]
-#[derive(Debug, PartialEq, Eq, Clone)]
-#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-pub enum SerializationError {
-    /// The provided buffer was too small.
-    BufferTooSmall,
-    /// The serialization could not succeed because the data was not in order. (e.g. too big to fit)
-    InvalidData,
-    /// The deserialization could not succeed because the bytes are in an invalid format.
-    InvalidFormat,
-    /// An implementation defined error that might contain more information than the other predefined
-    /// error variants.
-    Custom(i32),
+    'cache: {
+        if let Some(cached_location) = cache.key_location(search_key) {
+            let page_index = calculate_page_index::<S>(flash_range.clone(), cached_location);
+            let page_data_end_address =
+                calculate_page_end_address::<S>(flash_range.clone(), page_index)
+                    - S::WORD_SIZE as u32;
+
+            let Some(header) =
+                ItemHeader::read_new(flash, cached_location, page_data_end_address).await?
+            else {
+                // The cache points to a non-existing item?
+                if cfg!(feature = "_test") {
+                    panic!("Wrong cache value. Addr: {cached_location}");
+                }
+                cache.invalidate_cache_state();
+                break 'cache;
+            };
+
+            let item = header
+                .read_item(flash, data_buffer, cached_location, page_data_end_address)
+                .await?;
+
+            match item {
+                item::MaybeItem::Corrupted(_, _)
+                | item::MaybeItem::Erased(_, _)
+                | item::MaybeItem::Tombstone(_) => {
+                    if cfg!(feature = "_test") {
+                        panic!("Wrong cache value. Addr: {cached_location}");
+                    }
+
+                    // The cache points to a corrupted or erased item?
+                    cache.invalidate_cache_state();
+                    break 'cache;
+                }
+                item::MaybeItem::Present(item) => {
+                    return Ok(Some((item.unborrow(), cached_location, None)));
+                }
+            }
+        }
+    }
+
+    if cache.is_key_absent(search_key) {
+        cache.unmark_dirty();
+        return Ok(None);
+    }
+
+    let Some(last_used_page) = find_last_used_page(flash, flash_range.clone(), cache).await? else {
+        // All pages are open, so we don't have any items yet
+        cache.notice_all_keys_seen();
+        cache.unmark_dirty();
+        return Ok(None);
+    };
+
+    // We must now find the most recent storage item with the key that was asked for.
+    // If we don't find it in the current page, then we check again in the previous page if that page is closed.
+
+    let mut current_page_to_check = last_used_page;
+    let mut newest_found_item_data = None;
+
+    loop {
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), current_page_to_check)
+                + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), current_page_to_check)
+                - S::WORD_SIZE as u32;
+
+        let mut it = ItemIter::new(page_data_start_address, page_data_end_address);
+        while let Some((item, address, is_tombstone)) =
+            it.next_with_tombstones(flash, data_buffer).await?
+        {
+            if search_key.matches_serialized(item.data())? {
+                newest_found_item_data = Some((address, K::get_len(item.data())?, is_tombstone));
+            }
+            // Only deserialize all keys when the cache does something with them
+            if !is_tombstone && cache.wants_seen_keys() {
+                cache.notice_key_seen(&K::deserialize_from(item.data())?.0);
+            }
+        }
+
+        match newest_found_item_data {
+            // The newest item of the key is a tombstone, so the key has been removed
+            Some((_, _, true)) => {
+                cache.notice_key_erased(search_key);
+                cache.unmark_dirty();
+                return Ok(None);
+            }
+            // We've found the item! We can stop searching
+            Some((newest_found_item_address, _, false)) => {
+                cache.notice_key_location(search_key, newest_found_item_address, false);
+
+                break;
+            }
+            None => {}
+        }
+
+        // We have not found the item. We've got to look in the previous page, but only if that page is closed and contains data.
+        let previous_page = previous_page::<S>(flash_range.clone(), current_page_to_check);
+
+        if get_page_state(flash, flash_range.clone(), cache, previous_page).await?
+            != PageState::Closed
+        {
+            // We've looked through all the pages with data and couldn't find the item
+            cache.notice_all_keys_seen();
+            cache.unmark_dirty();
+            return Ok(None);
+        }
+
+        current_page_to_check = previous_page;
+    }
+
+    cache.unmark_dirty();
+
+    // We now need to reread the item because we lost all its data other than its address
+
+    if let Some((newest_found_item_address, newest_found_item_key_len, _)) = newest_found_item_data
+    {
+        let item = ItemHeader::read_new(flash, newest_found_item_address, u32::MAX)
+            .await?
+            .ok_or_else(|| {
+                // How come there's no item header here!? We just found it!
+                Error::Corrupted {
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                }
+            })?
+            .read_item(flash, data_buffer, newest_found_item_address, u32::MAX)
+            .await?;
+
+        Ok(Some((
+            item.unwrap()?.unborrow(),
+            newest_found_item_address,
+            Some(newest_found_item_key_len),
+        )))
+    } else {
+        Ok(None)
+    }
 }
 
-impl core::fmt::Display for SerializationError {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match self {
-            SerializationError::BufferTooSmall => write!(f, "Buffer too small"),
-            SerializationError::InvalidData => write!(f, "Invalid data"),
-            SerializationError::InvalidFormat => write!(f, "Invalid format"),
-            SerializationError::Custom(val) => write!(f, "Custom error: {val}"),
+/// Find the page that was last stored to. Returns None if all pages are open.
+async fn find_last_used_page<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateCacheImpl,
+) -> Result<Option<usize>, Error<S::Error>> {
+    // We need to find the page we were last using. This should be the only partial open page.
+    if let Some(partial_open_page) =
+        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?
+    {
+        return Ok(Some(partial_open_page));
+    }
+
+    // In the event that all pages are still open or the last used page was just closed, we search for the first open page.
+    // If the page one before that is closed, then that's the last used page.
+    if let Some(first_open_page) =
+        find_first_page(flash, flash_range.clone(), cache, 0, PageState::Open).await?
+    {
+        let previous_page = previous_page::<S>(flash_range.clone(), first_open_page);
+        if get_page_state(flash, flash_range.clone(), cache, previous_page)
+            .await?
+            .is_closed()
+        {
+            Ok(Some(previous_page))
+        } else {
+            // The page before the open page is not closed, so it must be open.
+            // This means that all pages are open and that we don't have any items yet.
+            Ok(None)
         }
+    } else {
+        // There are no open pages, so everything must be closed.
+        // Something is up and this should never happen.
+        // To recover, we will just erase all the flash.
+        Err(Error::Corrupted {
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })
     }
 }
 
-async fn migrate_items<K: Key, S: NorFlash>(
+/// Store a key-value pair into flash memory.
+/// It will overwrite the last value that has the same key.
+/// The flash needs to be at least 2 pages long.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn store_item<'d, K: Key, V: Value<'d>, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
-    cache: &mut impl PrivateKeyCacheImpl<K>,
+    cache: &mut impl KeyCacheImpl<K>,
     data_buffer: &mut [u8],
-    source_page: usize,
-    target_page: usize,
+    key: &K,
+    item: &V,
 ) -> Result<(), Error<S::Error>> {
-    // We need to move the data from the next buffer page to the next_page_to_use, but only if that data
-    // doesn't have a newer value somewhere else.
+    run_with_auto_repair!(
+        function = store_item_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            [(key, item as &dyn Value<'d>)].as_slice()
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// Store bytes that are already serialized as the value of the key, e.g. an encoded protobuf message.
+/// This is the same as calling [store_item] with a `&[u8]` value, without having to pick the value type.
+///
+/// The data buffer must be long enough to hold the serialized [Key] and the bytes combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn store_raw<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    value: &[u8],
+) -> Result<(), Error<S::Error>> {
+    store_item(flash, flash_range, cache, data_buffer, key, &value).await
+}
+
+/// Fetch the bytes of the last stored value of the key without deserializing them.
+/// The bytes are borrowed from the data buffer. If no value with the key is found, None is returned.
+///
+/// This works for values of any [Value] type, not only for values stored with [store_raw].
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn fetch_raw<'d, K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &'d mut [u8],
+    search_key: &K,
+) -> Result<Option<&'d [u8]>, Error<S::Error>> {
+    fetch_item(flash, flash_range, cache, data_buffer, search_key).await
+}
+
+/// Store all items. If there's more than one, they're written as a group that's only visible once it's committed.
+async fn store_item_inner<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    items: &(impl ItemsToStore<K> + ?Sized),
+) -> Result<(), Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    for index in 0..items.count() {
+        if items.key(index)?.is_reserved() {
+            return Err(Error::ReservedKey);
+        }
+    }
+
+    cache.recover_if_dirty();
+
+    if items.count() == 0 {
+        cache.unmark_dirty();
+        return Ok(());
+    }
+
+    let is_group = items.count() > 1;
+
+    let mut recursion_level = 0;
+    loop {
+        // Check if we're in an infinite recursion which happens when we don't have enough space to store the new data
+        if recursion_level == get_pages::<S>(flash_range.clone(), 0).count() {
+            cache.unmark_dirty();
+            return Err(Error::FullStorage);
+        }
+
+        // If there is a partial open page, we try to write in that first if there is enough space
+        let next_page_to_use = if let Some(partial_open_page) =
+            find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?
+        {
+            // We found a partial open page, but at this point it's relatively cheap to do a consistency check
+            if !get_page_state(
+                flash,
+                flash_range.clone(),
+                cache,
+                next_page::<S>(flash_range.clone(), partial_open_page),
+            )
+            .await?
+            .is_open()
+            {
+                // Oh oh, the next page which serves as the buffer page is not open. We're corrupt.
+                // This likely happened because of an unexpected shutdown during data migration from the
+                // then new buffer page to the new partial open page.
+                // The repair function should be able to repair this.
+                return Err(Error::Corrupted {
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                });
+            }
+
+            // We've got to search where the free space is since the page starts with items present already
+
+            let page_data_start_address =
+                calculate_page_address::<S>(flash_range.clone(), partial_open_page)
+                    + S::WORD_SIZE as u32;
+            let page_data_end_address =
+                calculate_page_end_address::<S>(flash_range.clone(), partial_open_page)
+                    - S::WORD_SIZE as u32;
+
+            // A group is written as one big item. Its data is made up of all the items including their headers.
+            // A single item stays serialized in the data buffer, so it doesn't have to be serialized again to write it.
+            let mut total_length = 0usize;
+            for index in 0..items.count() {
+                let item_data_length = items.serialize_item(index, data_buffer)?;
+
+                if item_data_length > max_item_size::<S>() {
+                    cache.unmark_dirty();
+                    return Err(Error::ItemTooBig);
+                }
+
+                total_length = if is_group {
+                    total_length
+                        .saturating_add(ItemHeader::data_address::<S>(0) as usize)
+                        .saturating_add(round_up_to_alignment_usize::<S>(item_data_length))
+                } else {
+                    item_data_length
+                };
+            }
+
+            // The whole group must fit in a single page.
+            // Its data is the commit marker and all the items including their headers.
+            let group_length = total_length;
+            if is_group {
+                total_length =
+                    total_length.saturating_add(ItemHeader::data_address::<S>(0) as usize);
+            }
+            if total_length > max_item_size::<S>() {
+                cache.unmark_dirty();
+                return Err(Error::ItemTooBig);
+            }
+
+            let free_spot_address = find_next_free_item_spot(
+                flash,
+                flash_range.clone(),
+                cache,
+                page_data_start_address,
+                page_data_end_address,
+                total_length as u32,
+            )
+            .await?;
+
+            match free_spot_address {
+                Some(free_spot_address) => {
+                    // None of the items of a group can be found until the group is committed
+                    let mut next_address = if is_group {
+                        ItemHeader::write_group(
+                            flash,
+                            flash_range.clone(),
+                            cache,
+                            free_spot_address,
+                            group_length,
+                        )
+                        .await?;
+                        ItemHeader::group_items_address::<S>(free_spot_address)
+                    } else {
+                        free_spot_address
+                    };
+
+                    for index in 0..items.count() {
+                        let item_data_length = if is_group {
+                            items.serialize_item(index, data_buffer)?
+                        } else {
+                            total_length
+                        };
+
+                        let header = if items.is_removal(index) {
+                            cache.notice_key_erased(&items.key(index)?);
+                            Item::write_new_tombstone(
+                                flash,
+                                flash_range.clone(),
+                                cache,
+                                next_address,
+                                &data_buffer[..item_data_length],
+                            )
+                            .await?
+                        } else {
+                            cache.notice_key_location(&items.key(index)?, next_address, true);
+                            Item::write_new(
+                                flash,
+                                flash_range.clone(),
+                                cache,
+                                next_address,
+                                &data_buffer[..item_data_length],
+                            )
+                            .await?
+                        };
+                        next_address = header.next_item_address::<S>(next_address);
+                    }
+
+                    if is_group {
+                        ItemHeader::write_commit_marker(
+                            flash,
+                            flash_range.clone(),
+                            cache,
+                            free_spot_address,
+                        )
+                        .await?;
+                    }
+
+                    cache.unmark_dirty();
+                    return Ok(());
+                }
+                None => {
+                    // The item doesn't fit here, so we need to close this page and move to the next
+                    close_page(flash, flash_range.clone(), cache, partial_open_page).await?;
+                    Some(next_page::<S>(flash_range.clone(), partial_open_page))
+                }
+            }
+        } else {
+            None
+        };
+
+        // If we get here, there was no partial page found or the partial page has now been closed because the item didn't fit.
+        // If there was a partial page, then we need to look at the next page. It's supposed to be open since it was the previous empty buffer page.
+        // The new buffer page has to be emptied if it was closed.
+        // If there was no partial page, we just use the first open page.
+
+        match next_page_to_use {
+            Some(next_page_to_use) => {
+                let next_page_state =
+                    get_page_state(flash, flash_range.clone(), cache, next_page_to_use).await?;
+
+                if !next_page_state.is_open() {
+                    // What was the previous buffer page was not open...
+                    return Err(Error::Corrupted {
+                        #[cfg(feature = "_test")]
+                        backtrace: std::backtrace::Backtrace::capture(),
+                    });
+                }
+
+                // Since we're gonna write data here, let's already partially close the page
+                // This could be done after moving the data, but this is more robust in the
+                // face of shutdowns and cancellations
+                partial_close_page(flash, flash_range.clone(), cache, next_page_to_use).await?;
+
+                let next_buffer_page = next_page::<S>(flash_range.clone(), next_page_to_use);
+                let next_buffer_page_state =
+                    get_page_state(flash, flash_range.clone(), cache, next_buffer_page).await?;
+
+                if !next_buffer_page_state.is_open() {
+                    migrate_items::<K, _>(
+                        flash,
+                        flash_range.clone(),
+                        cache,
+                        data_buffer,
+                        next_buffer_page,
+                        next_page_to_use,
+                    )
+                    .await?;
+                }
+            }
+            None => {
+                // There's no partial open page, so we just gotta turn the first open page into a partial open one
+                let first_open_page =
+                    match find_first_page(flash, flash_range.clone(), cache, 0, PageState::Open)
+                        .await?
+                    {
+                        Some(first_open_page) => first_open_page,
+                        None => {
+                            // Uh oh, no open pages.
+                            // Something has gone wrong.
+                            // We should never get here.
+                            return Err(Error::Corrupted {
+                                #[cfg(feature = "_test")]
+                                backtrace: std::backtrace::Backtrace::capture(),
+                            });
+                        }
+                    };
+
+                partial_close_page(flash, flash_range.clone(), cache, first_open_page).await?;
+            }
+        }
+
+        // If we get here, we just freshly partially closed a new page, so the next loop iteration should succeed.
+        recursion_level += 1;
+    }
+}
+
+/// The items that are stored together by [store_item_inner]
+trait ItemsToStore<K: Key> {
+    /// The amount of items
+    fn count(&self) -> usize;
+    /// The key of the item with the given index
+    fn key(&self, index: usize) -> Result<K, SerializationError>;
+    /// Serialize the key and the value of the item with the given index behind each other into the buffer.
+    /// The total serialized length is returned.
+    fn serialize_item(&self, index: usize, buffer: &mut [u8]) -> Result<usize, SerializationError>;
+    /// True if the item with the given index removes its key. It's then serialized as only the key
+    /// and written as a tombstone.
+    fn is_removal(&self, _index: usize) -> bool {
+        false
+    }
+}
+
+impl<'d, K: Key> ItemsToStore<K> for [(&K, &dyn Value<'d>)] {
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    fn key(&self, index: usize) -> Result<K, SerializationError> {
+        Ok(self[index].0.clone())
+    }
+
+    fn serialize_item(&self, index: usize, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let (key, item) = self[index];
+        let key_len = key.serialize_into(buffer)?;
+        Ok(key_len + item.serialize_into(&mut buffer[key_len..])?)
+    }
+}
+
+/// Store multiple key-value pairs into flash memory atomically.
+/// Even if storing is interrupted by e.g. a power loss, either all new values or all old values can be fetched later.
+///
+/// All items must fit on a single page together with their headers and one extra header.
+/// If they don't, [Error::ItemTooBig] is returned.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn store_items<'d, K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    items: &[(&K, &dyn Value<'d>)],
+) -> Result<(), Error<S::Error>> {
+    run_with_auto_repair!(
+        function = store_item_inner(flash, flash_range.clone(), cache, data_buffer, items).await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// A set of items that are staged in RAM and then stored together in one go with [MapTransaction::commit].
+/// Just like with [store_items], either all new values or all old values can be fetched later, even if the commit is
+/// interrupted by e.g. a power loss.
+///
+/// Dropping the transaction without committing it throws away the staged items and leaves the flash untouched.
+///
+/// The items are serialized when they're staged into the staging buffer, which is why it must be long enough to fit
+/// all staged items with three extra bytes per item.
+/// All items must fit on a single page together with their headers and one extra header.
+///
+/// Removals can be staged too with [MapTransaction::remove]. Unlike [remove_item], they don't erase the existing
+/// items in place. Instead a tombstone item with the key is stored with the rest of the transaction, so this also
+/// works on flash that doesn't implement [MultiwriteNorFlash].
+/// The old values stay in flash until their page is erased, but can't be fetched anymore.
+pub struct MapTransaction<'b, K: Key> {
+    staging_buffer: &'b mut [u8],
+    staged_bytes: usize,
+    staged_items: usize,
+    _key: PhantomData<K>,
+}
+
+/// The bytes in front of every staged item: the length as a little endian u16 and whether it's a removal
+const STAGED_ITEM_HEADER_SIZE: usize = 3;
+
+impl<'b, K: Key> MapTransaction<'b, K> {
+    /// Begin a new transaction with no items staged yet
+    pub fn new(staging_buffer: &'b mut [u8]) -> Self {
+        Self {
+            staging_buffer,
+            staged_bytes: 0,
+            staged_items: 0,
+            _key: PhantomData,
+        }
+    }
+
+    /// Stage the key-value pair to be stored when the transaction is committed.
+    ///
+    /// If the staging buffer is too small, [SerializationError::BufferTooSmall] is returned and nothing is staged.
+    pub fn store<'d, V: Value<'d>>(&mut self, key: &K, item: &V) -> Result<(), SerializationError> {
+        self.stage(false, |buffer| {
+            let key_len = key.serialize_into(buffer)?;
+            Ok(key_len + item.serialize_into(&mut buffer[key_len..])?)
+        })
+    }
+
+    /// Stage the removal of the key when the transaction is committed.
+    /// After the commit, the key can't be fetched anymore, just like after [remove_item].
+    ///
+    /// If the staging buffer is too small, [SerializationError::BufferTooSmall] is returned and nothing is staged.
+    pub fn remove(&mut self, key: &K) -> Result<(), SerializationError> {
+        self.stage(true, |buffer| key.serialize_into(buffer))
+    }
+
+    fn stage(
+        &mut self,
+        is_removal: bool,
+        serialize: impl FnOnce(&mut [u8]) -> Result<usize, SerializationError>,
+    ) -> Result<(), SerializationError> {
+        let buffer = self
+            .staging_buffer
+            .get_mut(self.staged_bytes + STAGED_ITEM_HEADER_SIZE..)
+            .ok_or(SerializationError::BufferTooSmall)?;
+
+        let item_data_length = serialize(buffer)?;
+        let item_data_length =
+            u16::try_from(item_data_length).map_err(|_| SerializationError::InvalidData)?;
+
+        let header = &mut self.staging_buffer[self.staged_bytes..][..STAGED_ITEM_HEADER_SIZE];
+        header[..2].copy_from_slice(&item_data_length.to_le_bytes());
+        header[2] = is_removal as u8;
+        self.staged_bytes += STAGED_ITEM_HEADER_SIZE + item_data_length as usize;
+        self.staged_items += 1;
+
+        Ok(())
+    }
+
+    /// The amount of staged items, including the removals
+    pub fn len(&self) -> usize {
+        self.staged_items
+    }
+
+    /// Returns true if no items are staged
+    pub fn is_empty(&self) -> bool {
+        self.staged_items == 0
+    }
+
+    /// The serialized key and value of the staged item with the given index and whether it's a removal
+    fn staged_item(&self, index: usize) -> (&[u8], bool) {
+        let item_data_length = |staged: &[u8]| u16::from_le_bytes([staged[0], staged[1]]) as usize;
+
+        let mut staged = &self.staging_buffer[..self.staged_bytes];
+        for _ in 0..index {
+            staged = &staged[STAGED_ITEM_HEADER_SIZE + item_data_length(staged)..];
+        }
+
+        (
+            &staged[STAGED_ITEM_HEADER_SIZE..][..item_data_length(staged)],
+            staged[2] != 0,
+        )
+    }
+
+    /// Store all staged items and removals into flash memory atomically.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn commit<S: NorFlash>(
+        self,
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl KeyCacheImpl<K>,
+        data_buffer: &mut [u8],
+    ) -> Result<(), Error<S::Error>> {
+        run_with_auto_repair!(
+            function =
+                store_item_inner(flash, flash_range.clone(), cache, data_buffer, &self).await,
+            repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+        )
+    }
+}
+
+impl<'b, K: Key> Debug for MapTransaction<'b, K> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MapTransaction")
+            .field("staged_bytes", &self.staged_bytes)
+            .field("staged_items", &self.staged_items)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'b, K: Key> ItemsToStore<K> for MapTransaction<'b, K> {
+    fn count(&self) -> usize {
+        self.staged_items
+    }
+
+    fn key(&self, index: usize) -> Result<K, SerializationError> {
+        Ok(K::deserialize_from(self.staged_item(index).0)?.0)
+    }
+
+    fn serialize_item(&self, index: usize, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let (item_data, _) = self.staged_item(index);
+
+        buffer
+            .get_mut(..item_data.len())
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(item_data);
+
+        Ok(item_data.len())
+    }
+
+    fn is_removal(&self, index: usize) -> bool {
+        self.staged_item(index).1
+    }
+}
+
+/// Store a key-value pair into flash memory like [store_item] and return the value it replaced.
+/// If the key had no value yet, None is returned.
+///
+/// The previous value is read into the previous buffer, which must be long enough to hold the longest serialized
+/// data of your [Key] + [Value] types combined, rounded up to flash word alignment. The same goes for the data buffer.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn replace_item<'d, 'p, K: Key, V: Value<'d>, P: Value<'p>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    previous_buffer: &'p mut [u8],
+    key: &K,
+    item: &V,
+) -> Result<Option<P>, Error<S::Error>> {
+    let result = run_with_auto_repair!(
+        function = replace_item_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            previous_buffer,
+            key,
+            item
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    );
+
+    let Some((item_key_len, data_len)) = result? else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        P::deserialize_from(&previous_buffer[item_key_len..][..data_len - item_key_len])
+            .map_err(Error::SerializationError)?,
+    ))
+}
+
+/// Returns the key length and the data length of the previous item if there was one.
+/// The data of that item is then in the previous buffer.
+async fn replace_item_inner<'d, K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    previous_buffer: &mut [u8],
+    key: &K,
+    item: &dyn Value<'d>,
+) -> Result<Option<(usize, usize)>, Error<S::Error>> {
+    let previous =
+        match fetch_item_with_location(flash, flash_range.clone(), cache, previous_buffer, key)
+            .await?
+        {
+            Some((previous_item, _, previous_key_len)) => {
+                let data_len = previous_item.header.length as usize;
+                let previous_key_len = match previous_key_len {
+                    Some(previous_key_len) => previous_key_len,
+                    None => K::get_len(&previous_buffer[..data_len])?,
+                };
+                Some((previous_key_len, data_len))
+            }
+            None => None,
+        };
+
+    store_item_inner(
+        flash,
+        flash_range,
+        cache,
+        data_buffer,
+        [(key, item)].as_slice(),
+    )
+    .await?;
+
+    Ok(previous)
+}
+
+/// Store a key-value pair into flash memory like [store_item], unless the last stored value of the key
+/// is byte-identical to the new value.
+/// This saves flash wear when the same value is often stored again, e.g. on every boot.
+///
+/// Returns true if the item was stored and false if it was the same as the stored value.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn store_item_if_changed<'d, K: Key, V: Value<'d>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    item: &V,
+) -> Result<bool, Error<S::Error>> {
+    run_with_auto_repair!(
+        function =
+            store_item_if_changed_inner(flash, flash_range.clone(), cache, data_buffer, key, item)
+                .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+async fn store_item_if_changed_inner<'d, K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    item: &dyn Value<'d>,
+) -> Result<bool, Error<S::Error>> {
+    if let Some((stored_item, stored_address, _)) =
+        fetch_item_with_location(flash, flash_range.clone(), cache, data_buffer, key).await?
+    {
+        // The stored item got read into the buffer, so we compare against what's in flash
+        let key_len = key.serialize_into(data_buffer)?;
+        let item_data_length = key_len
+            + item
+                .serialize_into(&mut data_buffer[key_len..])
+                .map_err(Error::SerializationError)?;
+
+        if stored_item
+            .header
+            .data_equals(flash, stored_address, &data_buffer[..item_data_length])
+            .await?
+        {
+            return Ok(false);
+        }
+    }
+
+    store_item_inner(
+        flash,
+        flash_range,
+        cache,
+        data_buffer,
+        [(key, item)].as_slice(),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Store a new value for the key, but only if the last stored value is byte-identical to the expected value.
+/// An expected value of None means that the key must not have a value yet.
+///
+/// This makes it possible to safely update a value that is read and written from multiple places.
+///
+/// If the new value was stored, `Ok(())` is returned.
+/// Otherwise the current value is returned as the error, which can be None if the key has no value.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn compare_and_swap<'d, K: Key, V: Value<'d>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &'d mut [u8],
+    key: &K,
+    expected: Option<&V>,
+    new: &V,
+) -> Result<Result<(), Option<V>>, Error<S::Error>> {
+    let result = run_with_auto_repair!(
+        function = compare_and_swap_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            key,
+            expected.map(|expected| expected as &dyn Value<'d>),
+            new
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    );
+
+    match result? {
+        Ok(()) => Ok(Ok(())),
+        Err(None) => Ok(Err(None)),
+        Err(Some((item, item_key_len))) => {
+            let data_len = item.header.length as usize;
+            Ok(Err(Some(
+                V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
+                    .map_err(Error::SerializationError)?,
+            )))
+        }
+    }
+}
+
+/// Returns the current item and its key length if the swap didn't happen.
+/// The data of the current item is then in the data buffer.
+#[allow(clippy::type_complexity)]
+async fn compare_and_swap_inner<'d, K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    expected: Option<&dyn Value<'d>>,
+    new: &dyn Value<'d>,
+) -> Result<Result<(), Option<(ItemUnborrowed, usize)>>, Error<S::Error>> {
+    let stored =
+        fetch_item_with_location(flash, flash_range.clone(), cache, data_buffer, key).await?;
+
+    match (stored, expected) {
+        (None, None) => {}
+        (None, Some(_)) => return Ok(Err(None)),
+        (Some((stored_item, _, stored_key_len)), None) => {
+            let data_len = stored_item.header.length as usize;
+            let stored_key_len = match stored_key_len {
+                Some(stored_key_len) => stored_key_len,
+                None => K::get_len(&data_buffer[..data_len])?,
+            };
+            return Ok(Err(Some((stored_item, stored_key_len))));
+        }
+        (Some((stored_item, stored_address, _)), Some(expected)) => {
+            let key_len = key.serialize_into(data_buffer)?;
+            let item_data_length = key_len
+                + expected
+                    .serialize_into(&mut data_buffer[key_len..])
+                    .map_err(Error::SerializationError)?;
+
+            if !stored_item
+                .header
+                .data_equals(flash, stored_address, &data_buffer[..item_data_length])
+                .await?
+            {
+                // The buffer got overwritten, so we read the stored item again
+                let stored_item = stored_item
+                    .header
+                    .read_item(flash, data_buffer, stored_address, u32::MAX)
+                    .await?
+                    .unwrap()?;
+                let stored_key_len = K::get_len(stored_item.data())?;
+                return Ok(Err(Some((stored_item.unborrow(), stored_key_len))));
+            }
+        }
+    }
+
+    store_item_inner(
+        flash,
+        flash_range,
+        cache,
+        data_buffer,
+        [(key, new)].as_slice(),
+    )
+    .await?;
+
+    Ok(Ok(()))
+}
+
+/// Fetch the value of the key, give it to the closure and store what the closure returns.
+/// The closure gets None if the key has no value. If it returns None, nothing is stored.
+///
+/// Returns true if a new value was stored.
+///
+/// The value is deserialized from the data buffer, so only one buffer is needed.
+/// Because storing an item may need the data buffer to move other items, the value can't borrow from the buffer.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn update_item<K: Key, V: for<'a> Value<'a>, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    f: impl FnOnce(Option<V>) -> Option<V>,
+) -> Result<bool, Error<S::Error>> {
+    let current =
+        fetch_item::<K, V, S>(flash, flash_range.clone(), cache, data_buffer, key).await?;
+
+    let Some(new) = f(current) else {
+        return Ok(false);
+    };
+
+    store_item(flash, flash_range, cache, data_buffer, key, &new).await?;
+
+    Ok(true)
+}
+
+/// Add the delta to the counter of the key and return the new count.
+/// A counter that isn't stored yet starts at 0. The count saturates at the bounds of its integer type.
+///
+/// This is safe against power loss: every count is stored as a new item that is only valid once it's
+/// fully written, so after a loss either the old or the new count is fetched.
+/// When the count doesn't change, like for a delta of 0, nothing is written to save wear.
+/// Counters that are updated often can be cheaply found again with a key cache like [crate::cache::KeyPointerCache].
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. The counter must always be incremented with the same integer type.
+///
+/// </div>
+pub async fn increment<K: Key, V: Counter, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    key: &K,
+    delta: V,
+) -> Result<V, Error<S::Error>> {
+    let current = fetch_item::<K, V, S>(flash, flash_range.clone(), cache, data_buffer, key)
+        .await?
+        .unwrap_or(V::ZERO);
+
+    let new = current.add_delta(delta);
+    if new != current {
+        store_item(flash, flash_range, cache, data_buffer, key, &new).await?;
+    }
+
+    Ok(new)
+}
+
+/// Fully remove an item. Additional calls to fetch with the same key will return None until
+/// a new one is stored again.
+///
+/// All items with the key are marked as erased, so no placeholder value has to be stored.
+/// Erased items are not copied over when their page is erased, so that's when their space is reclaimed.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read and deserialized to find the items with the key.
+/// This is unlikely to be cached well.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// Also watch out for using integers. This function will take any integer and it's easy to pass the wrong type.
+///
+/// </div>
+pub async fn remove_item<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    search_key: &K,
+) -> Result<(), Error<S::Error>> {
+    run_with_auto_repair!(
+        function = remove_item_inner::<K, _>(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            RemoveItems::Key(search_key)
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// Fully remove all stored items. Additional calls to fetch with any key will return None until
+/// new items are stored again.
+///
+/// <div class="warning">
+/// This might be really slow!
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn remove_all_items<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<(), Error<S::Error>> {
+    run_with_auto_repair!(
+        function = remove_item_inner::<K, _>(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            RemoveItems::AllExcept(&[])
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// Fully remove all stored items, except for the items with one of the given keys.
+/// Additional calls to fetch with any other key will return None until new items are stored again.
+///
+/// <div class="warning">
+/// This might be really slow!
+///
+/// All items in flash have to be read and deserialized to find the items with the keys to keep.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn remove_all_items_except<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    keep_keys: &[K],
+) -> Result<(), Error<S::Error>> {
+    run_with_auto_repair!(
+        function = remove_item_inner::<K, _>(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            RemoveItems::AllExcept(keep_keys)
+        )
+        .await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// Which items are removed by [remove_item_inner]
+enum RemoveItems<'a, K> {
+    /// All items with the given key
+    Key(&'a K),
+    /// All items, except the ones with one of the given keys
+    AllExcept(&'a [K]),
+    /// All items with a key for which the function returns true
+    Matching(&'a dyn Fn(&K) -> bool),
+}
+
+async fn remove_item_inner<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    remove: RemoveItems<'_, K>,
+) -> Result<(), Error<S::Error>> {
+    match remove {
+        RemoveItems::Key(key) if key.is_reserved() => return Err(Error::ReservedKey),
+        RemoveItems::Key(key) => cache.notice_key_erased(key),
+        _ => cache.invalidate_cache_state(),
+    }
+
+    // Search for the last used page. We're gonna erase from the one after this first.
+    // If we get an early shutoff or cancellation, this will make it so that we don't return
+    // an old version of the key on the next fetch.
+    let last_used_page =
+        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen)
+            .await?
+            .unwrap_or_default();
+
+    // Go through all the pages
+    for page_index in get_pages::<S>(
+        flash_range.clone(),
+        next_page::<S>(flash_range.clone(), last_used_page),
+    ) {
+        if get_page_state(flash, flash_range.clone(), cache, page_index)
+            .await?
+            .is_open()
+        {
+            // This page is open, we don't have to check it
+            continue;
+        }
+
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page_index) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page_index) - S::WORD_SIZE as u32;
+
+        // Go through all items on the page
+        let mut item_headers = ItemHeaderIter::new(page_data_start_address, page_data_end_address);
+
+        while let (Some(item_header), item_address) = item_headers.next(flash).await? {
+            let item = item_header
+                .read_item(flash, data_buffer, item_address, page_data_end_address)
+                .await?;
+
+            match item {
+                item::MaybeItem::Corrupted(_, _) => continue,
+                item::MaybeItem::Erased(_, _) => continue,
+                item::MaybeItem::Tombstone(_) => continue,
+                item::MaybeItem::Present(item) => {
+                    let item_match = match remove {
+                        RemoveItems::Key(search_key) => {
+                            search_key.matches_serialized(item.data())?
+                        }
+                        RemoveItems::AllExcept([]) => true,
+                        RemoveItems::AllExcept(keep_keys) => {
+                            !keep_keys.contains(&K::deserialize_from(item.data())?.0)
+                        }
+                        RemoveItems::Matching(matches) => {
+                            matches(&K::deserialize_from(item.data())?.0)
+                        }
+                    };
+                    // If this item has the same key as the key we're trying to erase, then erase the item.
+                    // But keep going! We need to erase everything.
+                    if item_match {
+                        item.header
+                            .erase_data(flash, flash_range.clone(), cache, item_address)
+                            .await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // We're done, we now know the cache is in a good state
+    cache.unmark_dirty();
+
+    Ok(())
+}
+
+/// Get an iterator-like interface over all keys in the map with their newest value.
+/// Keys that have been removed are not given back. The keys are given back in no particular order.
+///
+/// Use [MapIterator::next] to get the keys with their values or [MapIterator::next_key] to only get the keys.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// For every item in flash, the newest value for its key has to be searched, just like [fetch_item] does.
+/// Using a key cache helps a lot here.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn iter<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+) -> Result<MapIterator<'s, K, S, CI>, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    let page_count = get_pages::<S>(flash_range.clone(), 0).count();
+
+    Ok(MapIterator {
+        flash,
+        flash_range,
+        cache,
+        next_page: 0,
+        pages_left: page_count,
+        items: None,
+        _key: PhantomData,
+    })
+}
+
+/// An iterator-like interface over the keys and values in the map. See [iter].
+pub struct MapIterator<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>> {
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+    next_page: usize,
+    pages_left: usize,
+    items: Option<ItemIter>,
+    _key: PhantomData<K>,
+}
+
+impl<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>> Debug for MapIterator<'s, K, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MapIterator")
+            .field("next_page", &self.next_page)
+            .field("pages_left", &self.pages_left)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>> MapIterator<'s, K, S, CI> {
+    /// Get the next key with its newest value.
+    ///
+    /// If there are no more keys, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn next<'d, V: Value<'d>>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<(K, V)>, Error<S::Error>> {
+        let result = run_with_auto_repair!(
+            function = self.next_inner(data_buffer, &|_| true).await,
+            repair = try_repair::<K, _>(
+                self.flash,
+                self.flash_range.clone(),
+                self.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        let Some((key, item_key_len, data_len)) = result? else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            key,
+            V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
+                .map_err(Error::SerializationError)?,
+        )))
+    }
+
+    /// Get the next key without deserializing its value.
+    ///
+    /// If there are no more keys, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn next_key(&mut self, data_buffer: &mut [u8]) -> Result<Option<K>, Error<S::Error>> {
+        let result = run_with_auto_repair!(
+            function = self.next_inner(data_buffer, &|_| true).await,
+            repair = try_repair::<K, _>(
+                self.flash,
+                self.flash_range.clone(),
+                self.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        Ok(result?.map(|(key, _, _)| key))
+    }
+
+    /// Find the next item that holds the newest value of its key and for which the key filter returns true.
+    /// The item is read into the data buffer and its key, key length and data length are returned.
+    async fn next_inner(
+        &mut self,
+        data_buffer: &mut [u8],
+        key_filter: &impl Fn(&K) -> bool,
+    ) -> Result<Option<(K, usize, usize)>, Error<S::Error>> {
+        self.cache.recover_if_dirty();
+
+        loop {
+            let Some(items) = self.items.as_mut() else {
+                if self.pages_left == 0 {
+                    self.cache.unmark_dirty();
+                    return Ok(None);
+                }
+
+                let page = self.next_page;
+                self.next_page = next_page::<S>(self.flash_range.clone(), page);
+                self.pages_left -= 1;
+
+                if get_page_state(self.flash, self.flash_range.clone(), self.cache, page)
+                    .await?
+                    .is_open()
+                {
+                    continue;
+                }
+
+                self.items = Some(ItemIter::new(
+                    calculate_page_address::<S>(self.flash_range.clone(), page)
+                        + S::WORD_SIZE as u32,
+                    calculate_page_end_address::<S>(self.flash_range.clone(), page)
+                        - S::WORD_SIZE as u32,
+                ));
+                continue;
+            };
+
+            let Some((item, address)) = items.next(self.flash, data_buffer).await? else {
+                self.items = None;
+                continue;
+            };
+
+            let (key, _) = K::deserialize_from(item.data())?;
+
+            if !key_filter(&key) {
+                continue;
+            }
+
+            // Only the newest item of a key has the value we're looking for
+            let newest_item = fetch_item_with_location(
+                self.flash,
+                self.flash_range.clone(),
+                self.cache,
+                data_buffer,
+                &key,
+            )
+            .await?;
+
+            match newest_item {
+                Some((item, newest_address, item_key_len)) if newest_address == address => {
+                    let data_len = item.header.length as usize;
+                    let item_key_len = match item_key_len {
+                        Some(item_key_len) => item_key_len,
+                        None => K::get_len(&data_buffer[..data_len])?,
+                    };
+
+                    self.cache.unmark_dirty();
+                    return Ok(Some((key, item_key_len, data_len)));
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Iterate over the keys in the given range with their newest value, like [iter] does for all keys.
+/// This is useful when keys are made up of e.g. a channel number, so related keys are next to each other.
+///
+/// The items are returned in the order in which they're found in flash, not in the order of their keys.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read, but only for the items in the range the newest value is searched.
+/// Using a key cache helps a lot here.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn iter_range<'s, K: Key + PartialOrd, S: NorFlash, CI: KeyCacheImpl<K>>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+    key_range: impl RangeBounds<K>,
+) -> Result<MapRangeIterator<'s, K, S, CI>, Error<S::Error>> {
+    Ok(MapRangeIterator {
+        iter: iter(flash, flash_range, cache).await?,
+        key_range: (
+            key_range.start_bound().cloned(),
+            key_range.end_bound().cloned(),
+        ),
+    })
+}
+
+/// An iterator-like interface over the keys in a range and their values in the map. See [iter_range].
+pub struct MapRangeIterator<'s, K: Key + PartialOrd, S: NorFlash, CI: KeyCacheImpl<K>> {
+    iter: MapIterator<'s, K, S, CI>,
+    key_range: (Bound<K>, Bound<K>),
+}
+
+impl<'s, K: Key + PartialOrd, S: NorFlash, CI: KeyCacheImpl<K>> Debug
+    for MapRangeIterator<'s, K, S, CI>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MapRangeIterator")
+            .field("iter", &self.iter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s, K: Key + PartialOrd, S: NorFlash, CI: KeyCacheImpl<K>> MapRangeIterator<'s, K, S, CI> {
+    /// Get the next key in the range with its newest value.
+    ///
+    /// If there are no more keys, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn next<'d, V: Value<'d>>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<(K, V)>, Error<S::Error>> {
+        let key_filter = |key: &K| self.key_range.contains(key);
+        let result = run_with_auto_repair!(
+            function = self.iter.next_inner(data_buffer, &key_filter).await,
+            repair = try_repair::<K, _>(
+                self.iter.flash,
+                self.iter.flash_range.clone(),
+                self.iter.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        let Some((key, item_key_len, data_len)) = result? else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            key,
+            V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
+                .map_err(Error::SerializationError)?,
+        )))
+    }
+
+    /// Get the next key in the range without deserializing its value.
+    ///
+    /// If there are no more keys, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn next_key(&mut self, data_buffer: &mut [u8]) -> Result<Option<K>, Error<S::Error>> {
+        let key_filter = |key: &K| self.key_range.contains(key);
+        let result = run_with_auto_repair!(
+            function = self.iter.next_inner(data_buffer, &key_filter).await,
+            repair = try_repair::<K, _>(
+                self.iter.flash,
+                self.iter.flash_range.clone(),
+                self.iter.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        Ok(result?.map(|(key, _, _)| key))
+    }
+}
+
+/// Iterate over the keys for which the filter returns true with their newest value, like [iter] does for all keys.
+///
+/// The filter is called with the key of every item in flash. Only for the keys that pass it,
+/// the newest value is searched. That saves a lot of reads when only a few keys are of interest.
+///
+/// The items are returned in the order in which they're found in flash.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read, but only for the keys that pass the filter the newest value is searched.
+/// Using a key cache helps a lot here.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn iter_filtered<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>, F: Fn(&K) -> bool>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+    key_filter: F,
+) -> Result<MapFilteredIterator<'s, K, S, CI, F>, Error<S::Error>> {
+    Ok(MapFilteredIterator {
+        iter: iter(flash, flash_range, cache).await?,
+        key_filter,
+    })
+}
+
+/// An iterator-like interface over the keys that pass a filter and their values in the map. See [iter_filtered].
+pub struct MapFilteredIterator<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>, F: Fn(&K) -> bool> {
+    iter: MapIterator<'s, K, S, CI>,
+    key_filter: F,
+}
+
+impl<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>, F: Fn(&K) -> bool> Debug
+    for MapFilteredIterator<'s, K, S, CI, F>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MapFilteredIterator")
+            .field("iter", &self.iter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s, K: Key, S: NorFlash, CI: KeyCacheImpl<K>, F: Fn(&K) -> bool>
+    MapFilteredIterator<'s, K, S, CI, F>
+{
+    /// Get the next key that passes the filter with its newest value.
+    ///
+    /// If there are no more keys, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn next<'d, V: Value<'d>>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<(K, V)>, Error<S::Error>> {
+        let result = run_with_auto_repair!(
+            function = self.iter.next_inner(data_buffer, &self.key_filter).await,
+            repair = try_repair::<K, _>(
+                self.iter.flash,
+                self.iter.flash_range.clone(),
+                self.iter.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        let Some((key, item_key_len, data_len)) = result? else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            key,
+            V::deserialize_from(&data_buffer[item_key_len..][..data_len - item_key_len])
+                .map_err(Error::SerializationError)?,
+        )))
+    }
+
+    /// Get the next key that passes the filter without deserializing its value.
+    ///
+    /// If there are no more keys, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn next_key(&mut self, data_buffer: &mut [u8]) -> Result<Option<K>, Error<S::Error>> {
+        let result = run_with_auto_repair!(
+            function = self.iter.next_inner(data_buffer, &self.key_filter).await,
+            repair = try_repair::<K, _>(
+                self.iter.flash,
+                self.iter.flash_range.clone(),
+                self.iter.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        Ok(result?.map(|(key, _, _)| key))
+    }
+}
+
+/// Find all keys with the given tag. See [TaggedKey].
+///
+/// The matching keys are written into the keys buffer and the total amount of matching keys is returned.
+/// If the buffer is too short, the keys that don't fit are counted, but not given back.
+/// The keys are given back in no particular order.
+///
+/// This is the same as using [iter_filtered] with a filter on the tag, so it's not an index:
+/// every item in flash is read to get its key. Only for the keys with the tag the newest value is searched.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read, like [iter] does.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn fetch_by_tag<K: TaggedKey, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    tag: &K::Tag,
+    keys: &mut [K],
+) -> Result<usize, Error<S::Error>> {
+    let mut iterator =
+        iter_filtered(flash, flash_range, cache, |key: &K| key.tag() == *tag).await?;
+
+    let mut count = 0;
+    while let Some(key) = iterator.next_key(data_buffer).await? {
+        if let Some(slot) = keys.get_mut(count) {
+            *slot = key;
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Get the keys that have a value in the map in ascending order, e.g. for a deterministic export.
+///
+/// The keys are written into the keys buffer and the total amount of keys is returned.
+/// If the buffer is too short, only the smallest keys are given back, but all keys are counted.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All keys are found like [iter] does. Using a key cache helps a lot here.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn keys_sorted<K: Key + Ord, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    keys: &mut [K],
+) -> Result<usize, Error<S::Error>> {
+    let mut iterator = iter::<K, _, _>(flash, flash_range, cache).await?;
+
+    let mut count = 0;
+    while let Some(key) = iterator.next_key(data_buffer).await? {
+        // Every key is only given back once, so it's never found
+        let index = keys[..count.min(keys.len())]
+            .binary_search(&key)
+            .unwrap_or_else(|index| index);
+        if count < keys.len() {
+            keys[count] = key;
+            keys[index..=count].rotate_right(1);
+        } else if index < keys.len() {
+            // The buffer is full, so the biggest key falls off
+            keys[keys.len() - 1] = key;
+            keys[index..].rotate_right(1);
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Get the number of keys that have a value in the map.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read and for every item the newest value of its key has to be looked up,
+/// just like with [iter]. A key cache speeds up the lookups, but it can't tell whether it knows all keys,
+/// so the items are always read.
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn len<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<usize, Error<S::Error>> {
+    let mut items = iter(flash, flash_range, cache).await?;
+
+    let mut count = 0;
+    while items.next_key(data_buffer).await?.is_some() {
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Do the compaction that a store would otherwise have to do once the current page is full.
+///
+/// The live items of the oldest page are moved to a new page, after which the oldest page is erased.
+/// This way the erase can be done at a moment of your choosing, e.g. when the application is idle,
+/// instead of in the middle of a store.
+///
+/// The current page is closed to make this happen, so any free space left on it won't be used until it's erased.
+/// Because of this, calling this function on every idle moment wastes space and erases pages sooner.
+/// [stats] can be used to decide when it's worth it.
+///
+/// Nothing happens when no erase is needed yet, because there are still open pages to store items in.
+/// Returns true if a page was erased.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn compact<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<bool, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = compact_inner(flash, flash_range.clone(), cache, data_buffer).await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+async fn compact_inner<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<bool, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let Some(partial_open_page) =
+        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?
+    else {
+        // Without a partial open page, the next store starts on an open page
+        cache.unmark_dirty();
+        return Ok(false);
+    };
+
+    let next_page_to_use = next_page::<S>(flash_range.clone(), partial_open_page);
+    if !get_page_state(flash, flash_range.clone(), cache, next_page_to_use)
+        .await?
+        .is_open()
+    {
+        // The buffer page is not open. The repair function should be able to repair this.
+        return Err(Error::Corrupted {
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        });
+    }
+
+    let next_buffer_page = next_page::<S>(flash_range.clone(), next_page_to_use);
+    if get_page_state(flash, flash_range.clone(), cache, next_buffer_page)
+        .await?
+        .is_open()
+    {
+        // The next page change doesn't need an erase
+        cache.unmark_dirty();
+        return Ok(false);
+    }
+
+    // This is the same as what a store does when the item doesn't fit on the partial open page anymore
+    close_page(flash, flash_range.clone(), cache, partial_open_page).await?;
+    partial_close_page(flash, flash_range.clone(), cache, next_page_to_use).await?;
+    migrate_items::<K, _>(
+        flash,
+        flash_range.clone(),
+        cache,
+        data_buffer,
+        next_buffer_page,
+        next_page_to_use,
+    )
+    .await?;
+
+    cache.unmark_dirty();
+    Ok(true)
+}
+
+/// Statistics about the map. See [stats].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct MapStats {
+    /// The amount of keys that have a value
+    pub item_count: usize,
+    /// The total length of the serialized keys and values of all live items (so not including the overhead)
+    pub used_bytes: usize,
+    /// The space taken up by items that have been overwritten by a newer value, removed or that are corrupted,
+    /// including their overhead. This space is reclaimed when their page is erased.
+    pub stale_bytes: u32,
+    /// The space that can be written to before a page has to be erased.
+    /// One open page is always kept as buffer, so that page is not counted.
+    pub free_bytes: u32,
+    /// The amount of pages that are open and thus empty
+    pub open_pages: usize,
+    /// The amount of pages that are partially written. This is normally one page.
+    pub partial_open_pages: usize,
+    /// The amount of pages that are fully written
+    pub closed_pages: usize,
+}
+
+impl MapStats {
+    /// Get how many more items with the given serialized key + value length can be stored
+    /// before a page has to be erased. This is the same as [EraseForecast::stores_until_erase].
+    pub fn stores_until_erase<S: NorFlash>(&self, item_data_length: usize) -> usize {
+        // All free space is on the partial open page, except for the empty pages that are not the buffer page
+        let empty_pages = self.open_pages.saturating_sub(1);
+        let current_page_free_bytes = self
+            .free_bytes
+            .saturating_sub(empty_pages as u32 * calculate_page_size::<S>() as u32);
+
+        stores_until_erase::<S>(current_page_free_bytes, empty_pages, item_data_length)
+    }
+}
+
+/// Get how many items with the given serialized key + value length fit in the free space of the current page
+/// and the empty pages after it.
+fn stores_until_erase<S: NorFlash>(
+    current_page_free_bytes: u32,
+    empty_pages: usize,
+    item_data_length: usize,
+) -> usize {
+    let item_size =
+        item_overhead_size::<S>() as usize + round_up_to_alignment_usize::<S>(item_data_length);
+
+    // Items don't span pages, so the space at the end of a page that's too small for the item can't be used
+    current_page_free_bytes as usize / item_size
+        + empty_pages * (calculate_page_size::<S>() / item_size)
+}
+
+/// Get statistics about the map.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// All items in flash have to be read and for every item the newest value of its key has to be looked up,
+/// just like with [iter].
+/// </div>
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn stats<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<MapStats, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = stats_inner(flash, flash_range.clone(), cache, data_buffer).await,
+        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+async fn stats_inner<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<MapStats, Error<S::Error>> {
+    let mut stats = MapStats {
+        item_count: 0,
+        used_bytes: 0,
+        stale_bytes: 0,
+        free_bytes: 0,
+        open_pages: 0,
+        partial_open_pages: 0,
+        closed_pages: 0,
+    };
+
+    let mut live_bytes = 0;
+    let mut items = iter(flash, flash_range.clone(), cache).await?;
+    while let Some((_, _, data_len)) = items.next_inner(data_buffer, &|_| true).await? {
+        stats.item_count += 1;
+        stats.used_bytes += data_len;
+        live_bytes += item_overhead_size::<S>() + round_up_to_alignment::<S>(data_len as u32);
+    }
+
+    let mut written_bytes = 0;
+    for page in get_pages::<S>(flash_range.clone(), 0) {
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
+
+        let page_state = get_page_state(flash, flash_range.clone(), cache, page).await?;
+        match page_state {
+            PageState::Open => {
+                stats.open_pages += 1;
+                continue;
+            }
+            PageState::PartialOpen => stats.partial_open_pages += 1,
+            PageState::Closed => stats.closed_pages += 1,
+        }
+
+        let free_item_address = ItemHeaderIter::new(page_data_start_address, page_data_end_address)
+            .traverse(flash, |_, _| true)
+            .await?
+            .1;
+
+        written_bytes += free_item_address - page_data_start_address;
+
+        if page_state == PageState::PartialOpen {
+            stats.free_bytes += page_data_end_address - free_item_address;
+        }
+    }
+
+    stats.stale_bytes = written_bytes.saturating_sub(live_bytes);
+    stats.free_bytes +=
+        stats.open_pages.saturating_sub(1) as u32 * calculate_page_size::<S>() as u32;
+
+    cache.unmark_dirty();
+    Ok(stats)
+}
+
+/// When the next page of the map will be erased. See [erase_forecast].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct EraseForecast {
+    /// The index of the page in the flash range that will be erased next
+    pub next_erased_page: usize,
+    /// The space that is left on the page that is currently being written to
+    pub current_page_free_bytes: u32,
+    /// The amount of empty pages that will be written to before the page is erased
+    pub empty_pages_before_erase: usize,
+}
+
+impl EraseForecast {
+    /// The total space that can be written to before the page is erased
+    pub fn free_bytes<S: NorFlash>(&self) -> u32 {
+        self.current_page_free_bytes
+            + self.empty_pages_before_erase as u32 * calculate_page_size::<S>() as u32
+    }
+
+    /// Get how many more items with the given serialized key + value length can be stored
+    /// before the page is erased. The store after that will do the erase.
+    pub fn stores_until_erase<S: NorFlash>(&self, item_data_length: usize) -> usize {
+        stores_until_erase::<S>(
+            self.current_page_free_bytes,
+            self.empty_pages_before_erase,
+            item_data_length,
+        )
+    }
+}
+
+/// Find out which page will be erased next and how much can be stored before that happens.
+/// This can be used to e.g. only do stores that lead to an erase when the device is charging.
+/// With [compact] the erase can be done ahead of time.
+///
+/// When a page is full, the next page is taken into use. The page after that is kept empty, so if it's not,
+/// its items are moved and it's erased. This function follows that process to find the page that will be erased.
+///
+/// Only the page states and the current page are read, so this is a lot cheaper than [stats].
+///
+/// This function only reads the flash. If corruption is found, the error is returned without repairing it.
+pub async fn erase_forecast<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<EraseForecast, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let (current_page, current_page_free_bytes) = match find_first_page(
+        flash,
+        flash_range.clone(),
+        cache,
+        0,
+        PageState::PartialOpen,
+    )
+    .await?
+    {
+        Some(partial_open_page) => {
+            let page_data_start_address =
+                calculate_page_address::<S>(flash_range.clone(), partial_open_page)
+                    + S::WORD_SIZE as u32;
+            let page_data_end_address =
+                calculate_page_end_address::<S>(flash_range.clone(), partial_open_page)
+                    - S::WORD_SIZE as u32;
+
+            let free_item_address =
+                ItemHeaderIter::new(page_data_start_address, page_data_end_address)
+                    .traverse(flash, |_, _| true)
+                    .await?
+                    .1;
+
+            (partial_open_page, page_data_end_address - free_item_address)
+        }
+        // Without a partial open page, the first open page is taken into use without an erase
+        None => match find_first_page(flash, flash_range.clone(), cache, 0, PageState::Open).await?
+        {
+            Some(first_open_page) => (first_open_page, calculate_page_size::<S>() as u32),
+            None => {
+                return Err(Error::Corrupted {
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                })
+            }
+        },
+    };
+
+    let mut forecast = EraseForecast {
+        next_erased_page: current_page,
+        current_page_free_bytes,
+        empty_pages_before_erase: 0,
+    };
+
+    // Walk the pages like the stores will. The pages we pass will be closed by the time we get back to them.
+    let mut page = current_page;
+    loop {
+        let next_page_to_use = next_page::<S>(flash_range.clone(), page);
+        let next_buffer_page = next_page::<S>(flash_range.clone(), next_page_to_use);
+
+        if next_buffer_page == current_page
+            || !get_page_state(flash, flash_range.clone(), cache, next_buffer_page)
+                .await?
+                .is_open()
+        {
+            forecast.next_erased_page = next_buffer_page;
+            break;
+        }
+
+        forecast.empty_pages_before_erase += 1;
+        page = next_page_to_use;
+    }
+
+    cache.unmark_dirty();
+    Ok(forecast)
+}
+
+/// The result of checking the map with [check]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct MapCheckReport {
+    /// The amount of items that can be read just fine. This includes the items that have a newer value.
+    pub items: usize,
+    /// The amount of items that were removed, but are still in flash until their page is erased
+    pub erased_items: usize,
+    /// The amount of items of which the data doesn't match the crc.
+    /// This is normally caused by a write that got interrupted by e.g. a power loss.
+    pub corrupted_items: usize,
+    /// The amount of item headers that are corrupted.
+    /// This is normally caused by a write that got interrupted by e.g. a power loss.
+    pub corrupted_headers: usize,
+    /// The amount of pages on which data was found after the last item.
+    /// This is normally caused by an interrupted [store_items].
+    pub unfinished_writes: usize,
+    /// The amount of pages of which the state is not valid.
+    /// This is normally caused by an erase that got interrupted.
+    pub corrupted_pages: usize,
+    /// The amount of pages that are open. There must always be at least one.
+    pub open_pages: usize,
+    /// The amount of pages that are partially open. There should never be more than one.
+    pub partial_open_pages: usize,
+    /// True if the moving of items to a new page got interrupted.
+    /// The items that were already moved are then in flash twice and it's not clear which one is the newest.
+    pub interrupted_migration: bool,
+}
+
+impl MapCheckReport {
+    /// Returns true if nothing was found that points to corruption or interrupted operations
+    pub fn is_ok(&self) -> bool {
+        self.corrupted_items == 0
+            && self.corrupted_headers == 0
+            && self.unfinished_writes == 0
+            && self.corrupted_pages == 0
+            && self.open_pages > 0
+            && self.partial_open_pages <= 1
+            && !self.interrupted_migration
+    }
+}
+
+/// Check the whole map for corrupted items, interrupted writes and invalid page states.
+///
+/// Nothing is modified, not even in case corruption is found, so this can be used to decide
+/// whether to trust the data or not. Use [repair] to fix what was found.
+/// Most of what is reported is also repaired automatically the next time the map is used.
+///
+/// All data in the flash range is read, so this is a slow operation.
+/// Because of that no cache is used either.
+pub async fn check<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+) -> Result<MapCheckReport, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+    assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    let mut cache = cache::NoCache::new();
+    let mut report = MapCheckReport::default();
+
+    for page in get_pages::<S>(flash_range.clone(), 0) {
+        match get_page_state(flash, flash_range.clone(), &mut cache, page).await {
+            Ok(PageState::Open) => {
+                report.open_pages += 1;
+                continue;
+            }
+            Ok(PageState::PartialOpen) => {
+                report.partial_open_pages += 1;
+
+                // The page after the partial open page is the buffer page that is only written during a migration
+                let buffer_page = next_page::<S>(flash_range.clone(), page);
+                if !matches!(
+                    get_page_state(flash, flash_range.clone(), &mut cache, buffer_page).await,
+                    Ok(PageState::Open)
+                ) {
+                    report.interrupted_migration = true;
+                }
+            }
+            Ok(PageState::Closed) => {}
+            Err(Error::Corrupted { .. }) => {
+                report.corrupted_pages += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let page_check = check_page_items(
+            flash,
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32,
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32,
+        )
+        .await?;
+
+        report.items += page_check.items;
+        report.erased_items += page_check.erased_items;
+        report.corrupted_items += page_check.corrupted_items;
+        report.corrupted_headers += page_check.corrupted_headers;
+        if page_check.unfinished_write {
+            report.unfinished_writes += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Repair the map after e.g. an interrupted operation and return a new [check] of the map.
+///
+/// Pages with an invalid state are erased and an interrupted migration is done again,
+/// so every key has one clear newest value again.
+/// Corrupted items are left in flash. They are skipped when reading and are not moved when their page is erased,
+/// so in the end only the newest values that can still be read are kept.
+///
+/// This is the same repair that is done automatically when corruption is found during an operation.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn repair<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<MapCheckReport, Error<S::Error>> {
+    try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?;
+    check(flash, flash_range).await
+}
+
+/// A read-only view of a map. It owns the flash, flash range and cache, like the [Queue](crate::queue::Queue) does.
+///
+/// It has no methods that store or remove items, and it never repairs the flash, so it never writes or erases anything.
+/// This makes it usable from e.g. a bootloader or a secondary core that must not modify the flash.
+/// If corruption is found, [Error::Corrupted] is returned instead of repairing it.
+/// The next store or fetch with the normal functions will then do the repair.
+///
+/// Keep in mind that the flash can still be changed by others while the reader is in use.
+/// The reader only finds the items that were fully written at the time of reading.
+pub struct MapReader<K: Key, S: NorFlash, CI: KeyCacheImpl<K>> {
+    flash: S,
+    flash_range: Range<u32>,
+    cache: CI,
+    _key: PhantomData<K>,
+}
+
+impl<K: Key, S: NorFlash, CI: KeyCacheImpl<K>> MapReader<K, S, CI> {
+    /// Create a new read-only view of the map in the given flash range
+    pub fn new(flash: S, flash_range: Range<u32>, cache: CI) -> Self {
+        assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+        assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+        assert!(flash_range.end - flash_range.start >= S::ERASE_SIZE as u32 * 2);
+
+        assert!(S::ERASE_SIZE >= S::WORD_SIZE * 3);
+        assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+        Self {
+            flash,
+            flash_range,
+            cache,
+            _key: PhantomData,
+        }
+    }
+
+    /// Get back the flash and the cache
+    pub fn into_inner(self) -> (S, CI) {
+        (self.flash, self.cache)
+    }
+
+    /// The flash range of the map
+    pub fn flash_range(&self) -> Range<u32> {
+        self.flash_range.clone()
+    }
+
+    /// Get the last stored value that is associated with the given key, like [fetch_item] does.
+    /// If no value with the key is found, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn fetch_item<'d, V: Value<'d>>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+        search_key: &K,
+    ) -> Result<Option<V>, Error<S::Error>> {
+        let Some((item, _, item_key_len)) = fetch_item_with_location(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data_buffer,
+            search_key,
+        )
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(deserialize_item_value::<K, V, S::Error>(
+            data_buffer,
+            item.header.length as usize,
+            item_key_len,
+        )?))
+    }
+
+    /// Get the last stored value of a typed key, like [fetch_typed_item] does.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn fetch_typed_item<T: TypedKey<Key = K>>(
+        &mut self,
+        data_buffer: &mut [u8],
+    ) -> Result<Option<T::Value>, Error<S::Error>> {
+        self.fetch_item(data_buffer, &T::KEY).await
+    }
+}
+
+impl<K: Key, S: NorFlash, CI: KeyCacheImpl<K>> Debug for MapReader<K, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MapReader")
+            .field("flash_range", &self.flash_range)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Anything implementing this trait can be used as a key in the map functions.
+///
+/// It provides a way to serialize and deserialize the key.
+///
+/// The `Eq` bound is used because we need to be able to compare keys and the
+/// `Clone` bound helps us pass the key around.
+///
+/// The key cannot have a lifetime like the [Value]
+///
+/// Besides the premade implementations, you can implement it for your own types like enums or small structs.
+/// Make sure that equal keys always serialize to the same bytes and that the deserialization
+/// can tell from the bytes alone how long the key is, since the value is stored right after it.
+///
+/// ```rust
+/// # use sequential_storage::map::{Key, SerializationError};
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// enum Setting {
+///     Volume,
+///     Channel(u8),
+/// }
+///
+/// impl Key for Setting {
+///     fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+///         let bytes = match self {
+///             Setting::Volume => [0, 0],
+///             Setting::Channel(index) => [1, *index],
+///         };
+///
+///         if buffer.len() < bytes.len() {
+///             return Err(SerializationError::BufferTooSmall);
+///         }
+///
+///         buffer[..bytes.len()].copy_from_slice(&bytes);
+///         Ok(bytes.len())
+///     }
+///
+///     fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+///         match buffer {
+///             [0, 0, ..] => Ok((Setting::Volume, 2)),
+///             [1, index, ..] => Ok((Setting::Channel(*index), 2)),
+///             [_, _, ..] => Err(SerializationError::InvalidFormat),
+///             _ => Err(SerializationError::BufferTooSmall),
+///         }
+///     }
+/// }
+///
+/// let mut buffer = [0; 2];
+/// assert_eq!(Setting::Channel(3).serialize_into(&mut buffer), Ok(2));
+/// assert_eq!(Setting::deserialize_from(&buffer), Ok((Setting::Channel(3), 2)));
+/// ```
+pub trait Key: Eq + Clone + Sized {
+    /// Serialize the key into the given buffer.
+    /// The serialized size is returned.
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError>;
+    /// Deserialize the key from the given buffer.
+    /// The key is returned together with the serialized length.
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError>;
+    /// Get the length of the key from the buffer.
+    /// This is an optimized version of [Self::deserialize_from] that doesn't have to deserialize everything.
+    fn get_len(buffer: &[u8]) -> Result<usize, SerializationError> {
+        Self::deserialize_from(buffer).map(|(_, len)| len)
+    }
+
+    /// True if the key is reserved for future use by the crate, like [RESERVED_NAMESPACES] for [Namespaced] keys.
+    /// Reserved keys can't be stored or removed by the application and return [Error::ReservedKey].
+    ///
+    /// None of the keys are reserved by default.
+    fn is_reserved(&self) -> bool {
+        false
+    }
+
+    /// Check if the key that is serialized at the start of the buffer is equal to this key.
+    /// This is used to search the items in flash, so keys that are expensive to deserialize
+    /// can speed that up by comparing a part of their serialized data first, like [Hashed] does.
+    fn matches_serialized(&self, buffer: &[u8]) -> Result<bool, SerializationError> {
+        Ok(Self::deserialize_from(buffer)?.0 == *self)
+    }
+}
+
+macro_rules! impl_key_num {
+    ($int:ty) => {
+        impl Key for $int {
+            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+                let len = core::mem::size_of::<Self>();
+                if buffer.len() < len {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[..len].copy_from_slice(&self.to_le_bytes());
+                Ok(len)
+            }
+
+            fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+                let len = core::mem::size_of::<Self>();
+                if buffer.len() < len {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+
+                Ok((
+                    Self::from_le_bytes(buffer[..len].try_into().unwrap()),
+                    core::mem::size_of::<Self>(),
+                ))
+            }
+
+            fn get_len(_buffer: &[u8]) -> Result<usize, SerializationError> {
+                Ok(core::mem::size_of::<Self>())
+            }
+        }
+    };
+}
+
+impl_key_num!(u8);
+impl_key_num!(u16);
+impl_key_num!(u32);
+impl_key_num!(u64);
+impl_key_num!(u128);
+impl_key_num!(i8);
+impl_key_num!(i16);
+impl_key_num!(i32);
+impl_key_num!(i64);
+impl_key_num!(i128);
+
+impl<const N: usize> Key for [u8; N] {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < N {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        buffer[..N].copy_from_slice(self);
+        Ok(N)
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        if buffer.len() < N {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        Ok((buffer[..N].try_into().unwrap(), N))
+    }
+
+    fn get_len(_buffer: &[u8]) -> Result<usize, SerializationError> {
+        Ok(N)
+    }
+}
+
+/// The namespaces of [Namespaced] keys that are reserved for future use by the crate.
+/// The crate doesn't store anything in them yet, but they can't be used by the application,
+/// so they're still free once it does. See [Key::is_reserved].
+pub const RESERVED_NAMESPACES: RangeInclusive<u8> = 0xF0..=0xFF;
+
+/// A key that is prefixed with a namespace id, so multiple parts of an application can use the same map
+/// without having to coordinate their keys. Keys in different namespaces never overwrite each other.
+///
+/// The namespace is stored as one byte in front of the key.
+/// The namespaces in [RESERVED_NAMESPACES] can't be used.
+///
+/// <div class="warning">
+///
+/// *All parts of the application must use the same key type (so `Namespaced<K>` with the same `K`) on the flash range.*
+///
+/// </div>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Namespaced<K> {
+    /// The id of the namespace the key belongs to
+    pub namespace: u8,
+    /// The key within the namespace
+    pub key: K,
+}
+
+impl<K: Key> Key for Namespaced<K> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let (namespace, buffer) = buffer
+            .split_first_mut()
+            .ok_or(SerializationError::BufferTooSmall)?;
+        *namespace = self.namespace;
+        Ok(1 + self.key.serialize_into(buffer)?)
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let (namespace, buffer) = buffer
+            .split_first()
+            .ok_or(SerializationError::BufferTooSmall)?;
+        let (key, key_len) = K::deserialize_from(buffer)?;
+        Ok((
+            Self {
+                namespace: *namespace,
+                key,
+            },
+            1 + key_len,
+        ))
+    }
+
+    fn get_len(buffer: &[u8]) -> Result<usize, SerializationError> {
+        Ok(1 + K::get_len(buffer.get(1..).ok_or(SerializationError::BufferTooSmall)?)?)
+    }
+
+    fn is_reserved(&self) -> bool {
+        RESERVED_NAMESPACES.contains(&self.namespace)
+    }
+
+    fn matches_serialized(&self, buffer: &[u8]) -> Result<bool, SerializationError> {
+        let (namespace, buffer) = buffer
+            .split_first()
+            .ok_or(SerializationError::BufferTooSmall)?;
+        Ok(*namespace == self.namespace && self.key.matches_serialized(buffer)?)
+    }
+}
+
+/// A key that is stored with a hash of its serialized data in front of it.
+/// Useful for long keys, like paths or names, because when the items in flash are searched,
+/// only the hash has to be compared for most of them. Only when the hash is the same,
+/// the full key is deserialized and compared, so keys with a colliding hash still work.
+///
+/// The hash is a crc32 that is stored as 4 bytes in front of the key.
+///
+/// ```rust
+/// # use sequential_storage::map::{Hashed, Key};
+/// let mut buffer = [0; 32];
+/// let key = Hashed::new(*b"settings/network/wifi/ssid", &mut buffer).unwrap();
+///
+/// let len = key.serialize_into(&mut buffer).unwrap();
+/// assert!(key.matches_serialized(&buffer[..len]).unwrap());
+/// ```
+///
+/// <div class="warning">
+///
+/// *All parts of the application must use the same key type (so `Hashed<K>` with the same `K`) on the flash range.*
+///
+/// </div>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Hashed<K> {
+    // The hash goes first so it's compared first
+    hash: u32,
+    key: K,
+}
+
+impl<K: Key> Hashed<K> {
+    /// Create a hashed key. To calculate the hash, the key is serialized into the given buffer,
+    /// so it must be long enough to hold the serialized key. The data buffer can be used for this.
+    pub fn new(key: K, buffer: &mut [u8]) -> Result<Self, SerializationError> {
+        let key_len = key.serialize_into(buffer)?;
+        Ok(Self {
+            hash: crate::item::crc32(&buffer[..key_len]),
+            key,
+        })
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Get the key back
+    pub fn into_inner(self) -> K {
+        self.key
+    }
+}
+
+impl<K: Key> Key for Hashed<K> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < 4 {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        buffer[..4].copy_from_slice(&self.hash.to_le_bytes());
+        Ok(4 + self.key.serialize_into(&mut buffer[4..])?)
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let (hash, buffer) = buffer
+            .split_first_chunk::<4>()
+            .ok_or(SerializationError::BufferTooSmall)?;
+        let (key, key_len) = K::deserialize_from(buffer)?;
+        Ok((
+            Self {
+                hash: u32::from_le_bytes(*hash),
+                key,
+            },
+            4 + key_len,
+        ))
+    }
+
+    fn get_len(buffer: &[u8]) -> Result<usize, SerializationError> {
+        Ok(4 + K::get_len(buffer.get(4..).ok_or(SerializationError::BufferTooSmall)?)?)
+    }
+
+    fn is_reserved(&self) -> bool {
+        self.key.is_reserved()
+    }
+
+    fn matches_serialized(&self, buffer: &[u8]) -> Result<bool, SerializationError> {
+        let (hash, buffer) = buffer
+            .split_first_chunk::<4>()
+            .ok_or(SerializationError::BufferTooSmall)?;
+        Ok(u32::from_le_bytes(*hash) == self.hash && self.key.matches_serialized(buffer)?)
+    }
+}
+
+/// A key from which a tag can be derived, e.g. the sensor a measurement belongs to.
+/// All keys with the same tag can then be found with [fetch_by_tag].
+///
+/// Because the tag is derived from the key, nothing extra is stored in flash.
+/// That also means there's no index, so finding the keys with a tag reads all items.
+///
+/// ```rust
+/// # use sequential_storage::map::{Namespaced, TaggedKey};
+/// // The tag of a namespaced key is its namespace
+/// let key = Namespaced { namespace: 3, key: 42u16 };
+/// assert_eq!(key.tag(), 3);
+/// ```
+pub trait TaggedKey: Key {
+    /// The type of the tag
+    type Tag: PartialEq;
+
+    /// Get the tag of the key
+    fn tag(&self) -> Self::Tag;
+}
+
+impl<K: Key> TaggedKey for Namespaced<K> {
+    type Tag = u8;
+
+    fn tag(&self) -> Self::Tag {
+        self.namespace
+    }
+}
+
+/// The trait that defines how map values are serialized and deserialized.
+///
+/// It also carries a lifetime so that zero-copy deserialization is supported.
+/// Zero-copy serialization is not supported due to technical restrictions.
+pub trait Value<'a> {
+    /// Serialize the value into the given buffer. If everything went ok, this function returns the length
+    /// of the used part of the buffer.
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError>;
+    /// Deserialize the value from the buffer. Because of the added lifetime, the implementation can borrow from the
+    /// buffer which opens up some zero-copy possibilities.
+    ///
+    /// The buffer will be the same length as the serialize function returned for this value. Though note that the length
+    /// is written from flash, so bitflips can affect that (though the length is separately crc-protected) and the key deserialization might
+    /// return a wrong length.
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized;
+}
+
+impl<'a> Value<'a> for &'a [u8] {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < self.len() {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        buffer[..self.len()].copy_from_slice(self);
+        Ok(self.len())
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        Ok(buffer)
+    }
+}
+
+impl<'a, const N: usize> Value<'a> for [u8; N] {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < self.len() {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        buffer[..self.len()].copy_from_slice(self);
+        Ok(self.len())
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        buffer
+            .try_into()
+            .map_err(|_| SerializationError::BufferTooSmall)
+    }
+}
+
+/// A value that is only valid until the given time. See [fetch_unexpired_item].
+///
+/// The value is stored behind a 4 byte marker and the expiry time as 8 bytes, so 12 bytes of overhead.
+/// The marker lets [remove_expired_items] tell expiring values apart from other values.
+/// Deserializing a value without the marker as an [Expiring] value gives a [SerializationError::InvalidFormat].
+/// What unit and epoch the time has is up to you, as long as you use the same one for storing and fetching.
+///
+/// Expired items are not removed from flash automatically, so they're kept when their page is erased
+/// just like any other item.
+/// Use [remove_expired_items] to get rid of them, or store a new value or remove the item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Expiring<V> {
+    /// The time from which the value is expired
+    pub expires_at: u64,
+    /// The actual value
+    pub value: V,
+}
+
+impl<V> Expiring<V> {
+    /// The marker in front of every serialized expiring value
+    const MARKER: [u8; 4] = *b"EXP\x01";
+    const HEADER_LENGTH: usize = Self::MARKER.len() + 8;
+}
+
+impl<'a, V: Value<'a>> Value<'a> for Expiring<V> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let header = buffer
+            .get_mut(..Self::HEADER_LENGTH)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        header[..Self::MARKER.len()].copy_from_slice(&Self::MARKER);
+        header[Self::MARKER.len()..].copy_from_slice(&self.expires_at.to_le_bytes());
+
+        Ok(Self::HEADER_LENGTH
+            + self
+                .value
+                .serialize_into(&mut buffer[Self::HEADER_LENGTH..])?)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        let (marker, buffer) = buffer
+            .split_first_chunk::<4>()
+            .ok_or(SerializationError::InvalidFormat)?;
+        if *marker != Self::MARKER {
+            return Err(SerializationError::InvalidFormat);
+        }
+
+        let (expires_at, value) = buffer
+            .split_first_chunk::<8>()
+            .ok_or(SerializationError::BufferTooSmall)?;
+
+        Ok(Self {
+            expires_at: u64::from_le_bytes(*expires_at),
+            value: V::deserialize_from(value)?,
+        })
+    }
+}
+
+/// A value with a layout version, so its layout can change in a firmware update. Store it in the [Versioned] wrapper.
+///
+/// The current layout is serialized with the [Value] implementation.
+/// When a value with an older version is fetched, [VersionedValue::migrate] is called to convert the old layout.
+/// This way old values don't have to be converted all at once after an update.
+/// A migrated value is not stored again, so store it yourself if you want to skip the migration next time.
+///
+/// The `Value` derive macro implements this trait too. A derived struct stores its version itself,
+/// so it doesn't need the wrapper. See [FixedSizeValue].
+///
+/// ```rust
+/// # use sequential_storage::map::{SerializationError, Value, VersionedValue};
+/// struct Settings {
+///     brightness: u8,
+///     // Added in version 2
+///     volume: u8,
+/// }
+///
+/// impl<'a> Value<'a> for Settings {
+///     fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+///         [self.brightness, self.volume].serialize_into(buffer)
+///     }
+///
+///     fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+///         let [brightness, volume] = <[u8; 2]>::deserialize_from(buffer)?;
+///         Ok(Self { brightness, volume })
+///     }
+/// }
+///
+/// impl<'a> VersionedValue<'a> for Settings {
+///     const VERSION: u8 = 2;
+///
+///     fn migrate(version: u8, buffer: &'a [u8]) -> Result<Self, SerializationError> {
+///         match version {
+///             1 => Ok(Self { brightness: u8::deserialize_from(buffer)?, volume: 50 }),
+///             _ => Err(SerializationError::InvalidFormat),
+///         }
+///     }
+/// }
+/// ```
+pub trait VersionedValue<'a>: Value<'a> {
+    /// The version of the current layout
+    const VERSION: u8;
+
+    /// Convert the data of an older version to the current layout.
+    /// The default returns [SerializationError::InvalidFormat], so only the current version can be fetched.
+    fn migrate(version: u8, buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        let _ = (version, buffer);
+        Err(SerializationError::InvalidFormat)
+    }
+}
+
+/// A wrapper that stores a [VersionedValue] with its version in front of it as one byte.
+///
+/// When fetching, the version is checked and the value is migrated if it's older than the current version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Versioned<V>(pub V);
+
+impl<'a, V: VersionedValue<'a>> Value<'a> for Versioned<V> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let (version, buffer) = buffer
+            .split_first_mut()
+            .ok_or(SerializationError::BufferTooSmall)?;
+        *version = V::VERSION;
+        Ok(1 + self.0.serialize_into(buffer)?)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        let (version, buffer) = buffer
+            .split_first()
+            .ok_or(SerializationError::BufferTooSmall)?;
+
+        match *version {
+            version if version == V::VERSION => V::deserialize_from(buffer),
+            version => V::migrate(version, buffer),
+        }
+        .map(Self)
+    }
+}
+
+macro_rules! impl_map_item_num {
+    ($int:ty) => {
+        impl<'a> Value<'a> for $int {
+            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+                buffer
+                    .get_mut(..core::mem::size_of::<Self>())
+                    .ok_or(SerializationError::BufferTooSmall)?
+                    .copy_from_slice(&self.to_le_bytes());
+                Ok(core::mem::size_of::<Self>())
+            }
+
+            fn deserialize_from(buffer: &[u8]) -> Result<Self, SerializationError> {
+                Ok(Self::from_le_bytes(
+                    buffer
+                        .get(..core::mem::size_of::<Self>())
+                        .ok_or(SerializationError::BufferTooSmall)?
+                        .try_into()
+                        .unwrap(),
+                ))
+            }
+        }
+    };
+}
+
+impl_map_item_num!(u8);
+impl_map_item_num!(u16);
+impl_map_item_num!(u32);
+impl_map_item_num!(u64);
+impl_map_item_num!(u128);
+impl_map_item_num!(i8);
+impl_map_item_num!(i16);
+impl_map_item_num!(i32);
+impl_map_item_num!(i64);
+impl_map_item_num!(i128);
+impl_map_item_num!(f32);
+impl_map_item_num!(f64);
+
+impl<'a> Value<'a> for bool {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        Value::serialize_into(&u8::from(*self), buffer)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        match <u8 as Value>::deserialize_from(buffer)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SerializationError::InvalidFormat),
+        }
+    }
+}
+
+/// An option is stored with one extra byte in front of the value that tells whether it's `Some`
+impl<'a, T: Value<'a>> Value<'a> for Option<T> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let (tag, buffer) = buffer
+            .split_first_mut()
+            .ok_or(SerializationError::BufferTooSmall)?;
+
+        match self {
+            Some(value) => {
+                *tag = 1;
+                Ok(1 + value.serialize_into(buffer)?)
+            }
+            None => {
+                *tag = 0;
+                Ok(1)
+            }
+        }
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        match buffer
+            .split_first()
+            .ok_or(SerializationError::BufferTooSmall)?
+        {
+            (0, _) => Ok(None),
+            (1, buffer) => Ok(Some(T::deserialize_from(buffer)?)),
+            _ => Err(SerializationError::InvalidFormat),
+        }
+    }
+}
+
+/// A [Value] that always has the same serialized size.
+///
+/// This is used by the `Value` derive macro, which is available with the `derive` feature.
+/// The macro can be used on structs (without generics) of which all fields implement this trait,
+/// like the integers, floats, `bool` and byte arrays. The fields are stored in order behind each other.
+///
+/// In front of the fields, a layout version byte is stored. It's 0 by default and can be set with
+/// `#[value(version = 1)]` on the struct. When the layout of the struct changes, change the version too.
+/// Then a value with a different layout fails to deserialize with [SerializationError::InvalidFormat]
+/// instead of being read as garbage.
+///
+/// The version is the [VersionedValue::VERSION] of the struct. To still read values with an older layout,
+/// pass a function with the signature of [VersionedValue::migrate] as `#[value(version = 2, migrate = my_fn)]`.
+/// A struct that is used as a field can't be migrated this way, because the old layout has another size.
+/// So when the layout of a field changes, change the version of the struct that contains it too.
+///
+/// Every field must serialize to exactly its [FixedSizeValue::SIZE], else serializing the struct fails
+/// with [SerializationError::InvalidData].
+///
+/// A derived struct implements this trait as well, so it can be used as a field of another derived struct.
+pub trait FixedSizeValue: for<'a> Value<'a> {
+    /// The amount of bytes the value is serialized to
+    const SIZE: usize;
+}
+
+macro_rules! impl_fixed_size_value_num {
+    ($($int:ty),*) => {
+        $(
+            impl FixedSizeValue for $int {
+                const SIZE: usize = core::mem::size_of::<Self>();
+            }
+        )*
+    };
+}
+
+impl_fixed_size_value_num!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+/// An integer [Value] that can be used as a counter with [increment].
+pub trait Counter: FixedSizeValue + Copy + PartialEq {
+    /// The count of a counter that isn't stored yet
+    const ZERO: Self;
+
+    /// Add the delta to the count, saturating at the bounds of the type
+    fn add_delta(self, delta: Self) -> Self;
+}
+
+macro_rules! impl_counter_num {
+    ($($int:ty),*) => {
+        $(
+            impl Counter for $int {
+                const ZERO: Self = 0;
+
+                fn add_delta(self, delta: Self) -> Self {
+                    self.saturating_add(delta)
+                }
+            }
+        )*
+    };
+}
+
+impl_counter_num!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl FixedSizeValue for bool {
+    const SIZE: usize = 1;
+}
+
+impl<const N: usize> FixedSizeValue for [u8; N] {
+    const SIZE: usize = N;
+}
+
+/// Error for map value (de)serialization.
+///
+/// This error type is predefined (in contrast to using generics) to save many kilobytes of binary size.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum SerializationError {
+    /// The provided buffer was too small.
+    BufferTooSmall,
+    /// The serialization could not succeed because the data was not in order. (e.g. too big to fit)
+    InvalidData,
+    /// The deserialization could not succeed because the bytes are in an invalid format.
+    InvalidFormat,
+    /// An implementation defined error that might contain more information than the other predefined
+    /// error variants.
+    Custom(i32),
+}
+
+impl core::fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SerializationError::BufferTooSmall => write!(f, "Buffer too small"),
+            SerializationError::InvalidData => write!(f, "Invalid data"),
+            SerializationError::InvalidFormat => write!(f, "Invalid format"),
+            SerializationError::Custom(val) => write!(f, "Custom error: {val}"),
+        }
+    }
+}
+
+async fn migrate_items<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateKeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    source_page: usize,
+    target_page: usize,
+) -> Result<(), Error<S::Error>> {
+    // We need to move the data from the next buffer page to the next_page_to_use, but only if that data
+    // doesn't have a newer value somewhere else.
+
+    let mut next_page_write_address =
+        calculate_page_address::<S>(flash_range.clone(), target_page) + S::WORD_SIZE as u32;
+
+    let mut it = ItemIter::new(
+        calculate_page_address::<S>(flash_range.clone(), source_page) + S::WORD_SIZE as u32,
+        calculate_page_end_address::<S>(flash_range.clone(), source_page) - S::WORD_SIZE as u32,
+    );
+    while let Some((item, item_address)) = it.next(flash, data_buffer).await? {
+        let (key, _) = K::deserialize_from(item.data())?;
+        let (_, data_buffer) = item.destruct();
+
+        // We're in a decent state here
+        cache.unmark_dirty();
+
+        // Search for the newest item with the key we found
+        let Some((found_item, found_address, _)) =
+            fetch_item_with_location::<K, S>(flash, flash_range.clone(), cache, data_buffer, &key)
+                .await?
+        else {
+            // A newer tombstone removed the key, so the item doesn't have to be kept.
+            // Tombstones themselves are never moved, because nothing older than the page being erased is left.
+            continue;
+        };
+
+        let found_item = found_item.reborrow(data_buffer);
+
+        if found_address == item_address {
+            cache.notice_key_location(&key, next_page_write_address, true);
+            found_item
+                .write(flash, flash_range.clone(), cache, next_page_write_address)
+                .await?;
+            next_page_write_address = found_item
+                .header
+                .next_item_address::<S>(next_page_write_address);
+        }
+    }
+
+    open_page(flash, flash_range.clone(), cache, source_page).await?;
+
+    Ok(())
+}
+
+/// Try to repair the state of the flash to hopefull get back everything in working order.
+/// Care is taken that no data is lost, but this depends on correctly repairing the state and
+/// so is only best effort.
+///
+/// This function might be called after a different function returned the [Error::Corrupted] error.
+/// There's no guarantee it will work.
+///
+/// If this function or the function call after this crate returns [Error::Corrupted], then it's unlikely
+/// that the state can be recovered. To at least make everything function again at the cost of losing the data,
+/// erase the flash range.
+async fn try_repair<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+) -> Result<(), Error<S::Error>> {
+    cache.invalidate_cache_state();
+
+    crate::try_general_repair(flash, flash_range.clone(), cache).await?;
+
+    // Let's check if we corrupted in the middle of a migration
+    if let Some(partial_open_page) =
+        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?
+    {
+        let buffer_page = next_page::<S>(flash_range.clone(), partial_open_page);
+        if !get_page_state(flash, flash_range.clone(), cache, buffer_page)
+            .await?
+            .is_open()
+        {
+            // Yes, the migration got interrupted. Let's redo it.
+            // To do that, we erase the partial open page first because it contains incomplete data.
+            open_page(flash, flash_range.clone(), cache, partial_open_page).await?;
+
+            // Then partially close it again
+            partial_close_page(flash, flash_range.clone(), cache, partial_open_page).await?;
+
+            migrate_items::<K, _>(
+                flash,
+                flash_range.clone(),
+                cache,
+                data_buffer,
+                buffer_page,
+                partial_open_page,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+    type MockFlashTiny = mock_flash::MockFlashBase<2, 1, 32>;
+
+    #[test]
+    async fn store_and_fetch() {
+        let mut flash = MockFlashBig::default();
+        let flash_range = 0x000..0x1000;
+
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let start_snapshot = flash.stats_snapshot();
+
+        let item = fetch_item::<u8, &[u8], _>(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(item, None);
+
+        let item = fetch_item::<u8, &[u8], _>(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &60,
+        )
+        .await
+        .unwrap();
+        assert_eq!(item, None);
+
+        let item = fetch_item::<u8, &[u8], _>(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0xFF,
+        )
+        .await
+        .unwrap();
+        assert_eq!(item, None);
+
+        store_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &[5],
+        )
+        .await
+        .unwrap();
+        store_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &[5, 6],
+        )
+        .await
+        .unwrap();
+
+        let item = fetch_item::<u8, &[u8], _>(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(item, &[5, 6]);
+
+        store_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &1u8,
+            &[2, 2, 2, 2, 2, 2],
+        )
+        .await
+        .unwrap();
+
+        let item = fetch_item::<u8, &[u8], _>(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(item, &[5, 6]);
+
+        let item = fetch_item::<u8, &[u8], _>(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &1,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(item, &[2, 2, 2, 2, 2, 2]);
+
+        for index in 0..4000 {
+            store_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &((index % 10) as u8),
+                &vec![(index % 10) as u8 * 2; index % 10].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        for i in 0..10 {
+            let item = fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(item, &vec![(i % 10) * 2; (i % 10) as usize]);
+        }
+
+        for _ in 0..4000 {
+            store_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &11u8,
+                &[0; 10],
+            )
+            .await
+            .unwrap();
+        }
+
+        for i in 0..10 {
+            let item = fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(item, &vec![(i % 10) * 2; (i % 10) as usize]);
+        }
+
+        println!("{:?}", start_snapshot.compare_to(flash.stats_snapshot()),);
+    }
+
+    #[test]
+    async fn store_too_many_items() {
+        const UPPER_BOUND: u8 = 3;
+
+        let mut tiny_flash = MockFlashTiny::default();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for i in 0..UPPER_BOUND {
+            println!("Storing {i:?}");
+
+            store_item(
+                &mut tiny_flash,
+                0x00..0x40,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+                &vec![i; i as usize].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            store_item(
+                &mut tiny_flash,
+                0x00..0x40,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &UPPER_BOUND,
+                &vec![0; UPPER_BOUND as usize].as_slice(),
+            )
+            .await,
+            Err(Error::FullStorage)
+        );
+
+        for i in 0..UPPER_BOUND {
+            let item = fetch_item::<u8, &[u8], _>(
+                &mut tiny_flash,
+                0x00..0x40,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            println!("Fetched {item:?}");
+
+            assert_eq!(item, vec![i; i as usize]);
+        }
+    }
+
+    #[test]
+    async fn store_too_many_items_big() {
+        const UPPER_BOUND: u8 = 68;
+
+        let mut big_flash = MockFlashBig::default();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for i in 0..UPPER_BOUND {
+            println!("Storing {i:?}");
+
+            store_item(
+                &mut big_flash,
+                0x0000..0x1000,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+                &vec![i; i as usize].as_slice(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            store_item(
+                &mut big_flash,
+                0x0000..0x1000,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &UPPER_BOUND,
+                &vec![0; UPPER_BOUND as usize].as_slice(),
+            )
+            .await,
+            Err(Error::FullStorage)
+        );
+
+        for i in 0..UPPER_BOUND {
+            let item = fetch_item::<u8, &[u8], _>(
+                &mut big_flash,
+                0x0000..0x1000,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            println!("Fetched {item:?}");
+
+            assert_eq!(item, vec![i; i as usize]);
+        }
+    }
+
+    #[test]
+    async fn store_many_items_big() {
+        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::default();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        const LENGHT_PER_KEY: [usize; 24] = [
+            11, 13, 6, 13, 13, 10, 2, 3, 5, 36, 1, 65, 4, 6, 1, 15, 10, 7, 3, 15, 9, 3, 4, 5,
+        ];
+
+        for _ in 0..100 {
+            for (i, &len) in LENGHT_PER_KEY.iter().enumerate() {
+                store_item(
+                    &mut flash,
+                    0x0000..0x4000,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &(i as u16),
+                    &vec![i as u8; len].as_slice(),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        for (i, &len) in LENGHT_PER_KEY.iter().enumerate() {
+            let item = fetch_item::<u16, &[u8], _>(
+                &mut flash,
+                0x0000..0x4000,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &(i as u16),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            println!("Fetched {item:?}");
+
+            assert_eq!(item, vec![i as u8; len]);
+        }
+    }
+
+    #[test]
+    async fn remove_items() {
+        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::new(
+            mock_flash::WriteCountCheck::Twice,
+            None,
+            true,
+        );
+        let mut data_buffer = AlignedBuf([0; 128]);
+        const FLASH_RANGE: Range<u32> = 0x0000..0x4000;
+
+        // Add some data to flash
+        for j in 0..10 {
+            for i in 0..24 {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &(i as u8),
+                    &vec![i as u8; j + 2].as_slice(),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        for j in (0..24).rev() {
+            // Are all things still in flash that we expect?
+            for i in 0..=j {
+                assert!(fetch_item::<u8, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &i
+                )
+                .await
+                .unwrap()
+                .is_some());
+            }
+
+            // Remove the item
+            remove_item::<u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &j,
+            )
+            .await
+            .unwrap();
+
+            // Are all things still in flash that we expect?
+            for i in 0..j {
+                assert!(fetch_item::<u8, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &i
+                )
+                .await
+                .unwrap()
+                .is_some());
+            }
+
+            assert!(fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &j
+            )
+            .await
+            .unwrap()
+            .is_none());
+        }
+    }
+
+    #[test]
+    async fn removed_item_stays_removed() {
+        let mut flash = MockFlashTiny::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &[0xAAu8; 2],
+        )
+        .await
+        .unwrap();
+        remove_item::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0,
+        )
+        .await
+        .unwrap();
+
+        // Cycle through all pages multiple times so the page with the removed item gets erased
+        for i in 0..20u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &1u8,
+                &[i; 2],
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(
+                fetch_item::<u8, [u8; 2], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0
+                )
+                .await
+                .unwrap(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    async fn remove_all() {
+        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::new(
+            mock_flash::WriteCountCheck::Twice,
+            None,
+            true,
+        );
+        let mut data_buffer = AlignedBuf([0; 128]);
+        const FLASH_RANGE: Range<u32> = 0x0000..0x4000;
+
+        // Add some data to flash
+        for value in 0..10 {
+            for key in 0..24u8 {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &vec![key; value + 2].as_slice(),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        // Sanity check that we can find all the keys we just added.
+        for key in 0..24u8 {
+            assert!(fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &key
+            )
+            .await
+            .unwrap()
+            .is_some());
+        }
+
+        // Remove all the items
+        remove_all_items::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap();
+
+        // Verify that none of the keys are present in flash.
+        for key in 0..24 {
+            assert!(fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &key
+            )
+            .await
+            .unwrap()
+            .is_none());
+        }
+    }
+
+    #[test]
+    async fn remove_all_except() {
+        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::new(
+            mock_flash::WriteCountCheck::Twice,
+            None,
+            true,
+        );
+        let mut data_buffer = AlignedBuf([0; 128]);
+        const FLASH_RANGE: Range<u32> = 0x0000..0x4000;
+
+        for value in 0..10 {
+            for key in 0..24u8 {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &vec![key; value + 2].as_slice(),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        remove_all_items_except::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &[3, 17],
+        )
+        .await
+        .unwrap();
+
+        for key in 0..24u8 {
+            let item = fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &key,
+            )
+            .await
+            .unwrap();
+
+            if key == 3 || key == 17 {
+                assert_eq!(item, Some(&vec![key; 11][..]));
+            } else {
+                assert_eq!(item, None);
+            }
+        }
+    }
+
+    #[test]
+    async fn iterate_items() {
+        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::new(
+            mock_flash::WriteCountCheck::Twice,
+            None,
+            true,
+        );
+        let mut data_buffer = AlignedBuf([0; 128]);
+        const FLASH_RANGE: Range<u32> = 0x0000..0x4000;
+
+        // Enough data so there are old values on multiple pages
+        for value in 0..10 {
+            for key in 0..24u8 {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &vec![key; value + 2].as_slice(),
+                )
+                .await
+                .unwrap();
+            }
+        }
+        for key in [5u8, 6, 20] {
+            remove_item::<u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &key,
+            )
+            .await
+            .unwrap();
+        }
+
+        async fn collect_keys(
+            flash: &mut mock_flash::MockFlashBase<4, 1, 4096>,
+            cache: &mut impl KeyCacheImpl<u8>,
+            data_buffer: &mut [u8],
+        ) -> Vec<u8> {
+            let mut iterator = iter(flash, FLASH_RANGE, cache).await.unwrap();
+            let mut keys = Vec::new();
+
+            while let Some((key, value)) = iterator.next::<&[u8]>(data_buffer).await.unwrap() {
+                assert_eq!(value, &vec![key; 11][..]);
+                keys.push(key);
+            }
+
+            keys.sort();
+            keys
+        }
+
+        let expected_keys = (0..24u8)
+            .filter(|key| ![5, 6, 20].contains(key))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            collect_keys(&mut flash, &mut cache::NoCache::new(), &mut data_buffer).await,
+            expected_keys
+        );
+        assert_eq!(
+            collect_keys(
+                &mut flash,
+                &mut cache::KeyPointerCache::<4, u8, 8>::new(),
+                &mut data_buffer
+            )
+            .await,
+            expected_keys
+        );
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter::<u8, _, _>(&mut flash, FLASH_RANGE, &mut cache)
+            .await
+            .unwrap();
+        let mut keys = Vec::new();
+        while let Some(key) = iterator.next_key(&mut data_buffer).await.unwrap() {
+            keys.push(key);
+        }
+        keys.sort();
+        assert_eq!(keys, expected_keys);
+    }
+
+    #[test]
+    async fn store_only_changed_items() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for (key, value, stored) in [
+            (0u8, 1u32, true),
+            (0, 1, false),
+            (1, 1, true),
+            (0, 2, true),
+            (0, 2, false),
+            (1, 1, false),
+        ] {
+            let snapshot = flash.stats_snapshot();
+            assert_eq!(
+                store_item_if_changed(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &value,
+                )
+                .await
+                .unwrap(),
+                stored
+            );
+            assert_eq!(
+                snapshot.compare_to(flash.stats_snapshot()).writes > 0,
+                stored
+            );
+
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap(),
+                Some(value)
+            );
+        }
+    }
+
+    #[test]
+    async fn compare_and_swap_items() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for (expected, new, result) in [
+            (Some(1u32), 2u32, Err(None)),
+            (None, 1, Ok(())),
+            (None, 2, Err(Some(1))),
+            (Some(2), 3, Err(Some(1))),
+            (Some(1), 3, Ok(())),
+            (Some(3), 4, Ok(())),
+        ] {
+            assert_eq!(
+                compare_and_swap(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0u8,
+                    expected.as_ref(),
+                    &new,
+                )
+                .await
+                .unwrap(),
+                result
+            );
+        }
+
+        assert_eq!(
+            fetch_item::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(4)
+        );
+    }
+
+    #[test]
+    async fn update_items() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for (stored, value) in [(true, 0u32), (true, 1), (true, 2), (false, 2)] {
+            assert_eq!(
+                update_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0,
+                    |current| match current {
+                        None => Some(0),
+                        Some(current) if current < 2 => Some(current + 1),
+                        Some(_) => None,
+                    },
+                )
+                .await
+                .unwrap(),
+                stored
+            );
+
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0,
+                )
+                .await
+                .unwrap(),
+                Some(value)
+            );
+        }
+    }
+
+    #[test]
+    async fn store_multiple_items() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        store_items(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &[(&0u8, &[0xAAu8; 16]), (&1, &5u32), (&2, &&b"hello"[..])],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fetch_item::<u8, [u8; 16], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some([0xAA; 16])
+        );
+        assert_eq!(
+            fetch_item::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &1,
+            )
+            .await
+            .unwrap(),
+            Some(5)
+        );
+        assert_eq!(
+            fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &2,
+            )
+            .await
+            .unwrap(),
+            Some(&b"hello"[..])
+        );
+
+        assert_eq!(
+            store_items(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &[(&0u8, &[0u8; 600]), (&1, &[0u8; 600])],
+            )
+            .await,
+            Err(Error::ItemTooBig)
+        );
+    }
+
+    #[test]
+    async fn single_store_serializes_once() {
+        /// A value that counts how often it's serialized
+        struct Counted<'c>(&'c core::cell::Cell<usize>);
+
+        impl<'a> Value<'a> for Counted<'_> {
+            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+                self.0.set(self.0.get() + 1);
+                Value::serialize_into(&42u32, buffer)
+            }
+
+            fn deserialize_from(_buffer: &'a [u8]) -> Result<Self, SerializationError> {
+                Err(SerializationError::InvalidFormat)
+            }
+        }
+
+        let mut flash = MockFlashBig::default();
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let serializations = core::cell::Cell::new(0);
+
+        for i in 0..100u8 {
+            serializations.set(0);
+            store_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &i,
+                &Counted(&serializations),
+            )
+            .await
+            .unwrap();
+            // Only when a page gets full the value has to be serialized again after the migration
+            assert!(serializations.get() <= 2);
+        }
+
+        serializations.set(0);
+        store_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &Counted(&serializations),
+        )
+        .await
+        .unwrap();
+        assert_eq!(serializations.get(), 1);
+    }
+
+    #[test]
+    async fn store_items_is_atomic() {
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for shutoff in 0..64 {
+            let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+            const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+
+            store_items(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &[(&0u8, &1u32), (&1, &1u32), (&2, &1u32)],
+            )
+            .await
+            .unwrap();
+
+            flash.bytes_until_shutoff = Some(shutoff);
+            let result = store_items(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &[(&0u8, &2u32), (&1, &2u32), (&2, &2u32)],
+            )
+            .await;
+            flash.bytes_until_shutoff = None;
+
+            // After the shutoff we store another item which must always be readable
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &3u8,
+                &3u32,
+            )
+            .await
+            .unwrap();
+
+            let mut values = Vec::new();
+            for key in 0..4u8 {
+                values.push(
+                    fetch_item::<u8, u32, _>(
+                        &mut flash,
+                        FLASH_RANGE,
+                        &mut cache::NoCache::new(),
+                        &mut data_buffer,
+                        &key,
+                    )
+                    .await
+                    .unwrap(),
+                );
+            }
+
+            let all_stored = vec![Some(2), Some(2), Some(2), Some(3)];
+            if result.is_ok() {
+                assert_eq!(values, all_stored, "Shutoff at {shutoff}");
+            } else {
+                assert!(
+                    values == vec![Some(1), Some(1), Some(1), Some(3)] || values == all_stored,
+                    "Shutoff at {shutoff}: {values:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    async fn commit_transaction() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut staging_buffer = [0; 16];
+
+        {
+            // Dropped without committing
+            let mut transaction = MapTransaction::new(&mut staging_buffer);
+            transaction.store(&0u8, &1u32).unwrap();
+            transaction.store(&1u8, &1u32).unwrap();
+        }
+
+        let mut transaction = MapTransaction::new(&mut staging_buffer);
+        transaction.store(&0u8, &2u32).unwrap();
+        transaction.store(&2u8, &&b"ok"[..]).unwrap();
+        assert_eq!(
+            transaction.store(&1u8, &2u32),
+            Err(SerializationError::BufferTooSmall)
+        );
+        assert_eq!(transaction.len(), 2);
+        transaction
+            .commit(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap();
+
+        for (key, value) in [(0u8, Some(2u32)), (1, None)] {
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap(),
+                value
+            );
+        }
+        assert_eq!(
+            fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &2,
+            )
+            .await
+            .unwrap(),
+            Some(&b"ok"[..])
+        );
+    }
+
+    #[test]
+    async fn transaction_removes_atomically() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut staging_buffer = [0; 32];
+
+        async fn fetch_all(flash: &mut MockFlashBig, data_buffer: &mut [u8]) -> Vec<Option<u32>> {
+            let mut values = Vec::new();
+            for key in 0..3u8 {
+                values.push(
+                    fetch_item::<u8, u32, _>(
+                        flash,
+                        FLASH_RANGE,
+                        &mut cache::NoCache::new(),
+                        data_buffer,
+                        &key,
+                    )
+                    .await
+                    .unwrap(),
+                );
+            }
+            values
+        }
+
+        store_items(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &[(&0u8, &1u32), (&1, &1u32), (&2, &1u32)],
+        )
+        .await
+        .unwrap();
+
+        {
+            // Dropped without committing, so the removed key is kept
+            let mut transaction = MapTransaction::new(&mut staging_buffer);
+            transaction.remove(&0u8).unwrap();
+            transaction.store(&1u8, &2u32).unwrap();
+        }
+        assert_eq!(
+            fetch_all(&mut flash, &mut data_buffer).await,
+            [Some(1), Some(1), Some(1)]
+        );
+
+        let mut transaction = MapTransaction::new(&mut staging_buffer);
+        transaction.remove(&0u8).unwrap();
+        transaction.store(&1u8, &2u32).unwrap();
+        assert_eq!(transaction.len(), 2);
+        transaction
+            .commit(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch_all(&mut flash, &mut data_buffer).await,
+            [None, Some(2), Some(1)]
+        );
+        assert_eq!(
+            fetch_history::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+                &mut [None, None],
+            )
+            .await
+            .unwrap(),
+            0
+        );
+
+        // The removal must survive the erase of the pages with the old values and the tombstone
+        let mut cache = cache::KeyPointerCache::<4, u8, 4>::new();
+        for value in 0..200u32 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &2u8,
+                &value,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            fetch_all(&mut flash, &mut data_buffer).await,
+            [None, Some(2), Some(199)]
+        );
+        assert_eq!(
+            check(&mut flash, FLASH_RANGE)
+                .await
+                .unwrap()
+                .corrupted_items,
+            0
+        );
+
+        // An interrupted commit either removes and stores everything or nothing
+        for shutoff in 0..48 {
+            let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+
+            store_items(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &[(&0u8, &1u32), (&1, &1u32)],
+            )
+            .await
+            .unwrap();
+
+            let mut transaction = MapTransaction::new(&mut staging_buffer);
+            transaction.remove(&0u8).unwrap();
+            transaction.store(&1u8, &2u32).unwrap();
+
+            flash.bytes_until_shutoff = Some(shutoff);
+            let result = transaction
+                .commit(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                )
+                .await;
+            flash.bytes_until_shutoff = None;
+
+            let values = fetch_all(&mut flash, &mut data_buffer).await;
+            if result.is_ok() {
+                assert_eq!(values, [None, Some(2), None], "Shutoff at {shutoff}");
+            } else {
+                assert!(
+                    values == [Some(1), Some(1), None] || values == [None, Some(2), None],
+                    "Shutoff at {shutoff}: {values:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    async fn map_stats() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        // Every item is 5 bytes of data, so 8 bytes of header + 8 bytes of padded data
+        const ITEM_SIZE: u32 = 16;
+        const PAGE_SIZE: u32 = 1024 - 8;
+
+        let empty = stats::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            empty,
+            MapStats {
+                item_count: 0,
+                used_bytes: 0,
+                stale_bytes: 0,
+                free_bytes: 3 * PAGE_SIZE,
+                open_pages: 4,
+                partial_open_pages: 0,
+                closed_pages: 0,
+            }
+        );
+        assert_eq!(
+            empty.stores_until_erase::<MockFlashBig>(5),
+            // The space at the end of every page is too small for another item
+            (3 * (PAGE_SIZE / ITEM_SIZE)) as usize
+        );
+
+        for value in 0..3u32 {
+            for key in 0..10u8 {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &value,
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        assert_eq!(
+            stats::<u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            MapStats {
+                item_count: 10,
+                used_bytes: 50,
+                stale_bytes: 20 * ITEM_SIZE,
+                free_bytes: 3 * PAGE_SIZE - 30 * ITEM_SIZE,
+                open_pages: 3,
+                partial_open_pages: 1,
+                closed_pages: 0,
+            }
+        );
+
+        // The stats and the forecast count the free space the same way
+        let map_stats = stats::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap();
+        let forecast = erase_forecast(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+            .await
+            .unwrap();
+        for item_data_length in [5, 37, 200] {
+            assert_eq!(
+                map_stats.stores_until_erase::<MockFlashBig>(item_data_length),
+                forecast.stores_until_erase::<MockFlashBig>(item_data_length)
+            );
+        }
+    }
+
+    #[test]
+    async fn compact_ahead_of_time() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        // There are still open pages, so there's nothing to compact
+        assert!(!compact::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer
+        )
+        .await
+        .unwrap());
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &0u32,
+        )
+        .await
+        .unwrap();
+        assert!(!compact::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer
+        )
+        .await
+        .unwrap());
+
+        // Fill up the flash so the next page change needs an erase
+        let mut value = 0u32;
+        loop {
+            value += 1;
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &((value % 10) as u8),
+                &value,
+            )
+            .await
+            .unwrap();
+
+            if get_page_state(&mut flash, FLASH_RANGE, &mut cache::NoCache::new(), 3)
+                .await
+                .unwrap()
+                .is_partial_open()
+            {
+                break;
+            }
+        }
+
+        let snapshot = flash.stats_snapshot();
+        assert!(compact::<u8, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer
+        )
+        .await
+        .unwrap());
+        assert_eq!(snapshot.compare_to(flash.stats_snapshot()).erases, 1);
+
+        // Storing is possible without erasing after a compact
+        let snapshot = flash.stats_snapshot();
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &u32::MAX,
+        )
+        .await
+        .unwrap();
+        assert_eq!(snapshot.compare_to(flash.stats_snapshot()).erases, 0);
+
+        for key in 0..10u8 {
+            let expected = if key == 0 {
+                u32::MAX
+            } else {
+                (0..=value).rev().find(|v| v % 10 == key as u32).unwrap()
+            };
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    async fn count_keys() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut cache = cache::KeyPointerCache::<4, u8, 4>::new();
+
+        assert_eq!(
+            len(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap(),
+            0
+        );
+
+        for value in 0..100u32 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &((value % 7) as u8),
+                &value,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            len(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap(),
+            7
+        );
+        assert_eq!(
+            len::<u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            7
+        );
+    }
+
+    #[test]
+    async fn remove_expired_items_frees_space() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut expired_keys = [None; 4];
+
+        // A plain value that would read as long expired if it were taken as an expiring value
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &u8::MAX,
+            &&[0u8; 12][..],
+        )
+        .await
+        .unwrap();
+
+        // Fill the map until it's full. Every even key expires early.
+        let mut stored_keys = 0u8;
+        loop {
+            let result = store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &stored_keys,
+                &Expiring {
+                    expires_at: if stored_keys.is_multiple_of(2) {
+                        10
+                    } else {
+                        1000
+                    },
+                    value: &[stored_keys; 8][..],
+                },
+            )
+            .await;
+            match result {
+                Ok(()) => stored_keys += 1,
+                Err(Error::FullStorage) => break,
+                Err(e) => panic!("{e:?}"),
+            }
+        }
+
+        // Nothing has expired yet
+        assert_eq!(
+            remove_expired_items::<u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                5,
+                |_| true,
+                &mut expired_keys,
+            )
+            .await
+            .unwrap(),
+            0
+        );
+        assert_eq!(
+            remove_expired_items::<u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                100,
+                |key| *key != 0,
+                &mut expired_keys,
+            )
+            .await
+            .unwrap(),
+            (stored_keys as usize).div_ceil(2) - 1
+        );
+
+        for key in 0..stored_keys {
+            let expected = match key {
+                // Key 0 was filtered out, so it's kept even though it expired
+                0 => Some(10),
+                key if key % 2 == 1 => Some(1000),
+                _ => None,
+            };
+            assert_eq!(
+                fetch_item::<u8, Expiring<&[u8]>, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap()
+                .map(|item| item.expires_at),
+                expected
+            );
+        }
+
+        assert_eq!(
+            fetch_item::<u8, &[u8], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &u8::MAX,
+            )
+            .await
+            .unwrap(),
+            Some(&[0u8; 12][..])
+        );
+
+        // The space of the removed keys can be used again
+        for key in stored_keys..stored_keys + stored_keys / 4 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &key,
+                &Expiring {
+                    expires_at: 1000,
+                    value: &[key; 8][..],
+                },
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    #[test]
+    async fn store_expiring_items() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &Expiring {
+                expires_at: 100,
+                value: &b"token"[..],
+            },
+        )
+        .await
+        .unwrap();
+
+        for (now, expected) in [
+            (0, Some(&b"token"[..])),
+            (99, Some(&b"token"[..])),
+            (100, None),
+        ] {
+            assert_eq!(
+                fetch_unexpired_item::<u8, &[u8], _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0,
+                    now,
+                )
+                .await
+                .unwrap(),
+                expected
+            );
+        }
+
+        // The expired value can still be looked at
+        assert_eq!(
+            fetch_item::<u8, Expiring<&[u8]>, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(Expiring {
+                expires_at: 100,
+                value: &b"token"[..]
+            })
+        );
+    }
+
+    #[test]
+    async fn fetch_value_history() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let mut history = [None; 4];
+        assert_eq!(
+            fetch_history::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+                &mut history,
+            )
+            .await
+            .unwrap(),
+            0
+        );
+
+        // Enough to go over multiple pages
+        for value in 0..200u32 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &((value % 2) as u8),
+                &value,
+            )
+            .await
+            .unwrap();
+
+            let mut history = [None; 4];
+            let found = fetch_history::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &((value % 2) as u8),
+                &mut history,
+            )
+            .await
+            .unwrap();
+
+            let expected_found = (value as usize / 2 + 1).min(4);
+            assert_eq!(found, expected_found);
+            for (i, entry) in history.iter().enumerate() {
+                if i < expected_found {
+                    assert_eq!(*entry, Some(value - i as u32 * 2));
+                } else {
+                    assert_eq!(*entry, None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    async fn fetch_or_store_default() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for default in [5u32, 6] {
+            assert_eq!(
+                fetch_or_store(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0u8,
+                    default,
+                )
+                .await
+                .unwrap(),
+                5
+            );
+        }
+
+        assert_eq!(
+            fetch_item::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(5)
+        );
+    }
+
+    #[test]
+    async fn replace_items() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut previous_buffer = AlignedBuf([0; 128]);
+
+        for (value, previous) in [(1u32, None), (2, Some(1u32)), (3, Some(2))] {
+            assert_eq!(
+                replace_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &mut previous_buffer,
+                    &0u8,
+                    &value,
+                )
+                .await
+                .unwrap(),
+                previous
+            );
+        }
+
+        assert_eq!(
+            fetch_item::<u8, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    async fn iterate_key_range() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for value in 0..2u32 {
+            for key in (0x0C0..0x240u16).step_by(0x40) {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &(key as u32 + value),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut items = iter_range(&mut flash, FLASH_RANGE, &mut cache, 0x100..0x200u16)
+            .await
+            .unwrap();
+
+        let mut found = Vec::new();
+        while let Some((key, value)) = items.next::<u32>(&mut data_buffer).await.unwrap() {
+            found.push((key, value));
+        }
+        found.sort();
+
+        assert_eq!(
+            found,
+            vec![
+                (0x100, 0x101),
+                (0x140, 0x141),
+                (0x180, 0x181),
+                (0x1C0, 0x1C1)
+            ]
+        );
+
+        let mut cache = cache::NoCache::new();
+        let mut items = iter_range(&mut flash, FLASH_RANGE, &mut cache, ..=0x100u16)
+            .await
+            .unwrap();
+
+        let mut found = Vec::new();
+        while let Some(key) = items.next_key(&mut data_buffer).await.unwrap() {
+            found.push(key);
+        }
+        found.sort();
+
+        assert_eq!(found, vec![0x0C0, 0x100]);
+    }
+
+    #[test]
+    async fn iterate_filtered_keys() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for value in 0..2u32 {
+            for key in 0..20u16 {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &key,
+                    &(key as u32 + value),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        let snapshot = flash.stats_snapshot();
+        let mut cache = cache::NoCache::new();
+        let mut items = iter::<u16, _, _>(&mut flash, FLASH_RANGE, &mut cache)
+            .await
+            .unwrap();
+        while items.next::<u32>(&mut data_buffer).await.unwrap().is_some() {}
+        let all_reads = snapshot.compare_to(flash.stats_snapshot()).reads;
+
+        let snapshot = flash.stats_snapshot();
+        let mut cache = cache::NoCache::new();
+        let mut items = iter_filtered(&mut flash, FLASH_RANGE, &mut cache, |key: &u16| {
+            key.is_multiple_of(5)
+        })
+        .await
+        .unwrap();
+
+        let mut found = Vec::new();
+        while let Some((key, value)) = items.next::<u32>(&mut data_buffer).await.unwrap() {
+            found.push((key, value));
+        }
+        found.sort();
+        let filtered_reads = snapshot.compare_to(flash.stats_snapshot()).reads;
+
+        assert_eq!(found, vec![(0, 1), (5, 6), (10, 11), (15, 16)]);
+        assert!(filtered_reads * 2 < all_reads);
+
+        let mut cache = cache::NoCache::new();
+        let mut items = iter_filtered(&mut flash, FLASH_RANGE, &mut cache, |key: &u16| *key >= 18)
+            .await
+            .unwrap();
+
+        let mut found = Vec::new();
+        while let Some(key) = items.next_key(&mut data_buffer).await.unwrap() {
+            found.push(key);
+        }
+        found.sort();
+
+        assert_eq!(found, vec![18, 19]);
+    }
+
+    #[test]
+    async fn store_namespaced_keys() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        const WIFI: u8 = 0;
+        const BLUETOOTH: u8 = 1;
+
+        for (namespace, value) in [(WIFI, 1u32), (BLUETOOTH, 2)] {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &Namespaced {
+                    namespace,
+                    key: 0u16,
+                },
+                &value,
+            )
+            .await
+            .unwrap();
+        }
+
+        for (namespace, value) in [(WIFI, 1u32), (BLUETOOTH, 2)] {
+            assert_eq!(
+                fetch_item::<_, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &Namespaced {
+                        namespace,
+                        key: 0u16
+                    },
+                )
+                .await
+                .unwrap(),
+                Some(value)
+            );
+        }
+
+        let mut buffer = [0; 3];
+        let key = Namespaced {
+            namespace: BLUETOOTH,
+            key: 0x1234u16,
+        };
+        assert_eq!(key.serialize_into(&mut buffer), Ok(3));
+        assert_eq!(buffer, [1, 0x34, 0x12]);
+        assert_eq!(Namespaced::<u16>::get_len(&buffer), Ok(3));
+        assert_eq!(Namespaced::deserialize_from(&buffer), Ok((key, 3)));
+    }
+
+    #[test]
+    async fn store_bool_and_option_values() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_item::<u8, bool, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(true)
+        );
+
+        for value in [Some(5u16), None] {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &1u8,
+                &value,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                fetch_item::<u8, Option<u16>, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &1,
+                )
+                .await
+                .unwrap(),
+                Some(value)
+            );
+        }
 
-    let mut next_page_write_address =
-        calculate_page_address::<S>(flash_range.clone(), target_page) + S::WORD_SIZE as u32;
+        assert_eq!(
+            bool::deserialize_from(&[2]),
+            Err(SerializationError::InvalidFormat)
+        );
+        assert_eq!(
+            Option::<u8>::deserialize_from(&[2, 0]),
+            Err(SerializationError::InvalidFormat)
+        );
+        assert_eq!(
+            Some(5u32).serialize_into(&mut [0; 4]),
+            Err(SerializationError::BufferTooSmall)
+        );
+    }
 
-    let mut it = ItemIter::new(
-        calculate_page_address::<S>(flash_range.clone(), source_page) + S::WORD_SIZE as u32,
-        calculate_page_end_address::<S>(flash_range.clone(), source_page) - S::WORD_SIZE as u32,
-    );
-    while let Some((item, item_address)) = it.next(flash, data_buffer).await? {
-        let (key, _) = K::deserialize_from(item.data())?;
-        let (_, data_buffer) = item.destruct();
+    #[cfg(feature = "derive")]
+    #[test]
+    async fn store_derived_values() {
+        #[derive(Debug, PartialEq, Value)]
+        struct Color(u8, u8, u8);
+
+        #[derive(Debug, PartialEq, Value)]
+        #[value(version = 2)]
+        struct Settings {
+            brightness: f32,
+            enabled: bool,
+            color: Color,
+            id: [u8; 4],
+        }
 
-        // We're in a decent state here
-        cache.unmark_dirty();
+        assert_eq!(<Color as FixedSizeValue>::SIZE, 4);
+        assert_eq!(<Settings as FixedSizeValue>::SIZE, 14);
 
-        // Search for the newest item with the key we found
-        let Some((found_item, found_address, _)) =
-            fetch_item_with_location::<K, S>(flash, flash_range.clone(), cache, data_buffer, &key)
-                .await?
-        else {
-            // We couldn't even find our own item?
-            return Err(Error::Corrupted {
-                #[cfg(feature = "_test")]
-                backtrace: std::backtrace::Backtrace::capture(),
-            });
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let settings = Settings {
+            brightness: 0.5,
+            enabled: true,
+            color: Color(1, 2, 3),
+            id: [4, 5, 6, 7],
         };
 
-        let found_item = found_item.reborrow(data_buffer);
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &settings,
+        )
+        .await
+        .unwrap();
 
-        if found_address == item_address {
-            cache.notice_key_location(&key, next_page_write_address, true);
-            found_item
-                .write(flash, flash_range.clone(), cache, next_page_write_address)
-                .await?;
-            next_page_write_address = found_item
-                .header
-                .next_item_address::<S>(next_page_write_address);
-        }
-    }
+        assert_eq!(
+            fetch_item::<u8, Settings, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(settings)
+        );
 
-    open_page(flash, flash_range.clone(), cache, source_page).await?;
+        // A value with another layout version is rejected
+        assert_eq!(
+            fetch_item::<u8, Color, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await,
+            Err(Error::SerializationError(SerializationError::InvalidFormat))
+        );
+        assert_eq!(
+            Color::deserialize_from(&[0, 1]),
+            Err(SerializationError::BufferTooSmall)
+        );
 
-    Ok(())
-}
+        // The old layout can still be read with a migration
+        fn migrate_brightness(
+            version: u8,
+            buffer: &[u8],
+        ) -> Result<Brightness, SerializationError> {
+            match version {
+                0 => Ok(Brightness(
+                    <u8 as Value>::deserialize_from(buffer)? as f32 / 255.0,
+                )),
+                _ => Err(SerializationError::InvalidFormat),
+            }
+        }
 
-/// Try to repair the state of the flash to hopefull get back everything in working order.
-/// Care is taken that no data is lost, but this depends on correctly repairing the state and
-/// so is only best effort.
-///
-/// This function might be called after a different function returned the [Error::Corrupted] error.
-/// There's no guarantee it will work.
-///
-/// If this function or the function call after this crate returns [Error::Corrupted], then it's unlikely
-/// that the state can be recovered. To at least make everything function again at the cost of losing the data,
-/// erase the flash range.
-async fn try_repair<K: Key, S: NorFlash>(
-    flash: &mut S,
-    flash_range: Range<u32>,
-    cache: &mut impl KeyCacheImpl<K>,
-    data_buffer: &mut [u8],
-) -> Result<(), Error<S::Error>> {
-    cache.invalidate_cache_state();
+        #[derive(Debug, PartialEq, Value)]
+        #[value(version = 1, migrate = migrate_brightness)]
+        struct Brightness(f32);
 
-    crate::try_general_repair(flash, flash_range.clone(), cache).await?;
+        assert_eq!(<Brightness as VersionedValue>::VERSION, 1);
+        assert_eq!(<Settings as VersionedValue>::VERSION, 2);
+        assert_eq!(Brightness::deserialize_from(&[0, 255]), Ok(Brightness(1.0)));
+        assert_eq!(
+            Brightness::deserialize_from(&[1, 0, 0, 0, 63]),
+            Ok(Brightness(0.5))
+        );
+        assert_eq!(
+            Brightness::deserialize_from(&[2, 0, 0, 0, 63]),
+            Err(SerializationError::InvalidFormat)
+        );
 
-    // Let's check if we corrupted in the middle of a migration
-    if let Some(partial_open_page) =
-        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?
-    {
-        let buffer_page = next_page::<S>(flash_range.clone(), partial_open_page);
-        if !get_page_state(flash, flash_range.clone(), cache, buffer_page)
-            .await?
-            .is_open()
-        {
-            // Yes, the migration got interrupted. Let's redo it.
-            // To do that, we erase the partial open page first because it contains incomplete data.
-            open_page(flash, flash_range.clone(), cache, partial_open_page).await?;
+        // A field that doesn't serialize to its fixed size is caught
+        struct Liar;
 
-            // Then partially close it again
-            partial_close_page(flash, flash_range.clone(), cache, partial_open_page).await?;
+        impl Value<'_> for Liar {
+            fn serialize_into(&self, _buffer: &mut [u8]) -> Result<usize, SerializationError> {
+                Ok(0)
+            }
 
-            migrate_items::<K, _>(
-                flash,
-                flash_range.clone(),
-                cache,
-                data_buffer,
-                buffer_page,
-                partial_open_page,
-            )
-            .await?;
+            fn deserialize_from(_buffer: &[u8]) -> Result<Self, SerializationError> {
+                Ok(Liar)
+            }
         }
-    }
 
-    Ok(())
-}
+        impl FixedSizeValue for Liar {
+            const SIZE: usize = 2;
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use futures_test::test;
+        #[derive(Value)]
+        struct Wrapper(Liar, u8);
 
-    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
-    type MockFlashTiny = mock_flash::MockFlashBase<2, 1, 32>;
+        assert_eq!(
+            Wrapper(Liar, 1).serialize_into(&mut [0; 8]),
+            Err(SerializationError::InvalidData)
+        );
+        assert_eq!(
+            Wrapper(Liar, 1).serialize_into(&mut [0; 2]),
+            Err(SerializationError::BufferTooSmall)
+        );
+    }
 
     #[test]
-    async fn store_and_fetch() {
-        let mut flash = MockFlashBig::default();
-        let flash_range = 0x000..0x1000;
+    async fn store_typed_items() {
+        crate::map_keys! {
+            key_type: u16;
+            Brightness: u8 = 1;
+            Volume: Option<u32> = 0x100;
+        }
 
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
         let mut data_buffer = AlignedBuf([0; 128]);
 
-        let start_snapshot = flash.stats_snapshot();
-
-        let item = fetch_item::<u8, &[u8], _>(
+        store_typed_item::<Brightness, _>(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &0,
+            &50,
         )
         .await
         .unwrap();
-        assert_eq!(item, None);
-
-        let item = fetch_item::<u8, &[u8], _>(
+        store_typed_item::<Volume, _>(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &60,
+            &Some(7),
         )
         .await
         .unwrap();
-        assert_eq!(item, None);
 
-        let item = fetch_item::<u8, &[u8], _>(
+        assert_eq!(
+            fetch_typed_item::<Brightness, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            Some(50)
+        );
+        assert_eq!(
+            fetch_typed_item::<Volume, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            Some(Some(7))
+        );
+
+        // The typed keys are normal keys in the map
+        assert_eq!(
+            fetch_item::<u16, u8, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &1,
+            )
+            .await
+            .unwrap(),
+            Some(50)
+        );
+    }
+
+    #[test]
+    async fn read_only_map() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        store_item(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &0xFF,
+            &1u8,
+            &42u32,
         )
         .await
         .unwrap();
-        assert_eq!(item, None);
 
-        store_item(
+        let snapshot = flash.stats_snapshot();
+        let mut reader = MapReader::<u8, _, _>::new(&mut flash, FLASH_RANGE, cache::NoCache::new());
+
+        assert_eq!(
+            reader
+                .fetch_item::<u32>(&mut data_buffer, &1)
+                .await
+                .unwrap(),
+            Some(42)
+        );
+        assert_eq!(
+            reader
+                .fetch_item::<u32>(&mut data_buffer, &2)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // Corruption is reported instead of repaired. Closing all pages is something that can't normally happen.
+        for page in flash.as_bytes_mut().chunks_mut(1024) {
+            page[..4].fill(0);
+            page[1020..].fill(0);
+        }
+        let mut reader = MapReader::<u8, _, _>::new(&mut flash, FLASH_RANGE, cache::NoCache::new());
+        assert!(matches!(
+            reader.fetch_item::<u32>(&mut data_buffer, &1).await,
+            Err(Error::Corrupted { .. })
+        ));
+
+        let stats = snapshot.compare_to(flash.stats_snapshot());
+        assert_eq!(stats.writes, 0);
+        assert_eq!(stats.erases, 0);
+    }
+
+    #[test]
+    async fn check_and_repair() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for i in 0..20u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &(i % 4),
+                &[i; 100],
+            )
+            .await
+            .unwrap();
+            let report = check(&mut flash, FLASH_RANGE).await.unwrap();
+            assert!(report.is_ok(), "{report:?}");
+        }
+
+        // Interrupt stores until a migration gets interrupted
+        let mut i = 20u8;
+        loop {
+            flash.bytes_until_shutoff = Some(200);
+            let result = store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &(i % 4),
+                &[i; 100],
+            )
+            .await;
+            flash.bytes_until_shutoff = None;
+
+            let report = check(&mut flash, FLASH_RANGE).await.unwrap();
+            if report.interrupted_migration {
+                assert!(result.is_err());
+                assert!(!report.is_ok());
+                break;
+            }
+            assert!(report.is_ok(), "{report:?}");
+
+            i += 1;
+            assert!(i < 100);
+        }
+
+        let report = repair::<u8, _>(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &0u8,
-            &[5],
         )
         .await
         .unwrap();
-        store_item(
+        assert!(report.is_ok(), "{report:?}");
+
+        for key in 0..4u8 {
+            let value = fetch_item::<u8, [u8; 100], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &key,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(value[0] % 4, key);
+        }
+    }
+
+    #[test]
+    async fn fetch_keys_by_tag() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for (namespace, key) in [(0, 0u8), (1, 0), (1, 1), (2, 0), (1, 2), (1, 0)] {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &Namespaced { namespace, key },
+                &key,
+            )
+            .await
+            .unwrap();
+        }
+        remove_item(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &0u8,
-            &[5, 6],
+            &Namespaced {
+                namespace: 1,
+                key: 1u8,
+            },
         )
         .await
         .unwrap();
 
-        let item = fetch_item::<u8, &[u8], _>(
+        let mut keys = [Namespaced {
+            namespace: 0,
+            key: 0u8,
+        }; 4];
+        let count = fetch_by_tag(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &0,
+            &1,
+            &mut keys,
         )
         .await
-        .unwrap()
         .unwrap();
-        assert_eq!(item, &[5, 6]);
+        assert_eq!(count, 2);
+        let mut found = keys[..count].iter().map(|k| k.key).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, [0, 2]);
+
+        // Too small buffer still counts all keys
+        assert_eq!(
+            fetch_by_tag(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &1,
+                &mut keys[..1],
+            )
+            .await
+            .unwrap(),
+            2
+        );
+        assert_eq!(
+            fetch_by_tag(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &5,
+                &mut keys,
+            )
+            .await
+            .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn fetch_migrated_value() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        #[derive(Debug, PartialEq)]
+        struct SettingsV1(u8);
+        #[derive(Debug, PartialEq)]
+        struct SettingsV2(u8, u16);
+
+        impl<'a> Value<'a> for SettingsV1 {
+            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+                Value::serialize_into(&self.0, buffer)
+            }
+
+            fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+                Ok(Self(<u8 as Value>::deserialize_from(buffer)?))
+            }
+        }
+
+        impl<'a> VersionedValue<'a> for SettingsV1 {
+            const VERSION: u8 = 1;
+        }
+
+        impl<'a> Value<'a> for SettingsV2 {
+            fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+                let len = Value::serialize_into(&self.0, buffer)?;
+                Ok(len + Value::serialize_into(&self.1, &mut buffer[len..])?)
+            }
+
+            fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+                Ok(Self(
+                    <u8 as Value>::deserialize_from(buffer)?,
+                    <u16 as Value>::deserialize_from(&buffer[1..])?,
+                ))
+            }
+        }
 
+        impl<'a> VersionedValue<'a> for SettingsV2 {
+            const VERSION: u8 = 2;
+
+            fn migrate(version: u8, buffer: &'a [u8]) -> Result<Self, SerializationError> {
+                match version {
+                    1 => Ok(Self(SettingsV1::deserialize_from(buffer)?.0, 1000)),
+                    _ => Err(SerializationError::InvalidFormat),
+                }
+            }
+        }
+
+        // Stored by the old firmware
         store_item(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &1u8,
-            &[2, 2, 2, 2, 2, 2],
+            &0u8,
+            &Versioned(SettingsV1(5)),
         )
         .await
         .unwrap();
 
-        let item = fetch_item::<u8, &[u8], _>(
-            &mut flash,
-            flash_range.clone(),
-            &mut cache::NoCache::new(),
-            &mut data_buffer,
-            &0,
-        )
-        .await
-        .unwrap()
-        .unwrap();
-        assert_eq!(item, &[5, 6]);
+        // Fetched by the new firmware
+        assert_eq!(
+            fetch_item::<u8, Versioned<SettingsV2>, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(Versioned(SettingsV2(5, 1000)))
+        );
 
-        let item = fetch_item::<u8, &[u8], _>(
+        store_item(
             &mut flash,
-            flash_range.clone(),
+            FLASH_RANGE,
             &mut cache::NoCache::new(),
             &mut data_buffer,
-            &1,
+            &0u8,
+            &Versioned(SettingsV2(6, 2000)),
         )
         .await
-        .unwrap()
         .unwrap();
-        assert_eq!(item, &[2, 2, 2, 2, 2, 2]);
-
-        for index in 0..4000 {
-            store_item(
+        assert_eq!(
+            fetch_item::<u8, Versioned<SettingsV2>, _>(
                 &mut flash,
-                flash_range.clone(),
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &((index % 10) as u8),
-                &vec![(index % 10) as u8 * 2; index % 10].as_slice(),
+                &0,
             )
             .await
-            .unwrap();
-        }
+            .unwrap(),
+            Some(Versioned(SettingsV2(6, 2000)))
+        );
 
-        for i in 0..10 {
-            let item = fetch_item::<u8, &[u8], _>(
+        // The old firmware doesn't know how to read the new version
+        assert_eq!(
+            fetch_item::<u8, Versioned<SettingsV1>, _>(
                 &mut flash,
-                flash_range.clone(),
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &i,
+                &0,
+            )
+            .await,
+            Err(Error::SerializationError(SerializationError::InvalidFormat))
+        );
+    }
+
+    #[test]
+    async fn keep_history_through_erases() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut history_buffer = [0; 32];
+
+        for value in 0..5u32 {
+            store_item_with_history(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &mut history_buffer,
+                &0u8,
+                &value,
+                NonZeroUsize::new(3).unwrap(),
             )
             .await
-            .unwrap()
             .unwrap();
-            assert_eq!(item, &vec![(i % 10) * 2; (i % 10) as usize]);
         }
 
-        for _ in 0..4000 {
+        // Erase every page a couple of times
+        for i in 0..100u32 {
             store_item(
                 &mut flash,
-                flash_range.clone(),
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &11u8,
-                &[0; 10],
+                &1u8,
+                &[i as u8; 64],
             )
             .await
             .unwrap();
         }
 
-        for i in 0..10 {
-            let item = fetch_item::<u8, &[u8], _>(
+        for (version, value) in [(0, Some(4)), (1, Some(3)), (2, Some(2)), (3, None)] {
+            assert_eq!(
+                fetch_item_version::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                    &0,
+                    version,
+                )
+                .await
+                .unwrap(),
+                value
+            );
+        }
+
+        assert_eq!(
+            fetch_item_version::<u8, u32, _>(
                 &mut flash,
-                flash_range.clone(),
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &i,
+                &2,
+                0,
             )
             .await
-            .unwrap()
-            .unwrap();
-            assert_eq!(item, &vec![(i % 10) * 2; (i % 10) as usize]);
-        }
+            .unwrap(),
+            None
+        );
 
-        println!("{:?}", start_snapshot.compare_to(flash.stats_snapshot()),);
+        assert_eq!(
+            store_item_with_history(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &mut history_buffer[..10],
+                &0u8,
+                &5u32,
+                NonZeroUsize::new(3).unwrap(),
+            )
+            .await,
+            Err(Error::BufferTooSmall(12))
+        );
     }
 
     #[test]
-    async fn store_too_many_items() {
-        const UPPER_BOUND: u8 = 3;
-
-        let mut tiny_flash = MockFlashTiny::default();
+    async fn forecast_erases() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut cache = cache::PageStateCache::<4>::new();
         let mut data_buffer = AlignedBuf([0; 128]);
 
-        for i in 0..UPPER_BOUND {
-            println!("Storing {i:?}");
+        let mut value = 0u8;
+        for _ in 0..3 {
+            let forecast = erase_forecast(&mut flash, FLASH_RANGE, &mut cache)
+                .await
+                .unwrap();
+            let stores_until_erase = forecast.stores_until_erase::<MockFlashBig>(1 + 100);
+
+            let snapshot = flash.stats_snapshot();
+            for _ in 0..stores_until_erase {
+                store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    &(value % 4),
+                    &[value; 100],
+                )
+                .await
+                .unwrap();
+                value = value.wrapping_add(1);
+            }
+            assert_eq!(snapshot.compare_to(flash.stats_snapshot()).erases, 0);
 
+            // The next store does the erase of the forecasted page
             store_item(
-                &mut tiny_flash,
-                0x00..0x40,
-                &mut cache::NoCache::new(),
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
                 &mut data_buffer,
-                &i,
-                &vec![i; i as usize].as_slice(),
+                &(value % 4),
+                &[value; 100],
             )
             .await
             .unwrap();
+            value = value.wrapping_add(1);
+            assert_eq!(snapshot.compare_to(flash.stats_snapshot()).erases, 1);
+
+            let erased_page = &flash.as_bytes()[forecast.next_erased_page * MockFlashBig::ERASE_SIZE
+                ..(forecast.next_erased_page + 1) * MockFlashBig::ERASE_SIZE];
+            assert!(erased_page.iter().all(|byte| *byte == 0xFF));
         }
+    }
+
+    #[test]
+    async fn reserved_keys() {
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let user_key = Namespaced {
+            namespace: *RESERVED_NAMESPACES.start() - 1,
+            key: 0u8,
+        };
+        let system_key = Namespaced {
+            namespace: *RESERVED_NAMESPACES.start(),
+            key: 0u8,
+        };
+        assert!(!user_key.is_reserved());
+        assert!(system_key.is_reserved());
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &user_key,
+            &1u8,
+        )
+        .await
+        .unwrap();
 
         assert_eq!(
             store_item(
-                &mut tiny_flash,
-                0x00..0x40,
+                &mut flash,
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &UPPER_BOUND,
-                &vec![0; UPPER_BOUND as usize].as_slice(),
+                &system_key,
+                &1u8,
             )
             .await,
-            Err(Error::FullStorage)
+            Err(Error::ReservedKey)
+        );
+        assert_eq!(
+            store_items(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &[(&user_key, &2u8), (&system_key, &2u8)],
+            )
+            .await,
+            Err(Error::ReservedKey)
+        );
+        assert_eq!(
+            remove_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &system_key,
+            )
+            .await,
+            Err(Error::ReservedKey)
         );
 
-        for i in 0..UPPER_BOUND {
-            let item = fetch_item::<u8, &[u8], _>(
-                &mut tiny_flash,
-                0x00..0x40,
+        assert_eq!(
+            fetch_item::<_, u8, _>(
+                &mut flash,
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &i,
+                &user_key,
             )
             .await
-            .unwrap()
-            .unwrap();
-
-            println!("Fetched {item:?}");
-
-            assert_eq!(item, vec![i; i as usize]);
-        }
+            .unwrap(),
+            Some(1)
+        );
     }
 
     #[test]
-    async fn store_too_many_items_big() {
-        const UPPER_BOUND: u8 = 68;
-
-        let mut big_flash = MockFlashBig::default();
-        let mut data_buffer = AlignedBuf([0; 128]);
+    async fn fetch_value_in_parts() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 1024]);
+        let mut chunk_buffer = [0; 256];
 
-        for i in 0..UPPER_BOUND {
-            println!("Storing {i:?}");
+        let certificate: Vec<u8> = (0..900).map(|i| (i % 251) as u8).collect();
 
+        for (key, value) in [(0u8, &[1u8; 10][..]), (1, &certificate), (2, &[2; 10])] {
             store_item(
-                &mut big_flash,
-                0x0000..0x1000,
+                &mut flash,
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &i,
-                &vec![i; i as usize].as_slice(),
+                &key,
+                &value,
             )
             .await
             .unwrap();
         }
 
+        let mut cache = cache::KeyPointerCache::<4, u8, 4>::new();
+        for _ in 0..2 {
+            // The second time, the location of the key is cached
+            let mut fetched = Vec::new();
+            loop {
+                let read = fetch_item_range(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut chunk_buffer,
+                    &1u8,
+                    fetched.len()..fetched.len() + 256,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+
+                if read == 0 {
+                    break;
+                }
+                fetched.extend_from_slice(&chunk_buffer[..read]);
+            }
+            assert_eq!(fetched, certificate);
+        }
+
         assert_eq!(
-            store_item(
-                &mut big_flash,
-                0x0000..0x1000,
+            fetch_item_range(
+                &mut flash,
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
-                &mut data_buffer,
-                &UPPER_BOUND,
-                &vec![0; UPPER_BOUND as usize].as_slice(),
+                &mut chunk_buffer,
+                &2u8,
+                8..12,
             )
-            .await,
-            Err(Error::FullStorage)
+            .await
+            .unwrap(),
+            Some(2)
         );
+        assert_eq!(chunk_buffer[..2], [2, 2]);
 
-        for i in 0..UPPER_BOUND {
-            let item = fetch_item::<u8, &[u8], _>(
-                &mut big_flash,
-                0x0000..0x1000,
+        assert_eq!(
+            fetch_item_range(
+                &mut flash,
+                FLASH_RANGE,
                 &mut cache::NoCache::new(),
-                &mut data_buffer,
-                &i,
+                &mut chunk_buffer,
+                &3u8,
+                0..256,
             )
             .await
-            .unwrap()
-            .unwrap();
-
-            println!("Fetched {item:?}");
-
-            assert_eq!(item, vec![i; i as usize]);
-        }
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            fetch_item_range(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut chunk_buffer,
+                &1u8,
+                0..257,
+            )
+            .await,
+            Err(Error::BufferTooSmall(257))
+        );
     }
 
     #[test]
-    async fn store_many_items_big() {
-        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::default();
+    async fn store_and_fetch_raw_bytes() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
         let mut data_buffer = AlignedBuf([0; 128]);
 
-        const LENGHT_PER_KEY: [usize; 24] = [
-            11, 13, 6, 13, 13, 10, 2, 3, 5, 36, 1, 65, 4, 6, 1, 15, 10, 7, 3, 15, 9, 3, 4, 5,
-        ];
+        store_raw(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &1u8,
+            &[0x08, 0x96, 0x01],
+        )
+        .await
+        .unwrap();
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &2u8,
+            &0x12345678u32,
+        )
+        .await
+        .unwrap();
 
-        for _ in 0..100 {
-            for i in 0..24 {
-                store_item(
-                    &mut flash,
-                    0x0000..0x4000,
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer,
-                    &(i as u16),
-                    &vec![i as u8; LENGHT_PER_KEY[i]].as_slice(),
-                )
+        assert_eq!(
+            fetch_raw(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &1u8)
                 .await
-                .unwrap();
-            }
-        }
+                .unwrap(),
+            Some([0x08, 0x96, 0x01].as_slice())
+        );
+        assert_eq!(
+            fetch_raw(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &2u8)
+                .await
+                .unwrap(),
+            Some(0x12345678u32.to_le_bytes().as_slice())
+        );
+        assert_eq!(
+            fetch_raw(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &3u8)
+                .await
+                .unwrap(),
+            None
+        );
+    }
 
-        for i in 0..24 {
-            let item = fetch_item::<u16, &[u8], _>(
+    #[test]
+    async fn hashed_keys() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let wifi = Hashed::new(*b"settings/network/wifi", &mut data_buffer).unwrap();
+        let ble = Hashed::new(*b"settings/network/ble!", &mut data_buffer).unwrap();
+        // A key with the same hash as the wifi key must still not be mistaken for it
+        let collision = Hashed {
+            hash: wifi.hash,
+            key: *b"settings/network/lte!",
+        };
+        assert_ne!(wifi.hash, ble.hash);
+
+        for (key, value) in [(&wifi, 1u32), (&ble, 2), (&wifi, 3)] {
+            store_item(
                 &mut flash,
-                0x0000..0x4000,
-                &mut cache::NoCache::new(),
+                FLASH_RANGE,
+                &mut cache,
                 &mut data_buffer,
-                &(i as u16),
+                key,
+                &value,
             )
             .await
-            .unwrap()
             .unwrap();
+        }
 
-            println!("Fetched {item:?}");
-
-            assert_eq!(item, vec![i as u8; LENGHT_PER_KEY[i]]);
+        for (key, value) in [(&wifi, Some(3u32)), (&ble, Some(2)), (&collision, None)] {
+            assert_eq!(
+                fetch_item::<_, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    key,
+                )
+                .await
+                .unwrap(),
+                value
+            );
         }
+
+        let len = wifi.serialize_into(&mut data_buffer).unwrap();
+        assert_eq!(len, 4 + 21);
+        assert_eq!(
+            Hashed::deserialize_from(&data_buffer[..len]),
+            Ok((wifi, len))
+        );
+        assert!(!collision.matches_serialized(&data_buffer[..len]).unwrap());
     }
 
     #[test]
-    async fn remove_items() {
-        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::new(
-            mock_flash::WriteCountCheck::Twice,
-            None,
-            true,
-        );
+    async fn increment_counters() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut cache = cache::KeyPointerCache::<4, u8, 4>::new();
         let mut data_buffer = AlignedBuf([0; 128]);
-        const FLASH_RANGE: Range<u32> = 0x0000..0x4000;
 
-        // Add some data to flash
-        for j in 0..10 {
-            for i in 0..24 {
-                store_item(
+        for count in 1..=1000u32 {
+            assert_eq!(
+                increment(
                     &mut flash,
                     FLASH_RANGE,
-                    &mut cache::NoCache::new(),
+                    &mut cache,
                     &mut data_buffer,
-                    &(i as u8),
-                    &vec![i as u8; j + 2].as_slice(),
+                    &0u8,
+                    1u32
                 )
                 .await
-                .unwrap();
-            }
+                .unwrap(),
+                count
+            );
         }
 
-        for j in (0..24).rev() {
-            // Are all things still in flash that we expect?
-            for i in 0..=j {
-                assert!(fetch_item::<u8, &[u8], _>(
+        // Nothing is written if the count doesn't change
+        increment(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &1u8,
+            250u8,
+        )
+        .await
+        .unwrap();
+        for (delta, count) in [(0u8, 250u8), (10, 255), (1, 255)] {
+            let snapshot = flash.stats_snapshot();
+            assert_eq!(
+                increment(
                     &mut flash,
                     FLASH_RANGE,
-                    &mut cache::NoCache::new(),
+                    &mut cache,
                     &mut data_buffer,
-                    &i
+                    &1u8,
+                    delta
                 )
                 .await
-                .unwrap()
-                .is_some());
-            }
+                .unwrap(),
+                count
+            );
+            assert_eq!(
+                snapshot.compare_to(flash.stats_snapshot()).writes == 0,
+                delta != 10
+            );
+        }
 
-            // Remove the item
-            remove_item::<u8, _>(
+        // A power loss during an increment leaves either the old or the new count
+        for shutoff in 0..32 {
+            flash.bytes_until_shutoff = Some(shutoff);
+            let result = increment(
                 &mut flash,
                 FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &j,
+                &0u8,
+                1u32,
             )
-            .await
-            .unwrap();
-
-            // Are all things still in flash that we expect?
-            for i in 0..j {
-                assert!(fetch_item::<u8, &[u8], _>(
-                    &mut flash,
-                    FLASH_RANGE,
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer,
-                    &i
-                )
-                .await
-                .unwrap()
-                .is_some());
-            }
+            .await;
+            flash.bytes_until_shutoff = None;
 
-            assert!(fetch_item::<u8, &[u8], _>(
+            let count = fetch_item::<u8, u32, _>(
                 &mut flash,
                 FLASH_RANGE,
                 &mut cache::NoCache::new(),
                 &mut data_buffer,
-                &j
+                &0u8,
             )
             .await
             .unwrap()
-            .is_none());
-        }
-    }
-
-    #[test]
-    async fn remove_all() {
-        let mut flash = mock_flash::MockFlashBase::<4, 1, 4096>::new(
-            mock_flash::WriteCountCheck::Twice,
-            None,
-            true,
-        );
-        let mut data_buffer = AlignedBuf([0; 128]);
-        const FLASH_RANGE: Range<u32> = 0x0000..0x4000;
+            .unwrap();
+            assert!(count == 1000 + shutoff || count == 1001 + shutoff);
+            if result.is_ok() {
+                assert_eq!(count, 1001 + shutoff);
+            }
 
-        // Add some data to flash
-        for value in 0..10 {
-            for key in 0..24u8 {
-                store_item(
+            // Keep the expected count simple by completing the increment
+            if count == 1000 + shutoff {
+                increment(
                     &mut flash,
                     FLASH_RANGE,
                     &mut cache::NoCache::new(),
                     &mut data_buffer,
-                    &key,
-                    &vec![key; value + 2].as_slice(),
+                    &0u8,
+                    1u32,
                 )
                 .await
                 .unwrap();
             }
         }
+    }
 
-        // Sanity check that we can find all the keys we just added.
-        for key in 0..24u8 {
-            assert!(fetch_item::<u8, &[u8], _>(
+    #[test]
+    async fn list_keys_sorted() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for key in [7u16, 3, 9, 1, 3, 5, 7] {
+            store_item(
                 &mut flash,
                 FLASH_RANGE,
-                &mut cache::NoCache::new(),
+                &mut cache,
                 &mut data_buffer,
-                &key
+                &key,
+                &0u8,
             )
             .await
-            .unwrap()
-            .is_some());
+            .unwrap();
         }
+        remove_item(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &9u16)
+            .await
+            .unwrap();
 
-        // Remove all the items
-        remove_all_items::<u8, _>(
+        let mut keys = [0u16; 8];
+        let count = keys_sorted(
             &mut flash,
             FLASH_RANGE,
-            &mut cache::NoCache::new(),
+            &mut cache,
             &mut data_buffer,
+            &mut keys,
         )
         .await
         .unwrap();
+        assert_eq!(keys[..count], [1, 3, 5, 7]);
 
-        // Verify that none of the keys are present in flash.
-        for key in 0..24 {
-            assert!(fetch_item::<u8, &[u8], _>(
+        let mut keys = [0u16; 3];
+        let count = keys_sorted(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &mut keys,
+        )
+        .await
+        .unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(keys, [1, 3, 5]);
+    }
+
+    #[test]
+    async fn get_item_info() {
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut flash = MockFlashBig::new(mock_flash::WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for (key, value) in [(0u8, &[0u8; 10][..]), (1, &[1; 100]), (0, &[0; 20])] {
+            store_item(
                 &mut flash,
                 FLASH_RANGE,
-                &mut cache::NoCache::new(),
+                &mut cache,
                 &mut data_buffer,
-                &key
+                &key,
+                &value,
             )
             .await
-            .unwrap()
-            .is_none());
+            .unwrap();
         }
+
+        // Only the key has to fit in the buffer
+        let mut key_buffer = [0; 4];
+        let info = item_info(&mut flash, FLASH_RANGE, &mut cache, &mut key_buffer, &0u8)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.value_length, 20);
+        assert_eq!(info.page_index, 0);
+
+        let info = item_info(&mut flash, FLASH_RANGE, &mut cache, &mut key_buffer, &1u8)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.value_length, 100);
+        // Behind the page state and the first item of key 0
+        assert_eq!(info.address, 4 + 8 + 12);
+
+        assert_eq!(
+            item_info(&mut flash, FLASH_RANGE, &mut cache, &mut key_buffer, &2u8)
+                .await
+                .unwrap(),
+            None
+        );
     }
 
     #[test]
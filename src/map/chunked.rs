@@ -0,0 +1,581 @@
+use crate::item::crc32;
+
+use super::*;
+
+/// The length of the head item of a large value. See [store_large_item] for the layout.
+const HEAD_LENGTH: usize = 9;
+/// The generation of the chunks is stored in the highest bit of the chunk index
+const GENERATION_BIT: u16 = 0x8000;
+
+/// A key that can address the chunks of a value that doesn't fit in a single item.
+/// Such values are stored with [store_large_item] and fetched with [fetch_large_item].
+///
+/// The chunk index is stored as two bytes behind the key. A key made with [Chunked::new] addresses
+/// the first chunk, so it can also be used with all other map functions to store normal values.
+///
+/// <div class="warning">
+///
+/// *All parts of the application must use the same key type (so `Chunked<K>` with the same `K`) on the flash range.*
+///
+/// </div>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Chunked<K> {
+    /// The key the value belongs to
+    pub key: K,
+    chunk: u16,
+}
+
+impl<K> Chunked<K> {
+    /// Create the key of the first chunk of the value of the given key
+    pub const fn new(key: K) -> Self {
+        Self { key, chunk: 0 }
+    }
+
+    /// The key of a chunk of the data of a large value
+    fn data(key: K, generation: u8, index: usize) -> Self {
+        Self {
+            key,
+            chunk: (generation as u16 * GENERATION_BIT) | (index as u16 + 1),
+        }
+    }
+
+    /// The generation and index of the data chunk this key addresses, or None for the first key
+    fn data_chunk(&self) -> Option<(u8, usize)> {
+        match self.chunk {
+            0 => None,
+            chunk => Some((
+                (chunk / GENERATION_BIT) as u8,
+                (chunk % GENERATION_BIT) as usize - 1,
+            )),
+        }
+    }
+}
+
+impl<K: Key> Key for Chunked<K> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let key_len = self.key.serialize_into(buffer)?;
+        buffer
+            .get_mut(key_len..key_len + 2)
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(&self.chunk.to_le_bytes());
+        Ok(key_len + 2)
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let (key, key_len) = K::deserialize_from(buffer)?;
+        let chunk = buffer
+            .get(key_len..key_len + 2)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        Ok((
+            Self {
+                key,
+                chunk: u16::from_le_bytes(chunk.try_into().unwrap()),
+            },
+            key_len + 2,
+        ))
+    }
+
+    fn get_len(buffer: &[u8]) -> Result<usize, SerializationError> {
+        Ok(K::get_len(buffer)? + 2)
+    }
+
+    fn is_reserved(&self) -> bool {
+        self.key.is_reserved()
+    }
+
+    fn matches_serialized(&self, buffer: &[u8]) -> Result<bool, SerializationError> {
+        let key_len = K::get_len(buffer)?;
+        let chunk = buffer
+            .get(key_len..key_len + 2)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        Ok(*chunk == self.chunk.to_le_bytes() && self.key.matches_serialized(buffer)?)
+    }
+}
+
+/// Store a value that may be too big to fit in a single item, like a certificate that is bigger than a page.
+/// It will overwrite the last value that has the same key.
+///
+/// The value is split in chunks that are stored as separate items. The chunks are as big as fit in the data buffer
+/// together with the key, so the value can span many pages. Every chunk has its own [Chunked] key.
+/// When all chunks are written, an item with the first key is stored that holds:
+///
+/// | Field | Size | Description |
+/// | ----- | ---- | ----------- |
+/// | Generation | 1 | Which of the two sets of chunk keys is used |
+/// | Length | 4 | The length of the value as a u32 |
+/// | CRC | 4 | A crc32 over the whole value as a u32 |
+///
+/// The chunks of a new value are written with the other generation than the current value uses,
+/// so if storing is interrupted, the current value can still be fetched.
+/// This means that the flash must have room for both the current and the new value.
+/// Once the new value is stored, the chunks of the previous value are removed in one pass over the flash,
+/// together with any chunks that were left behind by an interrupted store.
+///
+/// The data buffer must be long enough to hold the serialized [Chunked] key and at least one byte
+/// of the value, rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn store_large_item<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<Chunked<K>>,
+    data_buffer: &mut [u8],
+    key: &K,
+    value: &[u8],
+) -> Result<(), Error<S::Error>> {
+    let head_key = Chunked::new(key.clone());
+
+    let generation =
+        match fetch_item::<_, &[u8], _>(flash, flash_range.clone(), cache, data_buffer, &head_key)
+            .await?
+        {
+            Some(head) => LargeItemHead::deserialize_from(head)?.generation ^ 1,
+            None => 0,
+        };
+
+    let key_len = head_key.serialize_into(data_buffer)?;
+    let chunk_len = round_down_to_alignment_usize::<S>(data_buffer.len())
+        .min(max_item_size::<S>())
+        .saturating_sub(key_len);
+    if chunk_len == 0 {
+        return Err(Error::BufferTooSmall(round_up_to_alignment_usize::<S>(
+            key_len + 1,
+        )));
+    }
+    if value.len().div_ceil(chunk_len) >= GENERATION_BIT as usize || value.len() > u32::MAX as usize
+    {
+        return Err(Error::ItemTooBig);
+    }
+
+    for (index, chunk) in value.chunks(chunk_len).enumerate() {
+        store_item(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            &Chunked::data(key.clone(), generation, index),
+            &chunk,
+        )
+        .await?;
+    }
+
+    let head = LargeItemHead {
+        generation,
+        length: value.len() as u32,
+        crc: crc32(value),
+    }
+    .serialize();
+
+    store_item(
+        flash,
+        flash_range.clone(),
+        cache,
+        data_buffer,
+        &head_key,
+        &head.as_slice(),
+    )
+    .await?;
+
+    let chunk_count = value.len().div_ceil(chunk_len);
+    let is_stale = |chunked: &Chunked<K>| {
+        chunked.key == *key
+            && chunked
+                .data_chunk()
+                .is_some_and(|(chunk_generation, index)| {
+                    chunk_generation != generation || index >= chunk_count
+                })
+    };
+
+    run_with_auto_repair!(
+        function = remove_item_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            RemoveItems::Matching(&is_stale)
+        )
+        .await,
+        repair =
+            try_repair::<Chunked<K>, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// Remove a value that was stored with [store_large_item], including all of its chunks.
+///
+/// The first key is removed first, so if removing is interrupted, the value is already gone
+/// and the chunks that are left are removed by the next [store_large_item] with the same key.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn remove_large_item<K: Key, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<Chunked<K>>,
+    data_buffer: &mut [u8],
+    key: &K,
+) -> Result<(), Error<S::Error>> {
+    remove_item(
+        flash,
+        flash_range.clone(),
+        cache,
+        data_buffer,
+        &Chunked::new(key.clone()),
+    )
+    .await?;
+
+    let is_chunk = |chunked: &Chunked<K>| chunked.key == *key;
+
+    run_with_auto_repair!(
+        function = remove_item_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            RemoveItems::Matching(&is_chunk)
+        )
+        .await,
+        repair =
+            try_repair::<Chunked<K>, _>(flash, flash_range.clone(), cache, data_buffer).await?
+    )
+}
+
+/// Fetch a value that was stored with [store_large_item].
+/// The value is read into the value buffer and its length is returned.
+/// If no value with the key is found, None is returned.
+///
+/// Every chunk is searched like [fetch_item] does, so using a key cache helps a lot here.
+/// If a chunk is missing or the value doesn't match its crc, [Error::Corrupted] is returned.
+///
+/// The data buffer must be at least as long as the one the value was stored with.
+/// The value buffer must be long enough to hold the whole value.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn fetch_large_item<K: Key, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<Chunked<K>>,
+    data_buffer: &mut [u8],
+    key: &K,
+    value_buffer: &mut [u8],
+) -> Result<Option<usize>, Error<S::Error>> {
+    let Some(head) = fetch_item::<_, &[u8], _>(
+        flash,
+        flash_range.clone(),
+        cache,
+        data_buffer,
+        &Chunked::new(key.clone()),
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    let head = LargeItemHead::deserialize_from(head)?;
+
+    let length = head.length as usize;
+    if value_buffer.len() < length {
+        return Err(Error::BufferTooSmall(length));
+    }
+
+    let mut read = 0;
+    let mut index = 0;
+    while read < length {
+        let chunk = fetch_item::<_, &[u8], _>(
+            flash,
+            flash_range.clone(),
+            cache,
+            data_buffer,
+            &Chunked::data(key.clone(), head.generation, index),
+        )
+        .await?;
+
+        match chunk {
+            Some(chunk) if !chunk.is_empty() && read + chunk.len() <= length => {
+                value_buffer[read..][..chunk.len()].copy_from_slice(chunk);
+                read += chunk.len();
+                index += 1;
+            }
+            _ => {
+                return Err(Error::Corrupted {
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                })
+            }
+        }
+    }
+
+    if crc32(&value_buffer[..length]) != head.crc {
+        return Err(Error::Corrupted {
+            #[cfg(feature = "_test")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        });
+    }
+
+    Ok(Some(length))
+}
+
+/// The item with the first key of a large value that tells where its chunks are
+struct LargeItemHead {
+    generation: u8,
+    length: u32,
+    crc: u32,
+}
+
+impl LargeItemHead {
+    fn serialize(&self) -> [u8; HEAD_LENGTH] {
+        let mut buffer = [0; HEAD_LENGTH];
+        buffer[0] = self.generation;
+        buffer[1..5].copy_from_slice(&self.length.to_le_bytes());
+        buffer[5..9].copy_from_slice(&self.crc.to_le_bytes());
+        buffer
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<Self, SerializationError> {
+        let buffer: &[u8; HEAD_LENGTH] = buffer
+            .try_into()
+            .map_err(|_| SerializationError::InvalidFormat)?;
+
+        Ok(Self {
+            generation: buffer[0] & 1,
+            length: u32::from_le_bytes(buffer[1..5].try_into().unwrap()),
+            crc: u32::from_le_bytes(buffer[5..9].try_into().unwrap()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<8, 4, 256>;
+
+    const FLASH_RANGE: Range<u32> = 0x000..0x2000;
+
+    #[test]
+    async fn store_values_bigger_than_a_page() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::KeyPointerCache::<8, Chunked<u8>, 16>::new();
+        let mut data_buffer = AlignedBuf([0; 256]);
+        let mut value_buffer = [0; 2048];
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &Chunked::new(0u8),
+            &42u32,
+        )
+        .await
+        .unwrap();
+
+        for (seed, length) in [(1u8, 1500usize), (2, 1500), (3, 300), (4, 0)] {
+            let value: Vec<u8> = (0..length).map(|i| (i as u8).wrapping_mul(seed)).collect();
+
+            store_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &1u8,
+                &value,
+            )
+            .await
+            .unwrap();
+
+            let fetched_length = fetch_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &1u8,
+                &mut value_buffer,
+            )
+            .await
+            .unwrap();
+            assert_eq!(fetched_length, Some(length));
+            assert_eq!(value_buffer[..length], value);
+        }
+
+        assert_eq!(
+            fetch_item::<_, u32, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &Chunked::new(0u8),
+            )
+            .await
+            .unwrap(),
+            Some(42)
+        );
+        assert_eq!(
+            fetch_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &2u8,
+                &mut value_buffer,
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn shrinking_removes_old_chunks() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 256]);
+        let mut value_buffer = [0; 2048];
+
+        store_large_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &1u8,
+            &[0xAA; 1500],
+        )
+        .await
+        .unwrap();
+
+        let big_stats =
+            stats::<Chunked<u8>, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap();
+        assert!(big_stats.used_bytes >= 1500);
+
+        store_large_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &1u8,
+            &[0x55; 300],
+        )
+        .await
+        .unwrap();
+
+        // Only the head and the two chunks of the new value are left
+        let small_stats =
+            stats::<Chunked<u8>, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap();
+        assert_eq!(small_stats.item_count, 3);
+        assert!(small_stats.used_bytes < 400);
+        assert!(small_stats.stale_bytes >= 1500);
+
+        assert_eq!(
+            fetch_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &1u8,
+                &mut value_buffer,
+            )
+            .await
+            .unwrap(),
+            Some(300)
+        );
+        assert_eq!(value_buffer[..300], [0x55; 300]);
+
+        remove_large_item(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &1u8)
+            .await
+            .unwrap();
+
+        let removed_stats =
+            stats::<Chunked<u8>, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+                .await
+                .unwrap();
+        assert_eq!(removed_stats.item_count, 0);
+        assert_eq!(removed_stats.used_bytes, 0);
+        assert_eq!(
+            fetch_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &1u8,
+                &mut value_buffer,
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn missing_chunk_is_corruption() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 256]);
+        let mut value_buffer = [0; 2048];
+
+        store_large_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &1u8,
+            &[0xAA; 1000],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fetch_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &1u8,
+                &mut value_buffer[..999],
+            )
+            .await,
+            Err(Error::BufferTooSmall(1000))
+        );
+
+        remove_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &Chunked::data(1u8, 0, 2),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            fetch_large_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &1u8,
+                &mut value_buffer,
+            )
+            .await,
+            Err(Error::Corrupted { .. })
+        ));
+    }
+}
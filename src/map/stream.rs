@@ -0,0 +1,545 @@
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use crate::item::crc32;
+
+use super::*;
+
+/// The magic bytes every exported stream starts with.
+pub const EXPORT_MAGIC: [u8; 4] = *b"SSMX";
+/// The version of the export format. See [export] for the layout.
+pub const EXPORT_VERSION: u8 = 1;
+
+/// The key length field value that marks the end of the entries in the stream.
+/// Items are always shorter than this, so it can't be a real key length.
+const END_MARKER: u16 = 0xFFFF;
+
+/// The error of a stream operation on the map, like [export] and [import].
+#[non_exhaustive]
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum StreamError<S, IO> {
+    /// An error of the map in flash
+    Map(Error<S>),
+    /// An error of the reader or writer of the stream
+    Io(IO),
+    /// The stream is not a valid export, e.g. because it has an entry with the wrong crc or because it ended early
+    InvalidStream,
+    /// The stream was exported with a version of the format that is not supported
+    UnsupportedVersion(u8),
+}
+
+impl<S, IO> From<Error<S>> for StreamError<S, IO> {
+    fn from(value: Error<S>) -> Self {
+        Self::Map(value)
+    }
+}
+
+impl<S: PartialEq, IO: PartialEq> PartialEq for StreamError<S, IO> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Map(l0), Self::Map(r0)) => l0 == r0,
+            (Self::Io(l0), Self::Io(r0)) => l0 == r0,
+            (Self::UnsupportedVersion(l0), Self::UnsupportedVersion(r0)) => l0 == r0,
+            (Self::InvalidStream, Self::InvalidStream) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<S, IO> core::fmt::Display for StreamError<S, IO>
+where
+    S: core::fmt::Display,
+    IO: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StreamError::Map(value) => write!(f, "Map error: {value}"),
+            StreamError::Io(value) => write!(f, "Stream io error: {value:?}"),
+            StreamError::InvalidStream => write!(f, "The stream is not a valid export"),
+            StreamError::UnsupportedVersion(version) => {
+                write!(f, "The export format version {version} is not supported")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S, IO> std::error::Error for StreamError<S, IO>
+where
+    S: std::error::Error,
+    IO: core::fmt::Debug,
+{
+}
+
+/// Write all live items of the map to the writer, e.g. to make a backup over a serial connection.
+/// The number of exported items is returned.
+///
+/// The stream is self-describing, so it can be parsed without knowing the [Key] and [Value] types.
+/// All numbers are little endian.
+///
+/// | Field | Size | Description |
+/// | ----- | ---- | ----------- |
+/// | Magic | 4 | Always `SSMX` (see [EXPORT_MAGIC]) |
+/// | Version | 1 | The version of the format, currently 1 (see [EXPORT_VERSION]) |
+/// | Entries | ... | Zero or more entries |
+/// | End marker | 2 | Always `0xFFFF` |
+/// | Entry count | 4 | The number of entries in the stream as a u32 |
+///
+/// Every entry has this layout:
+///
+/// | Field | Size | Description |
+/// | ----- | ---- | ----------- |
+/// | Key length | 2 | The length of the serialized key as a u16 |
+/// | Value length | 2 | The length of the serialized value as a u16 |
+/// | Key | key length | The key as serialized by its [Key] implementation |
+/// | Value | value length | The value as serialized by its [Value] implementation |
+/// | CRC | 4 | A crc32 over the key and value bytes as a u32 |
+///
+/// The crc is a CRC-32C (Castagnoli) with `0xEEEEEEEE` as initial value instead of the usual `0xFFFFFFFF`.
+///
+/// The entries are given back in no particular order, just like [iter] does.
+/// The stream can be stored again with [import].
+///
+/// <div class="warning">
+/// This is really slow!
+///
+/// For every item in flash, the newest value for its key has to be searched, just like [fetch_item] does.
+/// Using a key cache helps a lot here.
+/// </div>
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn export<K: Key, S: NorFlash, W: Write>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    writer: &mut W,
+) -> Result<u32, StreamError<S::Error, W::Error>> {
+    let mut iterator = iter::<K, _, _>(flash, flash_range, cache).await?;
+
+    writer
+        .write_all(&EXPORT_MAGIC)
+        .await
+        .map_err(StreamError::Io)?;
+    writer
+        .write_all(&[EXPORT_VERSION])
+        .await
+        .map_err(StreamError::Io)?;
+
+    let mut entry_count = 0u32;
+
+    loop {
+        let result = run_with_auto_repair!(
+            function = iterator.next_inner(data_buffer, &|_| true).await,
+            repair = try_repair::<K, _>(
+                iterator.flash,
+                iterator.flash_range.clone(),
+                iterator.cache,
+                data_buffer
+            )
+            .await?
+        );
+
+        let Some((_, key_len, data_len)) = result? else {
+            break;
+        };
+
+        let (key, value) = data_buffer[..data_len].split_at(key_len);
+
+        writer
+            .write_all(&(key.len() as u16).to_le_bytes())
+            .await
+            .map_err(StreamError::Io)?;
+        writer
+            .write_all(&(value.len() as u16).to_le_bytes())
+            .await
+            .map_err(StreamError::Io)?;
+        writer.write_all(key).await.map_err(StreamError::Io)?;
+        writer.write_all(value).await.map_err(StreamError::Io)?;
+        writer
+            .write_all(&crc32(&data_buffer[..data_len]).to_le_bytes())
+            .await
+            .map_err(StreamError::Io)?;
+
+        entry_count += 1;
+    }
+
+    writer
+        .write_all(&END_MARKER.to_le_bytes())
+        .await
+        .map_err(StreamError::Io)?;
+    writer
+        .write_all(&entry_count.to_le_bytes())
+        .await
+        .map_err(StreamError::Io)?;
+    writer.flush().await.map_err(StreamError::Io)?;
+
+    Ok(entry_count)
+}
+
+/// Read a stream that was created with [export] and store all of its entries in the map.
+/// The number of imported entries is returned.
+///
+/// The flash range can be fresh or already hold items. Entries in the stream overwrite the items with the same key.
+///
+/// The magic, version and the crc of every entry are checked. Every entry is only stored once it has been checked,
+/// but the entries that come before an invalid entry are already stored. If the stream is invalid,
+/// [StreamError::InvalidStream] is returned.
+/// The keys in the stream must be of the [Key] type used for the flash range.
+///
+/// The entry buffer must be long enough to hold the longest key and value of an entry in the stream combined.
+/// If it's not, [Error::BufferTooSmall] is returned.
+///
+/// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+/// rounded up to flash word alignment.
+///
+/// <div class="warning">
+///
+/// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+/// *multiple [Value] types. See the module-level docs for more information about this.*
+///
+/// </div>
+pub async fn import<K: Key, S: NorFlash, R: Read>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl KeyCacheImpl<K>,
+    data_buffer: &mut [u8],
+    entry_buffer: &mut [u8],
+    reader: &mut R,
+) -> Result<u32, StreamError<S::Error, R::Error>> {
+    let mut magic = [0; 4];
+    read_exact(reader, &mut magic).await?;
+    if magic != EXPORT_MAGIC {
+        return Err(StreamError::InvalidStream);
+    }
+
+    let mut version = [0];
+    read_exact(reader, &mut version).await?;
+    if version[0] != EXPORT_VERSION {
+        return Err(StreamError::UnsupportedVersion(version[0]));
+    }
+
+    let mut entry_count = 0u32;
+
+    loop {
+        let mut length = [0; 2];
+        read_exact(reader, &mut length).await?;
+        let key_len = u16::from_le_bytes(length);
+
+        if key_len == END_MARKER {
+            break;
+        }
+
+        read_exact(reader, &mut length).await?;
+        let value_len = u16::from_le_bytes(length);
+
+        let data_len = key_len as usize + value_len as usize;
+        let Some(data) = entry_buffer.get_mut(..data_len) else {
+            return Err(Error::BufferTooSmall(data_len).into());
+        };
+        read_exact(reader, data).await?;
+
+        let mut crc = [0; 4];
+        read_exact(reader, &mut crc).await?;
+        if u32::from_le_bytes(crc) != crc32(data) {
+            return Err(StreamError::InvalidStream);
+        }
+
+        let (key, value) = data.split_at(key_len as usize);
+        let key = match K::deserialize_from(key) {
+            Ok((key, len)) if len == key_len as usize => key,
+            _ => return Err(StreamError::InvalidStream),
+        };
+
+        store_item(flash, flash_range.clone(), cache, data_buffer, &key, &value).await?;
+
+        entry_count += 1;
+    }
+
+    let mut count = [0; 4];
+    read_exact(reader, &mut count).await?;
+    if u32::from_le_bytes(count) != entry_count {
+        return Err(StreamError::InvalidStream);
+    }
+
+    Ok(entry_count)
+}
+
+/// Fill the buffer from the reader. A stream that ends early is invalid.
+async fn read_exact<S, R: Read>(
+    reader: &mut R,
+    buffer: &mut [u8],
+) -> Result<(), StreamError<S, R::Error>> {
+    reader.read_exact(buffer).await.map_err(|e| match e {
+        ReadExactError::UnexpectedEof => StreamError::InvalidStream,
+        ReadExactError::Other(e) => StreamError::Io(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+
+    const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+
+    #[test]
+    async fn export_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for (key, value) in [(1u8, 10u32), (2, 20), (1, 11)] {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &key,
+                &value,
+            )
+            .await
+            .unwrap();
+        }
+        remove_item::<u8, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &2)
+            .await
+            .unwrap();
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &3u8,
+            &[0xAAu8; 3].as_slice(),
+        )
+        .await
+        .unwrap();
+
+        let mut stream = [0; 64];
+        let mut writer = stream.as_mut_slice();
+        assert_eq!(
+            export::<u8, _, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &mut writer
+            )
+            .await
+            .unwrap(),
+            2
+        );
+        let written = 64 - writer.len();
+
+        let mut expected = std::vec::Vec::new();
+        expected.extend_from_slice(b"SSMX");
+        expected.push(1);
+        for data in [[1u8, 11, 0, 0, 0].as_slice(), &[3, 0xAA, 0xAA, 0xAA]] {
+            expected.extend_from_slice(&1u16.to_le_bytes());
+            expected.extend_from_slice(&(data.len() as u16 - 1).to_le_bytes());
+            expected.extend_from_slice(data);
+            expected.extend_from_slice(&crc32(data).to_le_bytes());
+        }
+        expected.extend_from_slice(&0xFFFFu16.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+
+        assert_eq!(stream[..written], expected);
+    }
+
+    #[test]
+    async fn export_empty_map() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let mut stream = [0; 64];
+        let mut writer = stream.as_mut_slice();
+        assert_eq!(
+            export::<u8, _, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &mut writer
+            )
+            .await
+            .unwrap(),
+            0
+        );
+        let written = 64 - writer.len();
+
+        assert_eq!(
+            stream[..written],
+            [b'S', b'S', b'M', b'X', 1, 0xFF, 0xFF, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    async fn export_and_import() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        for key in 0..10u8 {
+            store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &key,
+                &(key as u32 * 100),
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut stream = [0; 256];
+        let mut writer = stream.as_mut_slice();
+        export::<u8, _, _>(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &mut writer,
+        )
+        .await
+        .unwrap();
+        let written = 256 - writer.len();
+
+        // Import into a region that already has an item with an old value
+        let mut new_flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        store_item(
+            &mut new_flash,
+            FLASH_RANGE,
+            &mut cache,
+            &mut data_buffer,
+            &3u8,
+            &0u32,
+        )
+        .await
+        .unwrap();
+
+        let mut entry_buffer = [0; 16];
+        assert_eq!(
+            import::<u8, _, _>(
+                &mut new_flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &mut entry_buffer,
+                &mut &stream[..written],
+            )
+            .await
+            .unwrap(),
+            10
+        );
+
+        for key in 0..10u8 {
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut new_flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    &key,
+                )
+                .await
+                .unwrap(),
+                Some(key as u32 * 100)
+            );
+        }
+    }
+
+    #[test]
+    async fn import_invalid_stream() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut entry_buffer = [0; 16];
+
+        let data = [1u8, 42];
+        let mut stream = std::vec::Vec::new();
+        stream.extend_from_slice(b"SSMX");
+        stream.push(1);
+        stream.extend_from_slice(&1u16.to_le_bytes());
+        stream.extend_from_slice(&1u16.to_le_bytes());
+        stream.extend_from_slice(&data);
+        stream.extend_from_slice(&crc32(&data).to_le_bytes());
+        stream.extend_from_slice(&0xFFFFu16.to_le_bytes());
+        stream.extend_from_slice(&1u32.to_le_bytes());
+
+        let mut corrupted = stream.clone();
+        corrupted[10] ^= 0x01;
+        assert_eq!(
+            import::<u8, _, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &mut entry_buffer,
+                &mut corrupted.as_slice(),
+            )
+            .await,
+            Err(StreamError::InvalidStream)
+        );
+
+        let mut new_version = stream.clone();
+        new_version[4] = 2;
+        assert_eq!(
+            import::<u8, _, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &mut entry_buffer,
+                &mut new_version.as_slice(),
+            )
+            .await,
+            Err(StreamError::UnsupportedVersion(2))
+        );
+
+        assert_eq!(
+            import::<u8, _, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &mut entry_buffer,
+                &mut &stream[..stream.len() - 2],
+            )
+            .await,
+            Err(StreamError::InvalidStream)
+        );
+
+        // Nothing was stored from the corrupted stream, but the truncated stream did have a valid entry
+        assert_eq!(
+            fetch_item::<u8, u8, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &1,)
+                .await
+                .unwrap(),
+            Some(42)
+        );
+
+        assert_eq!(
+            import::<u8, _, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &mut [0; 1],
+                &mut stream.as_slice(),
+            )
+            .await,
+            Err(StreamError::Map(Error::BufferTooSmall(2)))
+        );
+    }
+}
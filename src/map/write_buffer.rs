@@ -0,0 +1,432 @@
+use super::*;
+
+/// The amount of bytes in front of every staged item that hold its length
+const LENGTH_SIZE: usize = 2;
+
+/// A buffer in RAM that collects stores to the map and writes them to flash together.
+///
+/// When a key is stored again before the buffer is flushed, only the newest value is kept,
+/// so keys that change often or many keys that change together cause less wear.
+/// The items are written with [WriteBuffer::flush] or when a new item doesn't fit in the buffer anymore.
+/// If the buffered items fit on a single page, they're written atomically like [store_items] does.
+///
+/// Items are stored in the buffer as their serialized key and value, plus two bytes for their length.
+/// So `BYTES` decides how many items are collected before they're written.
+///
+/// <div class="warning">
+///
+/// Stored items that haven't been flushed yet are lost when the device loses power or the buffer is dropped.
+/// Use [WriteBuffer::fetch_item] to see them before they're flushed.
+///
+/// </div>
+pub struct WriteBuffer<K: Key, const BYTES: usize> {
+    data: [u8; BYTES],
+    used: usize,
+    count: usize,
+    _key: PhantomData<K>,
+}
+
+impl<K: Key, const BYTES: usize> Debug for WriteBuffer<K, BYTES> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WriteBuffer")
+            .field("used", &self.used)
+            .field("count", &self.count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Key, const BYTES: usize> Default for WriteBuffer<K, BYTES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key, const BYTES: usize> WriteBuffer<K, BYTES> {
+    /// Create a new, empty write buffer
+    pub const fn new() -> Self {
+        Self {
+            data: [0; BYTES],
+            used: 0,
+            count: 0,
+            _key: PhantomData,
+        }
+    }
+
+    /// The amount of items in the buffer that still have to be written to flash
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// True if there are no items in the buffer that still have to be written to flash
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Throw away all items in the buffer without writing them to flash
+    pub fn discard(&mut self) {
+        self.used = 0;
+        self.count = 0;
+    }
+
+    /// Store a key-value pair in the buffer. It replaces the value of the key if that's still in the buffer.
+    ///
+    /// If the item doesn't fit in the buffer anymore, the buffer is flushed first.
+    /// An item that doesn't even fit in an empty buffer is stored in flash right away.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    ///
+    /// <div class="warning">
+    ///
+    /// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+    /// *multiple [Value] types. See the module-level docs for more information about this.*
+    ///
+    /// </div>
+    pub async fn store_item<'d, V: Value<'d>, S: NorFlash>(
+        &mut self,
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl KeyCacheImpl<K>,
+        data_buffer: &mut [u8],
+        key: &K,
+        item: &V,
+    ) -> Result<(), Error<S::Error>> {
+        if key.is_reserved() {
+            return Err(Error::ReservedKey);
+        }
+
+        if self.serialize_at_end(key, item).is_err() {
+            self.flush(flash, flash_range.clone(), cache, data_buffer)
+                .await?;
+
+            if self.serialize_at_end(key, item).is_err() {
+                return super::store_item(flash, flash_range, cache, data_buffer, key, item).await;
+            }
+        }
+
+        self.push(key);
+        Ok(())
+    }
+
+    /// Get the last stored value of the key. If it's still in the buffer, it's taken from there.
+    /// Otherwise it's fetched from flash like [fetch_item] does.
+    /// If no value with the key is found, None is returned.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    ///
+    /// <div class="warning">
+    ///
+    /// *You are required to, on a given flash range, use the same [Key] type every time. You are allowed to use*
+    /// *multiple [Value] types. See the module-level docs for more information about this.*
+    ///
+    /// </div>
+    pub async fn fetch_item<'d, V: Value<'d>, S: NorFlash>(
+        &self,
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl KeyCacheImpl<K>,
+        data_buffer: &'d mut [u8],
+        search_key: &K,
+    ) -> Result<Option<V>, Error<S::Error>> {
+        let Some(index) = self.find(search_key)? else {
+            return super::fetch_item(flash, flash_range, cache, data_buffer, search_key).await;
+        };
+
+        let item = self.item(index);
+        let data_buffer = data_buffer
+            .get_mut(..item.len())
+            .ok_or(Error::BufferTooSmall(item.len()))?;
+        data_buffer.copy_from_slice(item);
+
+        let key_len = K::get_len(data_buffer)?;
+        Ok(Some(
+            V::deserialize_from(&data_buffer[key_len..]).map_err(Error::SerializationError)?,
+        ))
+    }
+
+    /// Write all items in the buffer to flash and empty the buffer.
+    ///
+    /// If the items fit on a single page together, they're written as a group, so either all or none
+    /// of them are stored. Otherwise, they're stored one by one.
+    ///
+    /// The data buffer must be long enough to hold the longest serialized data of your [Key] + [Value] types combined,
+    /// rounded up to flash word alignment.
+    pub async fn flush<S: NorFlash>(
+        &mut self,
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut impl KeyCacheImpl<K>,
+        data_buffer: &mut [u8],
+    ) -> Result<(), Error<S::Error>> {
+        let result = run_with_auto_repair!(
+            function = store_item_inner(flash, flash_range.clone(), cache, data_buffer, self).await,
+            repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer).await?
+        );
+
+        match result {
+            Err(Error::ItemTooBig) if self.count > 1 => {
+                for index in 0..self.count {
+                    let item = StagedItem {
+                        buffer: self,
+                        index,
+                    };
+                    run_with_auto_repair!(
+                        function =
+                            store_item_inner(flash, flash_range.clone(), cache, data_buffer, &item)
+                                .await,
+                        repair = try_repair::<K, _>(flash, flash_range.clone(), cache, data_buffer)
+                            .await?
+                    )?;
+                }
+            }
+            result => result?,
+        }
+
+        self.discard();
+        Ok(())
+    }
+
+    /// Serialize the item behind the last item in the buffer without adding it yet.
+    fn serialize_at_end<'d, V: Value<'d>>(
+        &mut self,
+        key: &K,
+        item: &V,
+    ) -> Result<(), SerializationError> {
+        let buffer = self
+            .data
+            .get_mut(self.used + LENGTH_SIZE..)
+            .ok_or(SerializationError::BufferTooSmall)?;
+        let key_len = key.serialize_into(buffer)?;
+        let item_len = key_len + item.serialize_into(&mut buffer[key_len..])?;
+        let item_len = u16::try_from(item_len).map_err(|_| SerializationError::BufferTooSmall)?;
+
+        self.data[self.used..][..LENGTH_SIZE].copy_from_slice(&item_len.to_le_bytes());
+        Ok(())
+    }
+
+    /// Add the item that was serialized at the end and remove the older value of the key
+    fn push(&mut self, key: &K) {
+        let older = self.find(key).ok().flatten();
+
+        self.used += LENGTH_SIZE + self.item_len_at(self.used);
+        self.count += 1;
+
+        if let Some(index) = older {
+            let start = self.item_offset(index);
+            let end = start + LENGTH_SIZE + self.item_len_at(start);
+            self.data.copy_within(end..self.used, start);
+            self.used -= end - start;
+            self.count -= 1;
+        }
+    }
+
+    /// Find the index of the item with the key
+    fn find(&self, key: &K) -> Result<Option<usize>, SerializationError> {
+        for index in 0..self.count {
+            if key.matches_serialized(self.item(index))? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    fn item_len_at(&self, offset: usize) -> usize {
+        u16::from_le_bytes(self.data[offset..][..LENGTH_SIZE].try_into().unwrap()) as usize
+    }
+
+    fn item_offset(&self, index: usize) -> usize {
+        let mut offset = 0;
+        for _ in 0..index {
+            offset += LENGTH_SIZE + self.item_len_at(offset);
+        }
+        offset
+    }
+
+    /// The serialized key and value of the item with the index
+    fn item(&self, index: usize) -> &[u8] {
+        let offset = self.item_offset(index);
+        &self.data[offset + LENGTH_SIZE..][..self.item_len_at(offset)]
+    }
+}
+
+impl<K: Key, const BYTES: usize> ItemsToStore<K> for WriteBuffer<K, BYTES> {
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    fn key(&self, index: usize) -> Result<K, SerializationError> {
+        Ok(K::deserialize_from(self.item(index))?.0)
+    }
+
+    fn serialize_item(&self, index: usize, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let item = self.item(index);
+        buffer
+            .get_mut(..item.len())
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(item);
+        Ok(item.len())
+    }
+}
+
+/// A single item of the write buffer, to store the items one by one
+struct StagedItem<'b, K: Key, const BYTES: usize> {
+    buffer: &'b WriteBuffer<K, BYTES>,
+    index: usize,
+}
+
+impl<K: Key, const BYTES: usize> ItemsToStore<K> for StagedItem<'_, K, BYTES> {
+    fn count(&self) -> usize {
+        1
+    }
+
+    fn key(&self, _index: usize) -> Result<K, SerializationError> {
+        self.buffer.key(self.index)
+    }
+
+    fn serialize_item(
+        &self,
+        _index: usize,
+        buffer: &mut [u8],
+    ) -> Result<usize, SerializationError> {
+        self.buffer.serialize_item(self.index, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+
+    const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+
+    #[test]
+    async fn coalesce_stores() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+        let mut write_buffer = WriteBuffer::<u8, 32>::new();
+
+        let snapshot = flash.stats_snapshot();
+        for value in 0..10u32 {
+            for key in 0..3u8 {
+                write_buffer
+                    .store_item(
+                        &mut flash,
+                        FLASH_RANGE,
+                        &mut cache,
+                        &mut data_buffer,
+                        &key,
+                        &(key as u32 * 100 + value),
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+        assert_eq!(snapshot.compare_to(flash.stats_snapshot()).writes, 0);
+        assert_eq!(write_buffer.len(), 3);
+
+        assert_eq!(
+            write_buffer
+                .fetch_item::<u32, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &1)
+                .await
+                .unwrap(),
+            Some(109)
+        );
+        assert_eq!(
+            fetch_item::<u8, u32, _>(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer, &1)
+                .await
+                .unwrap(),
+            None
+        );
+
+        write_buffer
+            .flush(&mut flash, FLASH_RANGE, &mut cache, &mut data_buffer)
+            .await
+            .unwrap();
+        assert!(write_buffer.is_empty());
+
+        for key in 0..3u8 {
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    &key
+                )
+                .await
+                .unwrap(),
+                Some(key as u32 * 100 + 9)
+            );
+        }
+    }
+
+    #[test]
+    async fn flush_when_full() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 128]);
+        // Room for 4 items of 7 bytes
+        let mut write_buffer = WriteBuffer::<u8, 28>::new();
+
+        for key in 0..5u8 {
+            write_buffer
+                .store_item(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    &key,
+                    &(key as u32),
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(write_buffer.len(), 1);
+
+        // Too big for the write buffer, so it's stored right away
+        write_buffer
+            .store_item(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &5u8,
+                &[5u8; 40],
+            )
+            .await
+            .unwrap();
+        assert!(write_buffer.is_empty());
+
+        for key in 0..5u8 {
+            assert_eq!(
+                fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache,
+                    &mut data_buffer,
+                    &key
+                )
+                .await
+                .unwrap(),
+                Some(key as u32)
+            );
+        }
+        assert_eq!(
+            fetch_item::<u8, [u8; 40], _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache,
+                &mut data_buffer,
+                &5
+            )
+            .await
+            .unwrap(),
+            Some([5; 40])
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use crate::map::{SerializationError, Value};
+
+/// A map value that is serialized with [postcard].
+///
+/// This makes it possible to store any type that implements the serde [Serialize] and [Deserialize] traits
+/// without writing a [Value] implementation for it.
+/// The deserialization can borrow from the buffer, so zero-copy types like `&str` are supported as well.
+///
+/// Enable the `postcard` feature to use this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Postcard<T>(pub T);
+
+impl<'a, T: Serialize + Deserialize<'a>> Value<'a> for Postcard<T> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        postcard::to_slice(&self.0, buffer)
+            .map(|used| used.len())
+            .map_err(|e| match e {
+                postcard::Error::SerializeBufferFull => SerializationError::BufferTooSmall,
+                _ => SerializationError::InvalidData,
+            })
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        postcard::from_bytes(buffer)
+            .map(Postcard)
+            .map_err(|_| SerializationError::InvalidFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ops::Range;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        cache,
+        map::{fetch_item, store_item},
+        mock_flash::{self, WriteCountCheck},
+        AlignedBuf,
+    };
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+    const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Settings<'a> {
+        name: &'a str,
+        volume: u8,
+        brightness: Option<f32>,
+    }
+
+    #[futures_test::test]
+    async fn store_serde_value() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let mut data_buffer = AlignedBuf([0; 128]);
+
+        let settings = Settings {
+            name: "living room",
+            volume: 11,
+            brightness: Some(0.5),
+        };
+
+        store_item(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            &0u8,
+            &Postcard(settings.clone()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fetch_item::<u8, Postcard<Settings>, _>(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                &0,
+            )
+            .await
+            .unwrap(),
+            Some(Postcard(settings))
+        );
+    }
+
+    #[test]
+    fn serialize_into_too_small_buffer() {
+        let mut buffer = [0; 4];
+        assert_eq!(
+            Postcard([0xAAu8; 16]).serialize_into(&mut buffer),
+            Err(SerializationError::BufferTooSmall)
+        );
+        assert_eq!(
+            Postcard::<u32>::deserialize_from(&[]),
+            Err(SerializationError::InvalidFormat)
+        );
+    }
+}
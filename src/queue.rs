@@ -1,6 +1,7 @@
 //! A queue (fifo) implementation for storing arbitrary data in flash memory.
 //!
 //! Use [push] to add data to the fifo and use [peek] and [pop] to get the data back.
+//! If you don't want to pass the flash, flash range and cache to every call, use the [Queue] type instead.
 //!
 //! ```rust
 //! # use sequential_storage::queue::{push, peek, pop};
@@ -8,6 +9,10 @@
 //! # use mock_flash::MockFlashBase;
 //! # use futures::executor::block_on;
 //! # type Flash = MockFlashBase<10, 1, 4096>;
+//! # // With the `_test` feature the crate exports the mock flash, which can't be included on its own then
+//! # #[cfg(feature = "_test")]
+//! # use sequential_storage::mock_flash;
+//! # #[cfg(not(feature = "_test"))]
 //! # mod mock_flash {
 //! #   include!("mock_flash.rs");
 //! # }
@@ -54,20 +59,52 @@
 //! );
 //! # });
 //! ```
-
-use crate::item::{find_next_free_item_spot, is_page_empty, Item, ItemHeader, ItemHeaderIter};
+//!
+//! ## Small items
+//!
+//! Every item has some overhead for its header (see [item_overhead_size]).
+//! For small items, like fixed size sensor samples, this overhead can be bigger than the data itself.
+//! In that case it's more efficient to push multiple records as one item.
+//! With [push_records] this can be done without buffering all records in ram.
+//! Because all records have the same size, the position of a record is known from the lengths of the items,
+//! so [peek_record] can read a single record by its index.
+
+use crate::{
+    item::{
+        check_page_items, find_next_free_item_spot, is_page_empty, Item, ItemHeader,
+        ItemHeaderIter, ItemWriter,
+    },
+    map::{SerializationError, Value},
+};
 
 use self::{cache::CacheImpl, item::ItemUnborrowed};
 
 use super::*;
+use core::{num::NonZeroU32, task::Waker};
 use embedded_storage_async::nor_flash::MultiwriteNorFlash;
 
+mod chunked;
+mod metadata;
+mod records;
+mod split;
+#[cfg(feature = "embedded-io-async")]
+mod stream;
+#[cfg(feature = "embedded-io-async")]
+pub use stream::QueueStream;
+
+pub use chunked::{peek_large_item, pop_large_item, push_large_item};
+pub use metadata::ItemMetadata;
+pub use records::{peek_record, push_records, RecordPushWriter};
+pub use split::{QueueConsumer, QueueProducer, SharedQueue};
+
 /// Push data into the queue in the given flash memory with the given range.
 /// The data can only be taken out with the [pop] function.
 ///
 /// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
 /// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
 ///
+/// Empty data can be pushed too. It's stored as an item without data, which can be useful as e.g. an event marker.
+///
 /// *Note: If a page is already used and you push more data than the remaining capacity of the page,
 /// the entire remaining capacity will go unused because the data is stored on the next page.*
 pub async fn push<S: NorFlash>(
@@ -82,20 +119,98 @@ pub async fn push<S: NorFlash>(
             flash,
             flash_range.clone(),
             cache,
-            data,
-            allow_overwrite_old_data
+            &[data],
+            false,
+            allow_overwrite_old_data,
+            None
         )
         .await,
         repair = try_repair(flash, flash_range.clone(), cache).await?
     )
 }
 
-async fn push_inner<S: NorFlash>(
+/// Push data into the queue like [push] with `allow_overwrite_old_data` set to true,
+/// but get notified about the old data that got removed to make space for the new data.
+///
+/// When unpopped items are erased, `on_evict` is called with how many items and bytes of data were lost.
+pub async fn push_with_eviction<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data: &[u8],
+    mut on_evict: impl FnMut(EvictedItems),
+) -> Result<(), Error<S::Error>> {
+    run_with_auto_repair!(
+        function = push_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            &[data],
+            false,
+            true,
+            Some(&mut on_evict)
+        )
+        .await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+/// Push data into the queue like [push], but also check whether the queue has reached the given watermark.
+///
+/// Returns true if, after pushing, the queue holds at least as much as the watermark specifies.
+/// This can be used to e.g. flush the queue early before old data has to be overwritten.
+///
+/// *Note: To check the watermark all items are counted like [stats] does, which makes this a lot more expensive than [push].*
+pub async fn push_with_watermark<S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
     data: &[u8],
     allow_overwrite_old_data: bool,
+    watermark: Watermark,
+) -> Result<bool, Error<S::Error>> {
+    push(
+        flash,
+        flash_range.clone(),
+        cache,
+        data,
+        allow_overwrite_old_data,
+    )
+    .await?;
+
+    Ok(stats(flash, flash_range, cache).await?.reached(watermark))
+}
+
+/// A threshold for the amount of data in the queue. See [push_with_watermark] and [QueueStats::reached].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Watermark {
+    /// The amount of items in the queue
+    Items(usize),
+    /// The total length of the data of all items in the queue (so not including the overhead)
+    Bytes(usize),
+}
+
+/// Information about the items that were erased to make space for new data. See [push_with_eviction].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct EvictedItems {
+    /// The amount of items that were erased
+    pub count: usize,
+    /// The total length of the data of the erased items
+    pub bytes: usize,
+}
+
+/// Push an item of which the data is made up of all parts after each other.
+/// With `has_metadata`, the first part must be a serialized [ItemMetadata].
+async fn push_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    parts: &[&[u8]],
+    has_metadata: bool,
+    allow_overwrite_old_data: bool,
+    on_evict: Option<&mut dyn FnMut(EvictedItems)>,
 ) -> Result<(), Error<S::Error>> {
     assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
     assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
@@ -103,86 +218,34 @@ async fn push_inner<S: NorFlash>(
     assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
     assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
 
-    if cache.is_dirty() {
-        cache.invalidate_cache_state();
-    }
+    cache.recover_if_dirty();
+
+    // The data of the item is made up of all parts after each other
+    let data_length = parts.iter().map(|part| part.len()).sum::<usize>();
 
     // Data must fit in a single page
-    if data.len() > u16::MAX as usize
-        || data.len()
-            > calculate_page_size::<S>().saturating_sub(ItemHeader::data_address::<S>(0) as usize)
-    {
+    if data_length > max_item_size::<S>() {
         cache.unmark_dirty();
         return Err(Error::ItemTooBig);
     }
 
-    let current_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
-
-    let page_data_start_address =
-        calculate_page_address::<S>(flash_range.clone(), current_page) + S::WORD_SIZE as u32;
-    let page_data_end_address =
-        calculate_page_end_address::<S>(flash_range.clone(), current_page) - S::WORD_SIZE as u32;
-
-    partial_close_page(flash, flash_range.clone(), cache, current_page).await?;
-
-    // Find the last item on the page so we know where we need to write
-
-    let mut next_address = find_next_free_item_spot(
+    let next_address = find_push_address(
         flash,
         flash_range.clone(),
         cache,
-        page_data_start_address,
-        page_data_end_address,
-        data.len() as u32,
+        data_length as u32,
+        allow_overwrite_old_data,
+        on_evict,
     )
     .await?;
 
-    if next_address.is_none() {
-        // No cap left on this page, move to the next page
-        let next_page = next_page::<S>(flash_range.clone(), current_page);
-        match get_page_state(flash, flash_range.clone(), cache, next_page).await? {
-            PageState::Open => {
-                close_page(flash, flash_range.clone(), cache, current_page).await?;
-                partial_close_page(flash, flash_range.clone(), cache, next_page).await?;
-                next_address = Some(
-                    calculate_page_address::<S>(flash_range.clone(), next_page)
-                        + S::WORD_SIZE as u32,
-                );
-            }
-            state @ PageState::Closed => {
-                let next_page_data_start_address =
-                    calculate_page_address::<S>(flash_range.clone(), next_page)
-                        + S::WORD_SIZE as u32;
-
-                if !allow_overwrite_old_data
-                    && !is_page_empty(flash, flash_range.clone(), cache, next_page, Some(state))
-                        .await?
-                {
-                    cache.unmark_dirty();
-                    return Err(Error::FullStorage);
-                }
-
-                open_page(flash, flash_range.clone(), cache, next_page).await?;
-                close_page(flash, flash_range.clone(), cache, current_page).await?;
-                partial_close_page(flash, flash_range.clone(), cache, next_page).await?;
-                next_address = Some(next_page_data_start_address);
-            }
-            PageState::PartialOpen => {
-                // This should never happen
-                return Err(Error::Corrupted {
-                    #[cfg(feature = "_test")]
-                    backtrace: std::backtrace::Backtrace::capture(),
-                });
-            }
-        }
-    }
-
-    Item::write_new(
+    Item::write_new_parts(
         flash,
         flash_range.clone(),
         cache,
-        next_address.unwrap(),
-        data,
+        next_address,
+        parts,
+        has_metadata,
     )
     .await?;
 
@@ -190,749 +253,6389 @@ async fn push_inner<S: NorFlash>(
     Ok(())
 }
 
-/// Get an iterator-like interface to iterate over the items stored in the queue.
-/// This goes from oldest to newest.
-///
-/// The iteration happens non-destructively, or in other words it peeks at every item.
-/// The returned entry has a [QueueIteratorEntry::pop] function with which you can decide to pop the item
-/// after you've seen the contents.
-pub async fn iter<'s, S: NorFlash, CI: CacheImpl>(
-    flash: &'s mut S,
-    flash_range: Range<u32>,
-    cache: &'s mut CI,
-) -> Result<QueueIterator<'s, S, CI>, Error<S::Error>> {
-    // Note: Corruption repair is done in these functions already
-    QueueIterator::new(flash, flash_range, cache).await
-}
-
-/// Peek at the oldest data.
+/// Push data into the queue like [push], unless the newest item in the queue holds exactly the same data.
+/// This saves flash wear when the same data is often pushed multiple times in a row.
 ///
-/// If you also want to remove the data use [pop].
-///
-/// The data is written to the given `data_buffer` and the part that was written is returned.
-/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
-/// The `data_buffer` may contain extra data on ranges after the returned slice.
-/// You should not depend on that data.
+/// Returns true if the data was pushed and false if it was the same as the newest item.
 ///
-/// If the data buffer is not big enough an error is returned.
-pub async fn peek<'d, S: NorFlash>(
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+pub async fn push_dedup<S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
-    data_buffer: &'d mut [u8],
-) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
-    // Note: Corruption repair is done in these functions already
-    let mut iterator = iter(flash, flash_range, cache).await?;
-
-    let next_value = iterator.next(data_buffer).await?;
-
-    match next_value {
-        Some(entry) => Ok(Some(entry.into_buf())),
-        None => Ok(None),
-    }
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<bool, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = push_dedup_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data,
+            allow_overwrite_old_data
+        )
+        .await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
 }
 
-/// Pop the oldest data from the queue.
-///
-/// If you don't want to remove the data use [peek].
-///
-/// The data is written to the given `data_buffer` and the part that was written is returned.
-/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
-/// The `data_buffer` may contain extra data on ranges after the returned slice.
-/// You should not depend on that data.
-///
-/// If the data buffer is not big enough an error is returned.
-pub async fn pop<'d, S: MultiwriteNorFlash>(
+async fn push_dedup_inner<S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
-    data_buffer: &'d mut [u8],
-) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
-    let mut iterator = iter(flash, flash_range, cache).await?;
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<bool, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
 
-    let next_value = iterator.next(data_buffer).await?;
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
 
-    match next_value {
-        Some(entry) => Ok(Some(entry.pop().await?)),
-        None => Ok(None),
+    cache.recover_if_dirty();
+
+    if let Some((newest_header, newest_address)) =
+        find_newest_item(flash, flash_range.clone(), cache).await?
+    {
+        if newest_header
+            .data_equals(flash, newest_address, data)
+            .await?
+        {
+            cache.unmark_dirty();
+            return Ok(false);
+        }
     }
-}
 
-/// An iterator-like interface for peeking into data stored in flash with the option to pop it.
-pub struct QueueIterator<'s, S: NorFlash, CI: CacheImpl> {
-    flash: &'s mut S,
-    flash_range: Range<u32>,
-    cache: &'s mut CI,
-    next_address: NextAddress,
-}
+    push_inner(
+        flash,
+        flash_range,
+        cache,
+        &[data],
+        false,
+        allow_overwrite_old_data,
+        None,
+    )
+    .await?;
 
-impl<'d, S: NorFlash, CI: CacheImpl> Debug for QueueIterator<'d, S, CI> {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("QueueIterator")
-            .field("current_address", &self.next_address)
-            .finish_non_exhaustive()
-    }
+    Ok(true)
 }
 
-#[derive(Debug, Clone)]
-enum NextAddress {
-    Address(u32),
-    PageAfter(usize),
-}
+/// Find the header and address of the newest item that is not erased.
+/// Only the headers are read, so the data of the item may still turn out to be corrupted.
+async fn find_newest_item<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateCacheImpl,
+) -> Result<Option<(ItemHeader, u32)>, Error<S::Error>> {
+    let youngest_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+    let mut page = youngest_page;
 
-impl<'s, S: NorFlash, CI: CacheImpl> QueueIterator<'s, S, CI> {
-    async fn new(
-        flash: &'s mut S,
-        flash_range: Range<u32>,
-        cache: &'s mut CI,
-    ) -> Result<Self, Error<S::Error>> {
-        let start_address = run_with_auto_repair!(
-            function = Self::find_start_address(flash, flash_range.clone(), cache).await,
-            repair = try_repair(flash, flash_range.clone(), cache).await?
-        )?;
+    // Walk back from the youngest page until we find a page with items
+    loop {
+        if get_page_state(flash, flash_range.clone(), cache, page)
+            .await?
+            .is_open()
+        {
+            return Ok(None);
+        }
 
-        Ok(Self {
-            flash,
-            flash_range,
-            cache,
-            next_address: start_address,
-        })
-    }
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
 
-    async fn find_start_address(
-        flash: &mut S,
-        flash_range: Range<u32>,
-        cache: &mut CI,
-    ) -> Result<NextAddress, Error<S::Error>> {
-        assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
-        assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+        let mut it = ItemHeaderIter::new(
+            cache
+                .first_item_after_erased(page)
+                .unwrap_or(page_data_start_address),
+            page_data_end_address,
+        );
 
-        assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
-        assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+        let mut newest_item = None;
+        while let (Some(header), address) =
+            it.traverse(flash, |header, _| header.crc.is_none()).await?
+        {
+            newest_item = Some((header, address));
+        }
 
-        if cache.is_dirty() {
-            cache.invalidate_cache_state();
+        if newest_item.is_some() {
+            return Ok(newest_item);
         }
 
-        let oldest_page = find_oldest_page(flash, flash_range.clone(), cache).await?;
+        page = previous_page::<S>(flash_range.clone(), page);
+        if page == youngest_page {
+            return Ok(None);
+        }
+    }
+}
 
-        // We start at the start of the oldest page
-        let current_address = match cache.first_item_after_erased(oldest_page) {
-            Some(address) => address,
-            None => {
-                calculate_page_address::<S>(flash_range.clone(), oldest_page) + S::WORD_SIZE as u32
-            }
-        };
-
-        Ok(NextAddress::Address(current_address))
-    }
-
-    /// Get the next entry.
-    ///
-    /// If there are no more entries, None is returned.
-    ///
-    /// The `data_buffer` has to be large enough to be able to hold the largest item in flash.
-    pub async fn next<'d, 'q>(
-        &'q mut self,
-        data_buffer: &'d mut [u8],
-    ) -> Result<Option<QueueIteratorEntry<'s, 'd, 'q, S, CI>>, Error<S::Error>> {
-        let value = run_with_auto_repair!(
-            function = self.next_inner(data_buffer).await,
-            repair = try_repair(self.flash, self.flash_range.clone(), self.cache).await?
-        );
-
-        value.map(|v| {
-            v.map(|(item, address)| QueueIteratorEntry {
-                iter: self,
-                item: item.reborrow(data_buffer),
-                address,
-            })
-        })
-    }
-
-    async fn next_inner(
-        &mut self,
-        data_buffer: &mut [u8],
-    ) -> Result<Option<(ItemUnborrowed, u32)>, Error<S::Error>> {
-        let mut data_buffer = Some(data_buffer);
-
-        if self.cache.is_dirty() {
-            self.cache.invalidate_cache_state();
-        }
-
-        loop {
-            // Get the current page and address based on what was stored
-            let (current_page, current_address) = match self.next_address {
-                NextAddress::PageAfter(previous_page) => {
-                    let next_page = next_page::<S>(self.flash_range.clone(), previous_page);
-                    if get_page_state(
-                        self.flash,
-                        self.flash_range.clone(),
-                        &mut self.cache,
-                        next_page,
-                    )
-                    .await?
-                    .is_open()
-                        || next_page
-                            == find_oldest_page(
-                                self.flash,
-                                self.flash_range.clone(),
-                                &mut self.cache,
-                            )
-                            .await?
-                    {
-                        self.cache.unmark_dirty();
-                        return Ok(None);
-                    }
-
-                    let current_address =
-                        calculate_page_address::<S>(self.flash_range.clone(), next_page)
-                            + S::WORD_SIZE as u32;
-
-                    self.next_address = NextAddress::Address(current_address);
-
-                    (next_page, current_address)
-                }
-                NextAddress::Address(address) => (
-                    calculate_page_index::<S>(self.flash_range.clone(), address),
-                    address,
-                ),
-            };
-
-            let page_data_end_address =
-                calculate_page_end_address::<S>(self.flash_range.clone(), current_page)
-                    - S::WORD_SIZE as u32;
-
-            // Search for the first item with data
-            let mut it = ItemHeaderIter::new(current_address, page_data_end_address);
-            // No need to worry about cache here since that has been dealt with at the creation of this iterator
-            if let (Some(found_item_header), found_item_address) = it
-                .traverse(self.flash, |header, _| header.crc.is_none())
-                .await?
-            {
-                let maybe_item = found_item_header
-                    .read_item(
-                        self.flash,
-                        data_buffer.take().unwrap(),
-                        found_item_address,
-                        page_data_end_address,
-                    )
-                    .await?;
-
-                match maybe_item {
-                    item::MaybeItem::Corrupted(header, db) => {
-                        let next_address = header.next_item_address::<S>(found_item_address);
-                        self.next_address = if next_address >= page_data_end_address {
-                            NextAddress::PageAfter(current_page)
-                        } else {
-                            NextAddress::Address(next_address)
-                        };
-                        data_buffer.replace(db);
-                    }
-                    item::MaybeItem::Erased(_, _) => unreachable!("Item is already erased"),
-                    item::MaybeItem::Present(item) => {
-                        let next_address = item.header.next_item_address::<S>(found_item_address);
-                        self.next_address = if next_address >= page_data_end_address {
-                            NextAddress::PageAfter(current_page)
-                        } else {
-                            NextAddress::Address(next_address)
-                        };
-                        // Return the item we found
-                        self.cache.unmark_dirty();
-                        return Ok(Some((item.unborrow(), found_item_address)));
-                    }
-                }
-            } else {
-                self.next_address = NextAddress::PageAfter(current_page);
-            }
-        }
-    }
-}
-
-/// An entry in the iteration over the queue flash
-pub struct QueueIteratorEntry<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> {
-    iter: &'q mut QueueIterator<'s, S, CI>,
-    address: u32,
-    item: Item<'d>,
-}
-
-impl<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> Deref for QueueIteratorEntry<'s, 'd, 'q, S, CI> {
-    type Target = [u8];
-
-    fn deref(&self) -> &Self::Target {
-        self.item.data()
-    }
-}
-
-impl<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> DerefMut for QueueIteratorEntry<'s, 'd, 'q, S, CI> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        self.item.data_mut()
-    }
-}
-
-impl<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> QueueIteratorEntry<'s, 'd, 'q, S, CI> {
-    /// Get a mutable reference to the data of this entry, but consume the entry too.
-    /// This function has some relaxed lifetime constraints compared to the deref impls.
-    pub fn into_buf(self) -> &'d mut [u8] {
-        let (header, data) = self.item.destruct();
-        &mut data[..header.length as usize]
-    }
-
-    /// Pop the data in flash that corresponds to this entry. This makes it so
-    /// future peeks won't find this data anymore.
-    pub async fn pop(self) -> Result<&'d mut [u8], Error<S::Error>>
-    where
-        S: MultiwriteNorFlash,
-    {
-        let (header, data_buffer) = self.item.destruct();
-        let ret = &mut data_buffer[..header.length as usize];
-
-        header
-            .erase_data(
-                self.iter.flash,
-                self.iter.flash_range.clone(),
-                &mut self.iter.cache,
-                self.address,
-            )
-            .await?;
-
-        self.iter.cache.unmark_dirty();
-        Ok(ret)
-    }
-}
-
-/// Find the largest size of data that can be stored.
+/// Push multiple items into the queue in the given flash memory with the given range.
+/// The items are pushed atomically. Even if the push is interrupted by e.g. a power loss,
+/// either all items or none of them can be popped later.
 ///
-/// This will read through the entire flash to find the largest chunk of
-/// data that can be stored, taking alignment requirements of the item into account.
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
 ///
-/// If there is no space left, `None` is returned.
-pub async fn find_max_fit<S: NorFlash>(
+/// All items must fit on a single page together with their headers and one extra header.
+/// If they don't, [Error::ItemTooBig] is returned.
+pub async fn push_all<S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
-) -> Result<Option<u32>, Error<S::Error>> {
+    data: &[&[u8]],
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
     run_with_auto_repair!(
-        function = find_max_fit_inner(flash, flash_range.clone(), cache).await,
+        function = push_all_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data,
+            allow_overwrite_old_data
+        )
+        .await,
         repair = try_repair(flash, flash_range.clone(), cache).await?
     )
 }
 
-async fn find_max_fit_inner<S: NorFlash>(
+async fn push_all_inner<S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
-) -> Result<Option<u32>, Error<S::Error>> {
+    data: &[&[u8]],
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
     assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
     assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
 
     assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
     assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
 
-    if cache.is_dirty() {
-        cache.invalidate_cache_state();
+    cache.recover_if_dirty();
+
+    if data.is_empty() {
+        cache.unmark_dirty();
+        return Ok(());
     }
 
-    let current_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+    // The items are written as one big item. Its data is the commit marker and all the items including their headers.
+    let group_length = data.iter().fold(0usize, |length, item| {
+        length
+            .saturating_add(ItemHeader::data_address::<S>(0) as usize)
+            .saturating_add(round_up_to_alignment_usize::<S>(item.len()))
+    });
+    let group_data_length = group_length.saturating_add(ItemHeader::data_address::<S>(0) as usize);
 
-    // Check if we have space on the next page
-    let next_page = next_page::<S>(flash_range.clone(), current_page);
-    match get_page_state(flash, flash_range.clone(), cache, next_page).await? {
-        state @ PageState::Closed => {
-            if is_page_empty(flash, flash_range.clone(), cache, next_page, Some(state)).await? {
-                cache.unmark_dirty();
-                return Ok(Some((S::ERASE_SIZE - (2 * S::WORD_SIZE)) as u32));
-            }
-        }
-        PageState::Open => {
-            cache.unmark_dirty();
-            return Ok(Some((S::ERASE_SIZE - (2 * S::WORD_SIZE)) as u32));
-        }
-        PageState::PartialOpen => {
-            // This should never happen
-            return Err(Error::Corrupted {
-                #[cfg(feature = "_test")]
-                backtrace: std::backtrace::Backtrace::capture(),
-            });
-        }
-    };
+    // The whole group must fit in a single page
+    if group_data_length > max_item_size::<S>() {
+        cache.unmark_dirty();
+        return Err(Error::ItemTooBig);
+    }
 
-    // See how much space we can find in the current page.
-    let page_data_start_address =
-        calculate_page_address::<S>(flash_range.clone(), current_page) + S::WORD_SIZE as u32;
-    let page_data_end_address =
-        calculate_page_end_address::<S>(flash_range.clone(), current_page) - S::WORD_SIZE as u32;
+    let group_address = find_push_address(
+        flash,
+        flash_range.clone(),
+        cache,
+        group_data_length as u32,
+        allow_overwrite_old_data,
+        None,
+    )
+    .await?;
 
-    let next_item_address = match cache.first_item_after_written(current_page) {
-        Some(next_item_address) => next_item_address,
-        None => {
-            ItemHeaderIter::new(
-                cache
-                    .first_item_after_erased(current_page)
-                    .unwrap_or(page_data_start_address),
-                page_data_end_address,
-            )
-            .traverse(flash, |_, _| true)
+    // None of the items can be found until the group is committed
+    ItemHeader::write_group(
+        flash,
+        flash_range.clone(),
+        cache,
+        group_address,
+        group_length,
+    )
+    .await?;
+    let mut next_address = ItemHeader::group_items_address::<S>(group_address);
+    for item in data {
+        next_address = Item::write_new(flash, flash_range.clone(), cache, next_address, item)
             .await?
-            .1
-        }
-    };
+            .next_item_address::<S>(next_address);
+    }
+
+    ItemHeader::write_commit_marker(flash, flash_range.clone(), cache, group_address).await?;
 
     cache.unmark_dirty();
-    Ok(ItemHeader::available_data_bytes::<S>(
-        page_data_end_address - next_item_address,
-    ))
+    Ok(())
 }
 
-/// Calculate how much space is left free in the queue (in bytes).
+/// Start pushing an item of the given length into the queue piece by piece.
+/// This way the data of the item doesn't have to be in memory all at once.
 ///
-/// The number given back is accurate, however there are lots of things that add overhead and padding.
-/// Every push is an item with its own overhead. You can check the overhead per item with [crate::item_overhead_size].
+/// The returned writer must be fed exactly `length` bytes with [QueuePushWriter::write] after which
+/// it has to be committed with [QueuePushWriter::commit]. Until it's committed, the item can't be read back.
+/// If the writer is dropped or the device loses power before the commit, the item is never readable,
+/// but its space in flash is not reclaimed until its page is erased.
 ///
-/// Furthermore, every item has to fully fit in a page. So if a page has 50 bytes left and you push an item of 60 bytes,
-/// the current page is closed and the item is stored on the next page, 'wasting' the 50 you had.
+/// All the work of finding a spot for the item, including erasing a page if needed, is done when the writer is created.
+/// Writing and committing only write the item itself to flash. So this can also be used to reserve space
+/// for an item in advance, so only the fast part is left for e.g. a time critical moment.
 ///
-/// So unless you're tracking all this, the returned number should only be used as a rough indication.
-pub async fn space_left<S: NorFlash>(
-    flash: &mut S,
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+///
+/// *Note: This only works on flash with a word size of 4 bytes or smaller. Using it with a bigger word size doesn't compile.*
+pub async fn push_writer<'s, S: NorFlash>(
+    flash: &'s mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
-) -> Result<u32, Error<S::Error>> {
-    run_with_auto_repair!(
-        function = space_left_inner(flash, flash_range.clone(), cache).await,
+    length: usize,
+    allow_overwrite_old_data: bool,
+) -> Result<QueuePushWriter<'s, S>, Error<S::Error>> {
+    let writer = run_with_auto_repair!(
+        function = push_writer_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            length,
+            allow_overwrite_old_data
+        )
+        .await,
         repair = try_repair(flash, flash_range.clone(), cache).await?
-    )
+    )?;
+
+    Ok(QueuePushWriter { flash, writer })
 }
 
-async fn space_left_inner<S: NorFlash>(
+async fn push_writer_inner<S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
     cache: &mut impl CacheImpl,
-) -> Result<u32, Error<S::Error>> {
+    length: usize,
+    allow_overwrite_old_data: bool,
+) -> Result<ItemWriter, Error<S::Error>> {
     assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
     assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
 
     assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
     assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
 
-    if cache.is_dirty() {
-        cache.invalidate_cache_state();
+    cache.recover_if_dirty();
+
+    // Data must fit in a single page
+    if length > max_item_size::<S>() {
+        cache.unmark_dirty();
+        return Err(Error::ItemTooBig);
     }
 
-    let mut total_free_space = 0;
+    let next_address = find_push_address(
+        flash,
+        flash_range.clone(),
+        cache,
+        length as u32,
+        allow_overwrite_old_data,
+        None,
+    )
+    .await?;
 
-    for page in get_pages::<S>(flash_range.clone(), 0) {
-        let state = get_page_state(flash, flash_range.clone(), cache, page).await?;
-        let page_empty =
-            is_page_empty(flash, flash_range.clone(), cache, page, Some(state)).await?;
+    let writer = ItemWriter::new(flash, flash_range, cache, next_address, length as u16).await?;
 
-        if state.is_closed() && !page_empty {
-            continue;
-        }
+    cache.unmark_dirty();
+    Ok(writer)
+}
 
-        // See how much space we can find in the current page.
-        let page_data_start_address =
-            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
-        let page_data_end_address =
-            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
+/// A writer for pushing an item into the queue piece by piece. See [push_writer].
+pub struct QueuePushWriter<'s, S: NorFlash> {
+    flash: &'s mut S,
+    writer: ItemWriter,
+}
 
-        if page_empty {
-            total_free_space += page_data_end_address - page_data_start_address;
-            continue;
-        }
+impl<'s, S: NorFlash> Debug for QueuePushWriter<'s, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueuePushWriter")
+            .field("remaining", &self.writer.remaining())
+            .finish_non_exhaustive()
+    }
+}
 
-        // Partial open page
-        let next_item_address = match cache.first_item_after_written(page) {
-            Some(next_item_address) => next_item_address,
-            None => {
-                ItemHeaderIter::new(
-                    cache
-                        .first_item_after_erased(page)
-                        .unwrap_or(page_data_start_address),
-                    page_data_end_address,
-                )
-                .traverse(flash, |_, _| true)
-                .await?
-                .1
+impl<'s, S: NorFlash> QueuePushWriter<'s, S> {
+    /// The amount of bytes that still have to be written before the item can be committed
+    pub fn remaining(&self) -> usize {
+        self.writer.remaining()
+    }
+
+    /// Write the next part of the data of the item.
+    ///
+    /// If the data is longer than what's remaining, [Error::BufferTooBig] is returned and nothing is written.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error<S::Error>> {
+        self.writer.write(self.flash, data).await
+    }
+
+    /// Commit the item. After this it can be read from the queue.
+    ///
+    /// If not all data has been written yet, [Error::BufferTooSmall] is returned with the amount of missing bytes.
+    pub async fn commit(self) -> Result<(), Error<S::Error>> {
+        self.writer.finish(self.flash).await?;
+        Ok(())
+    }
+}
+
+/// Find the address where a new item with the given data length can be written.
+/// If the current page is full, the queue moves on to the next page.
+///
+/// If items that were not popped yet get erased to make space, `on_evict` is called with them if it's given.
+async fn find_push_address<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_length: u32,
+    allow_overwrite_old_data: bool,
+    on_evict: Option<&mut dyn FnMut(EvictedItems)>,
+) -> Result<u32, Error<S::Error>> {
+    let current_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+
+    let page_data_start_address =
+        calculate_page_address::<S>(flash_range.clone(), current_page) + S::WORD_SIZE as u32;
+    let page_data_end_address =
+        calculate_page_end_address::<S>(flash_range.clone(), current_page) - S::WORD_SIZE as u32;
+
+    partial_close_page(flash, flash_range.clone(), cache, current_page).await?;
+
+    // Find the last item on the page so we know where we need to write
+
+    let mut next_address = find_next_free_item_spot(
+        flash,
+        flash_range.clone(),
+        cache,
+        page_data_start_address,
+        page_data_end_address,
+        data_length,
+    )
+    .await?;
+
+    if next_address.is_none() {
+        // No cap left on this page, move to the next page
+        let next_page = next_page::<S>(flash_range.clone(), current_page);
+        match get_page_state(flash, flash_range.clone(), cache, next_page).await? {
+            PageState::Open => {
+                close_page(flash, flash_range.clone(), cache, current_page).await?;
+                partial_close_page(flash, flash_range.clone(), cache, next_page).await?;
+                next_address = Some(
+                    calculate_page_address::<S>(flash_range.clone(), next_page)
+                        + S::WORD_SIZE as u32,
+                );
             }
-        };
+            state @ PageState::Closed => {
+                let next_page_data_start_address =
+                    calculate_page_address::<S>(flash_range.clone(), next_page)
+                        + S::WORD_SIZE as u32;
 
-        if ItemHeader::available_data_bytes::<S>(page_data_end_address - next_item_address)
-            .is_none()
-        {
-            // No data fits on this partial open page anymore.
-            // So if all data on this is already erased, then this page might as well be counted as empty.
-            // We can use [is_page_empty] and lie to to it so it checks the items.
-            if is_page_empty(
-                flash,
-                flash_range.clone(),
-                cache,
-                page,
-                Some(PageState::Closed),
-            )
-            .await?
-            {
-                total_free_space += page_data_end_address - page_data_start_address;
-                continue;
+                if !allow_overwrite_old_data
+                    && !is_page_empty(flash, flash_range.clone(), cache, next_page, Some(state))
+                        .await?
+                {
+                    cache.unmark_dirty();
+                    return Err(Error::FullStorage);
+                }
+
+                let evicted_items = match on_evict {
+                    Some(on_evict) => Some((
+                        on_evict,
+                        count_page_items(flash, flash_range.clone(), cache, next_page).await?,
+                    )),
+                    None => None,
+                };
+
+                open_page(flash, flash_range.clone(), cache, next_page).await?;
+
+                if let Some((on_evict, evicted_items)) = evicted_items {
+                    if evicted_items.count > 0 {
+                        on_evict(evicted_items);
+                    }
+                }
+
+                close_page(flash, flash_range.clone(), cache, current_page).await?;
+                partial_close_page(flash, flash_range.clone(), cache, next_page).await?;
+                next_address = Some(next_page_data_start_address);
+            }
+            PageState::PartialOpen => {
+                // This should never happen
+                return Err(Error::Corrupted {
+                    #[cfg(feature = "_test")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                });
             }
         }
-
-        total_free_space += page_data_end_address - next_item_address;
     }
 
-    cache.unmark_dirty();
-    Ok(total_free_space)
+    Ok(next_address.unwrap())
 }
 
-async fn find_youngest_page<S: NorFlash>(
+/// Get an iterator-like interface to iterate over the items stored in the queue.
+/// This goes from oldest to newest.
+///
+/// The iteration happens non-destructively, or in other words it peeks at every item.
+/// The returned entry has a [QueueIteratorEntry::pop] function with which you can decide to pop the item
+/// after you've seen the contents.
+pub async fn iter<'s, S: NorFlash, CI: CacheImpl>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+) -> Result<QueueIterator<'s, S, CI>, Error<S::Error>> {
+    // Note: Corruption repair is done in these functions already
+    QueueIterator::new(flash, flash_range, cache).await
+}
+
+/// Get an iterator-like interface to iterate over the items stored in the queue, starting after the item
+/// the given cursor was taken from. This goes from oldest to newest.
+///
+/// A cursor can be taken from an entry with [QueueIteratorEntry::cursor]. It can be stored somewhere,
+/// e.g. in flash with the [map](crate::map) because it implements [Value], so the iteration can continue after a reboot.
+///
+/// If the item of the cursor is no longer in the queue (because it was popped or overwritten),
+/// the iteration starts at the oldest item just like with [iter].
+/// This way no items are ever skipped, though items may be seen again.
+pub async fn iter_from<'s, S: NorFlash, CI: CacheImpl>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+    cursor: &QueueCursor,
+) -> Result<QueueIterator<'s, S, CI>, Error<S::Error>> {
+    // Note: Corruption repair is done in these functions already
+    QueueIterator::new_from_cursor(flash, flash_range, cache, cursor, false).await
+}
+
+/// Pop all items from the queue one by one, oldest first.
+///
+/// This is like [iter] where every entry is popped right after it's been read.
+/// Call [QueueDrain::next] until it returns `None` to empty the queue.
+///
+/// An item is only popped once it's been fully read. So if the draining is stopped early for any reason,
+/// e.g. an error, a cancelled future or a power loss, all items that weren't given back yet are still in the queue.
+/// Only the item that was being popped at that moment might or might not be popped.
+pub async fn drain<'s, S: MultiwriteNorFlash, CI: CacheImpl>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+) -> Result<QueueDrain<'s, S, CI>, Error<S::Error>> {
+    Ok(QueueDrain {
+        iter: iter(flash, flash_range, cache).await?,
+    })
+}
+
+/// Peek at the oldest data.
+///
+/// If you also want to remove the data use [pop].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn peek<'d, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
-    cache: &mut impl PrivateCacheImpl,
-) -> Result<usize, Error<S::Error>> {
-    let last_used_page =
-        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?;
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    // Note: Corruption repair is done in these functions already
+    let mut iterator = iter(flash, flash_range, cache).await?;
 
-    if let Some(last_used_page) = last_used_page {
-        return Ok(last_used_page);
+    let next_value = iterator.next(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.into_buf())),
+        None => Ok(None),
     }
+}
 
-    // We have no partial open page. Search for an open page to start in
-    let first_open_page = find_first_page(flash, flash_range, cache, 0, PageState::Open).await?;
+/// Peek at the data at the given position in the queue, where `n == 0` is the oldest data.
+/// Nothing is removed from the queue.
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// All items before the requested one are read into the `data_buffer` too,
+/// so if the data buffer is not big enough for any of them, an error is returned.
+/// If the queue holds `n` items or less, `None` is returned.
+pub async fn peek_nth<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    n: usize,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
 
-    if let Some(first_open_page) = first_open_page {
-        return Ok(first_open_page);
+    for _ in 0..n {
+        if iterator.next(data_buffer).await?.is_none() {
+            return Ok(None);
+        }
     }
 
-    // All pages are closed... This is not correct.
-    Err(Error::Corrupted {
-        #[cfg(feature = "_test")]
-        backtrace: std::backtrace::Backtrace::capture(),
-    })
+    let next_value = iterator.next(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.into_buf())),
+        None => Ok(None),
+    }
 }
 
-async fn find_oldest_page<S: NorFlash>(
+/// Peek at the oldest data and get a handle to it.
+///
+/// The handle can later be given to [ack] to pop the item.
+/// This way the item can e.g. stay in flash until its transmission has been confirmed.
+///
+/// If handling the item fails, don't call [ack]. The item then stays at the front of the queue
+/// and is given back again by the next peek, even if new data was pushed in the meantime.
+/// To hand out multiple items before they're acked and put the unacked ones back at the front
+/// later, use [Queue::peek_handle] and [Queue::requeue].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn peek_handle<'d, S: NorFlash>(
     flash: &mut S,
     flash_range: Range<u32>,
-    cache: &mut impl PrivateCacheImpl,
-) -> Result<usize, Error<S::Error>> {
-    let youngest_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(QueueCursor, &'d mut [u8])>, Error<S::Error>> {
+    // Note: Corruption repair is done in these functions already
+    let mut iterator = iter(flash, flash_range, cache).await?;
 
-    // The oldest page is the first non-open page after the youngest page
-    let oldest_closed_page =
-        find_first_page(flash, flash_range, cache, youngest_page, PageState::Closed).await?;
+    let next_value = iterator.next(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some((entry.cursor(), entry.into_buf()))),
+        None => Ok(None),
+    }
+}
+
+/// Find the oldest data for which the predicate returns true and get a handle to it.
+/// Nothing is removed from the queue.
+///
+/// The handle can later be given to [ack] to pop the item, or it can be used with [iter_from].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// All items before the found one are read into the `data_buffer` too,
+/// so if the data buffer is not big enough for any of them, an error is returned.
+pub async fn find<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+    mut predicate: impl FnMut(&[u8]) -> bool,
+) -> Result<Option<(QueueCursor, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator
+        .next_matching(data_buffer, |_, data| predicate(data))
+        .await?;
+
+    match next_value {
+        Some(entry) => Ok(Some((entry.cursor(), entry.into_buf()))),
+        None => Ok(None),
+    }
+}
+
+/// Pop the item the given handle points at. Handles can be gotten from [peek_handle], [find],
+/// or from any iterator entry with [QueueIteratorEntry::cursor].
+///
+/// The item doesn't have to be the oldest item, so items can be popped out of order.
+/// The other items stay in the queue in the same order.
+///
+/// Returns true if the item was popped and false if the item was not in the queue anymore.
+pub async fn ack<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    handle: &QueueCursor,
+) -> Result<bool, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = ack_inner(flash, flash_range.clone(), cache, handle).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn ack_inner<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    handle: &QueueCursor,
+) -> Result<bool, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let Some(header) = handle
+        .read_header(flash, flash_range.clone(), false)
+        .await?
+    else {
+        cache.unmark_dirty();
+        return Ok(false);
+    };
+
+    header
+        .erase_data(flash, flash_range, cache, handle.address)
+        .await?;
+
+    cache.unmark_dirty();
+    Ok(true)
+}
+
+/// Pop the oldest data from the queue.
+///
+/// If you don't want to remove the data use [peek].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn pop<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator.next(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.pop().await?)),
+        None => Ok(None),
+    }
+}
+
+/// Pop the newest data from the queue.
+///
+/// This gives the queue stack-like behaviour, but it's a lot more expensive than [pop]
+/// because the whole queue has to be iterated to find the newest data.
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// Every item in the queue is read into the `data_buffer`,
+/// so if the data buffer is not big enough for any of them, an error is returned.
+pub async fn pop_last<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let mut last_address = None;
+    while let Some(entry) = iterator.next(data_buffer).await? {
+        last_address = Some(entry.address);
+    }
+
+    let Some(last_address) = last_address else {
+        return Ok(None);
+    };
+
+    // Go back to the newest item so we can pop it
+    iterator.next_address = NextAddress::Address(last_address);
+    let next_value = iterator.next(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.pop().await?)),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data from the queue, but only if the given predicate returns true for it.
+///
+/// The predicate gets to see the data of the oldest item. If it returns false, the item stays in the queue
+/// and `None` is returned, just like when the queue is empty.
+/// This saves reading the item twice compared to doing a [peek] followed by a [pop].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn pop_if<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+    predicate: impl FnOnce(&[u8]) -> bool,
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator.next(data_buffer).await?;
+
+    match next_value {
+        Some(entry) if predicate(&entry) => Ok(Some(entry.pop().await?)),
+        Some(_) | None => Ok(None),
+    }
+}
+
+/// Peek at multiple items from the queue in one go, oldest first. Nothing is removed from the queue.
+///
+/// At most `data_buffers.len()` items are read. The first item goes into the first buffer, the second item
+/// into the second buffer, etc. Every buffer that received an item is shrunk to the length of that item.
+/// Buffers that didn't receive an item are left untouched.
+///
+/// The amount of read items is returned. This can be lower than the amount of buffers if the queue ran out of items.
+///
+/// This is cheaper than calling [peek_nth] multiple times since the search for the oldest data only has to be done once.
+///
+/// If a data buffer is not big enough for its item an error is returned.
+pub async fn peek_many<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffers: &mut [&mut [u8]],
+) -> Result<usize, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    for (index, data_buffer) in data_buffers.iter_mut().enumerate() {
+        let peeked_len = match iterator.next(data_buffer).await? {
+            Some(entry) => entry.len(),
+            None => return Ok(index),
+        };
+
+        let full_buffer = core::mem::take(data_buffer);
+        *data_buffer = &mut full_buffer[..peeked_len];
+    }
+
+    Ok(data_buffers.len())
+}
+
+/// Pop multiple items from the queue in one go, oldest first.
+///
+/// At most `data_buffers.len()` items are popped. The first item goes into the first buffer, the second item
+/// into the second buffer, etc. Every buffer that received an item is shrunk to the length of that item.
+/// Buffers that didn't receive an item are left untouched.
+///
+/// The amount of popped items is returned. This can be lower than the amount of buffers if the queue ran out of items.
+///
+/// This is cheaper than calling [pop] multiple times since the search for the oldest data only has to be done once.
+///
+/// If a data buffer is not big enough for its item an error is returned.
+/// The items that were popped before that remain popped.
+pub async fn pop_many<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffers: &mut [&mut [u8]],
+) -> Result<usize, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    for (index, data_buffer) in data_buffers.iter_mut().enumerate() {
+        let popped_len = match iterator.next(data_buffer).await? {
+            Some(entry) => entry.pop().await?.len(),
+            None => return Ok(index),
+        };
+
+        let full_buffer = core::mem::take(data_buffer);
+        *data_buffer = &mut full_buffer[..popped_len];
+    }
+
+    Ok(data_buffers.len())
+}
+
+/// Pop the oldest data from the queue into multiple buffers.
+///
+/// The data is split over the buffers in order. The first buffer is filled up first, then the second, etc.
+/// This way e.g. a header and a payload can be read into separate buffers directly.
+///
+/// The total length of the data is returned. Buffers after the end of the data are left untouched.
+/// If the buffers together are not big enough for the data, [Error::BufferTooSmall] is returned
+/// and the item is not popped.
+///
+/// *Note: The data is read twice, once to check its crc and once to fill the buffers.*
+pub async fn pop_vectored<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffers: &mut [&mut [u8]],
+) -> Result<Option<usize>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let Some(mut reader) = iterator.next_reader().await? else {
+        return Ok(None);
+    };
+
+    let length = reader.len();
+    let buffers_length = data_buffers
+        .iter()
+        .map(|buffer| buffer.len())
+        .sum::<usize>();
+    if buffers_length < length {
+        return Err(Error::BufferTooSmall(length));
+    }
+
+    for data_buffer in data_buffers.iter_mut() {
+        if reader.remaining() == 0 {
+            break;
+        }
+
+        reader.read(data_buffer).await?;
+    }
+
+    reader.pop().await?;
+    Ok(Some(length))
+}
+
+/// Read out all items of the queue, oldest first. Nothing is removed from the queue.
+///
+/// This is mostly useful for tools running on a host and for tests, since no buffers have to be managed.
+#[cfg(feature = "std")]
+pub async fn dump<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<Vec<Vec<u8>>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+    let mut items = Vec::new();
+
+    while let Some(mut reader) = iterator.next_reader().await? {
+        let mut item = vec![0; reader.len()];
+        reader.read(&mut item).await?;
+        items.push(item);
+    }
+
+    Ok(items)
+}
+
+/// Pop items from the queue for as long as the given callback returns true, oldest first.
+///
+/// The data of every item is read into the `data_buffer` and given to the callback.
+/// If the callback returns true, the item is popped and the next item is given to the callback.
+/// If the callback returns false, the item is not popped and no more items are looked at.
+///
+/// The amount of popped items is returned.
+///
+/// If the data buffer is not big enough for an item an error is returned.
+/// The items that were popped before that remain popped.
+pub async fn pop_all<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &mut [u8],
+    mut callback: impl FnMut(&mut [u8]) -> bool,
+) -> Result<usize, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+    let mut popped = 0;
+
+    while let Some(mut entry) = iterator.next(data_buffer).await? {
+        if !callback(&mut entry) {
+            break;
+        }
+
+        entry.pop().await?;
+        popped += 1;
+    }
+
+    Ok(popped)
+}
+
+/// Move all items from the queue in `source_range` to the queue in `destination_range`.
+/// The order of the items is kept and the amount of pages of both ranges doesn't need to be the same.
+///
+/// Every item is read into the `data_buffer`, pushed to the destination and then popped from the source.
+/// So if the data buffer is not big enough for any of the items, an error is returned.
+/// If the destination is full, [Error::FullStorage] is returned. No data is overwritten.
+/// In both cases all items that weren't moved yet are still in the source queue.
+///
+/// The amount of moved items is returned.
+/// If the source and destination ranges overlap, [Error::OverlappingRanges] is returned and nothing is moved.
+///
+/// *Note: If the migration is interrupted (e.g. by a power loss) right after an item was pushed,
+/// that item is still in the source too. Running the migration again will then duplicate it.*
+pub async fn migrate<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    source_range: Range<u32>,
+    source_cache: &mut impl CacheImpl,
+    destination_range: Range<u32>,
+    destination_cache: &mut impl CacheImpl,
+    data_buffer: &mut [u8],
+) -> Result<usize, Error<S::Error>> {
+    if source_range.start < destination_range.end && destination_range.start < source_range.end {
+        return Err(Error::OverlappingRanges);
+    }
+
+    let mut moved = 0;
+
+    while let Some((metadata, data)) =
+        peek_with_metadata(flash, source_range.clone(), source_cache, data_buffer).await?
+    {
+        push_with_metadata(
+            flash,
+            destination_range.clone(),
+            destination_cache,
+            data,
+            metadata,
+            false,
+        )
+        .await?;
+        pop(flash, source_range.clone(), source_cache, data_buffer).await?;
+
+        moved += 1;
+    }
+
+    Ok(moved)
+}
+
+/// Push data into the queue like [push], but store the given metadata with it.
+/// The metadata can be read back with [peek_with_metadata], [pop_with_metadata] and [QueueIteratorEntry::metadata].
+///
+/// The metadata is stored in front of the data, so it takes up some of the space of the item.
+/// It's marked as such in the header of the item, so the functions that don't deal with metadata, like [peek],
+/// still give back only the data. Metadata without any fields set is not stored at all, so it's the same as [push].
+///
+/// The metadata as it was stored is returned. This includes the sequence number if one was requested
+/// with [ItemMetadata::with_sequence_number].
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+pub async fn push_with_metadata<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data: &[u8],
+    metadata: ItemMetadata,
+    allow_overwrite_old_data: bool,
+) -> Result<ItemMetadata, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = push_with_metadata_inner(
+            flash,
+            flash_range.clone(),
+            cache,
+            data,
+            metadata,
+            allow_overwrite_old_data
+        )
+        .await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn push_with_metadata_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data: &[u8],
+    mut metadata: ItemMetadata,
+    allow_overwrite_old_data: bool,
+) -> Result<ItemMetadata, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    if metadata.needs_sequence_number() {
+        cache.recover_if_dirty();
+
+        let sequence_number =
+            match find_newest_sequence_number(flash, flash_range.clone(), cache).await? {
+                Some(newest) => newest.wrapping_add(1),
+                None => 0,
+            };
+        metadata = metadata.with_assigned_sequence_number(sequence_number);
+    }
+
+    if metadata.is_empty() {
+        push_inner(
+            flash,
+            flash_range,
+            cache,
+            &[data],
+            false,
+            allow_overwrite_old_data,
+            None,
+        )
+        .await?;
+        return Ok(metadata);
+    }
+
+    let mut metadata_buffer = [0; ItemMetadata::MAX_LENGTH];
+    let metadata_length = metadata.serialize_into(&mut metadata_buffer);
+
+    push_inner(
+        flash,
+        flash_range,
+        cache,
+        &[&metadata_buffer[..metadata_length], data],
+        true,
+        allow_overwrite_old_data,
+        None,
+    )
+    .await?;
+    Ok(metadata)
+}
+
+/// Peek at the oldest data and its metadata. See [push_with_metadata].
+/// Items that were pushed without metadata give back [ItemMetadata::new].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn peek_with_metadata<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(ItemMetadata, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match iterator.next(data_buffer).await? {
+        Some(entry) => Ok(Some((entry.metadata(), entry.into_buf()))),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data and its metadata. See [push_with_metadata].
+/// Items that were pushed without metadata give back [ItemMetadata::new].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn pop_with_metadata<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(ItemMetadata, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match iterator.next(data_buffer).await? {
+        Some(entry) => {
+            let metadata = entry.metadata();
+            Ok(Some((metadata, entry.pop().await?)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Push data into the queue like [push], but tag it with the given channel.
+/// This way multiple logical queues can share the same flash range.
+/// The data can be taken out again with [peek_channel] and [pop_channel].
+///
+/// The channel is stored in the [ItemMetadata] of the item, so every item has three extra bytes of overhead.
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data,
+/// regardless of the channel that data belongs to.
+pub async fn push_channel<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    channel: u8,
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
+    push_with_metadata(
+        flash,
+        flash_range,
+        cache,
+        data,
+        ItemMetadata::new().with_channel(channel),
+        allow_overwrite_old_data,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Peek at the oldest data of the given channel. See [push_channel].
+/// Items without a channel are skipped.
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// All items in the queue before the found item are read into the `data_buffer` too,
+/// so if the data buffer is not big enough for any of them, an error is returned.
+pub async fn peek_channel<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    channel: u8,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator
+        .next_matching(data_buffer, |metadata, _| {
+            metadata.channel() == Some(channel)
+        })
+        .await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.into_buf())),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data of the given channel. See [push_channel].
+/// Items without a channel are skipped.
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// All items in the queue before the found item are read into the `data_buffer` too,
+/// so if the data buffer is not big enough for any of them, an error is returned.
+pub async fn pop_channel<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    channel: u8,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator
+        .next_matching(data_buffer, |metadata, _| {
+            metadata.channel() == Some(channel)
+        })
+        .await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.pop().await?)),
+        None => Ok(None),
+    }
+}
+
+/// The priority of an item pushed with [push_with_priority]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Priority {
+    /// The item is taken out in the normal order
+    #[default]
+    Normal,
+    /// The item is taken out before all items with normal priority
+    High,
+}
+
+/// Push data into the queue like [push], but with a priority.
+/// With [peek_prioritized] and [pop_prioritized] the items with high priority are taken out before the
+/// items with normal priority. Items with the same priority are taken out oldest first.
+///
+/// The priority is stored in the [ItemMetadata] of the item. High priority items have two extra bytes of overhead.
+/// Items with normal priority are stored like with [push], so all items that weren't pushed with a priority
+/// have normal priority.
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data,
+/// regardless of its priority.
+pub async fn push_with_priority<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data: &[u8],
+    priority: Priority,
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
+    push_with_metadata(
+        flash,
+        flash_range,
+        cache,
+        data,
+        ItemMetadata::new().with_priority(priority),
+        allow_overwrite_old_data,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Peek at the oldest data with the highest priority. See [push_with_priority].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If there are no items with high priority, the whole queue has to be read to find that out.
+/// So all items are read into the `data_buffer` and if the data buffer is not big enough for any of them,
+/// an error is returned.
+pub async fn peek_prioritized<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator.next_prioritized(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.into_buf())),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data with the highest priority. See [push_with_priority].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If there are no items with high priority, the whole queue has to be read to find that out.
+/// So all items are read into the `data_buffer` and if the data buffer is not big enough for any of them,
+/// an error is returned.
+pub async fn pop_prioritized<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let next_value = iterator.next_prioritized(data_buffer).await?;
+
+    match next_value {
+        Some(entry) => Ok(Some(entry.pop().await?)),
+        None => Ok(None),
+    }
+}
+
+/// Push data into the queue like [push], but store a timestamp with it.
+/// The data can be taken out again with [peek_with_timestamp] and [pop_with_timestamp]
+/// and old data can be removed with [pop_older_than].
+///
+/// The timestamp is stored in the [ItemMetadata] of the item, so it's not part of the data and every item
+/// has 10 bytes of extra overhead. The unit of the timestamp is up to you.
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+pub async fn push_with_timestamp<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    timestamp: u64,
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
+    push_with_metadata(
+        flash,
+        flash_range,
+        cache,
+        data,
+        ItemMetadata::new().with_timestamp(timestamp),
+        allow_overwrite_old_data,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Peek at the oldest data and its timestamp. See [push_with_timestamp].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+/// If the item was pushed without a timestamp, a [SerializationError::InvalidFormat] is returned.
+pub async fn peek_with_timestamp<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(u64, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match iterator.next(data_buffer).await? {
+        Some(entry) => Ok(Some((entry_timestamp(&entry)?, entry.into_buf()))),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data and its timestamp. See [push_with_timestamp].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+/// If the item was pushed without a timestamp, a [SerializationError::InvalidFormat] is returned
+/// and the item is not popped.
+pub async fn pop_with_timestamp<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(u64, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match iterator.next(data_buffer).await? {
+        Some(entry) => Ok(Some((entry_timestamp(&entry)?, entry.pop().await?))),
+        None => Ok(None),
+    }
+}
+
+/// Pop all items with a timestamp older than the given timestamp. See [push_with_timestamp].
+///
+/// Items are popped from the oldest until an item is found with a timestamp that is equal or newer.
+/// So this assumes the timestamps only go up.
+///
+/// Every item is read into the `data_buffer`, so if the data buffer is not big enough an error is returned.
+/// If an item was pushed without a timestamp, a [SerializationError::InvalidFormat] is returned.
+/// The items that were popped before that remain popped.
+///
+/// The amount of popped items is returned.
+pub async fn pop_older_than<S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &mut [u8],
+    timestamp: u64,
+) -> Result<usize, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+    let mut popped = 0;
+
+    while let Some(entry) = iterator.next(data_buffer).await? {
+        if entry_timestamp(&entry)? >= timestamp {
+            break;
+        }
+
+        entry.pop().await?;
+        popped += 1;
+    }
+
+    Ok(popped)
+}
+
+fn entry_timestamp<S: NorFlash, CI: CacheImpl>(
+    entry: &QueueIteratorEntry<'_, '_, '_, S, CI>,
+) -> Result<u64, Error<S::Error>> {
+    entry
+        .metadata()
+        .timestamp()
+        .ok_or(Error::SerializationError(SerializationError::InvalidFormat))
+}
+
+/// Push data into the queue like [push], but tag it with a flags byte.
+/// The flags can be anything you want, e.g. a bit for "urgent" and a bit for "compressed".
+/// The data can be taken out again with [peek_with_flags] and [pop_with_flags].
+///
+/// The flags are stored in the [ItemMetadata] of the item, so they're not part of the data and every item
+/// has three extra bytes of overhead. Flags that are 0 are not stored, so the item is the same as with [push].
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+pub async fn push_with_flags<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    flags: u8,
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
+    push_with_metadata(
+        flash,
+        flash_range,
+        cache,
+        data,
+        ItemMetadata::new().with_flags(flags),
+        allow_overwrite_old_data,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Peek at the oldest data and its flags. See [push_with_flags].
+/// Items that were pushed without flags have flags 0.
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn peek_with_flags<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(u8, &'d mut [u8])>, Error<S::Error>> {
+    Ok(peek_with_metadata(flash, flash_range, cache, data_buffer)
+        .await?
+        .map(|(metadata, data)| (metadata.flags(), data)))
+}
+
+/// Pop the oldest data and its flags. See [push_with_flags].
+/// Items that were pushed without flags have flags 0.
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+pub async fn pop_with_flags<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(u8, &'d mut [u8])>, Error<S::Error>> {
+    Ok(pop_with_metadata(flash, flash_range, cache, data_buffer)
+        .await?
+        .map(|(metadata, data)| (metadata.flags(), data)))
+}
+
+/// Push data into the queue like [push], but give it a sequence number.
+/// The data can be taken out again with [peek_with_sequence] and [pop_with_sequence].
+///
+/// Every pushed item gets the sequence number of the previously pushed item plus one, which is returned.
+/// This way gaps can be detected when e.g. uploading the items.
+/// Popped items are taken into account as long as they're still in flash.
+/// Only when all pages are erased, the sequence numbers start again from 0.
+/// After `u32::MAX` comes 0 again too.
+///
+/// The sequence number is stored in the [ItemMetadata] of the item, so it's not part of the data and every item
+/// has 6 bytes of extra overhead. Items without a sequence number don't take one up.
+/// To combine a sequence number with other metadata, use [push_with_metadata] with [ItemMetadata::with_sequence_number].
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+pub async fn push_with_sequence<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<u32, Error<S::Error>> {
+    let metadata = push_with_metadata(
+        flash,
+        flash_range,
+        cache,
+        data,
+        ItemMetadata::new().with_sequence_number(),
+        allow_overwrite_old_data,
+    )
+    .await?;
+
+    Ok(metadata
+        .sequence_number()
+        .expect("A sequence number is always assigned"))
+}
+
+/// Find the sequence number of the newest item with one, including the items that are already erased.
+async fn find_newest_sequence_number<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateCacheImpl,
+) -> Result<Option<u32>, Error<S::Error>> {
+    let youngest_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+    let mut page = youngest_page;
+
+    // Walk back from the youngest page until we find a page with items
+    loop {
+        if get_page_state(flash, flash_range.clone(), cache, page)
+            .await?
+            .is_open()
+        {
+            return Ok(None);
+        }
+
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
+
+        // Erased items are not skipped, since their sequence numbers have been used too
+        let mut it = ItemHeaderIter::new(page_data_start_address, page_data_end_address);
+
+        let mut newest_sequence_number = None;
+        while let (Some(header), address) =
+            it.traverse(flash, |header, _| !header.has_metadata).await?
+        {
+            let mut buffer = [0; ItemMetadata::MAX_LENGTH];
+            let read = header
+                .read_data_part(flash, address, 0, &mut buffer)
+                .await?;
+
+            // The data of erased items can't be checked, so metadata that can't be read is ignored
+            if let Ok((metadata, _)) = ItemMetadata::deserialize_from(&buffer[..read]) {
+                if let Some(sequence_number) = metadata.sequence_number() {
+                    newest_sequence_number = Some(sequence_number);
+                }
+            }
+        }
+
+        if newest_sequence_number.is_some() {
+            return Ok(newest_sequence_number);
+        }
+
+        page = previous_page::<S>(flash_range.clone(), page);
+        if page == youngest_page {
+            return Ok(None);
+        }
+    }
+}
+
+/// Peek at the oldest data and its sequence number. See [push_with_sequence].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+/// If the item was pushed without a sequence number, a [SerializationError::InvalidFormat] is returned.
+pub async fn peek_with_sequence<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(u32, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match iterator.next(data_buffer).await? {
+        Some(entry) => Ok(Some((entry_sequence_number(&entry)?, entry.into_buf()))),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data and its sequence number. See [push_with_sequence].
+///
+/// The data is written to the given `data_buffer` and the part that was written is returned.
+/// It is valid to only use the length of the returned slice and use the original `data_buffer`.
+/// The `data_buffer` may contain extra data on ranges after the returned slice.
+/// You should not depend on that data.
+///
+/// If the data buffer is not big enough an error is returned.
+/// If the item was pushed without a sequence number, a [SerializationError::InvalidFormat] is returned
+/// and the item is not popped.
+pub async fn pop_with_sequence<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<(u32, &'d mut [u8])>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match iterator.next(data_buffer).await? {
+        Some(entry) => Ok(Some((entry_sequence_number(&entry)?, entry.pop().await?))),
+        None => Ok(None),
+    }
+}
+
+fn entry_sequence_number<S: NorFlash, CI: CacheImpl>(
+    entry: &QueueIteratorEntry<'_, '_, '_, S, CI>,
+) -> Result<u32, Error<S::Error>> {
+    entry
+        .metadata()
+        .sequence_number()
+        .ok_or(Error::SerializationError(SerializationError::InvalidFormat))
+}
+
+/// An iterator-like interface for peeking into data stored in flash with the option to pop it.
+pub struct QueueIterator<'s, S: NorFlash, CI: CacheImpl> {
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+    next_address: NextAddress,
+    skipped_items: usize,
+    cursor: Option<QueueCursor>,
+}
+
+impl<'d, S: NorFlash, CI: CacheImpl> Debug for QueueIterator<'d, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueueIterator")
+            .field("current_address", &self.next_address)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum NextAddress {
+    Address(u32),
+    PageAfter(usize),
+}
+
+impl<'s, S: NorFlash, CI: CacheImpl> QueueIterator<'s, S, CI> {
+    async fn new(
+        flash: &'s mut S,
+        flash_range: Range<u32>,
+        cache: &'s mut CI,
+    ) -> Result<Self, Error<S::Error>> {
+        let start_address = run_with_auto_repair!(
+            function = Self::find_start_address(flash, flash_range.clone(), cache).await,
+            repair = try_repair(flash, flash_range.clone(), cache).await?
+        )?;
+
+        Ok(Self {
+            flash,
+            flash_range,
+            cache,
+            next_address: start_address,
+            skipped_items: 0,
+            cursor: None,
+        })
+    }
+
+    /// Start after the item of the cursor. With `allow_popped`, this also works when that item
+    /// has been popped since, which is used to continue after the items [Queue::peek_handle] handed out.
+    async fn new_from_cursor(
+        flash: &'s mut S,
+        flash_range: Range<u32>,
+        cache: &'s mut CI,
+        cursor: &QueueCursor,
+        allow_popped: bool,
+    ) -> Result<Self, Error<S::Error>> {
+        let start_address = run_with_auto_repair!(
+            function =
+                Self::find_cursor_address(flash, flash_range.clone(), cache, cursor, allow_popped)
+                    .await,
+            repair = try_repair(flash, flash_range.clone(), cache).await?
+        )?;
+
+        Ok(Self {
+            flash,
+            flash_range,
+            cache,
+            next_address: start_address,
+            skipped_items: 0,
+            cursor: None,
+        })
+    }
+
+    async fn find_cursor_address(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut CI,
+        cursor: &QueueCursor,
+        allow_popped: bool,
+    ) -> Result<NextAddress, Error<S::Error>> {
+        let start_address = Self::find_start_address(flash, flash_range.clone(), cache).await?;
+
+        match cursor
+            .read_header(flash, flash_range.clone(), allow_popped)
+            .await?
+        {
+            Some(header) => {
+                let page = calculate_page_index::<S>(flash_range.clone(), cursor.address);
+                let page_data_end_address =
+                    calculate_page_end_address::<S>(flash_range, page) - S::WORD_SIZE as u32;
+
+                let next_address = header.next_item_address::<S>(cursor.address);
+                if next_address >= page_data_end_address {
+                    Ok(NextAddress::PageAfter(page))
+                } else {
+                    Ok(NextAddress::Address(next_address))
+                }
+            }
+            None => Ok(start_address),
+        }
+    }
+
+    async fn find_start_address(
+        flash: &mut S,
+        flash_range: Range<u32>,
+        cache: &mut CI,
+    ) -> Result<NextAddress, Error<S::Error>> {
+        assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+        assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+        assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+        assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+        cache.recover_if_dirty();
+
+        let oldest_page = find_oldest_page(flash, flash_range.clone(), cache).await?;
+
+        // We start at the start of the oldest page
+        let current_address = match cache.first_item_after_erased(oldest_page) {
+            Some(address) => address,
+            None => {
+                calculate_page_address::<S>(flash_range.clone(), oldest_page) + S::WORD_SIZE as u32
+            }
+        };
+
+        Ok(NextAddress::Address(current_address))
+    }
+
+    /// Get a cursor to the entry that was given back last, or `None` if no entry has been given back yet.
+    ///
+    /// This is the position of the iterator. Give it to [iter_from] to continue the iteration later,
+    /// e.g. after doing other operations on the flash.
+    pub fn cursor(&self) -> Option<QueueCursor> {
+        self.cursor
+    }
+
+    /// The amount of corrupted items that have been skipped by this iterator so far.
+    ///
+    /// Corrupted items are never given back, but they don't stop the iteration either.
+    /// Items are typically only corrupted when a push was interrupted by e.g. a power loss.
+    pub fn skipped_items(&self) -> usize {
+        self.skipped_items
+    }
+
+    /// Get the next entry.
+    ///
+    /// If there are no more entries, None is returned.
+    ///
+    /// The `data_buffer` has to be large enough to be able to hold the largest item in flash.
+    pub async fn next<'d, 'q>(
+        &'q mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<QueueIteratorEntry<'s, 'd, 'q, S, CI>>, Error<S::Error>> {
+        self.next_matching(data_buffer, |_, _| true).await
+    }
+
+    /// Get the oldest entry with the highest priority. See [push_with_priority].
+    async fn next_prioritized<'d, 'q>(
+        &'q mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<QueueIteratorEntry<'s, 'd, 'q, S, CI>>, Error<S::Error>> {
+        let high_priority_address = self
+            .next_matching(data_buffer, |metadata, _| {
+                metadata.priority() == Priority::High
+            })
+            .await?
+            .map(|entry| entry.address);
+
+        // Go back to the found item or to the start, where the oldest item then has normal priority
+        self.next_address = match high_priority_address {
+            Some(address) => NextAddress::Address(address),
+            None => run_with_auto_repair!(
+                function =
+                    Self::find_start_address(self.flash, self.flash_range.clone(), self.cache)
+                        .await,
+                repair = try_repair(self.flash, self.flash_range.clone(), self.cache).await?
+            )?,
+        };
+
+        self.next(data_buffer).await
+    }
+
+    /// Get the next entry for which the predicate returns true. All entries before it are skipped.
+    /// The predicate gets the metadata and the data of every entry.
+    ///
+    /// If there are no more entries, None is returned.
+    ///
+    /// The `data_buffer` has to be large enough to be able to hold the largest item in flash.
+    pub(crate) async fn next_matching<'d, 'q>(
+        &'q mut self,
+        data_buffer: &'d mut [u8],
+        mut predicate: impl FnMut(&ItemMetadata, &[u8]) -> bool,
+    ) -> Result<Option<QueueIteratorEntry<'s, 'd, 'q, S, CI>>, Error<S::Error>> {
+        loop {
+            let value = run_with_auto_repair!(
+                function = self.next_inner(Some(data_buffer)).await,
+                repair = try_repair(self.flash, self.flash_range.clone(), self.cache).await?
+            )?;
+
+            let Some((item, address)) = value else {
+                return Ok(None);
+            };
+
+            let header = item.header;
+            let (metadata, data_start) = if header.has_metadata {
+                match ItemMetadata::deserialize_from(&data_buffer[..header.length as usize]) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
+                        // Stored by a newer version of the crate, so we can't tell where the data starts
+                        self.skipped_items += 1;
+                        continue;
+                    }
+                }
+            } else {
+                (ItemMetadata::new(), 0)
+            };
+
+            // The data always starts at the start of the buffer
+            data_buffer.copy_within(data_start..header.length as usize, 0);
+            let data_length = header.length as usize - data_start;
+
+            if !predicate(&metadata, &data_buffer[..data_length]) {
+                continue;
+            }
+
+            let cursor = QueueCursor {
+                address,
+                crc: header.crc.expect("Entries are never erased items"),
+            };
+            self.cursor = Some(cursor);
+
+            return Ok(Some(QueueIteratorEntry {
+                iter: self,
+                header,
+                address,
+                metadata,
+                data: &mut data_buffer[..data_length],
+            }));
+        }
+    }
+
+    /// Find the next item.
+    ///
+    /// If a data buffer is given, the item is read into it and checked.
+    /// Without one the item is only checked and not kept in memory.
+    async fn next_inner(
+        &mut self,
+        mut data_buffer: Option<&mut [u8]>,
+    ) -> Result<Option<(ItemUnborrowed, u32)>, Error<S::Error>> {
+        let read_data = data_buffer.is_some();
+
+        self.cache.recover_if_dirty();
+
+        loop {
+            // Get the current page and address based on what was stored
+            let (current_page, current_address) = match self.next_address {
+                NextAddress::PageAfter(previous_page) => {
+                    let next_page = next_page::<S>(self.flash_range.clone(), previous_page);
+                    if get_page_state(
+                        self.flash,
+                        self.flash_range.clone(),
+                        &mut self.cache,
+                        next_page,
+                    )
+                    .await?
+                    .is_open()
+                        || next_page
+                            == find_oldest_page(
+                                self.flash,
+                                self.flash_range.clone(),
+                                &mut self.cache,
+                            )
+                            .await?
+                    {
+                        self.cache.unmark_dirty();
+                        return Ok(None);
+                    }
+
+                    let current_address =
+                        calculate_page_address::<S>(self.flash_range.clone(), next_page)
+                            + S::WORD_SIZE as u32;
+
+                    self.next_address = NextAddress::Address(current_address);
+
+                    (next_page, current_address)
+                }
+                NextAddress::Address(address) => (
+                    calculate_page_index::<S>(self.flash_range.clone(), address),
+                    address,
+                ),
+            };
+
+            let page_data_end_address =
+                calculate_page_end_address::<S>(self.flash_range.clone(), current_page)
+                    - S::WORD_SIZE as u32;
+
+            // Search for the first item with data
+            let mut it = ItemHeaderIter::new(current_address, page_data_end_address);
+            // No need to worry about cache here since that has been dealt with at the creation of this iterator
+            let found = it
+                .traverse(self.flash, |header, _| header.crc.is_none())
+                .await?;
+            self.skipped_items += it.corrupted_headers();
+
+            if let (Some(found_item_header), found_item_address) = found {
+                let next_address = found_item_header.next_item_address::<S>(found_item_address);
+                self.next_address = if next_address >= page_data_end_address {
+                    NextAddress::PageAfter(current_page)
+                } else {
+                    NextAddress::Address(next_address)
+                };
+
+                let found_item = if read_data {
+                    let maybe_item = found_item_header
+                        .read_item(
+                            self.flash,
+                            data_buffer.take().unwrap(),
+                            found_item_address,
+                            page_data_end_address,
+                        )
+                        .await?;
+
+                    match maybe_item {
+                        item::MaybeItem::Corrupted(_, db) => {
+                            data_buffer.replace(db);
+                            None
+                        }
+                        // The queue doesn't write tombstones, so this is just a corrupted item
+                        item::MaybeItem::Tombstone(item) => {
+                            data_buffer.replace(item.destruct().1);
+                            None
+                        }
+                        item::MaybeItem::Erased(_, _) => unreachable!("Item is already erased"),
+                        item::MaybeItem::Present(item) => Some(item.unborrow()),
+                    }
+                } else if found_item_header
+                    .verify_data(self.flash, found_item_address, page_data_end_address)
+                    .await?
+                {
+                    Some(ItemUnborrowed::without_data(found_item_header))
+                } else {
+                    None
+                };
+
+                match found_item {
+                    Some(found_item) => {
+                        // Return the item we found
+                        self.cache.unmark_dirty();
+                        return Ok(Some((found_item, found_item_address)));
+                    }
+                    None => self.skipped_items += 1,
+                }
+            } else {
+                self.next_address = NextAddress::PageAfter(current_page);
+            }
+        }
+    }
+
+    /// Get the next entry as a reader with which the data can be read in parts.
+    /// This way no buffer is needed that's large enough for the whole item.
+    ///
+    /// The data of the item is checked against its crc before the reader is returned, so the data is read twice.
+    ///
+    /// If there are no more entries, None is returned.
+    pub async fn next_reader<'q>(
+        &'q mut self,
+    ) -> Result<Option<QueueItemReader<'s, 'q, S, CI>>, Error<S::Error>> {
+        loop {
+            let value = run_with_auto_repair!(
+                function = self.next_inner(None).await,
+                repair = try_repair(self.flash, self.flash_range.clone(), self.cache).await?
+            )?;
+
+            let Some((item, address)) = value else {
+                return Ok(None);
+            };
+            let header = item.header;
+
+            let (metadata, data_start) = if header.has_metadata {
+                let mut buffer = [0; ItemMetadata::MAX_LENGTH];
+                let read = header
+                    .read_data_part(self.flash, address, 0, &mut buffer)
+                    .await?;
+
+                match ItemMetadata::deserialize_from(&buffer[..read]) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
+                        // Stored by a newer version of the crate, so we can't tell where the data starts
+                        self.skipped_items += 1;
+                        continue;
+                    }
+                }
+            } else {
+                (ItemMetadata::new(), 0)
+            };
+
+            self.cursor = Some(QueueCursor {
+                address,
+                crc: header.crc.expect("Entries are never erased items"),
+            });
+
+            return Ok(Some(QueueItemReader {
+                iter: self,
+                header,
+                address,
+                metadata,
+                data_start,
+                position: 0,
+            }));
+        }
+    }
+}
+
+/// An entry in the iteration over the queue flash
+pub struct QueueIteratorEntry<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> {
+    iter: &'q mut QueueIterator<'s, S, CI>,
+    header: ItemHeader,
+    address: u32,
+    metadata: ItemMetadata,
+    data: &'d mut [u8],
+}
+
+impl<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> Deref for QueueIteratorEntry<'s, 'd, 'q, S, CI> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> DerefMut for QueueIteratorEntry<'s, 'd, 'q, S, CI> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+impl<'s, 'd, 'q, S: NorFlash, CI: CacheImpl> QueueIteratorEntry<'s, 'd, 'q, S, CI> {
+    /// Get a cursor that points at this entry.
+    /// It can be used with [iter_from] to continue iterating after this entry, even after a reboot.
+    pub fn cursor(&self) -> QueueCursor {
+        QueueCursor {
+            address: self.address,
+            crc: self.header.crc.expect("Entries are never erased items"),
+        }
+    }
+
+    /// The metadata that was pushed with the data. See [push_with_metadata].
+    pub fn metadata(&self) -> ItemMetadata {
+        self.metadata
+    }
+
+    /// Get a mutable reference to the data of this entry, but consume the entry too.
+    /// This function has some relaxed lifetime constraints compared to the deref impls.
+    pub fn into_buf(self) -> &'d mut [u8] {
+        self.data
+    }
+
+    /// Pop the data in flash that corresponds to this entry. This makes it so
+    /// future peeks won't find this data anymore.
+    pub async fn pop(self) -> Result<&'d mut [u8], Error<S::Error>>
+    where
+        S: MultiwriteNorFlash,
+    {
+        self.header
+            .erase_data(
+                self.iter.flash,
+                self.iter.flash_range.clone(),
+                &mut self.iter.cache,
+                self.address,
+            )
+            .await?;
+
+        self.iter.cache.unmark_dirty();
+        Ok(self.data)
+    }
+}
+
+/// A position in the queue that can be used to continue iterating with [iter_from] or to pop the item with [ack].
+///
+/// It points at an item and remembers its crc, so it can be detected if the item is no longer there.
+/// It implements [Value] so it can be stored in flash with the [map](crate::map).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct QueueCursor {
+    address: u32,
+    crc: NonZeroU32,
+}
+
+impl QueueCursor {
+    /// Read the header of the item this cursor points at.
+    /// If the item is no longer there, `None` is returned.
+    ///
+    /// With `allow_popped`, the header is also returned if the item has been popped since.
+    async fn read_header<S: NorFlash>(
+        &self,
+        flash: &mut S,
+        flash_range: Range<u32>,
+        allow_popped: bool,
+    ) -> Result<Option<ItemHeader>, Error<S::Error>> {
+        if !flash_range.contains(&self.address) || !self.address.is_multiple_of(S::WORD_SIZE as u32)
+        {
+            return Ok(None);
+        }
+
+        let page = calculate_page_index::<S>(flash_range.clone(), self.address);
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range, page) - S::WORD_SIZE as u32;
+
+        if !(page_data_start_address..page_data_end_address).contains(&self.address) {
+            return Ok(None);
+        }
+
+        match ItemHeader::read_new(flash, self.address, page_data_end_address).await {
+            Ok(Some(header))
+                if header.crc == Some(self.crc) || (allow_popped && header.crc.is_none()) =>
+            {
+                Ok(Some(header))
+            }
+            Ok(_) | Err(Error::Corrupted { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<'a> Value<'a> for QueueCursor {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < 8 {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        buffer[..4].copy_from_slice(&self.address.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.crc.get().to_le_bytes());
+        Ok(8)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        if buffer.len() < 8 {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        Ok(Self {
+            address: u32::from_le_bytes(buffer[..4].try_into().unwrap()),
+            crc: NonZeroU32::new(u32::from_le_bytes(buffer[4..8].try_into().unwrap()))
+                .ok_or(SerializationError::InvalidData)?,
+        })
+    }
+}
+
+/// An entry in the iteration over the queue flash of which the data can be read in parts.
+/// See [QueueIterator::next_reader].
+pub struct QueueItemReader<'s, 'q, S: NorFlash, CI: CacheImpl> {
+    iter: &'q mut QueueIterator<'s, S, CI>,
+    header: ItemHeader,
+    address: u32,
+    metadata: ItemMetadata,
+    /// Where the data starts in the item, which is after the metadata
+    data_start: usize,
+    position: usize,
+}
+
+impl<'s, 'q, S: NorFlash, CI: CacheImpl> Debug for QueueItemReader<'s, 'q, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueueItemReader")
+            .field("address", &self.address)
+            .field("len", &self.len())
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s, 'q, S: NorFlash, CI: CacheImpl> QueueItemReader<'s, 'q, S, CI> {
+    /// The total length of the data of the item
+    pub fn len(&self) -> usize {
+        self.header.length as usize - self.data_start
+    }
+
+    /// The metadata that was pushed with the data. See [push_with_metadata].
+    pub fn metadata(&self) -> ItemMetadata {
+        self.metadata
+    }
+
+    /// Returns true if the item has no data
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The amount of bytes that haven't been read yet
+    pub fn remaining(&self) -> usize {
+        self.len() - self.position
+    }
+
+    /// Skip the next part of the data without reading it.
+    /// Skipping past the end of the data stops at the end.
+    pub fn skip(&mut self, amount: usize) {
+        self.position = self.position.saturating_add(amount).min(self.len());
+    }
+
+    /// Read the next part of the data into the buffer.
+    /// The amount of bytes that were read is returned. Once all data has been read, this is 0.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error<S::Error>> {
+        let read = self
+            .header
+            .read_data_part(
+                self.iter.flash,
+                self.address,
+                self.data_start + self.position,
+                buffer,
+            )
+            .await?;
+        self.position += read;
+        Ok(read)
+    }
+
+    /// Pop the data in flash that corresponds to this entry. This makes it so
+    /// future peeks won't find this data anymore.
+    pub async fn pop(self) -> Result<(), Error<S::Error>>
+    where
+        S: MultiwriteNorFlash,
+    {
+        self.header
+            .erase_data(
+                self.iter.flash,
+                self.iter.flash_range.clone(),
+                &mut self.iter.cache,
+                self.address,
+            )
+            .await?;
+
+        self.iter.cache.unmark_dirty();
+        Ok(())
+    }
+}
+
+/// An iterator-like interface that pops every item of the queue. See [drain].
+pub struct QueueDrain<'s, S: NorFlash, CI: CacheImpl> {
+    iter: QueueIterator<'s, S, CI>,
+}
+
+impl<'s, S: NorFlash, CI: CacheImpl> Debug for QueueDrain<'s, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueueDrain")
+            .field("iter", &self.iter)
+            .finish()
+    }
+}
+
+impl<'s, S: MultiwriteNorFlash, CI: CacheImpl> QueueDrain<'s, S, CI> {
+    /// Pop the next oldest item and get its data.
+    ///
+    /// The data is written to the given `data_buffer` and the part that was written is returned.
+    /// If the data buffer is not big enough an error is returned and the item is not popped.
+    ///
+    /// Returns `None` when the queue is empty.
+    pub async fn next<'d>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+        match self.iter.next(data_buffer).await? {
+            Some(entry) => Ok(Some(entry.pop().await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Find the largest size of data that can be stored.
+///
+/// This will read through the entire flash to find the largest chunk of
+/// data that can be stored, taking alignment requirements of the item into account.
+///
+/// If there is no space left, `None` is returned.
+pub async fn find_max_fit<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<Option<u32>, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = find_max_fit_inner(flash, flash_range.clone(), cache).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn find_max_fit_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<Option<u32>, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let current_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+
+    // Check if we have space on the next page
+    let next_page = next_page::<S>(flash_range.clone(), current_page);
+    match get_page_state(flash, flash_range.clone(), cache, next_page).await? {
+        state @ PageState::Closed => {
+            if is_page_empty(flash, flash_range.clone(), cache, next_page, Some(state)).await? {
+                cache.unmark_dirty();
+                return Ok(Some((S::ERASE_SIZE - (2 * S::WORD_SIZE)) as u32));
+            }
+        }
+        PageState::Open => {
+            cache.unmark_dirty();
+            return Ok(Some((S::ERASE_SIZE - (2 * S::WORD_SIZE)) as u32));
+        }
+        PageState::PartialOpen => {
+            // This should never happen
+            return Err(Error::Corrupted {
+                #[cfg(feature = "_test")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            });
+        }
+    };
+
+    // See how much space we can find in the current page.
+    let page_data_start_address =
+        calculate_page_address::<S>(flash_range.clone(), current_page) + S::WORD_SIZE as u32;
+    let page_data_end_address =
+        calculate_page_end_address::<S>(flash_range.clone(), current_page) - S::WORD_SIZE as u32;
+
+    let next_item_address = match cache.first_item_after_written(current_page) {
+        Some(next_item_address) => next_item_address,
+        None => {
+            ItemHeaderIter::new(
+                cache
+                    .first_item_after_erased(current_page)
+                    .unwrap_or(page_data_start_address),
+                page_data_end_address,
+            )
+            .traverse(flash, |_, _| true)
+            .await?
+            .1
+        }
+    };
+
+    cache.unmark_dirty();
+    Ok(ItemHeader::available_data_bytes::<S>(
+        page_data_end_address - next_item_address,
+    ))
+}
+
+/// Calculate how much space is left free in the queue (in bytes).
+///
+/// The number given back is accurate, however there are lots of things that add overhead and padding.
+/// Every push is an item with its own overhead. You can check the overhead per item with [crate::item_overhead_size].
+///
+/// Furthermore, every item has to fully fit in a page. So if a page has 50 bytes left and you push an item of 60 bytes,
+/// the current page is closed and the item is stored on the next page, 'wasting' the 50 you had.
+///
+/// So unless you're tracking all this, the returned number should only be used as a rough indication.
+///
+/// Only space that can be used without overwriting data that is still in the queue is counted.
+/// Because the overhead and padding are included, pushing this many bytes of data will likely still require
+/// `allow_overwrite_old_data` to be true. To know how much data fits, use [space_remaining].
+/// If you need to know whether one specific item fits right now, use [find_max_fit] instead.
+pub async fn space_left<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<u32, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = space_left_inner(flash, flash_range.clone(), cache).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn space_left_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<u32, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let mut total_free_space = 0;
+
+    for page in get_pages::<S>(flash_range.clone(), 0) {
+        let state = get_page_state(flash, flash_range.clone(), cache, page).await?;
+        let page_empty =
+            is_page_empty(flash, flash_range.clone(), cache, page, Some(state)).await?;
+
+        if state.is_closed() && !page_empty {
+            continue;
+        }
+
+        // See how much space we can find in the current page.
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
+
+        if page_empty {
+            total_free_space += page_data_end_address - page_data_start_address;
+            continue;
+        }
+
+        // Partial open page
+        let next_item_address = match cache.first_item_after_written(page) {
+            Some(next_item_address) => next_item_address,
+            None => {
+                ItemHeaderIter::new(
+                    cache
+                        .first_item_after_erased(page)
+                        .unwrap_or(page_data_start_address),
+                    page_data_end_address,
+                )
+                .traverse(flash, |_, _| true)
+                .await?
+                .1
+            }
+        };
+
+        if ItemHeader::available_data_bytes::<S>(page_data_end_address - next_item_address)
+            .is_none()
+        {
+            // No data fits on this partial open page anymore.
+            // So if all data on this is already erased, then this page might as well be counted as empty.
+            // We can use [is_page_empty] and lie to to it so it checks the items.
+            if is_page_empty(
+                flash,
+                flash_range.clone(),
+                cache,
+                page,
+                Some(PageState::Closed),
+            )
+            .await?
+            {
+                total_free_space += page_data_end_address - page_data_start_address;
+                continue;
+            }
+        }
+
+        total_free_space += page_data_end_address - next_item_address;
+    }
+
+    cache.unmark_dirty();
+    Ok(total_free_space)
+}
+
+/// Calculate how much data can still be pushed in items of `item_length` bytes
+/// before the oldest data would need to be erased.
+///
+/// Unlike [space_left], the overhead of every item is subtracted, as is the space at the end of every page
+/// that is wasted because the next item doesn't fit there anymore.
+/// So pushing the returned amount of data in items of `item_length` bytes never requires `allow_overwrite_old_data`.
+/// The returned amount is always a multiple of `item_length`.
+///
+/// Smaller items have more overhead for the same data, so if you push items of different lengths,
+/// pass the smallest one to get a lower bound.
+/// If `item_length` is 0 or bigger than [max_item_size], 0 is returned.
+pub async fn space_remaining<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    item_length: u32,
+) -> Result<u32, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = space_remaining_inner(flash, flash_range.clone(), cache, item_length).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn space_remaining_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    item_length: u32,
+) -> Result<u32, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    if item_length == 0 || item_length as usize > max_item_size::<S>() {
+        cache.unmark_dirty();
+        return Ok(0);
+    }
+
+    let item_count = free_item_spots(flash, flash_range, cache, item_length, false).await?;
+
+    cache.unmark_dirty();
+    Ok(item_count * item_length)
+}
+
+/// Count how many items of the given length can still be pushed one after the other.
+/// When old data may be overwritten, the count stops before the pushes would wrap around
+/// and erase the youngest page, so none of the pushed items overwrite each other.
+///
+/// The item length must be non-zero and fit in a single item.
+async fn free_item_spots<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    item_length: u32,
+    allow_overwrite_old_data: bool,
+) -> Result<u32, Error<S::Error>> {
+    let item_size = ItemHeader::data_address::<S>(0) + round_up_to_alignment::<S>(item_length);
+    let youngest_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+    let mut item_count = 0;
+
+    // Pushes continue on the youngest page and then go through the pages after it,
+    // until a page is found that still holds data. When old data may be overwritten,
+    // those pages are erased instead, up until the push would get back to the youngest page.
+    for page in get_pages::<S>(flash_range.clone(), youngest_page) {
+        let page_data_start_address =
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+        let page_data_end_address =
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
+
+        let state = get_page_state(flash, flash_range.clone(), cache, page).await?;
+        let free_start_address = if page == youngest_page && state.is_partial_open() {
+            match cache.first_item_after_written(page) {
+                Some(next_item_address) => next_item_address,
+                None => {
+                    ItemHeaderIter::new(
+                        cache
+                            .first_item_after_erased(page)
+                            .unwrap_or(page_data_start_address),
+                        page_data_end_address,
+                    )
+                    .traverse(flash, |_, _| true)
+                    .await?
+                    .1
+                }
+            }
+        } else if (allow_overwrite_old_data && page != youngest_page)
+            || state.is_open()
+            || is_page_empty(flash, flash_range.clone(), cache, page, Some(state)).await?
+        {
+            page_data_start_address
+        } else if allow_overwrite_old_data {
+            // The youngest page is full
+            continue;
+        } else {
+            break;
+        };
+
+        item_count += (page_data_end_address - free_start_address) / item_size;
+    }
+
+    Ok(item_count)
+}
+
+/// Get the number of items currently stored in the queue.
+///
+/// Only the item headers are read, which makes this a lot cheaper than iterating over the queue.
+/// With a cache the already popped items at the start of a page can be skipped too.
+///
+/// *Note: An item of which the push got interrupted by e.g. a power loss may still be counted
+/// even though it will never be returned by [peek] or [pop].*
+pub async fn len<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<usize, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = len_inner(flash, flash_range.clone(), cache).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn len_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<usize, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let mut count = 0;
+
+    for page in get_pages::<S>(flash_range.clone(), 0) {
+        // Open pages don't contain any items
+        if get_page_state(flash, flash_range.clone(), cache, page)
+            .await?
+            .is_open()
+        {
+            continue;
+        }
+
+        count += count_page_items(flash, flash_range.clone(), cache, page)
+            .await?
+            .count;
+    }
+
+    cache.unmark_dirty();
+    Ok(count)
+}
+
+/// Erase the oldest page of the queue so the erase doesn't have to happen during a later push.
+///
+/// This is useful to e.g. do the erase when the system is idle and keep pushes fast.
+/// A page is only erased when it's fully written.
+/// Old data will not be erased unless `allow_erase_old_data` is true, so if the oldest page still contains
+/// items that weren't popped, nothing happens.
+///
+/// Returns true if a page was erased.
+pub async fn erase_oldest_page<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    allow_erase_old_data: bool,
+) -> Result<bool, Error<S::Error>> {
+    run_with_auto_repair!(
+        function =
+            erase_oldest_page_inner(flash, flash_range.clone(), cache, allow_erase_old_data).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn erase_oldest_page_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    allow_erase_old_data: bool,
+) -> Result<bool, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let oldest_page = find_oldest_page(flash, flash_range.clone(), cache).await?;
+    let page_state = get_page_state(flash, flash_range.clone(), cache, oldest_page).await?;
+
+    if !page_state.is_closed()
+        || (!allow_erase_old_data
+            && !is_page_empty(
+                flash,
+                flash_range.clone(),
+                cache,
+                oldest_page,
+                Some(page_state),
+            )
+            .await?)
+    {
+        cache.unmark_dirty();
+        return Ok(false);
+    }
+
+    open_page(flash, flash_range.clone(), cache, oldest_page).await?;
+
+    cache.unmark_dirty();
+    Ok(true)
+}
+
+/// Statistics about the queue. See [stats].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct QueueStats {
+    /// The amount of items in the queue, just like [len] returns
+    pub item_count: usize,
+    /// The total length of the data of all items in the queue (so not including the overhead)
+    pub used_bytes: usize,
+    /// The space that is still free, just like [space_left] returns
+    pub free_bytes: u32,
+    /// The amount of pages that are open and thus empty
+    pub open_pages: usize,
+    /// The amount of pages that are partially written. This is normally one page.
+    pub partial_open_pages: usize,
+    /// The amount of pages that are fully written
+    pub closed_pages: usize,
+    /// The amount of pages that were erased through the cache, as counted in [CacheStats::page_erases](crate::cache::CacheStats::page_erases).
+    /// This is `None` for caches that don't count anything, like [NoCache](crate::cache::NoCache).
+    pub page_erases: Option<u32>,
+}
+
+impl QueueStats {
+    /// Returns true if the queue holds at least as much as the watermark specifies
+    pub fn reached(&self, watermark: Watermark) -> bool {
+        match watermark {
+            Watermark::Items(count) => self.item_count >= count,
+            Watermark::Bytes(bytes) => self.used_bytes >= bytes,
+        }
+    }
+}
+
+/// Get statistics about the queue.
+///
+/// This has to look at all pages so it's about as expensive as [len] and [space_left] together.
+///
+/// The amount of erases can't be found out from the flash itself, so it's taken from the cache.
+/// Use the same cache for all operations on the queue, else not all erases are counted.
+pub async fn stats<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<QueueStats, Error<S::Error>> {
+    run_with_auto_repair!(
+        function = stats_inner(flash, flash_range.clone(), cache).await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )
+}
+
+async fn stats_inner<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<QueueStats, Error<S::Error>> {
+    let free_bytes = space_left_inner(flash, flash_range.clone(), cache).await?;
+
+    let mut stats = QueueStats {
+        item_count: 0,
+        used_bytes: 0,
+        free_bytes,
+        open_pages: 0,
+        partial_open_pages: 0,
+        closed_pages: 0,
+        page_erases: cache.stats_tracker(|stats| stats.page_erases),
+    };
+
+    for page in get_pages::<S>(flash_range.clone(), 0) {
+        match get_page_state(flash, flash_range.clone(), cache, page).await? {
+            PageState::Open => {
+                stats.open_pages += 1;
+                continue;
+            }
+            PageState::PartialOpen => stats.partial_open_pages += 1,
+            PageState::Closed => stats.closed_pages += 1,
+        }
+
+        let items = count_page_items(flash, flash_range.clone(), cache, page).await?;
+        stats.item_count += items.count;
+        stats.used_bytes += items.bytes;
+    }
+
+    cache.unmark_dirty();
+    Ok(stats)
+}
+
+/// The result of checking the queue with [check]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct QueueCheckReport {
+    /// The amount of items that can be read just fine
+    pub items: usize,
+    /// The amount of items that were popped, but are still in flash until their page is erased
+    pub erased_items: usize,
+    /// The amount of items of which the data doesn't match the crc.
+    /// This is normally caused by a write that got interrupted by e.g. a power loss.
+    pub corrupted_items: usize,
+    /// The amount of item headers that are corrupted.
+    /// This is normally caused by a write that got interrupted by e.g. a power loss.
+    pub corrupted_headers: usize,
+    /// The amount of pages on which data was found after the last item.
+    /// This is normally caused by an interrupted [push_all].
+    pub unfinished_writes: usize,
+    /// The amount of pages of which the state is not valid.
+    /// This is normally caused by an erase that got interrupted.
+    pub corrupted_pages: usize,
+    /// The amount of pages that are partially open. There should never be more than one.
+    pub partial_open_pages: usize,
+}
+
+impl QueueCheckReport {
+    /// Returns true if nothing was found that points to corruption or interrupted operations
+    pub fn is_ok(&self) -> bool {
+        self.corrupted_items == 0
+            && self.corrupted_headers == 0
+            && self.unfinished_writes == 0
+            && self.corrupted_pages == 0
+            && self.partial_open_pages <= 1
+    }
+}
+
+/// Check the whole queue for corrupted items, interrupted writes and invalid page states.
+///
+/// Nothing is modified, not even in case corruption is found, so this can be used to decide
+/// whether to trust the data or not. Most of what is reported is repaired automatically
+/// the next time the queue is used.
+///
+/// All data in the flash range is read, so this is a slow operation.
+/// Because of that no cache is used either.
+pub async fn check<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+) -> Result<QueueCheckReport, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    let mut cache = cache::NoCache::new();
+    let mut report = QueueCheckReport::default();
+
+    for page in get_pages::<S>(flash_range.clone(), 0) {
+        match get_page_state(flash, flash_range.clone(), &mut cache, page).await {
+            Ok(PageState::Open) => continue,
+            Ok(PageState::PartialOpen) => report.partial_open_pages += 1,
+            Ok(PageState::Closed) => {}
+            Err(Error::Corrupted { .. }) => {
+                report.corrupted_pages += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let page_check = check_page_items(
+            flash,
+            calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32,
+            calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32,
+        )
+        .await?;
+
+        report.items += page_check.items;
+        report.erased_items += page_check.erased_items;
+        report.corrupted_items += page_check.corrupted_items;
+        report.corrupted_headers += page_check.corrupted_headers;
+        if page_check.unfinished_write {
+            report.unfinished_writes += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// A queue that owns its flash, flash range and cache.
+///
+/// This is a thin wrapper around the free functions of this module, so you don't have to pass
+/// the same flash, flash range and cache to every call. This especially helps when using multiple queues,
+/// since every queue can't accidentally be used with the wrong range or cache.
+/// For the documentation of the methods, see the free functions with the same name.
+///
+/// The flash cannot be shared with other users while it's owned by the queue.
+/// If you need that, pass e.g. a `&mut` to your flash as the flash type or use the free functions.
+///
+/// To use the queue from a producer and a consumer task, share it with [SharedQueue] and split it.
+/// For other ways of sharing, put it in an async mutex like the one from `embassy-sync`
+/// and lock it for every operation.
+/// To let a consumer wait for new data instead of polling, use [QueueConsumer::wait_for_item].
+/// When using your own mutex, register a waker with [Queue::register_push_waker] instead.
+#[derive(Debug)]
+pub struct Queue<S: NorFlash, CI: CacheImpl> {
+    flash: S,
+    flash_range: Range<u32>,
+    cache: CI,
+    push_waker: Option<Waker>,
+    /// The newest item given out by [Queue::peek_handle] since the last [Queue::requeue]
+    handed_out: Option<QueueCursor>,
+}
+
+impl<S: NorFlash, CI: CacheImpl> Queue<S, CI> {
+    /// Create a new queue in the given flash range
+    pub fn new(flash: S, flash_range: Range<u32>, cache: CI) -> Self {
+        Self {
+            flash,
+            flash_range,
+            cache,
+            push_waker: None,
+            handed_out: None,
+        }
+    }
+
+    /// Register a waker that is woken when the next item is pushed with this queue.
+    /// Only one waker is kept, so registering a new one replaces the previous one.
+    ///
+    /// The waker is only woken by pushes through this [Queue], not by the free functions.
+    pub fn register_push_waker(&mut self, waker: &Waker) {
+        match &mut self.push_waker {
+            Some(push_waker) => push_waker.clone_from(waker),
+            None => self.push_waker = Some(waker.clone()),
+        }
+    }
+
+    fn wake_after_push<E>(&mut self, result: Result<(), E>) -> Result<(), E> {
+        if result.is_ok() {
+            if let Some(waker) = self.push_waker.take() {
+                waker.wake();
+            }
+        }
+
+        result
+    }
+
+    /// Get back the flash and the cache
+    pub fn into_inner(self) -> (S, CI) {
+        (self.flash, self.cache)
+    }
+
+    /// The flash range of the queue
+    pub fn flash_range(&self) -> Range<u32> {
+        self.flash_range.clone()
+    }
+
+    /// See [push]
+    pub async fn push(
+        &mut self,
+        data: &[u8],
+        allow_overwrite_old_data: bool,
+    ) -> Result<(), Error<S::Error>> {
+        let result = push(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data,
+            allow_overwrite_old_data,
+        )
+        .await;
+        self.wake_after_push(result)
+    }
+
+    /// See [push_all]
+    pub async fn push_all(
+        &mut self,
+        data: &[&[u8]],
+        allow_overwrite_old_data: bool,
+    ) -> Result<(), Error<S::Error>> {
+        let result = push_all(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data,
+            allow_overwrite_old_data,
+        )
+        .await;
+        self.wake_after_push(result)
+    }
+
+    /// See [iter]
+    pub async fn iter(&mut self) -> Result<QueueIterator<'_, S, CI>, Error<S::Error>> {
+        iter(&mut self.flash, self.flash_range.clone(), &mut self.cache).await
+    }
+
+    /// See [peek]
+    pub async fn peek<'d>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+        peek(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data_buffer,
+        )
+        .await
+    }
+
+    /// Peek at the oldest item that hasn't been handed out yet and get a handle to it.
+    /// Nothing is removed from the queue.
+    ///
+    /// Unlike [peek_handle], consecutive calls hand out consecutive items, so multiple items
+    /// can be in flight at the same time. Give the handle to [Queue::ack] to pop the item once it's been handled.
+    /// Call [Queue::requeue] to put the items that were handed out but not acked back at the front of the queue.
+    ///
+    /// The handed out position is only kept in RAM. After a reboot (or when the item at that position was
+    /// overwritten) the items are handed out from the oldest one again, so no item is ever lost.
+    ///
+    /// The data is written to the given `data_buffer` and the part that was written is returned.
+    /// If the data buffer is not big enough an error is returned.
+    pub async fn peek_handle<'d>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<(QueueCursor, &'d mut [u8])>, Error<S::Error>> {
+        let mut iterator = match &self.handed_out {
+            Some(handed_out) => {
+                QueueIterator::new_from_cursor(
+                    &mut self.flash,
+                    self.flash_range.clone(),
+                    &mut self.cache,
+                    handed_out,
+                    true,
+                )
+                .await?
+            }
+            None => iter(&mut self.flash, self.flash_range.clone(), &mut self.cache).await?,
+        };
+
+        match iterator.next(data_buffer).await? {
+            Some(entry) => {
+                let cursor = entry.cursor();
+                self.handed_out = Some(cursor);
+                Ok(Some((cursor, entry.into_buf())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Put all items that were handed out by [Queue::peek_handle] but not acked yet back at the front of the queue.
+    ///
+    /// The next [Queue::peek_handle] starts at the oldest item again, so these items are handed out
+    /// again in their original order, before any item that wasn't handed out yet.
+    /// Nothing is written to flash for this.
+    pub fn requeue(&mut self) {
+        self.handed_out = None;
+    }
+
+    /// See [peek_many]
+    pub async fn peek_many(
+        &mut self,
+        data_buffers: &mut [&mut [u8]],
+    ) -> Result<usize, Error<S::Error>> {
+        peek_many(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data_buffers,
+        )
+        .await
+    }
+
+    /// See [find_max_fit]
+    pub async fn find_max_fit(&mut self) -> Result<Option<u32>, Error<S::Error>> {
+        find_max_fit(&mut self.flash, self.flash_range.clone(), &mut self.cache).await
+    }
+
+    /// See [space_left]
+    pub async fn space_left(&mut self) -> Result<u32, Error<S::Error>> {
+        space_left(&mut self.flash, self.flash_range.clone(), &mut self.cache).await
+    }
+
+    /// See [space_remaining]
+    pub async fn space_remaining(&mut self, item_length: u32) -> Result<u32, Error<S::Error>> {
+        space_remaining(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            item_length,
+        )
+        .await
+    }
+
+    /// See [len]
+    pub async fn len(&mut self) -> Result<usize, Error<S::Error>> {
+        len(&mut self.flash, self.flash_range.clone(), &mut self.cache).await
+    }
+
+    /// Returns true if there are no items in the queue
+    pub async fn is_empty(&mut self) -> Result<bool, Error<S::Error>> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// See [erase_oldest_page]
+    pub async fn erase_oldest_page(
+        &mut self,
+        allow_erase_old_data: bool,
+    ) -> Result<bool, Error<S::Error>> {
+        erase_oldest_page(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            allow_erase_old_data,
+        )
+        .await
+    }
+
+    /// See [stats]
+    pub async fn stats(&mut self) -> Result<QueueStats, Error<S::Error>> {
+        stats(&mut self.flash, self.flash_range.clone(), &mut self.cache).await
+    }
+
+    /// See [check]
+    pub async fn check(&mut self) -> Result<QueueCheckReport, Error<S::Error>> {
+        check(&mut self.flash, self.flash_range.clone()).await
+    }
+}
+
+impl<S: MultiwriteNorFlash, CI: CacheImpl> Queue<S, CI> {
+    /// See [ack]
+    ///
+    /// This also pops items handed out by [Queue::peek_handle] without affecting the items handed out after them.
+    pub async fn ack(&mut self, handle: &QueueCursor) -> Result<bool, Error<S::Error>> {
+        ack(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            handle,
+        )
+        .await
+    }
+
+    /// See [pop]
+    pub async fn pop<'d>(
+        &mut self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+        pop(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data_buffer,
+        )
+        .await
+    }
+
+    /// See [pop_many]
+    pub async fn pop_many(
+        &mut self,
+        data_buffers: &mut [&mut [u8]],
+    ) -> Result<usize, Error<S::Error>> {
+        pop_many(
+            &mut self.flash,
+            self.flash_range.clone(),
+            &mut self.cache,
+            data_buffers,
+        )
+        .await
+    }
+
+    /// See [drain]
+    pub async fn drain(&mut self) -> Result<QueueDrain<'_, S, CI>, Error<S::Error>> {
+        drain(&mut self.flash, self.flash_range.clone(), &mut self.cache).await
+    }
+}
+
+/// Count the items that are not erased yet on the given page
+async fn count_page_items<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateCacheImpl,
+    page: usize,
+) -> Result<EvictedItems, Error<S::Error>> {
+    let page_data_start_address =
+        calculate_page_address::<S>(flash_range.clone(), page) + S::WORD_SIZE as u32;
+    let page_data_end_address =
+        calculate_page_end_address::<S>(flash_range.clone(), page) - S::WORD_SIZE as u32;
+
+    let mut it = ItemHeaderIter::new(
+        cache
+            .first_item_after_erased(page)
+            .unwrap_or(page_data_start_address),
+        page_data_end_address,
+    );
+
+    let mut items = EvictedItems { count: 0, bytes: 0 };
+
+    // Stop at every item that is not erased yet
+    while let (Some(header), _) = it.traverse(flash, |header, _| header.crc.is_none()).await? {
+        items.count += 1;
+        items.bytes += header.length as usize;
+    }
+
+    Ok(items)
+}
+
+async fn find_youngest_page<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateCacheImpl,
+) -> Result<usize, Error<S::Error>> {
+    let last_used_page =
+        find_first_page(flash, flash_range.clone(), cache, 0, PageState::PartialOpen).await?;
+
+    if let Some(last_used_page) = last_used_page {
+        return Ok(last_used_page);
+    }
+
+    // We have no partial open page. Search for an open page to start in
+    let first_open_page = find_first_page(flash, flash_range, cache, 0, PageState::Open).await?;
+
+    if let Some(first_open_page) = first_open_page {
+        return Ok(first_open_page);
+    }
+
+    // All pages are closed... This is not correct.
+    Err(Error::Corrupted {
+        #[cfg(feature = "_test")]
+        backtrace: std::backtrace::Backtrace::capture(),
+    })
+}
+
+async fn find_oldest_page<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl PrivateCacheImpl,
+) -> Result<usize, Error<S::Error>> {
+    let youngest_page = find_youngest_page(flash, flash_range.clone(), cache).await?;
+
+    // The oldest page is the first non-open page after the youngest page
+    let oldest_closed_page =
+        find_first_page(flash, flash_range, cache, youngest_page, PageState::Closed).await?;
+
+    Ok(oldest_closed_page.unwrap_or(youngest_page))
+}
+
+/// Try to repair the state of the flash to hopefull get back everything in working order.
+/// Care is taken that no data is lost, but this depends on correctly repairing the state and
+/// so is only best effort.
+///
+/// This function might be called after a different function returned the [Error::Corrupted] error.
+/// There's no guarantee it will work.
+///
+/// If this function or the function call after this crate returns [Error::Corrupted], then it's unlikely
+/// that the state can be recovered. To at least make everything function again at the cost of losing the data,
+/// erase the flash range.
+async fn try_repair<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+) -> Result<(), Error<S::Error>> {
+    cache.invalidate_cache_state();
+
+    crate::try_general_repair(flash, flash_range.clone(), cache).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock_flash::{FlashAverageStatsResult, FlashStatsResult, WriteCountCheck};
+
+    use super::*;
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+    type MockFlashTiny = mock_flash::MockFlashBase<2, 1, 32>;
+
+    #[test]
+    async fn peek_and_overwrite_old_data() {
+        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+        const DATA_SIZE: usize = 22;
+
+        assert_eq!(
+            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            60
+        );
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        data_buffer[..DATA_SIZE].copy_from_slice(&[0xAA; DATA_SIZE]);
+        push(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &data_buffer[..DATA_SIZE],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            30
+        );
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[0xAA; DATA_SIZE]
+        );
+        data_buffer[..DATA_SIZE].copy_from_slice(&[0xBB; DATA_SIZE]);
+        push(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &data_buffer[..DATA_SIZE],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[0xAA; DATA_SIZE]
+        );
+
+        // Flash is full, this should fail
+        data_buffer[..DATA_SIZE].copy_from_slice(&[0xCC; DATA_SIZE]);
+        push(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &data_buffer[..DATA_SIZE],
+            false,
+        )
+        .await
+        .unwrap_err();
+        // Now we allow overwrite, so it should work
+        data_buffer[..DATA_SIZE].copy_from_slice(&[0xDD; DATA_SIZE]);
+        push(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &data_buffer[..DATA_SIZE],
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[0xBB; DATA_SIZE]
+        );
+        assert_eq!(
+            pop(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[0xBB; DATA_SIZE]
+        );
+
+        assert_eq!(
+            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            30
+        );
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[0xDD; DATA_SIZE]
+        );
+        assert_eq!(
+            pop(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[0xDD; DATA_SIZE]
+        );
+
+        assert_eq!(
+            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            60
+        );
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            pop(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn push_exactly_space_remaining() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..12u8 {
+            push(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 100]),
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        for _ in 0..2 {
+            pop(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap();
+        }
+
+        for item_length in [1, 37, 200, max_item_size::<MockFlashBig>() as u32] {
+            let mut flash = flash.clone();
+
+            let remaining = space_remaining(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                item_length,
+            )
+            .await
+            .unwrap();
+            assert!(remaining > 0);
+            assert_eq!(remaining % item_length, 0);
+            assert!(
+                remaining
+                    < space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                        .await
+                        .unwrap()
+            );
+
+            for _ in 0..remaining / item_length {
+                push(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &data_buffer[..item_length as usize],
+                    false,
+                )
+                .await
+                .unwrap();
+            }
+
+            assert_eq!(
+                push(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &data_buffer[..item_length as usize],
+                    false,
+                )
+                .await,
+                Err(Error::FullStorage)
+            );
+            assert_eq!(
+                space_remaining(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    item_length,
+                )
+                .await
+                .unwrap(),
+                0
+            );
+        }
+
+        assert_eq!(
+            space_remaining(&mut flash, FLASH_RANGE, &mut cache::NoCache::new(), 0)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            space_remaining(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                max_item_size::<MockFlashBig>() as u32 + 1
+            )
+            .await
+            .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn push_pop() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..2000 {
+            println!("{i}");
+            let data = vec![i as u8; i % 512 + 1];
+
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                true,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                peek(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap(),
+                &data,
+                "At {i}"
+            );
+            assert_eq!(
+                pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap(),
+                &data,
+                "At {i}"
+            );
+            assert_eq!(
+                peek(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap(),
+                None,
+                "At {i}"
+            );
+        }
+    }
+
+    #[test]
+    async fn push_pop_tiny() {
+        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x00..0x40;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..2000 {
+            println!("{i}");
+            let data = vec![i as u8; i % 20 + 1];
+
+            println!("PUSH");
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                true,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                peek(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap(),
+                &data,
+                "At {i}"
+            );
+            println!("POP");
+            assert_eq!(
+                pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap(),
+                &data,
+                "At {i}"
+            );
+            println!("PEEK");
+            assert_eq!(
+                peek(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap(),
+                None,
+                "At {i}"
+            );
+            println!("DONE");
+        }
+    }
+
+    #[test]
+    async fn pop_many_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+
+        for i in 0..5u8 {
+            let data = AlignedBuf([i; 8]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &data[..i as usize + 1],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut buffers = [[0; 32]; 3];
+        let [a, b, c] = &mut buffers;
+        let mut data_buffers = [&mut a[..], &mut b[..], &mut c[..]];
+
+        assert_eq!(
+            pop_many(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &mut data_buffers
+            )
+            .await
+            .unwrap(),
+            3
+        );
+        assert_eq!(data_buffers[0], &[0]);
+        assert_eq!(data_buffers[1], &[1, 1]);
+        assert_eq!(data_buffers[2], &[2, 2, 2]);
+
+        let [a, b, c] = &mut buffers;
+        let mut data_buffers = [&mut a[..], &mut b[..], &mut c[..]];
+
+        assert_eq!(
+            pop_many(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &mut data_buffers
+            )
+            .await
+            .unwrap(),
+            2
+        );
+        assert_eq!(data_buffers[0], &[3, 3, 3, 3]);
+        assert_eq!(data_buffers[1], &[4, 4, 4, 4, 4]);
+        assert_eq!(data_buffers[2].len(), 32);
+
+        assert_eq!(
+            pop_many(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &mut data_buffers
+            )
+            .await
+            .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn peek_many_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+
+        for i in 0..3u8 {
+            let data = AlignedBuf([i; 8]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &data[..i as usize + 1],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut buffers = [[0; 32]; 4];
+        let [a, b, c, d] = &mut buffers;
+        let mut data_buffers = [&mut a[..], &mut b[..], &mut c[..], &mut d[..]];
+
+        assert_eq!(
+            peek_many(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &mut data_buffers
+            )
+            .await
+            .unwrap(),
+            3
+        );
+        assert_eq!(data_buffers[0], &[0]);
+        assert_eq!(data_buffers[1], &[1, 1]);
+        assert_eq!(data_buffers[2], &[2, 2, 2]);
+        assert_eq!(data_buffers[3].len(), 32);
+
+        // Nothing got removed
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache)
+                .await
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    async fn peek_nth_item() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // Enough items to go over two pages
+        for i in 0..100u8 {
+            let data = AlignedBuf([i; 12]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        for n in [0, 1, 50, 99] {
+            assert_eq!(
+                &peek_nth(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    n,
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[n as u8; 12]
+            );
+        }
+
+        assert_eq!(
+            peek_nth(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                100,
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        // Nothing got removed
+        assert_eq!(
+            &peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[0; 12]
+        );
+    }
+
+    #[test]
+    async fn pop_with_predicate() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..2u8 {
+            let data = AlignedBuf([i; 4]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            pop_if(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                |data| data[0] == 1
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            &pop_if(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                |data| data[0] == 0
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[0; 4]
+        );
+
+        assert_eq!(
+            &pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[1; 4]
+        );
+
+        assert_eq!(
+            pop_if(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                |_| true
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn pop_newest_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // Enough items to go over two pages
+        for i in 0..100u8 {
+            let data = AlignedBuf([i; 12]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        for i in (98..100u8).rev() {
+            assert_eq!(
+                &pop_last(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 12]
+            );
+        }
+
+        for i in 0..98u8 {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 12]
+            );
+        }
+
+        assert_eq!(
+            pop_last(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn iterate_from_cursor() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..10u8 {
+            let data = AlignedBuf([i; 12]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        let mut cursor = None;
+        for _ in 0..3 {
+            cursor = Some(
+                iterator
+                    .next(&mut data_buffer)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .cursor(),
+            );
+        }
+
+        // Store and load the cursor like it would be done in a map
+        let mut cursor_buffer = [0; 8];
+        let len = cursor.unwrap().serialize_into(&mut cursor_buffer).unwrap();
+        let cursor = QueueCursor::deserialize_from(&cursor_buffer[..len]).unwrap();
+
+        let mut iterator = iter_from(&mut flash, flash_range.clone(), &mut cache, &cursor)
+            .await
+            .unwrap();
+        assert_eq!(
+            &*iterator.next(&mut data_buffer).await.unwrap().unwrap(),
+            &[3; 12]
+        );
+
+        // When the item of the cursor is popped, we start at the oldest item again
+        for _ in 0..5 {
+            pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &mut data_buffer,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut iterator = iter_from(&mut flash, flash_range.clone(), &mut cache, &cursor)
+            .await
+            .unwrap();
+        assert_eq!(
+            &*iterator.next(&mut data_buffer).await.unwrap().unwrap(),
+            &[5; 12]
+        );
+    }
+
+    #[test]
+    async fn peek_then_ack() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..2u8 {
+            let data = AlignedBuf([i; 12]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let (handle, data) = peek_handle(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(data, &[0; 12]);
+
+        // Peeking again gives the same item
+        let (same_handle, _) = peek_handle(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(handle, same_handle);
+
+        assert!(ack(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &handle
+        )
+        .await
+        .unwrap());
+        assert!(!ack(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &handle
+        )
+        .await
+        .unwrap());
+
+        assert_eq!(
+            &peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[1; 12]
+        );
+    }
+
+    #[test]
+    async fn retry_unacked_item() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..3u8 {
+            let (handle, data) = {
+                push(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &AlignedBuf([i; 12])[..],
+                    false,
+                )
+                .await
+                .unwrap();
+
+                peek_handle(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                )
+                .await
+                .unwrap()
+                .unwrap()
+            };
+
+            // Handling keeps failing, so the first item stays at the front
+            assert_eq!(data, &[0; 12]);
+            assert_eq!(handle.address, 4);
+        }
+
+        let (handle, _) = peek_handle(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(ack(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &handle
+        )
+        .await
+        .unwrap());
+
+        for i in 1..3u8 {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 12]
+            );
+        }
+    }
+
+    #[test]
+    async fn push_pop_channels() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // Unaligned lengths so the channel byte and the data don't line up with the words
+        for i in 0..10u8 {
+            let data = AlignedBuf([i; 10]);
+            push_channel(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                i % 2,
+                &data[..i as usize],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            &peek_channel(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                1,
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[1; 1]
+        );
+
+        for i in (1..10u8).step_by(2) {
+            assert_eq!(
+                &pop_channel(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    1,
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 10][..i as usize]
+            );
+        }
+
+        assert_eq!(
+            pop_channel(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                1,
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        for i in (0..10u8).step_by(2) {
+            assert_eq!(
+                &pop_channel(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    0,
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 10][..i as usize]
+            );
+        }
+
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn push_with_writer() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        let data: [u8; 100] = core::array::from_fn(|i| i as u8);
+
+        let mut writer = push_writer(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            data.len(),
+            false,
+        )
+        .await
+        .unwrap();
+        for chunk in data.chunks(7) {
+            writer.write(chunk).await.unwrap();
+        }
+        assert_eq!(writer.write(&[0]).await, Err(Error::BufferTooBig));
+        writer.commit().await.unwrap();
+
+        // An unfinished item can't be committed and is never read back
+        let mut writer = push_writer(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            10,
+            false,
+        )
+        .await
+        .unwrap();
+        writer.write(&[1, 2, 3]).await.unwrap();
+        assert_eq!(writer.commit().await, Err(Error::BufferTooSmall(7)));
+
+        push(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &AlignedBuf([5; 4])[..],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            &pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &data[..]
+        );
+        assert_eq!(
+            &pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[5; 4]
+        );
+        assert_eq!(
+            pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn reserve_with_writer() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // Fill the queue so the next push has to erase a page
+        while find_max_fit(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap()
+            .is_some_and(|max_fit| max_fit >= 100)
+        {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &AlignedBuf([0; 100])[..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        // The erase happens when the space is reserved, not when the item is written
+        let before_reserve = flash.stats_snapshot();
+        push_writer(&mut flash, flash_range.clone(), &mut cache, 100, true)
+            .await
+            .unwrap();
+        assert_eq!(before_reserve.compare_to(flash.stats_snapshot()).erases, 1);
+
+        let mut writer = push_writer(&mut flash, flash_range.clone(), &mut cache, 100, true)
+            .await
+            .unwrap();
+        writer.write(&[2; 100]).await.unwrap();
+        writer.commit().await.unwrap();
+
+        assert_eq!(
+            pop_last(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            &[2; 100]
+        );
+    }
+
+    #[test]
+    async fn push_with_writer_shutoff() {
+        let data: [u8; 30] = core::array::from_fn(|i| i as u8);
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for shutoff in 0..64 {
+            let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+            let flash_range = 0x000..0x1000;
+
+            flash.bytes_until_shutoff = Some(shutoff);
+            let result = async {
+                let mut writer = push_writer(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    data.len(),
+                    false,
+                )
+                .await?;
+                for chunk in data.chunks(8) {
+                    writer.write(chunk).await?;
+                }
+                writer.commit().await
+            }
+            .await;
+            flash.bytes_until_shutoff = None;
+
+            let popped = pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap()
+            .map(|data| data.to_vec());
+
+            match result {
+                Ok(()) => assert_eq!(popped.as_deref(), Some(&data[..])),
+                Err(_) => assert!(
+                    popped.is_none() || popped.as_deref() == Some(&data[..]),
+                    "Shutoff at {shutoff}: {popped:?}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    async fn read_items_in_parts() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+
+        let mut data = AlignedBuf([0; 203]);
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        for len in [203, 5] {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data[..len],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+
+        for len in [203, 5] {
+            let mut reader = iterator.next_reader().await.unwrap().unwrap();
+            assert_eq!(reader.len(), len);
+
+            let mut read_data = Vec::new();
+            let mut buffer = [0; 7];
+            loop {
+                let read = reader.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                read_data.extend_from_slice(&buffer[..read]);
+            }
+            assert_eq!(read_data, &data[..len]);
+            assert_eq!(reader.remaining(), 0);
+
+            reader.pop().await.unwrap();
+        }
+
+        assert!(iterator.next_reader().await.unwrap().is_none());
+    }
+
+    #[test]
+    async fn push_with_eviction_callback() {
+        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        let mut evicted = Vec::new();
+
+        // Both pages only have room for two items each
+        for i in 0..6 {
+            let data = AlignedBuf([i; 4]);
+            push_with_eviction(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &data,
+                |items| evicted.push(items),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(evicted, [EvictedItems { count: 2, bytes: 8 }]);
+
+        // Popped items don't count as evicted
+        pop(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        evicted.clear();
+        for i in 0..2 {
+            let data = AlignedBuf([i; 4]);
+            push_with_eviction(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &data,
+                |items| evicted.push(items),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(evicted, [EvictedItems { count: 1, bytes: 4 }]);
+    }
+
+    #[test]
+    async fn mix_channels_with_plain_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let source_range = 0x000..0x800;
+        let flash_range = 0x800..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // The first byte of the plain item looks like a channel, but it's still only data
+        push(
+            &mut flash,
+            source_range.clone(),
+            &mut cache::NoCache::new(),
+            &AlignedBuf([1, 2, 3]),
+            false,
+        )
+        .await
+        .unwrap();
+        push_channel(
+            &mut flash,
+            source_range.clone(),
+            &mut cache::NoCache::new(),
+            1,
+            &[4, 5],
+            false,
+        )
+        .await
+        .unwrap();
+        push(
+            &mut flash,
+            source_range.clone(),
+            &mut cache::NoCache::new(),
+            &AlignedBuf([6]),
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The metadata is moved along with the data
+        assert_eq!(
+            migrate(
+                &mut flash,
+                source_range,
+                &mut cache::NoCache::new(),
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            3
+        );
+
+        assert_eq!(
+            &peek_channel(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                1,
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[4, 5]
+        );
+
+        let mut buffers = [[0; 16]; 3];
+        let mut buffers = buffers.each_mut().map(|buffer| &mut buffer[..]);
+        assert_eq!(
+            peek_many(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut buffers
+            )
+            .await
+            .unwrap(),
+            3
+        );
+        assert_eq!(buffers, [&[1, 2, 3][..], &[4, 5], &[6]]);
+
+        let (metadata, data) = pop_with_metadata(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!((metadata, &data[..]), (ItemMetadata::new(), &[1, 2, 3][..]));
+
+        let (metadata, data) = pop_with_metadata(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(metadata.channel(), Some(1));
+        assert_eq!(data, &[4, 5]);
+
+        assert_eq!(
+            pop_channel(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                1,
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            &pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[6]
+        );
+    }
+
+    #[test]
+    async fn pop_high_priority_first() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for (i, priority) in [
+            Priority::Normal,
+            Priority::High,
+            Priority::Normal,
+            Priority::High,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let data = AlignedBuf([i as u8; 3]);
+            push_with_priority(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                priority,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            &peek_prioritized(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[1; 3]
+        );
+
+        for i in [1, 3, 0, 2] {
+            assert_eq!(
+                &pop_prioritized(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 3]
+            );
+        }
+
+        assert_eq!(
+            pop_prioritized(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn plain_items_have_normal_priority() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        let metadata = ItemMetadata::new()
+            .with_channel(2)
+            .with_priority(Priority::High);
+
+        for (data, metadata) in [
+            ([1], ItemMetadata::new()),
+            ([2], metadata),
+            ([3], ItemMetadata::new()),
+        ] {
+            push_with_metadata(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                metadata,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            &pop_prioritized(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[2]
+        );
+        assert_eq!(
+            peek_with_metadata(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .map(|(metadata, data)| (metadata.priority(), data.to_vec())),
+            Some((Priority::Normal, vec![1]))
+        );
+
+        for i in [1, 3] {
+            assert_eq!(
+                &pop_prioritized(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i]
+            );
+        }
+    }
+
+    #[test]
+    async fn read_items_of_other_layout_versions() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // An item without metadata, which is how every item was stored before metadata got a layout version
+        push(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &AlignedBuf([1, 2, 3, 4]),
+            false,
+        )
+        .await
+        .unwrap();
+        // An item with metadata of a layout version that doesn't exist yet
+        push_inner(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[&[ItemMetadata::VERSION + 1, 0], &[5, 6, 7, 8]],
+            true,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        push_with_metadata(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[9],
+            ItemMetadata::new().with_channel(1),
+            false,
+        )
+        .await
+        .unwrap();
+
+        for (expected_metadata, expected_data) in [
+            (ItemMetadata::new(), &[1, 2, 3, 4][..]),
+            (ItemMetadata::new().with_channel(1), &[9][..]),
+        ] {
+            let (metadata, data) = pop_with_metadata(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(metadata, expected_metadata);
+            assert_eq!(data, expected_data);
+        }
+        assert_eq!(
+            pop_with_metadata(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn find_item() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..10u8 {
+            let data = AlignedBuf([i; 4]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let (handle, data) = find(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+            |data| data[0] > 4,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(data, &[5; 4]);
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter_from(&mut flash, flash_range.clone(), &mut cache, &handle)
+            .await
+            .unwrap();
+        assert_eq!(
+            &*iterator.next(&mut data_buffer).await.unwrap().unwrap(),
+            &[6; 4]
+        );
+
+        assert_eq!(
+            find(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                |data| data[0] > 9,
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn ack_out_of_order() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..3u8 {
+            let data = AlignedBuf([i; 4]);
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        let mut handles = Vec::new();
+        while let Some(entry) = iterator.next(&mut data_buffer).await.unwrap() {
+            handles.push(entry.cursor());
+        }
+
+        assert!(
+            ack(&mut flash, flash_range.clone(), &mut cache, &handles[1])
+                .await
+                .unwrap()
+        );
+
+        for i in [0, 2] {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache,
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 4]
+            );
+        }
+    }
+
+    #[test]
+    async fn push_deduplicated() {
+        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for (data, pushed) in [
+            ([1; 4], true),
+            ([1; 4], false),
+            ([2; 4], true),
+            // This goes to the next page
+            ([3; 4], true),
+            ([3; 4], false),
+            ([2; 4], true),
+        ] {
+            assert_eq!(
+                push_dedup(
+                    &mut flash,
+                    FLASH_RANGE,
+                    &mut cache::NoCache::new(),
+                    &AlignedBuf(data)[..],
+                    false,
+                )
+                .await
+                .unwrap(),
+                pushed
+            );
+        }
+
+        assert_eq!(
+            len(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            4
+        );
+
+        // When the newest item is popped, it's not seen as a duplicate anymore
+        while pop(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .is_some()
+        {}
+
+        assert!(push_dedup(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &AlignedBuf([2; 4])[..],
+            false,
+        )
+        .await
+        .unwrap());
+    }
+
+    #[test]
+    async fn count_items() {
+        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        assert_eq!(
+            len(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+
+        for i in 0..10 {
+            let data = AlignedBuf([i; 4]);
+            push(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &data,
+                true,
+            )
+            .await
+            .unwrap();
+        }
+
+        // Both pages only have room for two items each. The rest got overwritten.
+        assert_eq!(
+            len(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            4
+        );
+
+        pop(
+            &mut flash,
+            FLASH_RANGE,
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            len(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    async fn queue_stats() {
+        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
+        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+
+        assert_eq!(
+            stats(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            QueueStats {
+                item_count: 0,
+                used_bytes: 0,
+                free_bytes: 60,
+                open_pages: 2,
+                partial_open_pages: 0,
+                closed_pages: 0,
+                page_erases: None,
+            }
+        );
+
+        for i in 0..3 {
+            let data = AlignedBuf([i; 4]);
+            push(
+                &mut flash,
+                FLASH_RANGE,
+                &mut cache::NoCache::new(),
+                &data,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            stats(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            QueueStats {
+                item_count: 3,
+                used_bytes: 12,
+                free_bytes: 18,
+                open_pages: 0,
+                partial_open_pages: 1,
+                closed_pages: 1,
+                page_erases: None,
+            }
+        );
+
+        // Overwriting old data erases pages, which is counted by the cache
+        let mut cache = cache::PageStateCache::<2>::new();
+        let snapshot = flash.stats_snapshot();
+        for i in 0..10 {
+            let data = AlignedBuf([i; 4]);
+            push(&mut flash, FLASH_RANGE, &mut cache, &data, true)
+                .await
+                .unwrap();
+        }
+        let erases = snapshot.compare_to(flash.stats_snapshot()).erases;
+        assert!(erases > 0);
+        assert_eq!(
+            stats(&mut flash, FLASH_RANGE, &mut cache)
+                .await
+                .unwrap()
+                .page_erases,
+            Some(erases as u32)
+        );
+    }
+
+    #[test]
+    async fn check_queue() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        assert_eq!(
+            check(&mut flash, flash_range.clone()).await.unwrap(),
+            QueueCheckReport::default()
+        );
+
+        for i in 0..3u8 {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 12])[..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        pop(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap();
+
+        let report = check(&mut flash, flash_range.clone()).await.unwrap();
+        assert!(report.is_ok());
+        assert_eq!(
+            report,
+            QueueCheckReport {
+                items: 2,
+                erased_items: 1,
+                partial_open_pages: 1,
+                ..Default::default()
+            }
+        );
+
+        // Interrupt a push halfway through the data
+        flash.bytes_until_shutoff = Some(16);
+        push(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &AlignedBuf([3; 12])[..],
+            false,
+        )
+        .await
+        .unwrap_err();
+
+        let report = check(&mut flash, flash_range.clone()).await.unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.items, 2);
+        assert_eq!(report.corrupted_items, 1);
+    }
+
+    #[test]
+    async fn migrate_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let source_range = 0x000..0x800;
+        let destination_range = 0x800..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..100u8 {
+            push(
+                &mut flash,
+                source_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 20])[..],
+                true,
+            )
+            .await
+            .unwrap();
+        }
+        let source_len = len(&mut flash, source_range.clone(), &mut cache::NoCache::new())
+            .await
+            .unwrap();
+        let first = peek(
+            &mut flash,
+            source_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap()[0];
+
+        assert_eq!(
+            migrate(
+                &mut flash,
+                source_range.clone(),
+                &mut cache::NoCache::new(),
+                destination_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            source_len
+        );
+        assert_eq!(
+            len(&mut flash, source_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+
+        for i in first..100 {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    destination_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 20]
+            );
+        }
+        assert_eq!(
+            pop(
+                &mut flash,
+                destination_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            migrate(
+                &mut flash,
+                destination_range.clone(),
+                &mut cache::NoCache::new(),
+                0x400..0xC00,
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await,
+            Err(Error::OverlappingRanges)
+        );
+    }
+
+    #[test]
+    async fn drain_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..10u8 {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 10])[..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut draining = drain(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        for i in 0..4u8 {
+            assert_eq!(
+                &draining.next(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 10]
+            );
+        }
+
+        // An interrupted drain leaves the rest of the items
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            6
+        );
+
+        let mut cache = cache::NoCache::new();
+        let mut draining = drain(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        for i in 4..10u8 {
+            assert_eq!(
+                &draining.next(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 10]
+            );
+        }
+        assert_eq!(draining.next(&mut data_buffer).await.unwrap(), None);
+
+        assert_eq!(
+            peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn push_until_watermark() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+        let mut cache = cache::NoCache::new();
+
+        for i in 0..4u8 {
+            assert!(!push_with_watermark(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache,
+                &AlignedBuf([i; 10])[..],
+                false,
+                Watermark::Items(5),
+            )
+            .await
+            .unwrap());
+        }
+        assert!(push_with_watermark(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache,
+            &AlignedBuf([4; 10])[..],
+            false,
+            Watermark::Items(5),
+        )
+        .await
+        .unwrap());
+
+        let queue_stats = stats(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        assert!(queue_stats.reached(Watermark::Bytes(50)));
+        assert!(!queue_stats.reached(Watermark::Bytes(51)));
+
+        pop(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache,
+            &mut data_buffer,
+        )
+        .await
+        .unwrap();
+        assert!(!push_with_watermark(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache,
+            &AlignedBuf([5; 10])[..],
+            false,
+            Watermark::Bytes(60),
+        )
+        .await
+        .unwrap());
+    }
+
+    #[test]
+    async fn owned_queue() {
+        let mut queue = Queue::new(
+            MockFlashBig::new(WriteCountCheck::Twice, None, true),
+            0x000..0x1000,
+            cache::PagePointerCache::<4>::new(),
+        );
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        assert!(queue.is_empty().await.unwrap());
+
+        for i in 0..10u8 {
+            queue.push(&AlignedBuf([i; 10])[..], false).await.unwrap();
+        }
+        assert_eq!(queue.len().await.unwrap(), 10);
+
+        assert_eq!(
+            &queue.peek(&mut data_buffer).await.unwrap().unwrap()[..],
+            &[0; 10]
+        );
+        for i in 0..10u8 {
+            assert_eq!(
+                &queue.pop(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 10]
+            );
+        }
+        assert_eq!(queue.pop(&mut data_buffer).await.unwrap(), None);
+        assert!(queue.check().await.unwrap().is_ok());
+
+        let (mut flash, _) = queue.into_inner();
+        assert_eq!(
+            len(&mut flash, 0x000..0x1000, &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn owned_queue_requeue() {
+        let mut queue = Queue::new(
+            MockFlashBig::new(WriteCountCheck::Twice, None, true),
+            0x000..0x1000,
+            cache::NoCache::new(),
+        );
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 1..=3u8 {
+            queue.push(&AlignedBuf([i; 4])[..], false).await.unwrap();
+        }
+
+        let (first, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[1; 4]);
+        let (_, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[2; 4]);
+
+        // Both go back to the front in their original order
+        queue.requeue();
+        let (handle, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[1; 4]);
+        assert_eq!(handle, first);
+        assert!(queue.ack(&handle).await.unwrap());
+
+        // Acking the newest handed out item doesn't lose the position
+        let (handle, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[2; 4]);
+        assert!(queue.ack(&handle).await.unwrap());
+        queue.push(&AlignedBuf([4; 4])[..], false).await.unwrap();
+
+        let (_, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[3; 4]);
+        let (handle, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[4; 4]);
+        assert!(queue.peek_handle(&mut data_buffer).await.unwrap().is_none());
+
+        // Only the unacked item comes back
+        assert!(queue.ack(&handle).await.unwrap());
+        queue.requeue();
+        let (_, data) = queue.peek_handle(&mut data_buffer).await.unwrap().unwrap();
+        assert_eq!(data, &[3; 4]);
+        assert!(queue.peek_handle(&mut data_buffer).await.unwrap().is_none());
+
+        assert_eq!(queue.len().await.unwrap(), 1);
+    }
+
+    #[test]
+    async fn push_empty_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+        let data = AlignedBuf([1, 2, 3, 0]);
+        let items = [&data[..0], &data[..3], &data[..0]];
+
+        for item in items {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                item,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            3
+        );
+
+        for item in items {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                item
+            );
+        }
+        assert_eq!(
+            pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn pop_all_until_false() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..100u8 {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 20])[..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut next = 0;
+        assert_eq!(
+            pop_all(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                |data| {
+                    assert_eq!(data, &[next; 20]);
+                    next += 1;
+                    next <= 60
+                },
+            )
+            .await
+            .unwrap(),
+            60
+        );
+
+        // The item the callback refused is still there
+        assert_eq!(
+            &peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[60; 20]
+        );
+
+        assert_eq!(
+            pop_all(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                |_| true,
+            )
+            .await
+            .unwrap(),
+            40
+        );
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn erase_oldest_page_early() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        // Nothing to erase in an empty queue
+        assert!(!erase_oldest_page(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            false
+        )
+        .await
+        .unwrap());
+
+        // Fill up the first page and a bit of the second
+        for i in 0..40u8 {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 20])[..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        // The first page still has data
+        assert!(!erase_oldest_page(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            false
+        )
+        .await
+        .unwrap());
+
+        for _ in 0..36 {
+            pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap();
+        }
+
+        let snapshot = flash.stats_snapshot();
+        assert!(erase_oldest_page(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            false
+        )
+        .await
+        .unwrap());
+        assert_eq!(snapshot.compare_to(flash.stats_snapshot()).erases, 1);
+
+        // Only the partial open page is left, which is never erased
+        assert!(!erase_oldest_page(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            true
+        )
+        .await
+        .unwrap());
+
+        let queue_stats = stats(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+            .await
+            .unwrap();
+        assert_eq!(queue_stats.item_count, 4);
+        assert_eq!(queue_stats.open_pages, 3);
+
+        for i in 36..40u8 {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[i; 20]
+            );
+        }
+    }
+
+    #[test]
+    async fn push_pop_with_flags() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..4u8 {
+            push_with_flags(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                1 << i,
+                &[i; 7],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let (flags, data) = peek_with_flags(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(flags, 1);
+        assert_eq!(data, &[0; 7]);
+
+        for i in 0..4u8 {
+            let (flags, data) = pop_with_flags(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(flags, 1 << i);
+            assert_eq!(data, &[i; 7]);
+        }
+
+        assert_eq!(
+            pop_with_flags(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        // A plain item has flags 0 and all of its data is given back
+        push(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &AlignedBuf([1; 4]),
+            false,
+        )
+        .await
+        .unwrap();
+        let (flags, data) = pop_with_flags(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(flags, 0);
+        assert_eq!(data, &[1; 4]);
+
+        // Flags can be combined with the other metadata
+        push_with_metadata(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[2; 5],
+            ItemMetadata::new().with_channel(3).with_flags(0b101),
+            false,
+        )
+        .await
+        .unwrap();
+        let (flags, data) = peek_with_flags(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(flags, 0b101);
+        assert_eq!(data, &[2; 5]);
+        assert_eq!(
+            &pop_channel(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                3,
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[2; 5]
+        );
+    }
+
+    #[test]
+    async fn push_pop_timestamped() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..10u8 {
+            push_with_timestamp(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                1000 + i as u64 * 10,
+                &[i; 5],
+                false,
+            )
+            .await
+            .unwrap();
+        }
 
-    Ok(oldest_closed_page.unwrap_or(youngest_page))
-}
+        let (timestamp, data) = peek_with_timestamp(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(timestamp, 1000);
+        assert_eq!(data, &[0; 5]);
 
-/// Try to repair the state of the flash to hopefull get back everything in working order.
-/// Care is taken that no data is lost, but this depends on correctly repairing the state and
-/// so is only best effort.
-///
-/// This function might be called after a different function returned the [Error::Corrupted] error.
-/// There's no guarantee it will work.
-///
-/// If this function or the function call after this crate returns [Error::Corrupted], then it's unlikely
-/// that the state can be recovered. To at least make everything function again at the cost of losing the data,
-/// erase the flash range.
-async fn try_repair<S: NorFlash>(
-    flash: &mut S,
-    flash_range: Range<u32>,
-    cache: &mut impl CacheImpl,
-) -> Result<(), Error<S::Error>> {
-    cache.invalidate_cache_state();
+        assert_eq!(
+            pop_older_than(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                1045,
+            )
+            .await
+            .unwrap(),
+            5
+        );
 
-    crate::try_general_repair(flash, flash_range.clone(), cache).await?;
-    Ok(())
-}
+        let (timestamp, data) = pop_with_timestamp(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(timestamp, 1050);
+        assert_eq!(data, &[5; 5]);
 
-#[cfg(test)]
-mod tests {
-    use crate::mock_flash::{FlashAverageStatsResult, FlashStatsResult, WriteCountCheck};
+        assert_eq!(
+            pop_older_than(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+                u64::MAX,
+            )
+            .await
+            .unwrap(),
+            4
+        );
 
-    use super::*;
-    use futures_test::test;
+        // Items without timestamp are rejected, even if they're long enough to hold one
+        for data in [&AlignedBuf([0; 4])[..], &AlignedBuf([0; 12])[..]] {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                data,
+                false,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                pop_with_timestamp(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                )
+                .await,
+                Err(Error::SerializationError(SerializationError::InvalidFormat))
+            );
+            pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap();
+        }
 
-    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
-    type MockFlashTiny = mock_flash::MockFlashBase<2, 1, 32>;
+        // The timestamp doesn't change the data given back by the plain functions
+        push_with_timestamp(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            7,
+            &[1, 2, 3],
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            &peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[1, 2, 3]
+        );
+    }
 
     #[test]
-    async fn peek_and_overwrite_old_data() {
-        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
-        const FLASH_RANGE: Range<u32> = 0x00..0x40;
+    async fn push_pop_sequence_numbers() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
         let mut data_buffer = AlignedBuf([0; 1024]);
-        const DATA_SIZE: usize = 22;
 
-        assert_eq!(
-            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+        // Enough items to overwrite old pages
+        for i in 0..200u32 {
+            assert_eq!(
+                push_with_sequence(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &[i as u8; 15],
+                    true,
+                )
                 .await
                 .unwrap(),
-            60
-        );
+                i
+            );
+
+            // Popping doesn't reset the sequence numbers
+            if i % 3 == 0 {
+                let (sequence_number, data) = pop_with_sequence(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+                assert_eq!(data, &[sequence_number as u8; 15]);
+            }
+        }
+
+        let mut next = None;
+        while let Some((sequence_number, data)) = pop_with_sequence(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        {
+            assert_eq!(data, &[sequence_number as u8; 15]);
+            if let Some(next) = next {
+                assert_eq!(sequence_number, next);
+            }
+            next = Some(sequence_number + 1);
+        }
+        assert_eq!(next, Some(200));
 
+        // The queue is empty, but the popped items are still in flash
         assert_eq!(
-            peek(
+            push_with_sequence(
                 &mut flash,
-                FLASH_RANGE,
+                flash_range.clone(),
                 &mut cache::NoCache::new(),
-                &mut data_buffer
+                &[0; 15],
+                false,
             )
             .await
             .unwrap(),
-            None
+            200
         );
-
-        data_buffer[..DATA_SIZE].copy_from_slice(&[0xAA; DATA_SIZE]);
-        push(
+        let (sequence_number, _) = peek_with_sequence(
             &mut flash,
-            FLASH_RANGE,
+            flash_range.clone(),
             &mut cache::NoCache::new(),
-            &data_buffer[..DATA_SIZE],
-            false,
+            &mut data_buffer,
         )
         .await
+        .unwrap()
         .unwrap();
+        assert_eq!(sequence_number, 200);
+    }
 
-        assert_eq!(
-            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
-                .await
-                .unwrap(),
-            30
-        );
+    #[test]
+    async fn sequence_numbers_with_other_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
 
         assert_eq!(
-            peek(
+            push_with_sequence(
                 &mut flash,
-                FLASH_RANGE,
+                flash_range.clone(),
                 &mut cache::NoCache::new(),
-                &mut data_buffer
+                &[1],
+                false,
             )
             .await
-            .unwrap()
             .unwrap(),
-            &[0xAA; DATA_SIZE]
+            0
         );
-        data_buffer[..DATA_SIZE].copy_from_slice(&[0xBB; DATA_SIZE]);
+        // Items without a sequence number don't take one up
+        push_channel(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            5,
+            &[9; 8],
+            false,
+        )
+        .await
+        .unwrap();
+        let metadata = push_with_metadata(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[2],
+            ItemMetadata::new().with_channel(1).with_sequence_number(),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(metadata.sequence_number(), Some(1));
+        assert_eq!(metadata.channel(), Some(1));
         push(
             &mut flash,
-            FLASH_RANGE,
+            flash_range.clone(),
             &mut cache::NoCache::new(),
-            &data_buffer[..DATA_SIZE],
+            &AlignedBuf([9; 8]),
             false,
         )
         .await
         .unwrap();
-
         assert_eq!(
-            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
-                .await
-                .unwrap(),
-            0
+            push_with_sequence(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &[3],
+                false,
+            )
+            .await
+            .unwrap(),
+            2
         );
 
+        let (sequence_number, data) = pop_with_sequence(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &mut data_buffer,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!((sequence_number, &data[..]), (0, &[1][..]));
+
         assert_eq!(
-            peek(
+            pop_with_sequence(
                 &mut flash,
-                FLASH_RANGE,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await,
+            Err(Error::SerializationError(SerializationError::InvalidFormat))
+        );
+        assert_eq!(
+            &pop_channel(
+                &mut flash,
+                flash_range.clone(),
                 &mut cache::NoCache::new(),
+                1,
                 &mut data_buffer
             )
             .await
             .unwrap()
-            .unwrap(),
-            &[0xAA; DATA_SIZE]
+            .unwrap()[..],
+            &[2]
         );
+    }
 
-        // Flash is full, this should fail
-        data_buffer[..DATA_SIZE].copy_from_slice(&[0xCC; DATA_SIZE]);
+    #[test]
+    async fn iterate_over_corrupted_item() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..4u8 {
+            // Interrupt the second push halfway through the data
+            if i == 1 {
+                flash.bytes_until_shutoff = Some(16);
+            }
+
+            let result = push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 12])[..],
+                false,
+            )
+            .await;
+            assert_eq!(result.is_err(), i == 1);
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        for i in [0, 2, 3u8] {
+            assert_eq!(
+                &iterator.next(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 12]
+            );
+        }
+        assert!(iterator.next(&mut data_buffer).await.unwrap().is_none());
+        assert_eq!(iterator.skipped_items(), 1);
+    }
+
+    #[test]
+    async fn resume_iteration_from_iterator_cursor() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        for i in 0..10u8 {
+            push(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &AlignedBuf([i; 12])[..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(iterator.cursor(), None);
+        for _ in 0..3 {
+            iterator.next(&mut data_buffer).await.unwrap().unwrap();
+        }
+        iterator.next_reader().await.unwrap().unwrap();
+        let cursor = iterator.cursor().unwrap();
+
+        // Do something else with the flash
         push(
             &mut flash,
-            FLASH_RANGE,
+            flash_range.clone(),
             &mut cache::NoCache::new(),
-            &data_buffer[..DATA_SIZE],
+            &AlignedBuf([10; 12])[..],
             false,
         )
         .await
-        .unwrap_err();
-        // Now we allow overwrite, so it should work
-        data_buffer[..DATA_SIZE].copy_from_slice(&[0xDD; DATA_SIZE]);
+        .unwrap();
+
+        let mut cache = cache::NoCache::new();
+        let mut iterator = iter_from(&mut flash, flash_range.clone(), &mut cache, &cursor)
+            .await
+            .unwrap();
+        for i in 4..11u8 {
+            assert_eq!(
+                &iterator.next(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 12]
+            );
+        }
+        assert!(iterator.next(&mut data_buffer).await.unwrap().is_none());
+    }
+
+    #[test]
+    async fn pop_into_multiple_buffers() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+
+        let data = AlignedBuf([1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
         push(
             &mut flash,
-            FLASH_RANGE,
-            &mut cache::NoCache::new(),
-            &data_buffer[..DATA_SIZE],
-            true,
+            flash_range.clone(),
+            &mut cache,
+            &data[..],
+            false,
         )
         .await
         .unwrap();
 
+        let mut header = [0; 3];
+        let mut payload = [0; 5];
+        let mut rest = [0; 8];
+
         assert_eq!(
-            peek(
+            pop_vectored(
                 &mut flash,
-                FLASH_RANGE,
-                &mut cache::NoCache::new(),
-                &mut data_buffer
+                flash_range.clone(),
+                &mut cache,
+                &mut [&mut header, &mut payload]
             )
-            .await
-            .unwrap()
-            .unwrap(),
-            &[0xBB; DATA_SIZE]
+            .await,
+            Err(Error::BufferTooSmall(10))
         );
+
         assert_eq!(
-            pop(
+            pop_vectored(
                 &mut flash,
-                FLASH_RANGE,
-                &mut cache::NoCache::new(),
-                &mut data_buffer
+                flash_range.clone(),
+                &mut cache,
+                &mut [&mut header, &mut payload, &mut rest]
             )
             .await
-            .unwrap()
             .unwrap(),
-            &[0xBB; DATA_SIZE]
-        );
-
-        assert_eq!(
-            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
-                .await
-                .unwrap(),
-            30
+            Some(10)
         );
+        assert_eq!(header, [1, 2, 3]);
+        assert_eq!(payload, [4, 5, 6, 7, 8]);
+        assert_eq!(rest, [9, 10, 0, 0, 0, 0, 0, 0]);
 
         assert_eq!(
-            peek(
+            pop_vectored(
                 &mut flash,
-                FLASH_RANGE,
-                &mut cache::NoCache::new(),
-                &mut data_buffer
+                flash_range.clone(),
+                &mut cache,
+                &mut [&mut header]
             )
             .await
-            .unwrap()
             .unwrap(),
-            &[0xDD; DATA_SIZE]
+            None
         );
-        assert_eq!(
-            pop(
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    async fn dump_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+
+        assert!(dump(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap()
+            .is_empty());
+
+        for i in 0..100u8 {
+            push(
                 &mut flash,
-                FLASH_RANGE,
-                &mut cache::NoCache::new(),
-                &mut data_buffer
+                flash_range.clone(),
+                &mut cache,
+                &AlignedBuf([i; 32])[..i as usize % 32],
+                true,
             )
             .await
-            .unwrap()
-            .unwrap(),
-            &[0xDD; DATA_SIZE]
-        );
+            .unwrap();
+        }
 
+        let items = dump(&mut flash, flash_range.clone(), &mut cache)
+            .await
+            .unwrap();
         assert_eq!(
-            space_left(&mut flash, FLASH_RANGE, &mut cache::NoCache::new())
+            items.len(),
+            len(&mut flash, flash_range.clone(), &mut cache)
                 .await
-                .unwrap(),
-            60
+                .unwrap()
         );
+        for (item, i) in items.iter().zip(100 - items.len() as u8..) {
+            assert_eq!(item, &vec![i; i as usize % 32]);
+        }
+    }
+
+    #[test]
+    async fn push_all_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = AlignedBuf([0; 1024]);
+
+        let a = AlignedBuf([1; 5]);
+        let b = AlignedBuf([2; 0]);
+        let c = AlignedBuf([3; 12]);
+
+        push_all(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[&a[..], &b[..], &c[..]],
+            false,
+        )
+        .await
+        .unwrap();
+
+        for expected in [&a[..], &b[..], &c[..]] {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                expected
+            );
+        }
 
         assert_eq!(
-            peek(
+            pop(
                 &mut flash,
-                FLASH_RANGE,
+                flash_range.clone(),
                 &mut cache::NoCache::new(),
                 &mut data_buffer
             )
@@ -940,140 +6643,88 @@ mod tests {
             .unwrap(),
             None
         );
+
+        let too_big = AlignedBuf([0; 600]);
         assert_eq!(
-            pop(
+            push_all(
                 &mut flash,
-                FLASH_RANGE,
+                flash_range.clone(),
                 &mut cache::NoCache::new(),
-                &mut data_buffer
+                &[&too_big[..], &too_big[..]],
+                false,
             )
-            .await
-            .unwrap(),
-            None
+            .await,
+            Err(Error::ItemTooBig)
         );
     }
 
     #[test]
-    async fn push_pop() {
-        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
-        let flash_range = 0x000..0x1000;
+    async fn push_all_is_atomic() {
+        let a = AlignedBuf([1; 8]);
+        let b = AlignedBuf([2; 8]);
+        let c = AlignedBuf([3; 8]);
         let mut data_buffer = AlignedBuf([0; 1024]);
 
-        for i in 0..2000 {
-            println!("{i}");
-            let data = vec![i as u8; i % 512 + 1];
+        for shutoff in 0..64 {
+            let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+            let flash_range = 0x000..0x1000;
 
             push(
                 &mut flash,
                 flash_range.clone(),
                 &mut cache::NoCache::new(),
-                &data,
-                true,
+                &a,
+                false,
             )
             .await
             .unwrap();
-            assert_eq!(
-                peek(
-                    &mut flash,
-                    flash_range.clone(),
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer
-                )
-                .await
-                .unwrap()
-                .unwrap(),
-                &data,
-                "At {i}"
-            );
-            assert_eq!(
-                pop(
-                    &mut flash,
-                    flash_range.clone(),
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer
-                )
-                .await
-                .unwrap()
-                .unwrap(),
-                &data,
-                "At {i}"
-            );
-            assert_eq!(
-                peek(
-                    &mut flash,
-                    flash_range.clone(),
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer
-                )
-                .await
-                .unwrap(),
-                None,
-                "At {i}"
-            );
-        }
-    }
-
-    #[test]
-    async fn push_pop_tiny() {
-        let mut flash = MockFlashTiny::new(WriteCountCheck::Twice, None, true);
-        let flash_range = 0x00..0x40;
-        let mut data_buffer = AlignedBuf([0; 1024]);
 
-        for i in 0..2000 {
-            println!("{i}");
-            let data = vec![i as u8; i % 20 + 1];
+            flash.bytes_until_shutoff = Some(shutoff);
+            let result = push_all(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &[&b[..], &c[..]],
+                false,
+            )
+            .await;
+            flash.bytes_until_shutoff = None;
 
-            println!("PUSH");
+            // After the shutoff we push another item which must always be readable
             push(
                 &mut flash,
                 flash_range.clone(),
                 &mut cache::NoCache::new(),
-                &data,
-                true,
+                &a,
+                false,
             )
             .await
             .unwrap();
-            assert_eq!(
-                peek(
-                    &mut flash,
-                    flash_range.clone(),
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer
-                )
-                .await
-                .unwrap()
-                .unwrap(),
-                &data,
-                "At {i}"
-            );
-            println!("POP");
-            assert_eq!(
-                pop(
-                    &mut flash,
-                    flash_range.clone(),
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer
-                )
-                .await
-                .unwrap()
-                .unwrap(),
-                &data,
-                "At {i}"
-            );
-            println!("PEEK");
-            assert_eq!(
-                peek(
-                    &mut flash,
-                    flash_range.clone(),
-                    &mut cache::NoCache::new(),
-                    &mut data_buffer
-                )
-                .await
-                .unwrap(),
-                None,
-                "At {i}"
-            );
-            println!("DONE");
+
+            let none_pushed = vec![&a[..], &a[..]];
+            let all_pushed = vec![&a[..], &b[..], &c[..], &a[..]];
+
+            let mut popped = Vec::new();
+            while let Some(data) = pop(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer,
+            )
+            .await
+            .unwrap()
+            {
+                popped.push(data.to_vec());
+            }
+
+            if result.is_ok() {
+                assert_eq!(popped, all_pushed, "Shutoff at {shutoff}");
+            } else {
+                assert!(
+                    popped == none_pushed || popped == all_pushed,
+                    "Shutoff at {shutoff}: {popped:?}"
+                );
+            }
         }
     }
 
@@ -0,0 +1,574 @@
+use super::*;
+
+/// The flag of the chunk that starts a large item
+const FIRST_CHUNK: u8 = 0b01;
+/// The flag of the chunk that ends a large item
+const LAST_CHUNK: u8 = 0b10;
+
+/// Push data that may be too big to fit in a single item, like a crash dump that is bigger than a page.
+/// The data can be taken out again with [peek_large_item] and [pop_large_item].
+///
+/// The data is split in chunks that are pushed as separate items, so the data can span many pages.
+/// Every chunk is marked in its [ItemMetadata] as a chunk, so other items can be pushed to the same queue.
+/// The large item functions skip those and the other queue functions see the chunks as items of their own.
+///
+/// Before anything is pushed, it's checked that all chunks fit and else [Error::FullStorage] is returned.
+/// This counts every chunk as big as the biggest one.
+/// The chunks are pushed one by one, so the push is not atomic. If it's interrupted by e.g. a power loss,
+/// the chunks that were pushed are skipped by [peek_large_item] and removed by [pop_large_item].
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest items are removed to make space for the new data.
+/// The data is never big enough to remove its own chunks, but data of which the first chunks are removed
+/// by a later push is skipped too.
+pub async fn push_large_item<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data: &[u8],
+    allow_overwrite_old_data: bool,
+) -> Result<(), Error<S::Error>> {
+    let mut metadata_buffer = [0; ItemMetadata::MAX_LENGTH];
+    let metadata_length = ItemMetadata::new()
+        .with_chunk(FIRST_CHUNK | LAST_CHUNK)
+        .serialize_into(&mut metadata_buffer);
+
+    let chunk_len = max_item_size::<S>() - metadata_length;
+    let chunk_count = data.len().div_ceil(chunk_len).max(1);
+
+    let fits = run_with_auto_repair!(
+        function = large_item_fits(
+            flash,
+            flash_range.clone(),
+            cache,
+            (metadata_length + data.len().min(chunk_len)) as u32,
+            chunk_count as u32,
+            allow_overwrite_old_data
+        )
+        .await,
+        repair = try_repair(flash, flash_range.clone(), cache).await?
+    )?;
+    if !fits {
+        return Err(Error::FullStorage);
+    }
+
+    for index in 0..chunk_count {
+        // Empty data is pushed as one empty chunk
+        let chunk = data.chunks(chunk_len).nth(index).unwrap_or_default();
+
+        let mut flags = 0;
+        if index == 0 {
+            flags |= FIRST_CHUNK;
+        }
+        if index == chunk_count - 1 {
+            flags |= LAST_CHUNK;
+        }
+
+        push_with_metadata(
+            flash,
+            flash_range.clone(),
+            cache,
+            chunk,
+            ItemMetadata::new().with_chunk(flags),
+            allow_overwrite_old_data,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Check that the given amount of chunks with the given item length can all be pushed
+async fn large_item_fits<S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    chunk_item_length: u32,
+    chunk_count: u32,
+    allow_overwrite_old_data: bool,
+) -> Result<bool, Error<S::Error>> {
+    assert_eq!(flash_range.start % S::ERASE_SIZE as u32, 0);
+    assert_eq!(flash_range.end % S::ERASE_SIZE as u32, 0);
+
+    assert!(S::ERASE_SIZE >= S::WORD_SIZE * 4);
+    assert!(S::WORD_SIZE <= MAX_WORD_SIZE);
+
+    cache.recover_if_dirty();
+
+    let free_spots = free_item_spots(
+        flash,
+        flash_range,
+        cache,
+        chunk_item_length,
+        allow_overwrite_old_data,
+    )
+    .await?;
+
+    cache.unmark_dirty();
+    Ok(free_spots >= chunk_count)
+}
+
+/// Peek at the oldest data that was pushed with [push_large_item].
+///
+/// The data is read into the data buffer and the part of the buffer that holds the data is returned.
+/// The data buffer must be long enough to hold the whole data, else [Error::BufferTooSmall]
+/// is returned with the length of the whole data.
+pub async fn peek_large_item<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    match read_large_item(&mut iterator, data_buffer).await? {
+        Some((length, _)) => Ok(Some(&mut data_buffer[..length])),
+        None => Ok(None),
+    }
+}
+
+/// Pop the oldest data that was pushed with [push_large_item].
+/// Chunks in front of it that are left over from an interrupted push are removed too.
+/// Items that are not chunks stay in the queue.
+///
+/// The data is read into the data buffer and the part of the buffer that holds the data is returned.
+/// The data buffer must be long enough to hold the whole data, else [Error::BufferTooSmall]
+/// is returned with the length of the whole data and nothing is popped.
+///
+/// The chunks are only popped after all of them have been read.
+/// If popping is interrupted, the chunks that are left are removed by the next pop.
+pub async fn pop_large_item<'d, S: MultiwriteNorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    let mut iterator = iter(flash, flash_range.clone(), cache).await?;
+
+    let Some((length, chunk_count)) = read_large_item(&mut iterator, data_buffer).await? else {
+        return Ok(None);
+    };
+
+    let mut iterator = iter(flash, flash_range, cache).await?;
+    let mut popped_chunks = 0;
+    while popped_chunks < chunk_count {
+        let Some(reader) = iterator.next_reader().await? else {
+            break;
+        };
+
+        if reader.metadata().chunk().is_some() {
+            reader.pop().await?;
+            popped_chunks += 1;
+        }
+    }
+
+    Ok(Some(&mut data_buffer[..length]))
+}
+
+/// Read the oldest complete large item into the data buffer.
+/// Returns the length of the data and the amount of chunks up to and including its last chunk.
+async fn read_large_item<S: NorFlash, CI: CacheImpl>(
+    iterator: &mut QueueIterator<'_, S, CI>,
+    data_buffer: &mut [u8],
+) -> Result<Option<(usize, usize)>, Error<S::Error>> {
+    // The length of the data so far, or None if no first chunk has been found yet
+    let mut length = None;
+    let mut chunk_count = 0;
+
+    while let Some(mut reader) = iterator.next_reader().await? {
+        let Some(flags) = reader.metadata().chunk() else {
+            // Not part of a large item
+            continue;
+        };
+        chunk_count += 1;
+
+        let start = if flags & FIRST_CHUNK != 0 {
+            // Whatever came before was left over from an interrupted push
+            0
+        } else {
+            match length {
+                Some(length) => length,
+                // The first chunks of this data are gone
+                None => continue,
+            }
+        };
+
+        // When the buffer is too small, the rest of the chunks are still counted to find the whole length
+        let end = start + reader.remaining();
+        if end <= data_buffer.len() {
+            reader.read(&mut data_buffer[start..end]).await?;
+        }
+
+        if flags & LAST_CHUNK != 0 {
+            if end > data_buffer.len() {
+                return Err(Error::BufferTooSmall(end));
+            }
+            return Ok(Some((end, chunk_count)));
+        }
+        length = Some(end);
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+
+    #[test]
+    async fn push_and_pop_large_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 2048];
+
+        let large_data: [u8; 2000] = core::array::from_fn(|i| i as u8);
+        assert!(large_data.len() > max_item_size::<MockFlashBig>());
+
+        push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &large_data,
+            false,
+        )
+        .await
+        .unwrap();
+        push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[],
+            false,
+        )
+        .await
+        .unwrap();
+        push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[1, 2, 3],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            &peek_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &large_data
+        );
+        assert!(matches!(
+            pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer[..1000]
+            )
+            .await,
+            Err(Error::BufferTooSmall(2000))
+        ));
+        assert_eq!(
+            &pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &large_data
+        );
+        assert_eq!(
+            &pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[]
+        );
+        assert_eq!(
+            &pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &[1, 2, 3]
+        );
+        assert_eq!(
+            pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn interrupted_push_is_skipped() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 2048];
+
+        let large_data: [u8; 2000] = core::array::from_fn(|i| i as u8);
+
+        // Power is lost while the second chunk is written
+        flash.bytes_until_shutoff = Some(1300);
+        assert!(push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &large_data,
+            false,
+        )
+        .await
+        .is_err());
+        flash.bytes_until_shutoff = None;
+
+        assert_eq!(
+            peek_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &large_data[..1000],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            &pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &large_data[..1000]
+        );
+        assert_eq!(
+            peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn push_is_checked_to_fit() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 2048];
+
+        let large_data: [u8; 2000] = core::array::from_fn(|i| i as u8);
+        let too_large_data = [0xAA; 5000];
+
+        // Nothing is pushed when the data doesn't fit, also not when old data may be overwritten
+        for allow_overwrite_old_data in [false, true] {
+            assert_eq!(
+                push_large_item(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &too_large_data,
+                    allow_overwrite_old_data,
+                )
+                .await,
+                Err(Error::FullStorage)
+            );
+            assert_eq!(
+                len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                    .await
+                    .unwrap(),
+                0
+            );
+        }
+
+        for index in 0..2 {
+            push_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &large_data[index..],
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        let item_count = len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            push_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &large_data[2..],
+                false,
+            )
+            .await,
+            Err(Error::FullStorage)
+        );
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            item_count
+        );
+
+        // Overwriting removes the oldest data, but never the chunks of the data that is pushed
+        push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &large_data[2..],
+            true,
+        )
+        .await
+        .unwrap();
+
+        for index in 1..3 {
+            assert_eq!(
+                &pop_large_item(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &large_data[index..]
+            );
+        }
+        assert_eq!(
+            peek(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn mix_large_items_with_other_items() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 2048];
+
+        let large_data: [u8; 2000] = core::array::from_fn(|i| i as u8);
+
+        push(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[1, 2, 3, 4],
+            false,
+        )
+        .await
+        .unwrap();
+        push_large_item(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &large_data,
+            false,
+        )
+        .await
+        .unwrap();
+        push(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            &[5, 6, 7, 8],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            &pop_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap()
+            .unwrap()[..],
+            &large_data
+        );
+        assert_eq!(
+            peek_large_item(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        for data in [[1, 2, 3, 4], [5, 6, 7, 8]] {
+            assert_eq!(
+                &pop(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &data
+            );
+        }
+    }
+}
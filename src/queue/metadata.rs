@@ -0,0 +1,252 @@
+use super::Priority;
+use crate::map::SerializationError;
+
+/// Extra information that is stored with a queue item next to its data, like the channel of [push_channel](super::push_channel).
+///
+/// It's not part of the data, so the data that is given back is always the data that was pushed,
+/// also when a queue mixes items with and without metadata.
+/// Use [push_with_metadata](super::push_with_metadata) to push an item with metadata and
+/// [peek_with_metadata](super::peek_with_metadata) or [QueueIteratorEntry::metadata](super::QueueIteratorEntry::metadata)
+/// to get it back. Items that were pushed without metadata give back [ItemMetadata::new].
+///
+/// Only the fields that are set are stored. They take up a byte for the version of the layout,
+/// a byte to mark which fields are set and the size of every field.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ItemMetadata {
+    channel: Option<u8>,
+    priority: Priority,
+    flags: u8,
+    timestamp: Option<u64>,
+    sequence_number: Option<u32>,
+    /// The next sequence number has to be assigned when the item is pushed
+    needs_sequence_number: bool,
+    /// The item is a chunk of a large item. See [push_large_item](super::push_large_item).
+    chunk: Option<u8>,
+}
+
+impl ItemMetadata {
+    /// The version of the layout that is written. Items without metadata are the layout of version 0.
+    ///
+    /// Metadata with a version that isn't known is from a newer version of the crate.
+    /// The items it belongs to are skipped like corrupted items.
+    pub(crate) const VERSION: u8 = 1;
+
+    const CHANNEL: u8 = 1 << 0;
+    /// High priority is only a mark, it has no bytes of its own
+    const HIGH_PRIORITY: u8 = 1 << 1;
+    const FLAGS: u8 = 1 << 2;
+    const TIMESTAMP: u8 = 1 << 3;
+    const SEQUENCE_NUMBER: u8 = 1 << 4;
+    const CHUNK: u8 = 1 << 5;
+    const ALL_FIELDS: u8 = Self::CHANNEL
+        | Self::HIGH_PRIORITY
+        | Self::FLAGS
+        | Self::TIMESTAMP
+        | Self::SEQUENCE_NUMBER
+        | Self::CHUNK;
+
+    /// The most bytes the metadata can take up in an item
+    pub(crate) const MAX_LENGTH: usize = 1 + 1 + 1 + 1 + 8 + 4 + 1;
+
+    /// Metadata without any fields set
+    pub const fn new() -> Self {
+        Self {
+            channel: None,
+            priority: Priority::Normal,
+            flags: 0,
+            timestamp: None,
+            sequence_number: None,
+            needs_sequence_number: false,
+            chunk: None,
+        }
+    }
+
+    /// Set the channel. See [push_channel](super::push_channel).
+    pub const fn with_channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// The channel, if one was set
+    pub const fn channel(&self) -> Option<u8> {
+        self.channel
+    }
+
+    /// Set the priority. See [push_with_priority](super::push_with_priority).
+    pub const fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The priority, which is [Priority::Normal] if none was set
+    pub const fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Set the user flags. See [push_with_flags](super::push_with_flags).
+    pub const fn with_flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// The user flags, which are 0 if none were set
+    pub const fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Set the timestamp. See [push_with_timestamp](super::push_with_timestamp).
+    pub const fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// The timestamp, if one was set
+    pub const fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Give the item the next sequence number when it's pushed. See [push_with_sequence](super::push_with_sequence).
+    ///
+    /// The sequence number can't be chosen. The number the item got is in the metadata
+    /// that [push_with_metadata](super::push_with_metadata) returns.
+    pub const fn with_sequence_number(mut self) -> Self {
+        self.needs_sequence_number = true;
+        self
+    }
+
+    /// The sequence number, if the item got one
+    pub const fn sequence_number(&self) -> Option<u32> {
+        self.sequence_number
+    }
+
+    pub(crate) fn needs_sequence_number(&self) -> bool {
+        self.needs_sequence_number
+    }
+
+    /// Store the sequence number that the push found for the item
+    pub(crate) fn with_assigned_sequence_number(mut self, sequence_number: u32) -> Self {
+        self.sequence_number = Some(sequence_number);
+        self.needs_sequence_number = false;
+        self
+    }
+
+    /// Mark the item as a chunk of a large item with the given chunk flags
+    pub(crate) fn with_chunk(mut self, chunk_flags: u8) -> Self {
+        self.chunk = Some(chunk_flags);
+        self
+    }
+
+    /// The chunk flags, if the item is a chunk of a large item
+    pub(crate) fn chunk(&self) -> Option<u8> {
+        self.chunk
+    }
+
+    /// Returns true if no fields are set. Items without metadata are stored without any overhead.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::new()
+    }
+
+    /// Serialize the metadata into the buffer and get the amount of bytes that were used
+    pub(crate) fn serialize_into(&self, buffer: &mut [u8; Self::MAX_LENGTH]) -> usize {
+        let mut fields = 0;
+        let mut length = 2;
+
+        if let Some(channel) = self.channel {
+            fields |= Self::CHANNEL;
+            buffer[length] = channel;
+            length += 1;
+        }
+
+        if self.priority == Priority::High {
+            fields |= Self::HIGH_PRIORITY;
+        }
+
+        if self.flags != 0 {
+            fields |= Self::FLAGS;
+            buffer[length] = self.flags;
+            length += 1;
+        }
+
+        if let Some(timestamp) = self.timestamp {
+            fields |= Self::TIMESTAMP;
+            buffer[length..][..8].copy_from_slice(&timestamp.to_le_bytes());
+            length += 8;
+        }
+
+        if let Some(sequence_number) = self.sequence_number {
+            fields |= Self::SEQUENCE_NUMBER;
+            buffer[length..][..4].copy_from_slice(&sequence_number.to_le_bytes());
+            length += 4;
+        }
+
+        if let Some(chunk_flags) = self.chunk {
+            fields |= Self::CHUNK;
+            buffer[length] = chunk_flags;
+            length += 1;
+        }
+
+        buffer[0] = Self::VERSION;
+        buffer[1] = fields;
+        length
+    }
+
+    /// Deserialize the metadata from the start of the data of an item.
+    /// Returns the metadata and the amount of bytes it took up, which is where the data that was pushed starts.
+    pub(crate) fn deserialize_from(item_data: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let mut data = item_data;
+        let [version] = take(&mut data)?;
+        if version != Self::VERSION {
+            return Err(SerializationError::InvalidFormat);
+        }
+
+        let [fields] = take(&mut data)?;
+
+        if fields & !Self::ALL_FIELDS != 0 {
+            // Stored by a newer version of the crate
+            return Err(SerializationError::InvalidFormat);
+        }
+
+        let mut metadata = Self::new();
+
+        if fields & Self::CHANNEL != 0 {
+            let [channel] = take(&mut data)?;
+            metadata.channel = Some(channel);
+        }
+
+        if fields & Self::HIGH_PRIORITY != 0 {
+            metadata.priority = Priority::High;
+        }
+
+        if fields & Self::FLAGS != 0 {
+            let [flags] = take(&mut data)?;
+            metadata.flags = flags;
+        }
+
+        if fields & Self::TIMESTAMP != 0 {
+            metadata.timestamp = Some(u64::from_le_bytes(take(&mut data)?));
+        }
+
+        if fields & Self::SEQUENCE_NUMBER != 0 {
+            metadata.sequence_number = Some(u32::from_le_bytes(take(&mut data)?));
+        }
+
+        if fields & Self::CHUNK != 0 {
+            let [chunk_flags] = take(&mut data)?;
+            metadata.chunk = Some(chunk_flags);
+        }
+
+        Ok((metadata, item_data.len() - data.len()))
+    }
+}
+
+/// Take the next field of `N` bytes from the data
+fn take<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], SerializationError> {
+    if data.len() < N {
+        return Err(SerializationError::InvalidFormat);
+    }
+
+    let (field, rest) = data.split_at(N);
+    *data = rest;
+    Ok(field.try_into().unwrap())
+}
@@ -0,0 +1,265 @@
+use super::*;
+
+/// Start pushing `count` records of `record_size` bytes each as a single item, so the records share one item header.
+/// This is useful for small fixed size data, like sensor samples, for which the header would be bigger than the data.
+///
+/// The records are written with [RecordPushWriter::push_record] and become readable together once the
+/// writer is committed. Just like with [push_writer], the records don't have to be in memory all at once.
+/// A single record can be read back with [peek_record].
+///
+/// All records in a queue should have the same size, else [peek_record] can't find them.
+/// Records need at least one byte, so a `record_size` of 0 returns [Error::BufferTooSmall] with size 1.
+///
+/// Old data will not be overwritten unless `allow_overwrite_old_data` is true.
+/// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+///
+/// *Note: Like [push_writer], this only works on flash with a word size of 4 bytes or smaller.*
+pub async fn push_records<'s, S: NorFlash>(
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    record_size: usize,
+    count: usize,
+    allow_overwrite_old_data: bool,
+) -> Result<RecordPushWriter<'s, S>, Error<S::Error>> {
+    if record_size == 0 {
+        return Err(Error::BufferTooSmall(1));
+    }
+
+    let length = record_size.checked_mul(count).ok_or(Error::ItemTooBig)?;
+
+    Ok(RecordPushWriter {
+        writer: push_writer(flash, flash_range, cache, length, allow_overwrite_old_data).await?,
+        record_size,
+    })
+}
+
+/// A writer for fixed size records. See [push_records].
+#[derive(Debug)]
+pub struct RecordPushWriter<'s, S: NorFlash> {
+    writer: QueuePushWriter<'s, S>,
+    record_size: usize,
+}
+
+impl<'s, S: NorFlash> RecordPushWriter<'s, S> {
+    /// The amount of records that still have to be pushed before the records can be committed
+    pub fn remaining_records(&self) -> usize {
+        self.writer.remaining() / self.record_size
+    }
+
+    /// Write the next record.
+    ///
+    /// If the record is shorter than the record size, [Error::BufferTooSmall] is returned with the record size.
+    /// If it's longer or all records have been written already, [Error::BufferTooBig] is returned.
+    /// In both cases nothing is written.
+    pub async fn push_record(&mut self, record: &[u8]) -> Result<(), Error<S::Error>> {
+        if record.len() < self.record_size {
+            return Err(Error::BufferTooSmall(self.record_size));
+        }
+        if record.len() > self.record_size {
+            return Err(Error::BufferTooBig);
+        }
+
+        self.writer.write(record).await
+    }
+
+    /// Commit the records. After this they can be read from the queue.
+    ///
+    /// If not all records have been written yet, [Error::BufferTooSmall] is returned with the amount of missing bytes.
+    pub async fn commit(self) -> Result<(), Error<S::Error>> {
+        self.writer.commit().await
+    }
+}
+
+/// Peek at the record with the given index, counting from the oldest record in the queue.
+/// All items in the queue are seen as records of `record_size` bytes, like the ones pushed with [push_records].
+///
+/// This is a linear scan over the items of the queue: the header of every item in front of the record is read
+/// to count its records, because the items don't all have to hold the same amount of records.
+/// Only the record itself is read into the data buffer.
+/// If there's no record with the index, None is returned.
+///
+/// The data buffer must be at least `record_size` long, else [Error::BufferTooSmall] is returned.
+/// Like with [push_records], a `record_size` of 0 returns [Error::BufferTooSmall] with size 1.
+pub async fn peek_record<'d, S: NorFlash>(
+    flash: &mut S,
+    flash_range: Range<u32>,
+    cache: &mut impl CacheImpl,
+    record_size: usize,
+    index: usize,
+    data_buffer: &'d mut [u8],
+) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+    if record_size == 0 {
+        return Err(Error::BufferTooSmall(1));
+    }
+    if data_buffer.len() < record_size {
+        return Err(Error::BufferTooSmall(record_size));
+    }
+
+    let mut iterator = iter(flash, flash_range, cache).await?;
+
+    let mut index = index;
+    while let Some(mut reader) = iterator.next_reader().await? {
+        let records = reader.len() / record_size;
+        if index >= records {
+            index -= records;
+            continue;
+        }
+
+        reader.skip(index * record_size);
+        let record = &mut data_buffer[..record_size];
+        reader.read(record).await?;
+        return Ok(Some(record));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+
+    #[test]
+    async fn push_and_peek_records() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 16];
+
+        for batch in 0..3u8 {
+            let mut writer = push_records(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                16,
+                10,
+                false,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(
+                writer.push_record(&[0; 15]).await,
+                Err(Error::BufferTooSmall(16))
+            );
+            assert_eq!(writer.push_record(&[0; 17]).await, Err(Error::BufferTooBig));
+
+            for record in 0..10u8 {
+                assert_eq!(writer.remaining_records(), 10 - record as usize);
+                writer
+                    .push_record(&[batch * 10 + record; 16])
+                    .await
+                    .unwrap();
+            }
+            writer.commit().await.unwrap();
+        }
+
+        // 3 items hold all 30 records
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            3
+        );
+
+        for index in [0, 9, 10, 15, 29] {
+            assert_eq!(
+                &peek_record(
+                    &mut flash,
+                    flash_range.clone(),
+                    &mut cache::NoCache::new(),
+                    16,
+                    index,
+                    &mut data_buffer
+                )
+                .await
+                .unwrap()
+                .unwrap()[..],
+                &[index as u8; 16]
+            );
+        }
+
+        assert_eq!(
+            peek_record(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                16,
+                30,
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn zero_sized_records() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 16];
+
+        assert!(matches!(
+            push_records(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                0,
+                10,
+                false,
+            )
+            .await,
+            Err(Error::BufferTooSmall(1))
+        ));
+        assert_eq!(
+            peek_record(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                0,
+                0,
+                &mut data_buffer
+            )
+            .await,
+            Err(Error::BufferTooSmall(1))
+        );
+    }
+
+    #[test]
+    async fn uncommitted_records_are_not_found() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut data_buffer = [0; 16];
+
+        let mut writer = push_records(
+            &mut flash,
+            flash_range.clone(),
+            &mut cache::NoCache::new(),
+            16,
+            2,
+            false,
+        )
+        .await
+        .unwrap();
+        writer.push_record(&[1; 16]).await.unwrap();
+        assert_eq!(writer.commit().await, Err(Error::BufferTooSmall(16)));
+
+        assert_eq!(
+            peek_record(
+                &mut flash,
+                flash_range.clone(),
+                &mut cache::NoCache::new(),
+                16,
+                0,
+                &mut data_buffer
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use core::{
+    cell::{Cell, RefCell, RefMut},
+    fmt::Debug,
+    future::poll_fn,
+    ops::{Deref, DerefMut},
+    task::Poll,
+};
+
+use super::*;
+
+/// A [Queue] that can be split into a producer and a consumer half, so e.g. a logging task can push
+/// while an uplink task pops. See [SharedQueue::split].
+///
+/// Only one operation can run on the queue at a time. If one half is busy with an operation,
+/// a call on the other half waits until the first operation is done. Only one waiting task is remembered,
+/// which is enough for one producer and one consumer task.
+///
+/// The halves can only be used from the same thread. To use them from multiple threads or interrupts,
+/// put the [Queue] in a real async mutex instead.
+pub struct SharedQueue<S: NorFlash, CI: CacheImpl> {
+    queue: RefCell<Queue<S, CI>>,
+    lock_waker: Cell<Option<Waker>>,
+}
+
+impl<S: NorFlash, CI: CacheImpl> Debug for SharedQueue<S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SharedQueue").finish_non_exhaustive()
+    }
+}
+
+impl<S: NorFlash, CI: CacheImpl> SharedQueue<S, CI> {
+    /// Share the given queue
+    pub fn new(queue: Queue<S, CI>) -> Self {
+        Self {
+            queue: RefCell::new(queue),
+            lock_waker: Cell::new(None),
+        }
+    }
+
+    /// Split the queue into a producer and a consumer half
+    pub fn split(&self) -> (QueueProducer<'_, S, CI>, QueueConsumer<'_, S, CI>) {
+        (
+            QueueProducer { shared: self },
+            QueueConsumer { shared: self },
+        )
+    }
+
+    /// Get back the queue
+    pub fn into_inner(self) -> Queue<S, CI> {
+        self.queue.into_inner()
+    }
+
+    /// Wait until the queue is not used by the other half anymore
+    async fn lock(&self) -> QueueGuard<'_, S, CI> {
+        poll_fn(|cx| match self.queue.try_borrow_mut() {
+            Ok(queue) => Poll::Ready(QueueGuard {
+                queue,
+                lock_waker: &self.lock_waker,
+            }),
+            Err(_) => {
+                // A task that gets replaced is woken, so it can register itself again
+                if let Some(previous) = self.lock_waker.replace(Some(cx.waker().clone())) {
+                    if !previous.will_wake(cx.waker()) {
+                        previous.wake();
+                    }
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Access to the queue of a [SharedQueue]. When it's dropped, the task that waits for the queue is woken.
+struct QueueGuard<'q, S: NorFlash, CI: CacheImpl> {
+    queue: RefMut<'q, Queue<S, CI>>,
+    lock_waker: &'q Cell<Option<Waker>>,
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> Deref for QueueGuard<'q, S, CI> {
+    type Target = Queue<S, CI>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.queue
+    }
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> DerefMut for QueueGuard<'q, S, CI> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.queue
+    }
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> Drop for QueueGuard<'q, S, CI> {
+    fn drop(&mut self) {
+        if let Some(waker) = self.lock_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The half of a [Queue] that pushes data. See [SharedQueue::split].
+pub struct QueueProducer<'q, S: NorFlash, CI: CacheImpl> {
+    shared: &'q SharedQueue<S, CI>,
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> Debug for QueueProducer<'q, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueueProducer").finish_non_exhaustive()
+    }
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> QueueProducer<'q, S, CI> {
+    /// See [push]
+    pub async fn push(
+        &self,
+        data: &[u8],
+        allow_overwrite_old_data: bool,
+    ) -> Result<(), Error<S::Error>> {
+        self.shared
+            .lock()
+            .await
+            .push(data, allow_overwrite_old_data)
+            .await
+    }
+
+    /// See [push_all]
+    pub async fn push_all(
+        &self,
+        data: &[&[u8]],
+        allow_overwrite_old_data: bool,
+    ) -> Result<(), Error<S::Error>> {
+        self.shared
+            .lock()
+            .await
+            .push_all(data, allow_overwrite_old_data)
+            .await
+    }
+
+    /// See [space_left]
+    pub async fn space_left(&self) -> Result<u32, Error<S::Error>> {
+        self.shared.lock().await.space_left().await
+    }
+}
+
+/// The half of a [Queue] that takes data out. See [SharedQueue::split].
+pub struct QueueConsumer<'q, S: NorFlash, CI: CacheImpl> {
+    shared: &'q SharedQueue<S, CI>,
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> Debug for QueueConsumer<'q, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueueConsumer").finish_non_exhaustive()
+    }
+}
+
+impl<'q, S: NorFlash, CI: CacheImpl> QueueConsumer<'q, S, CI> {
+    /// See [peek]
+    pub async fn peek<'d>(
+        &self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+        self.shared.lock().await.peek(data_buffer).await
+    }
+
+    /// See [len]
+    pub async fn len(&self) -> Result<usize, Error<S::Error>> {
+        self.shared.lock().await.len().await
+    }
+
+    /// Returns true if there are no items in the queue
+    pub async fn is_empty(&self) -> Result<bool, Error<S::Error>> {
+        self.shared.lock().await.is_empty().await
+    }
+
+    /// Wait until there is an item in the queue, so [QueueConsumer::peek] and [QueueConsumer::pop] return it.
+    /// Returns right away if the queue already holds an item.
+    ///
+    /// This doesn't poll the flash in a loop. The queue is only checked again after the producer pushed something.
+    /// The `data_buffer` is used to peek at the queue, so it must be big enough for the oldest item.
+    pub async fn wait_for_item(&self, data_buffer: &mut [u8]) -> Result<(), Error<S::Error>> {
+        loop {
+            if self.shared.lock().await.peek(data_buffer).await?.is_some() {
+                return Ok(());
+            }
+
+            // Nothing can be pushed between the peek and registering the waker,
+            // because both happen without yielding in between
+            let mut registered = false;
+            poll_fn(|cx| {
+                if registered {
+                    Poll::Ready(())
+                } else {
+                    self.shared
+                        .queue
+                        .borrow_mut()
+                        .register_push_waker(cx.waker());
+                    registered = true;
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+    }
+}
+
+impl<'q, S: MultiwriteNorFlash, CI: CacheImpl> QueueConsumer<'q, S, CI> {
+    /// See [pop]
+    pub async fn pop<'d>(
+        &self,
+        data_buffer: &'d mut [u8],
+    ) -> Result<Option<&'d mut [u8]>, Error<S::Error>> {
+        self.shared.lock().await.pop(data_buffer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use core::{future::Future, pin::pin, task::Context};
+    use futures::poll;
+    use futures_test::future::FutureTestExt;
+    use futures_test::task::new_count_waker;
+    use futures_test::test;
+
+    type MockFlash = mock_flash::MockFlashBase<4, 4, 256>;
+
+    #[test]
+    async fn interleaved_push_and_pop() {
+        let shared = SharedQueue::new(Queue::new(
+            MockFlash::new(WriteCountCheck::Twice, None, true),
+            0x000..0x1000,
+            cache::NoCache::new(),
+        ));
+        let (producer, consumer) = shared.split();
+        let mut data_buffer = AlignedBuf([0; 32]);
+
+        for i in 0..100u8 {
+            producer.push(&AlignedBuf([i; 12]), false).await.unwrap();
+            producer.push(&AlignedBuf([i; 8]), false).await.unwrap();
+
+            assert_eq!(
+                &consumer.pop(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 12]
+            );
+            assert_eq!(consumer.len().await.unwrap(), 1);
+            assert_eq!(
+                &consumer.pop(&mut data_buffer).await.unwrap().unwrap()[..],
+                &[i; 8]
+            );
+        }
+
+        assert!(consumer.is_empty().await.unwrap());
+    }
+
+    #[test]
+    async fn wait_for_pushed_item() {
+        let shared = SharedQueue::new(Queue::new(
+            MockFlash::new(WriteCountCheck::Twice, None, true),
+            0x000..0x1000,
+            cache::NoCache::new(),
+        ));
+        let (producer, consumer) = shared.split();
+
+        let consume = async {
+            let mut data_buffer = AlignedBuf([0; 32]);
+            for i in 0..5u8 {
+                consumer.wait_for_item(&mut data_buffer).await.unwrap();
+                assert_eq!(
+                    &consumer.pop(&mut data_buffer).await.unwrap().unwrap()[..],
+                    &[i; 8]
+                );
+            }
+        };
+        let produce = async {
+            for i in 0..5u8 {
+                async {}.pending_once().await;
+                producer.push(&AlignedBuf([i; 8]), false).await.unwrap();
+            }
+        };
+
+        let mut consume = pin!(consume);
+        // Nothing has been pushed yet, so the consumer has to wait
+        assert!(poll!(&mut consume).is_pending());
+        assert!(shared.queue.borrow().push_waker.is_some());
+
+        futures::join!(consume, produce);
+        assert!(consumer.is_empty().await.unwrap());
+
+        // An item that is already there is found right away
+        producer.push(&AlignedBuf([9; 8]), false).await.unwrap();
+        let mut data_buffer = AlignedBuf([0; 32]);
+        assert!(poll!(pin!(consumer.wait_for_item(&mut data_buffer))).is_ready());
+    }
+
+    #[test]
+    async fn wait_while_other_half_is_busy() {
+        let shared = SharedQueue::new(Queue::new(
+            MockFlash::new(WriteCountCheck::Twice, None, true),
+            0x000..0x1000,
+            cache::NoCache::new(),
+        ));
+        let (producer, consumer) = shared.split();
+        let (waker, woken) = new_count_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let busy = shared.lock().await;
+        let mut push = pin!(producer.push(&AlignedBuf([1; 4]), false));
+        assert!(push.as_mut().poll(&mut cx).is_pending());
+        assert!(push.as_mut().poll(&mut cx).is_pending());
+        // The waiting push is not woken until the queue is free again
+        assert_eq!(woken.get(), 0);
+
+        drop(busy);
+        assert_eq!(woken.get(), 1);
+        assert!(push.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(consumer.len().await.unwrap(), 1);
+    }
+}
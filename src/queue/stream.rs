@@ -0,0 +1,200 @@
+use core::fmt::Debug;
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+use super::*;
+
+/// A byte stream on top of the queue that implements the [embedded_io_async] [Read] and [Write] traits.
+///
+/// Every call to [Write::write] pushes the given data as one item.
+/// The data of an item can't be longer than [max_item_size], so a write might not take all data.
+///
+/// [Read::read] reads the data of the oldest item. Once all of the data of an item has been read, it's popped.
+/// When the queue is empty, 0 is returned, which signals the end of the stream.
+///
+/// *Note: Pushing with the stream works like [push_writer], so it only supports flashes with a word size up to 4.*
+pub struct QueueStream<'s, S: NorFlash, CI: CacheImpl> {
+    flash: &'s mut S,
+    flash_range: Range<u32>,
+    cache: &'s mut CI,
+    allow_overwrite_old_data: bool,
+    read_position: Option<(QueueCursor, usize)>,
+}
+
+impl<'s, S: NorFlash, CI: CacheImpl> QueueStream<'s, S, CI> {
+    /// Create a new stream on the queue in the given flash range.
+    ///
+    /// Old data will not be overwritten by pushes unless `allow_overwrite_old_data` is true.
+    /// If it is, then if the queue is full, the oldest data is removed to make space for the new data.
+    pub fn new(
+        flash: &'s mut S,
+        flash_range: Range<u32>,
+        cache: &'s mut CI,
+        allow_overwrite_old_data: bool,
+    ) -> Self {
+        Self {
+            flash,
+            flash_range,
+            cache,
+            allow_overwrite_old_data,
+            read_position: None,
+        }
+    }
+}
+
+impl<'s, S: NorFlash, CI: CacheImpl> Debug for QueueStream<'s, S, CI> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueueStream")
+            .field("flash_range", &self.flash_range)
+            .field("allow_overwrite_old_data", &self.allow_overwrite_old_data)
+            .field("read_position", &self.read_position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Debug> embedded_io_async::Error for Error<S> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Storage { .. } => ErrorKind::Other,
+            Error::FullStorage => ErrorKind::OutOfMemory,
+            Error::Corrupted { .. } => ErrorKind::InvalidData,
+            Error::BufferTooBig => ErrorKind::InvalidInput,
+            Error::BufferTooSmall(_) => ErrorKind::InvalidInput,
+            Error::SerializationError(_) => ErrorKind::InvalidData,
+            Error::ItemTooBig => ErrorKind::InvalidInput,
+            Error::ReservedKey => ErrorKind::InvalidInput,
+            Error::OverlappingRanges => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl<'s, S: NorFlash, CI: CacheImpl> ErrorType for QueueStream<'s, S, CI> {
+    type Error = Error<S::Error>;
+}
+
+impl<'s, S: NorFlash, CI: CacheImpl> Write for QueueStream<'s, S, CI> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let length = buf.len().min(max_item_size::<S>());
+
+        let mut writer = push_writer(
+            self.flash,
+            self.flash_range.clone(),
+            self.cache,
+            length,
+            self.allow_overwrite_old_data,
+        )
+        .await?;
+        writer.write(&buf[..length]).await?;
+        writer.commit().await?;
+
+        Ok(length)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // Every write is committed to flash right away
+        Ok(())
+    }
+}
+
+impl<'s, S: MultiwriteNorFlash, CI: CacheImpl> Read for QueueStream<'s, S, CI> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut iterator = iter(self.flash, self.flash_range.clone(), self.cache).await?;
+
+        while let Some(mut reader) = iterator.next_reader().await? {
+            let cursor = QueueCursor {
+                address: reader.address,
+                crc: reader.header.crc.expect("Entries are never erased items"),
+            };
+
+            // Continue where we left off if the oldest item is still the one we were reading
+            reader.position = match self.read_position {
+                Some((read_cursor, position)) if read_cursor == cursor => position,
+                _ => 0,
+            };
+
+            let read = reader.read(buf).await?;
+
+            if reader.remaining() == 0 {
+                self.read_position = None;
+                reader.pop().await?;
+            } else {
+                self.read_position = Some((cursor, reader.position));
+            }
+
+            if read > 0 {
+                return Ok(read);
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_flash::{self, WriteCountCheck};
+    use futures_test::test;
+
+    type MockFlashBig = mock_flash::MockFlashBase<4, 4, 256>;
+
+    #[test]
+    async fn write_and_read_stream() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+
+        let mut stream = QueueStream::new(&mut flash, flash_range.clone(), &mut cache, false);
+
+        // Unaligned data is fine
+        let data = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        stream.write_all(&data[1..]).await.unwrap();
+        stream.write_all(&[10; 5]).await.unwrap();
+        stream.flush().await.unwrap();
+
+        // Read the first item in parts
+        let mut buffer = [0; 4];
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 4);
+        assert_eq!(buffer, [1, 2, 3, 4]);
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 4);
+        assert_eq!(buffer, [5, 6, 7, 8]);
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 1);
+        assert_eq!(buffer[..1], [9]);
+
+        let mut buffer = [0; 16];
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 5);
+        assert_eq!(buffer[..5], [10; 5]);
+
+        // The queue is empty now
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+        assert_eq!(
+            len(&mut flash, flash_range.clone(), &mut cache::NoCache::new())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn write_too_much_data() {
+        let mut flash = MockFlashBig::new(WriteCountCheck::Twice, None, true);
+        let flash_range = 0x000..0x1000;
+        let mut cache = cache::NoCache::new();
+
+        let mut stream = QueueStream::new(&mut flash, flash_range.clone(), &mut cache, false);
+
+        let data = [0xAA; 2000];
+        assert_eq!(
+            stream.write(&data).await.unwrap(),
+            max_item_size::<MockFlashBig>()
+        );
+    }
+}